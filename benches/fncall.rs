@@ -0,0 +1,100 @@
+//! Round-trip latency of `UserContext::run_fncall()`.
+//!
+//! The user program returns to the kernel at once, so each iteration
+//! measures the switch to user and back. Run it by `cargo bench` on an
+//! x86_64 host, the bare-metal paths are measured by the `riscv` example.
+
+#[cfg(all(
+    target_arch = "x86_64",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+))]
+mod fncall {
+    use core::arch::global_asm;
+    use criterion::{black_box, Criterion};
+    use trapframe::{GeneralRegs, UserContext};
+
+    #[cfg(target_os = "macos")]
+    global_asm!(".set _bench_return, bench_return");
+
+    // Mock user program returning to the kernel at once.
+    global_asm!(
+        r#"
+bench_return:
+    call syscall_fn_entry
+"#
+    );
+
+    extern "sysv64" {
+        fn bench_return();
+    }
+
+    pub fn run_fncall(c: &mut Criterion) {
+        let mut stack = vec![0u8; 0x1000];
+        let stack_top = stack.as_mut_ptr() as usize + stack.len();
+        let mut cx = UserContext::default();
+        c.bench_function("run_fncall", |b| {
+            b.iter(|| {
+                cx.general = GeneralRegs {
+                    rsp: stack_top,
+                    rip: bench_return as usize,
+                    ..Default::default()
+                };
+                cx.run_fncall();
+                black_box(cx.general.rax)
+            })
+        });
+    }
+
+    pub fn run_fncall_full(c: &mut Criterion) {
+        let mut stack = vec![0u8; 0x1000];
+        let stack_top = stack.as_mut_ptr() as usize + stack.len();
+        let mut cx = UserContext::default();
+        c.bench_function("run_fncall with all registers", |b| {
+            b.iter(|| {
+                cx.general = GeneralRegs {
+                    rax: 1,
+                    rbx: 2,
+                    rcx: 3,
+                    rdx: 4,
+                    rsi: 5,
+                    rdi: 6,
+                    rbp: 7,
+                    rsp: stack_top,
+                    r8: 8,
+                    r9: 9,
+                    r10: 10,
+                    r11: 11,
+                    r12: 12,
+                    r13: 13,
+                    r14: 14,
+                    r15: 15,
+                    rip: bench_return as usize,
+                    rflags: 0,
+                    fsbase: 0,
+                    gsbase: 0,
+                };
+                cx.run_fncall();
+                black_box(cx.general.rax)
+            })
+        });
+    }
+}
+
+#[cfg(all(
+    target_arch = "x86_64",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+))]
+criterion::criterion_group!(benches, fncall::run_fncall, fncall::run_fncall_full);
+#[cfg(all(
+    target_arch = "x86_64",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+))]
+criterion::criterion_main!(benches);
+
+#[cfg(not(all(
+    target_arch = "x86_64",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+)))]
+fn main() {
+    println!("run_fncall() is only benchmarked on x86_64 Linux, macOS and Windows");
+}
@@ -3,31 +3,86 @@ use std::io::{Result, Write};
 use std::path::PathBuf;
 
 fn main() -> Result<()> {
+    emit_entry_cfgs();
+    emit_mips_cfgs();
     gen_vector_asm()?;
     Ok(())
 }
 
+/// Select the entry paths to build, by `cfg(baremetal)` and `cfg(fncall)`
+fn emit_entry_cfgs() {
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let feature = |name: &str| std::env::var(format!("CARGO_FEATURE_{}", name)).is_ok();
+    // only x86_64 and aarch64 have both, other backends are bare-metal only
+    let both = matches!(arch.as_str(), "x86_64" | "aarch64");
+    // bare metal is left out only if asked for `fncall` alone, so that kernels
+    // built without default features keep their entry
+    let baremetal = feature("BAREMETAL") || !feature("FNCALL") || !both;
+
+    if matches!(os.as_str(), "none" | "uefi") && baremetal {
+        println!("cargo:rustc-cfg=baremetal");
+    }
+    if matches!(os.as_str(), "linux" | "macos" | "windows") && both && feature("FNCALL") {
+        println!("cargo:rustc-cfg=fncall");
+    }
+}
+
+/// Select the MIPS release 6 encodings by `cfg(mips_r6)`, which keep
+/// `target_arch = "mips"` and differ only in the target features.
+/// MIPS64 is rejected in `lib.rs`.
+fn emit_mips_cfgs() {
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let features = std::env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let r6 = features.split(',').any(|f| f == "mips32r6");
+    if arch == "mips" && r6 {
+        println!("cargo:rustc-cfg=mips_r6");
+    }
+}
+
 /// Generate assembly file for x86_64 trap vector
 fn gen_vector_asm() -> Result<()> {
     let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
     let mut f = File::create(out_path.join("vector.S"))?;
+    // size of a pointer to vector
+    let ptr_directive = match std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() {
+        Ok("x86") => ".long",
+        _ => ".quad",
+    };
+
+    // entries must be mapped in user page tables with KPTI
+    let kpti = std::env::var("CARGO_FEATURE_KPTI").is_ok();
+    // entries are indirect branch targets with CET IBT
+    let endbr = std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("x86_64");
 
     writeln!(f, "# generated by build.rs - do not edit")?;
-    writeln!(f, ".section .text")?;
+    if kpti {
+        writeln!(f, ".section .text.trampoline, \"ax\"")?;
+    } else {
+        writeln!(f, ".section .text")?;
+    }
+    writeln!(f, ".global __trampoline_vectors_start")?;
+    writeln!(f, "__trampoline_vectors_start:")?;
     for i in 0..256 {
         writeln!(f, "vector{}:", i)?;
-        if !(i == 8 || (10..=14).contains(&i) || i == 17) {
+        if endbr {
+            writeln!(f, "\tendbr64")?;
+        }
+        // #DF, #TS, #NP, #SS, #GP, #PF, #AC, #CP, #VC and #SX push error codes
+        if !matches!(i, 8 | 10..=14 | 17 | 21 | 29 | 30) {
             writeln!(f, "\tpush 0")?;
         }
         writeln!(f, "\tpush {}", i)?;
         writeln!(f, "\tjmp __alltraps")?;
     }
+    writeln!(f, ".global __trampoline_vectors_end")?;
+    writeln!(f, "__trampoline_vectors_end:")?;
 
     writeln!(f, "\n.section .rodata")?;
     writeln!(f, ".global __vectors")?;
     writeln!(f, "__vectors:")?;
     for i in 0..256 {
-        writeln!(f, "\t.quad vector{}", i)?;
+        writeln!(f, "\t{} vector{}", ptr_directive, i)?;
     }
     Ok(())
 }
@@ -0,0 +1,68 @@
+//! Cycle-counter microbenchmarks of the trap paths, run under QEMU or KVM.
+
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU64, Ordering};
+use riscv::register::{sie, sip, sstatus};
+use trapframe::UserContext;
+
+/// Number of round trips to average over.
+const ROUNDS: u64 = 1000;
+
+// Mock user program making a syscall at once.
+global_asm!(
+    r#"
+bench_syscall:
+    ecall
+"#
+);
+
+extern "C" {
+    fn bench_syscall();
+}
+
+/// `cycle` when the last software interrupt arrives in `trap_handler`.
+pub static IRQ_CYCLE: AtomicU64 = AtomicU64::new(0);
+
+fn read_cycle() -> u64 {
+    let cycle: usize;
+    unsafe { asm!("rdcycle {}", out(reg) cycle) };
+    cycle as u64
+}
+
+/// Run all benchmarks and print the average cycles.
+pub fn run() {
+    println!("syscall round trip: {} cycles", syscall_round_trip());
+    println!("interrupt delivery: {} cycles", interrupt_delivery());
+}
+
+/// Cycles from `UserContext::run_until_trap()` to user and back by `ecall`.
+fn syscall_round_trip() -> u64 {
+    let mut cx = UserContext {
+        sstatus: 0,
+        ..Default::default()
+    };
+    let mut total = 0;
+    for _ in 0..ROUNDS {
+        cx.sepc = bench_syscall as usize;
+        total += cx.run_until_trap().user_cycles;
+    }
+    total / ROUNDS
+}
+
+/// Cycles from raising a supervisor software interrupt in the kernel to
+/// its arrival in `trap_handler`.
+fn interrupt_delivery() -> u64 {
+    let mut total = 0;
+    unsafe {
+        sie::set_ssoft();
+        for _ in 0..ROUNDS {
+            let start = read_cycle();
+            sip::set_ssoft();
+            sstatus::set_sie();
+            sstatus::clear_sie();
+            total += IRQ_CYCLE.load(Ordering::Relaxed).wrapping_sub(start);
+        }
+        sie::clear_ssoft();
+    }
+    total / ROUNDS
+}
@@ -4,15 +4,18 @@
 #[macro_use]
 extern crate opensbi_rt;
 
-use riscv::register::scause::{Exception as E, Trap};
-use riscv::register::{scause, stval};
-use trapframe::{GeneralRegs, TrapFrame, UserContext};
+mod bench;
+
+use core::sync::atomic::Ordering;
+use riscv::register::scause::{Exception as E, Interrupt as I, Trap};
+use riscv::register::{scause, sip, stval};
+use trapframe::{GeneralRegs, TrapFrame, TrapReason, UserContext};
 use core::arch::asm;
 
 #[no_mangle]
 extern "C" fn main() {
     unsafe {
-        trapframe::init();
+        trapframe::init().unwrap();
     }
     println!("Hello, OpenSBI!");
 
@@ -53,23 +56,35 @@ extern "C" fn main() {
         },
         sstatus: 0xdead_beaf,
         sepc: user_entry as usize,
+        ..Default::default()
     };
     println!("Go to user: {:#x?}", regs);
     regs.run();
-    let scause = scause::read();
-    let stval = stval::read();
-    println!(
-        "Back from user: {:?}, stval={:#x}\n{:#x?}",
-        scause.cause(),
-        stval,
-        regs
-    );
+    println!("Back from user: {:?}\n{:#x?}", regs.trap_reason(), regs);
+    assert_eq!(regs.trap_reason(), TrapReason::Syscall);
+    assert_eq!(regs.get_syscall_num(), 1);
 
     unsafe {
         asm!("ebreak");
     }
 
+    bench::run();
+
     println!("Exit...");
+    exit_qemu(0);
+}
+
+/// Exit QEMU with `code` by the `sifive_test` device of `virt`, so that a
+/// run is only successful if it gets here.
+fn exit_qemu(code: u32) -> ! {
+    /// `sifive_test` on QEMU virt
+    const SIFIVE_TEST: usize = 0x10_0000;
+    let value = match code {
+        0 => 0x5555,
+        _ => (code << 16) | 0x3333,
+    };
+    unsafe { (SIFIVE_TEST as *mut u32).write_volatile(value) };
+    loop {}
 }
 
 #[no_mangle]
@@ -81,6 +96,12 @@ extern "C" fn trap_handler(tf: &mut TrapFrame) {
             println!("TRAP: Breakpoint");
             tf.sepc += 2;
         }
+        Trap::Interrupt(I::SupervisorSoft) => {
+            let cycle: usize;
+            unsafe { asm!("rdcycle {}", out(reg) cycle) };
+            bench::IRQ_CYCLE.store(cycle as u64, Ordering::Relaxed);
+            unsafe { sip::clear_ssoft() };
+        }
         _ => panic!(
             "TRAP: scause={:?}, stval={:#x}, tf={:#x?}",
             scause.cause(),
@@ -21,7 +21,7 @@ fn efi_main(_image: Handle, mut st: SystemTable<Boot>) -> uefi::Status {
     check_and_set_cpu_features();
     allow_user_access(user_entry as usize);
     unsafe {
-        trapframe::init();
+        trapframe::init().unwrap();
     }
 
     let mut context = UserContext {
@@ -0,0 +1,122 @@
+//! Display registers in crash logs.
+
+#[cfg(baremetal)]
+use super::TrapFrame;
+use super::{GeneralRegs, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext);
+#[cfg(baremetal)]
+impl_debug_by_display!(TrapFrame);
+
+/// Bits in `SPSR_EL1`
+const SPSR_BITS: &[(usize, &str)] = &[
+    (1 << 31, "N"),
+    (1 << 30, "Z"),
+    (1 << 29, "C"),
+    (1 << 28, "V"),
+    (1 << 21, "SS"),
+    (1 << 9, "D"),
+    (1 << 8, "A"),
+    (1 << 7, "I"),
+    (1 << 6, "F"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 31] {
+        [
+            ("x0", self.x0),
+            ("x1", self.x1),
+            ("x2", self.x2),
+            ("x3", self.x3),
+            ("x4", self.x4),
+            ("x5", self.x5),
+            ("x6", self.x6),
+            ("x7", self.x7),
+            ("x8", self.x8),
+            ("x9", self.x9),
+            ("x10", self.x10),
+            ("x11", self.x11),
+            ("x12", self.x12),
+            ("x13", self.x13),
+            ("x14", self.x14),
+            ("x15", self.x15),
+            ("x16", self.x16),
+            ("x17", self.x17),
+            ("x18", self.x18),
+            ("x19", self.x19),
+            ("x20", self.x20),
+            ("x21", self.x21),
+            ("x22", self.x22),
+            ("x23", self.x23),
+            ("x24", self.x24),
+            ("x25", self.x25),
+            ("x26", self.x26),
+            ("x27", self.x27),
+            ("x28", self.x28),
+            ("x29", self.x29),
+            ("x30", self.x30),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("sp", self.sp),
+            ("elr", self.elr),
+            ("spsr", self.spsr),
+            ("tpidr", self.tpidr),
+            ("tpidrro", self.tpidrro),
+            ("trap_num", self.trap_num),
+            ("esr", self.esr),
+            ("far", self.far),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.general)?;
+        write_regs(
+            f,
+            &[
+                ("sp", self.sp),
+                ("elr", self.elr),
+                ("tpidr", self.tpidr),
+                ("tpidrro", self.tpidrro),
+                ("trap_num", self.trap_num),
+                ("esr", self.esr),
+                ("far", self.far),
+            ],
+        )?;
+        write_flags(f, "spsr", self.spsr, SPSR_BITS)?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+#[cfg(baremetal)]
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.general)?;
+        write_regs(
+            f,
+            &[
+                ("sp", self.sp),
+                ("elr", self.elr),
+                ("tpidr", self.tpidr),
+                ("trap_num", self.trap_num),
+            ],
+        )?;
+        write_flags(f, "spsr", self.spsr, SPSR_BITS)
+    }
+}
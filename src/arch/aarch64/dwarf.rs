@@ -0,0 +1,43 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    unsafe {
+        let g = addr_of!((*cx).general);
+        Some(match index.0 {
+            0 => addr_of!((*g).x0),
+            // x1 to x29 are in order
+            n @ 1..=29 => addr_of!((*g).x1).add(n as usize - 1),
+            30 => addr_of!((*g).x30),
+            31 => addr_of!((*cx).sp),
+            32 => addr_of!((*cx).elr),
+            _ => return None,
+        })
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    ///
+    /// `pc` is number 32, as `elr`.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,5 @@
+//! ELF header fields of core files.
+
+/// `EM_AARCH64`
+pub(crate) const ELF_MACHINE: u16 = 183;
+pub(crate) const ELF_FLAGS: u32 = 0;
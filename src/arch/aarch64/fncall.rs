@@ -32,10 +32,19 @@ impl UserContext {
     /// Go to user context by function return, within the same privilege level.
     ///
     /// User program should call `syscall_fn_entry()` to return back.
+    /// Trap num will always be set to 2, the same value as a `svc` from EL0
+    /// (source: lower EL using AArch64, kind: synchronous).
+    /// `esr` will be set as if by `svc #0`.
     pub fn run_fncall(&mut self) {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(false);
         unsafe {
             syscall_fn_return(self);
         }
+        self.trap_num = 2;
+        self.esr = 0x15 << 26;
+        self.far = 0;
+        self.after_user_trap(run);
     }
 }
 
@@ -200,5 +209,7 @@ elr_location:
             }
         );
         assert_eq!(cx.elr, elr_location as usize);
+        assert_eq!(cx.trap_num, 2);
+        assert_eq!(cx.trap_reason(), TrapReason::Syscall);
     }
 }
@@ -0,0 +1,79 @@
+//! Conversion between context types and GDB register layouts of `gdbstub_arch`.
+
+use super::{GeneralRegs, UserContext};
+use gdbstub_arch::aarch64::reg::AArch64CoreRegs;
+
+impl GeneralRegs {
+    /// Registers x0 to x30.
+    fn to_array(self) -> [u64; 31] {
+        [
+            self.x0, self.x1, self.x2, self.x3, self.x4, self.x5, self.x6, self.x7, self.x8,
+            self.x9, self.x10, self.x11, self.x12, self.x13, self.x14, self.x15, self.x16,
+            self.x17, self.x18, self.x19, self.x20, self.x21, self.x22, self.x23, self.x24,
+            self.x25, self.x26, self.x27, self.x28, self.x29, self.x30,
+        ]
+        .map(|x| x as u64)
+    }
+
+    /// Set registers x0 to x30.
+    fn set_array(&mut self, x: &[u64; 31]) {
+        let x = x.map(|x| x as usize);
+        [
+            self.x0, self.x1, self.x2, self.x3, self.x4, self.x5, self.x6, self.x7, self.x8,
+            self.x9, self.x10, self.x11, self.x12, self.x13, self.x14, self.x15, self.x16,
+            self.x17, self.x18, self.x19, self.x20, self.x21, self.x22, self.x23, self.x24,
+            self.x25, self.x26, self.x27, self.x28, self.x29, self.x30,
+        ] = x;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of GDB `org.gnu.gdb.aarch64.core`.
+    ///
+    /// SIMD registers are not part of the context and are reported as 0.
+    pub fn get_gdb_regs(&self) -> AArch64CoreRegs {
+        AArch64CoreRegs {
+            x: self.general.to_array(),
+            sp: self.sp as u64,
+            pc: self.elr as u64,
+            cpsr: self.spsr as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.aarch64.core`.
+    ///
+    /// SIMD registers are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &AArch64CoreRegs) {
+        self.general.set_array(&regs.x);
+        self.sp = regs.sp as usize;
+        self.elr = regs.pc as usize;
+        self.spsr = regs.cpsr as usize;
+    }
+}
+
+#[cfg(baremetal)]
+impl super::TrapFrame {
+    /// Get registers in the layout of GDB `org.gnu.gdb.aarch64.core`.
+    ///
+    /// SIMD registers are not saved in the trap frame and are reported as 0.
+    pub fn get_gdb_regs(&self) -> AArch64CoreRegs {
+        AArch64CoreRegs {
+            x: self.general.to_array(),
+            sp: self.sp as u64,
+            pc: self.elr as u64,
+            cpsr: self.spsr as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.aarch64.core`.
+    ///
+    /// SIMD registers are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &AArch64CoreRegs) {
+        self.general.set_array(&regs.x);
+        self.sp = regs.sp as usize;
+        self.elr = regs.pc as usize;
+        self.spsr = regs.cpsr as usize;
+    }
+}
@@ -0,0 +1,88 @@
+//! Generic Interrupt Controller (GIC) as the interrupt controller, see
+//! [`crate::intc`].
+//!
+//! An IRQ is acknowledged at the GIC on trap, and completed on return. It
+//! is reported as `TrapReason::Ipi(sgi)` for SGIs, INTID 0 to 15, or
+//! `TrapReason::External(intid)` for others, with the IRQ number in `esr`
+//! of the user context, or from `TrapFrame::irq()` in kernel. INTID 1020
+//! to 1023 are special, and dropped as spurious.
+
+use crate::intc::InterruptController;
+use crate::TrapReason;
+use core::arch::asm;
+
+/// First special INTID
+const SPECIAL: usize = 1020;
+/// Mask of INTID in the IRQ number, without the source CPU of GICv2 SGIs
+const INTID_MASK: usize = 0xff_ffff;
+
+/// Offset of `GICC_IAR`
+const GICC_IAR: usize = 0x0c;
+/// Offset of `GICC_EOIR`
+const GICC_EOIR: usize = 0x10;
+
+/// GICv2, accessed by the MMIO CPU interface.
+///
+/// The source CPU of an SGI, which `GICC_EOIR` expects back, is kept from
+/// bit 32 of the IRQ number.
+#[derive(Debug, Clone, Copy)]
+pub struct GicV2 {
+    cpu_base: usize,
+}
+
+impl GicV2 {
+    /// Create with the virtual address of the CPU interface registers.
+    ///
+    /// # Safety
+    ///
+    /// `cpu_base` must map the GICC registers of every CPU, uncached.
+    pub const unsafe fn new(cpu_base: usize) -> Self {
+        GicV2 { cpu_base }
+    }
+}
+
+impl InterruptController for GicV2 {
+    fn claim(&self, _vector: usize) -> Option<usize> {
+        let iar = unsafe { ((self.cpu_base + GICC_IAR) as *const u32).read_volatile() } as usize;
+        let intid = iar & 0x3ff;
+        if intid >= SPECIAL {
+            return None;
+        }
+        // CPUID of SGIs
+        let cpuid = (iar >> 10) & 0x7;
+        Some(cpuid << 32 | intid)
+    }
+
+    fn complete(&self, irq: usize) {
+        let eoir = (irq >> 32) << 10 | (irq & INTID_MASK);
+        unsafe { ((self.cpu_base + GICC_EOIR) as *mut u32).write_volatile(eoir as u32) };
+    }
+}
+
+/// GICv3, accessed by the system register interface of group 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GicV3;
+
+impl InterruptController for GicV3 {
+    fn claim(&self, _vector: usize) -> Option<usize> {
+        let intid: usize;
+        unsafe { asm!("mrs {}, icc_iar1_el1", out(reg) intid, options(nomem, nostack)) };
+        // INTID 1020 to 1023 are special, but LPIs start from 8192
+        if (SPECIAL..1024).contains(&intid) {
+            return None;
+        }
+        Some(intid)
+    }
+
+    fn complete(&self, irq: usize) {
+        unsafe { asm!("msr icc_eoir1_el1, {}", in(reg) irq, options(nomem, nostack)) };
+    }
+}
+
+/// Decode the IRQ number claimed from the GIC.
+pub(super) fn decode(irq: usize) -> TrapReason {
+    match irq & INTID_MASK {
+        sgi @ 0..=15 => TrapReason::Ipi(sgi),
+        intid => TrapReason::External(intid),
+    }
+}
@@ -0,0 +1,57 @@
+//! Inter-processor interrupts by software generated interrupts (SGI) of
+//! GICv3.
+//!
+//! The SGI ID, 0 to 15, is the payload. An SGI arrives as an IRQ, and is
+//! reported as `TrapReason::Ipi(sgi)` with the GIC set as the interrupt
+//! controller, see [`crate::gic`].
+
+use core::arch::asm;
+
+/// `ICC_SGI1R_EL1.IRM`: to all PEs except self
+const IRM: u64 = 1 << 40;
+
+/// `ICC_SGI1R_EL1` with INTID `sgi`.
+fn sgi1r(sgi: u8) -> u64 {
+    assert!(sgi < 16, "invalid SGI ID {}", sgi);
+    (sgi as u64) << 24
+}
+
+/// Write `ICC_SGI1R_EL1` to generate an SGI.
+unsafe fn write_sgi1r(value: u64) {
+    asm!("msr icc_sgi1r_el1, {}", "isb", in(reg) value, options(nomem, nostack));
+}
+
+/// Send SGI `sgi` to the CPU of affinity `mpidr`, as in its `MPIDR_EL1`.
+///
+/// # Safety
+///
+/// GICv3 system register interface must be enabled.
+pub unsafe fn send(mpidr: u64, sgi: u8) {
+    let aff0 = mpidr & 0xf;
+    let range = (mpidr >> 4) & 0xf;
+    let aff1 = (mpidr >> 8) & 0xff;
+    let aff2 = (mpidr >> 16) & 0xff;
+    let aff3 = (mpidr >> 32) & 0xff;
+    let target = aff3 << 48 | range << 44 | aff2 << 32 | aff1 << 16 | 1 << aff0;
+    write_sgi1r(target | sgi1r(sgi));
+}
+
+/// Send SGI `sgi` to each CPU in `mpidrs`.
+///
+/// # Safety
+///
+/// GICv3 system register interface must be enabled.
+pub unsafe fn send_to(mpidrs: impl IntoIterator<Item = u64>, sgi: u8) {
+    for mpidr in mpidrs {
+        send(mpidr, sgi);
+    }
+}
+
+/// Send SGI `sgi` to all CPUs except the current one.
+///
+/// # Safety
+///
+/// GICv3 system register interface must be enabled.
+pub unsafe fn broadcast(sgi: u8) {
+    write_sgi1r(IRM | sgi1r(sgi));
+}
@@ -0,0 +1,92 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+#[cfg(baremetal)]
+use super::TrapFrame;
+use super::UserContext;
+
+layout!(UserContext {
+    USER_CONTEXT_TRAP_NUM_OFFSET = 0 => trap_num,
+    USER_CONTEXT_ELR_OFFSET = 2 => elr,
+    USER_CONTEXT_SPSR_OFFSET = 3 => spsr,
+    USER_CONTEXT_SP_OFFSET = 4 => sp,
+    USER_CONTEXT_TPIDR_OFFSET = 5 => tpidr,
+    USER_CONTEXT_X1_OFFSET = 6 => general.x1,
+    USER_CONTEXT_X2_OFFSET = 7 => general.x2,
+    USER_CONTEXT_X3_OFFSET = 8 => general.x3,
+    USER_CONTEXT_X4_OFFSET = 9 => general.x4,
+    USER_CONTEXT_X5_OFFSET = 10 => general.x5,
+    USER_CONTEXT_X6_OFFSET = 11 => general.x6,
+    USER_CONTEXT_X7_OFFSET = 12 => general.x7,
+    USER_CONTEXT_X8_OFFSET = 13 => general.x8,
+    USER_CONTEXT_X9_OFFSET = 14 => general.x9,
+    USER_CONTEXT_X10_OFFSET = 15 => general.x10,
+    USER_CONTEXT_X11_OFFSET = 16 => general.x11,
+    USER_CONTEXT_X12_OFFSET = 17 => general.x12,
+    USER_CONTEXT_X13_OFFSET = 18 => general.x13,
+    USER_CONTEXT_X14_OFFSET = 19 => general.x14,
+    USER_CONTEXT_X15_OFFSET = 20 => general.x15,
+    USER_CONTEXT_X16_OFFSET = 21 => general.x16,
+    USER_CONTEXT_X17_OFFSET = 22 => general.x17,
+    USER_CONTEXT_X18_OFFSET = 23 => general.x18,
+    USER_CONTEXT_X19_OFFSET = 24 => general.x19,
+    USER_CONTEXT_X20_OFFSET = 25 => general.x20,
+    USER_CONTEXT_X21_OFFSET = 26 => general.x21,
+    USER_CONTEXT_X22_OFFSET = 27 => general.x22,
+    USER_CONTEXT_X23_OFFSET = 28 => general.x23,
+    USER_CONTEXT_X24_OFFSET = 29 => general.x24,
+    USER_CONTEXT_X25_OFFSET = 30 => general.x25,
+    USER_CONTEXT_X26_OFFSET = 31 => general.x26,
+    USER_CONTEXT_X27_OFFSET = 32 => general.x27,
+    USER_CONTEXT_X28_OFFSET = 33 => general.x28,
+    USER_CONTEXT_X29_OFFSET = 34 => general.x29,
+    USER_CONTEXT_X30_OFFSET = 36 => general.x30,
+    USER_CONTEXT_X0_OFFSET = 37 => general.x0,
+    USER_CONTEXT_ESR_OFFSET = 38 => esr,
+    USER_CONTEXT_FAR_OFFSET = 39 => far,
+    USER_CONTEXT_TPIDRRO_OFFSET = 40 => tpidrro,
+});
+
+#[cfg(baremetal)]
+layout!(TrapFrame {
+    TRAP_FRAME_TRAP_NUM_OFFSET = 0 => trap_num,
+    TRAP_FRAME_ELR_OFFSET = 2 => elr,
+    TRAP_FRAME_SPSR_OFFSET = 3 => spsr,
+    TRAP_FRAME_SP_OFFSET = 4 => sp,
+    TRAP_FRAME_TPIDR_OFFSET = 5 => tpidr,
+    TRAP_FRAME_X1_OFFSET = 6 => general.x1,
+    TRAP_FRAME_X2_OFFSET = 7 => general.x2,
+    TRAP_FRAME_X3_OFFSET = 8 => general.x3,
+    TRAP_FRAME_X4_OFFSET = 9 => general.x4,
+    TRAP_FRAME_X5_OFFSET = 10 => general.x5,
+    TRAP_FRAME_X6_OFFSET = 11 => general.x6,
+    TRAP_FRAME_X7_OFFSET = 12 => general.x7,
+    TRAP_FRAME_X8_OFFSET = 13 => general.x8,
+    TRAP_FRAME_X9_OFFSET = 14 => general.x9,
+    TRAP_FRAME_X10_OFFSET = 15 => general.x10,
+    TRAP_FRAME_X11_OFFSET = 16 => general.x11,
+    TRAP_FRAME_X12_OFFSET = 17 => general.x12,
+    TRAP_FRAME_X13_OFFSET = 18 => general.x13,
+    TRAP_FRAME_X14_OFFSET = 19 => general.x14,
+    TRAP_FRAME_X15_OFFSET = 20 => general.x15,
+    TRAP_FRAME_X16_OFFSET = 21 => general.x16,
+    TRAP_FRAME_X17_OFFSET = 22 => general.x17,
+    TRAP_FRAME_X18_OFFSET = 23 => general.x18,
+    TRAP_FRAME_X19_OFFSET = 24 => general.x19,
+    TRAP_FRAME_X20_OFFSET = 25 => general.x20,
+    TRAP_FRAME_X21_OFFSET = 26 => general.x21,
+    TRAP_FRAME_X22_OFFSET = 27 => general.x22,
+    TRAP_FRAME_X23_OFFSET = 28 => general.x23,
+    TRAP_FRAME_X24_OFFSET = 29 => general.x24,
+    TRAP_FRAME_X25_OFFSET = 30 => general.x25,
+    TRAP_FRAME_X26_OFFSET = 31 => general.x26,
+    TRAP_FRAME_X27_OFFSET = 32 => general.x27,
+    TRAP_FRAME_X28_OFFSET = 33 => general.x28,
+    TRAP_FRAME_X29_OFFSET = 34 => general.x29,
+    TRAP_FRAME_X30_OFFSET = 36 => general.x30,
+    TRAP_FRAME_X0_OFFSET = 37 => general.x0,
+});
@@ -0,0 +1,284 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::UserContext;
+use crate::signal::{read_at, write_at, zero_at, SigInfo};
+use core::mem::size_of;
+
+/// Bits in `PSTATE` that user can change by `ptrace` and `rt_sigreturn`:
+/// condition flags NZCV, tag check override TCO and branch type BTYPE
+const PSTATE_USER_MASK: usize = 0xf000_0000 | PSTATE_TCO | PSTATE_BTYPE;
+/// `PSTATE.TCO`
+const PSTATE_TCO: usize = 1 << 25;
+/// `PSTATE.BTYPE`
+const PSTATE_BTYPE: usize = 3 << 10;
+
+/// `struct user_pt_regs` of `NT_PRSTATUS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub regs: [usize; 31],
+    pub sp: usize,
+    pub pc: usize,
+    pub pstate: usize,
+}
+
+/// `struct sigcontext`, the `uc_mcontext` of `ucontext_t`, without the
+/// `__reserved` area for FP/SIMD and other records.
+///
+/// In `ucontext_t`, `__reserved` follows at the next 16 bytes boundary.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub fault_address: usize,
+    pub regs: [usize; 31],
+    pub sp: usize,
+    pub pc: usize,
+    pub pstate: usize,
+}
+
+/// Size of `__reserved` in `struct sigcontext`.
+const RESERVED_SIZE: usize = 4096;
+
+/// `struct ucontext` up to `uc_mcontext.__reserved`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    /// `sigset_t` of 1024 bits
+    sigmask: [u64; 16],
+    _pad0: usize,
+    mcontext: SigContext,
+    _pad1: usize,
+}
+
+/// `struct rt_sigframe` up to `uc.uc_mcontext.__reserved`, which is
+/// followed by the frame record.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+/// `struct frame_record`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct FrameRecord {
+    fp: usize,
+    lr: usize,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+unsafe impl pod::Pod for FrameRecord {}
+
+impl UserContext {
+    /// Get registers x0 to x30.
+    fn get_x(&self) -> [usize; 31] {
+        let g = &self.general;
+        [
+            g.x0, g.x1, g.x2, g.x3, g.x4, g.x5, g.x6, g.x7, g.x8, g.x9, g.x10, g.x11, g.x12, g.x13,
+            g.x14, g.x15, g.x16, g.x17, g.x18, g.x19, g.x20, g.x21, g.x22, g.x23, g.x24, g.x25,
+            g.x26, g.x27, g.x28, g.x29, g.x30,
+        ]
+    }
+
+    /// Set registers x0 to x30, and user flags in `spsr`.
+    fn set_x(&mut self, x: &[usize; 31], pstate: usize) {
+        let g = &mut self.general;
+        [
+            g.x0, g.x1, g.x2, g.x3, g.x4, g.x5, g.x6, g.x7, g.x8, g.x9, g.x10, g.x11, g.x12, g.x13,
+            g.x14, g.x15, g.x16, g.x17, g.x18, g.x19, g.x20, g.x21, g.x22, g.x23, g.x24, g.x25,
+            g.x26, g.x27, g.x28, g.x29, g.x30,
+        ] = *x;
+        self.spsr = (self.spsr & !PSTATE_USER_MASK) | (pstate & PSTATE_USER_MASK);
+    }
+
+    /// Get registers in the layout of `user_pt_regs`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        UserRegs {
+            regs: self.get_x(),
+            sp: self.sp,
+            pc: self.elr,
+            pstate: self.spsr,
+        }
+    }
+
+    /// Set registers from the layout of `user_pt_regs`.
+    ///
+    /// Only condition flags, TCO and BTYPE in `pstate` are taken.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        self.set_x(&regs.regs, regs.pstate);
+        self.sp = regs.sp;
+        self.elr = regs.pc;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    pub fn get_sigcontext(&self) -> SigContext {
+        SigContext {
+            fault_address: self.far,
+            regs: self.get_x(),
+            sp: self.sp,
+            pc: self.elr,
+            pstate: self.spsr,
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `rt_sigreturn`.
+    ///
+    /// Only condition flags, TCO and BTYPE in `pstate` are taken.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        self.set_x(&sc.regs, sc.pstate);
+        self.sp = sc.sp;
+        self.elr = sc.pc;
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `sp`. The frame
+    /// saves the registers and `mask` as the blocked signals, with an empty
+    /// `__reserved` area since FP/SIMD registers are not in the context.
+    /// The handler returns to `restorer`, which should call `rt_sigreturn`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.sp.checked_sub(stack.len())?;
+        let size = size_of::<RtSigFrame>() + RESERVED_SIZE + size_of::<FrameRecord>();
+        let sp = self.sp.checked_sub(size)? & !15;
+        let reserved = sp + size_of::<RtSigFrame>();
+        let record = reserved + RESERVED_SIZE;
+        let mut sigmask = [0; 16];
+        sigmask[0] = mask;
+        let frame = RtSigFrame {
+            info: *info,
+            uc: UContext {
+                sigmask,
+                mcontext: self.get_sigcontext(),
+                ..Default::default()
+            },
+        };
+        write_at(stack, base, sp, &frame)?;
+        // terminated by a null record
+        zero_at(stack, base, reserved, RESERVED_SIZE)?;
+        let fr = FrameRecord {
+            fp: self.general.x29,
+            lr: self.general.x30,
+        };
+        write_at(stack, base, record, &fr)?;
+
+        self.general.x0 = info.signo as usize;
+        self.general.x1 = sp;
+        self.general.x2 = sp + size_of::<SigInfo>();
+        self.general.x29 = record;
+        self.general.x30 = restorer;
+        self.sp = sp;
+        self.elr = handler;
+        // the handler starts with tag checks and without a pending branch
+        self.spsr &= !(PSTATE_TCO | PSTATE_BTYPE);
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.sp
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        self.set_sigcontext(&frame.uc.mcontext);
+        Some(frame.uc.sigmask[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_regs() -> UserRegs {
+        let mut regs = UserRegs {
+            sp: 0x7fff_0000,
+            pc: 0x40_1000,
+            pstate: 0x6000_0000 | PSTATE_TCO | 1 << 10,
+            ..Default::default()
+        };
+        for (i, x) in regs.regs.iter_mut().enumerate() {
+            *x = i + 1;
+        }
+        regs
+    }
+
+    #[test]
+    fn user_regs_round_trip() {
+        let mut cx = UserContext::default();
+        let regs = user_regs();
+        cx.set_user_regs(&regs);
+        assert_eq!((cx.general.x0, cx.general.x30), (1, 31));
+        assert_eq!((cx.sp, cx.elr), (0x7fff_0000, 0x40_1000));
+        assert_eq!(cx.get_user_regs(), regs);
+
+        // the exception level and interrupt masks are not taken
+        cx.spsr = 0x3c0;
+        cx.set_user_regs(&UserRegs { pstate: !0, ..regs });
+        assert_eq!(cx.spsr, 0x3c0 | PSTATE_USER_MASK);
+    }
+
+    #[test]
+    fn signal_frame_round_trip() {
+        const HANDLER: usize = 0x40_2000;
+        const RESTORER: usize = 0x40_3000;
+        const MASK: u64 = 0x4001;
+        let mut cx = UserContext::default();
+        cx.set_user_regs(&user_regs());
+        // unaligned stack pointer
+        cx.sp = 0x7fff_0ff8;
+        cx.far = 0x1234;
+        let saved = cx;
+        let mut stack = [0u8; 0x2000];
+        let base = cx.sp - stack.len();
+        let info = SigInfo::new(10, 0);
+        let sp = cx
+            .push_signal_frame(&mut stack, &info, HANDLER, RESTORER, MASK)
+            .unwrap();
+
+        assert_eq!(sp % 16, 0);
+        let record = sp + size_of::<RtSigFrame>() + RESERVED_SIZE;
+        assert!(record + size_of::<FrameRecord>() <= saved.sp);
+        let frame: RtSigFrame = read_at(&stack, base, sp).unwrap();
+        assert_eq!(frame.info, info);
+        assert_eq!(frame.uc.sigmask[0], MASK);
+        assert_eq!(frame.uc.mcontext, saved.get_sigcontext());
+        let fr: FrameRecord = read_at(&stack, base, record).unwrap();
+        assert_eq!((fr.fp, fr.lr), (30, 31));
+        let g = &cx.general;
+        assert_eq!((g.x0, g.x1, g.x2), (10, sp, sp + size_of::<SigInfo>()));
+        assert_eq!((g.x29, g.x30), (record, RESTORER));
+        assert_eq!((cx.sp, cx.elr), (sp, HANDLER));
+        assert_eq!(cx.spsr, 0x6000_0000);
+
+        // the handler returns to the restorer with `sp` at the frame
+        cx.general = Default::default();
+        assert_eq!(cx.signal_frame_addr(), sp);
+        assert_eq!(cx.restore_signal_frame(&stack[sp - base..]), Some(MASK));
+        assert_eq!(cx.general, saved.general);
+        assert_eq!((cx.sp, cx.elr, cx.spsr), (saved.sp, saved.elr, saved.spsr));
+    }
+}
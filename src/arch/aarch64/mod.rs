@@ -1,15 +1,108 @@
-#[cfg(target_os = "linux")]
+mod display;
+mod dwarf;
+mod elf;
+#[cfg(all(fncall, target_os = "linux"))]
 mod fncall;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+#[cfg(feature = "gdbstub")]
+mod gdb;
+#[cfg(baremetal)]
+pub mod gic;
+#[cfg(baremetal)]
+pub mod ipi;
+mod layout;
+pub mod linux;
+#[cfg(feature = "mte")]
+mod mte;
+#[cfg(feature = "pauth")]
+mod pauth;
+#[cfg(all(baremetal, feature = "sve"))]
+mod sve;
+#[cfg(baremetal)]
+pub mod timer;
+#[cfg(baremetal)]
 mod trap;
 
-#[cfg(target_os = "linux")]
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+#[cfg(all(fncall, target_os = "linux"))]
 pub use fncall::*;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+pub use layout::*;
+#[cfg(feature = "mte")]
+pub use mte::MteRegs;
+#[cfg(feature = "pauth")]
+pub use pauth::PauthKeys;
+#[cfg(all(baremetal, feature = "sve"))]
+pub use sve::{sve_vl, SveState, SVE_VL_MAX};
+#[cfg(baremetal)]
 pub use trap::*;
 
+use crate::{PageFaultFlags, PageFaultInfo, TrapReason};
+
+/// Decode trap num, `esr_el1` and `far_el1`.
+fn decode(trap_num: usize, esr: usize, far: usize) -> TrapReason {
+    // kind: synchronous, irq, fiq, serror
+    let kind = trap_num >> 16;
+    #[cfg(baremetal)]
+    if kind == 1 && timer::is_pending() {
+        return TrapReason::Timer;
+    }
+    // IRQ acknowledged at the GIC, with the IRQ number in `esr`
+    #[cfg(baremetal)]
+    if kind == 1 && crate::intc::controller().is_some() {
+        return gic::decode(esr);
+    }
+    if kind != 0 {
+        return TrapReason::Interrupt(kind);
+    }
+    // source: current EL with sp_el0, current EL with sp_elx, lower EL
+    let user = trap_num & 0x3 >= 2;
+    let ec = (esr >> 26) & 0x3f;
+    let iss = esr & 0x1ff_ffff;
+    match ec {
+        0x15 => TrapReason::Syscall,
+        0x3c => TrapReason::Breakpoint,
+        // software step from lower or current EL
+        0x32 | 0x33 => TrapReason::SingleStep,
+        0x00 | 0x0e => TrapReason::IllegalInstruction,
+        0x22 | 0x26 => TrapReason::Misaligned,
+        // instruction abort, data abort, from lower or current EL
+        0x20 | 0x21 | 0x24 | 0x25 => {
+            // fault status code
+            let fsc = iss & 0x3f;
+            if fsc == 0b100001 {
+                return TrapReason::Misaligned;
+            }
+            let data_abort = ec & 0x4 != 0;
+            let mut flags = PageFaultFlags::empty();
+            flags.set(PageFaultFlags::USER, user);
+            flags.set(PageFaultFlags::EXECUTE, !data_abort);
+            // WnR, not valid for cache maintenance (CM)
+            flags.set(
+                PageFaultFlags::WRITE,
+                data_abort && iss & (1 << 6) != 0 && iss & (1 << 8) == 0,
+            );
+            // access flag fault or permission fault
+            flags.set(
+                PageFaultFlags::PRESENT,
+                matches!(fsc & 0x3c, 0b1000 | 0b1100),
+            );
+            TrapReason::PageFault { addr: far, flags }
+        }
+        _ => TrapReason::Unknown(ec),
+    }
+}
+
+/// Read the virtual count of the generic timer, also readable in EL0 for
+/// `run_fncall()`.
+pub(crate) fn read_cycles() -> u64 {
+    let count: u64;
+    unsafe { core::arch::asm!("mrs {}, cntvct_el0", out(reg) count) };
+    count
+}
+
 /// Saved registers on a trap.
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct UserContext {
     /// Trap num: Source and Kind
@@ -25,12 +118,30 @@ pub struct UserContext {
     /// Software Thread ID Register, tpidr_el0
     pub tpidr: usize,
     /// General registers
-    /// Must be last in this struct
+    /// Trap saves registers downwards from the end of it
     pub general: GeneralRegs,
+    /// Exception Syndrome Register, esr_el1, saved on trap
+    pub esr: usize,
+    /// Fault Address Register, far_el1, saved on trap
+    pub far: usize,
+    /// Read-Only Software Thread ID Register, tpidrro_el0, loaded in `run()`
+    pub tpidrro: usize,
+    /// Pointer authentication keys, loaded in `run()`
+    #[cfg(feature = "pauth")]
+    pub pauth: PauthKeys,
+    /// Memory tagging registers, switched in `run()`
+    #[cfg(feature = "mte")]
+    pub mte: MteRegs,
+    /// Counter ticks spent in user by `run()` and `run_fncall()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
 }
 
 /// General registers
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct GeneralRegs {
     pub x1: usize,
@@ -69,7 +180,14 @@ pub struct GeneralRegs {
     // x31 means special
 }
 
+impl_bytemuck!(GeneralRegs, UserContext);
+
 impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.trap_num, self.esr, self.far)
+    }
+
     /// Get number of syscall
     pub fn get_syscall_num(&self) -> usize {
         self.general.x8
@@ -102,6 +220,11 @@ impl UserContext {
         self.elr = ip;
     }
 
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.elr
+    }
+
     /// Set stack pointer
     pub fn set_sp(&mut self, sp: usize) {
         self.sp = sp;
@@ -116,4 +239,35 @@ impl UserContext {
     pub fn set_tls(&mut self, tls: usize) {
         self.tpidr = tls;
     }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.tpidr
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs {
+            tpidr: self.tpidr,
+            tpidrro: self.tpidrro,
+        }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.tpidr = regs.tpidr;
+        self.tpidrro = regs.tpidrro;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// `TPIDR_EL0`, the thread pointer
+    pub tpidr: usize,
+    /// `TPIDRRO_EL0`, read-only in user
+    pub tpidrro: usize,
 }
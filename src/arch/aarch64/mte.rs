@@ -0,0 +1,70 @@
+//! Memory tagging extension (MTE) state of user contexts.
+//!
+//! `PSTATE.TCO` is in `spsr`, so it is kept across traps like other flags.
+//! With feature `mte`, `UserContext::run()` also switches the tag check
+//! fault mode of EL0 and the tags excluded from random generation, and
+//! collects asynchronous tag check faults of the user when it comes back.
+//!
+//! `GCR_EL1` is shared with EL1, so the value of the kernel is restored
+//! after running user.
+
+#[cfg(baremetal)]
+use core::arch::asm;
+
+/// Memory tagging registers of a user context.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct MteRegs {
+    /// Tag check fault mode of EL0, `SCTLR_EL1.TCF0`: 0 for ignored, 1 for
+    /// synchronous, 2 for asynchronous and 3 for asymmetric
+    pub tcf0: usize,
+    /// Tags excluded by `irg`, `GCR_EL1.Exclude`
+    pub exclude: usize,
+    /// Asynchronous tag check faults collected after running user,
+    /// `TFSRE0_EL1.TF0`, to be reported and cleared by the kernel
+    pub tfsre0: usize,
+}
+
+unsafe impl pod::Pod for MteRegs {}
+impl_bytemuck!(MteRegs);
+
+/// `SCTLR_EL1.TCF0` field
+#[cfg(baremetal)]
+const SCTLR_TCF0: usize = 3 << 38;
+/// `GCR_EL1.Exclude` field
+#[cfg(baremetal)]
+const GCR_EXCLUDE: usize = 0xffff;
+
+/// Switch to the registers of user, and return `GCR_EL1` of the kernel.
+#[cfg(baremetal)]
+pub(super) fn begin(regs: &MteRegs) -> usize {
+    let (sctlr, gcr): (usize, usize);
+    unsafe {
+        asm!("mrs {}, sctlr_el1", out(reg) sctlr, options(nomem, nostack));
+        // GCR_EL1
+        asm!("mrs {}, s3_0_c1_c0_6", out(reg) gcr, options(nomem, nostack));
+        let sctlr = (sctlr & !SCTLR_TCF0) | ((regs.tcf0 << 38) & SCTLR_TCF0);
+        let user_gcr = (gcr & !GCR_EXCLUDE) | (regs.exclude & GCR_EXCLUDE);
+        asm!("msr sctlr_el1, {}", in(reg) sctlr, options(nomem, nostack));
+        asm!("msr s3_0_c1_c0_6, {}", "isb", in(reg) user_gcr, options(nomem, nostack));
+    }
+    gcr
+}
+
+/// Collect asynchronous tag check faults of user into `regs`, and restore
+/// `GCR_EL1` of the kernel.
+#[cfg(baremetal)]
+pub(super) fn end(regs: &mut MteRegs, gcr: usize) {
+    let tfsre0: usize;
+    unsafe {
+        // TFSRE0_EL1, after the faults of user are recorded
+        asm!("dsb nsh", "isb", "mrs {}, s3_0_c5_c6_1", out(reg) tfsre0, options(nostack));
+        if tfsre0 != 0 {
+            asm!("msr s3_0_c5_c6_1, xzr", options(nomem, nostack));
+        }
+        asm!("msr s3_0_c1_c0_6, {}", "isb", in(reg) gcr, options(nomem, nostack));
+    }
+    regs.tfsre0 |= tfsre0;
+}
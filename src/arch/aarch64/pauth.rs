@@ -0,0 +1,93 @@
+//! Pointer authentication keys of user contexts.
+//!
+//! With feature `pauth`, `UserContext::run()` loads the keys in
+//! `UserContext::pauth` before going to user, and restores the ones of the
+//! kernel after coming back, so the kernel can use pointer authentication
+//! with its own keys. The keys are not accessible to user, so they are not
+//! saved back.
+//!
+//! Kernels usually generate random keys for a new program on `exec`, and
+//! keep them on `fork`. Pointer authentication of user is enabled by the
+//! kernel with `SCTLR_EL1.En*`, which the crate does not touch.
+
+/// Pointer authentication keys, each in low and high halves.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct PauthKeys {
+    /// Instruction key A, `APIAKey{Lo,Hi}_EL1`
+    pub apia: [usize; 2],
+    /// Instruction key B, `APIBKey{Lo,Hi}_EL1`
+    pub apib: [usize; 2],
+    /// Data key A, `APDAKey{Lo,Hi}_EL1`
+    pub apda: [usize; 2],
+    /// Data key B, `APDBKey{Lo,Hi}_EL1`
+    pub apdb: [usize; 2],
+    /// Generic key, `APGAKey{Lo,Hi}_EL1`
+    pub apga: [usize; 2],
+}
+
+unsafe impl pod::Pod for PauthKeys {}
+impl_bytemuck!(PauthKeys);
+
+/// Read a key by the encodings of its halves, as the names are only known
+/// to assemblers with `+pauth`.
+#[cfg(baremetal)]
+macro_rules! read_key {
+    ($lo:literal, $hi:literal) => {{
+        let (lo, hi): (usize, usize);
+        core::arch::asm!(
+            concat!("mrs {}, ", $lo),
+            concat!("mrs {}, ", $hi),
+            out(reg) lo,
+            out(reg) hi,
+            options(nomem, nostack),
+        );
+        [lo, hi]
+    }};
+}
+
+/// Write a key by the encodings of its halves.
+#[cfg(baremetal)]
+macro_rules! write_key {
+    ($lo:literal, $hi:literal, $key:expr) => {
+        core::arch::asm!(
+            concat!("msr ", $lo, ", {}"),
+            concat!("msr ", $hi, ", {}"),
+            in(reg) $key[0],
+            in(reg) $key[1],
+            options(nomem, nostack),
+        )
+    };
+}
+
+/// Read the keys of the current CPU.
+#[cfg(baremetal)]
+#[inline(always)]
+pub(super) fn read() -> PauthKeys {
+    unsafe {
+        PauthKeys {
+            apia: read_key!("s3_0_c2_c1_0", "s3_0_c2_c1_1"),
+            apib: read_key!("s3_0_c2_c1_2", "s3_0_c2_c1_3"),
+            apda: read_key!("s3_0_c2_c2_0", "s3_0_c2_c2_1"),
+            apdb: read_key!("s3_0_c2_c2_2", "s3_0_c2_c2_3"),
+            apga: read_key!("s3_0_c2_c3_0", "s3_0_c2_c3_1"),
+        }
+    }
+}
+
+/// Write the keys of the current CPU.
+///
+/// Always inlined, as a return address signed on entry would fail to
+/// authenticate on return with other keys.
+#[cfg(baremetal)]
+#[inline(always)]
+pub(super) unsafe fn write(keys: &PauthKeys) {
+    write_key!("s3_0_c2_c1_0", "s3_0_c2_c1_1", keys.apia);
+    write_key!("s3_0_c2_c1_2", "s3_0_c2_c1_3", keys.apib);
+    write_key!("s3_0_c2_c2_0", "s3_0_c2_c2_1", keys.apda);
+    write_key!("s3_0_c2_c2_2", "s3_0_c2_c2_3", keys.apdb);
+    write_key!("s3_0_c2_c3_0", "s3_0_c2_c3_1", keys.apga);
+    core::arch::asm!("isb", options(nomem, nostack));
+}
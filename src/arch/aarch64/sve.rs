@@ -0,0 +1,379 @@
+//! Scalable vector extension (SVE) state.
+//!
+//! The vector length (VL) is set to the largest the CPU supports by
+//! [`init()`](super::init), and read back by [`sve_vl()`].
+//!
+//! The state is switched lazily by [`UserContext::run_with_sve()`]: a
+//! context starts with SVE disabled for user by `CPACR_EL1.ZEN`, and its
+//! first SVE instruction traps. The trap is handled inside the call by
+//! taking the live FP/SIMD registers as the low bits of the vector
+//! registers, then the state is saved and restored around each run, as
+//! long as the context lives. Contexts that never use SVE do not pay for it.
+//!
+//! The state is loaded right before going to user, and the kernel must not
+//! use FP/SIMD registers while it is live, e.g. in IRQ handlers called by
+//! `run_with_sve()`. The Scalable Matrix Extension (SME), with its streaming
+//! mode and the ZA array, is not handled: the kernel should keep it trapped
+//! by `CPACR_EL1.SMEN`, so SME instructions of user are reported as traps.
+//!
+//! [`UserContext::run_with_sve()`]: super::UserContext::run_with_sve
+
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Largest vector length in bytes, of 2048 bits.
+pub const SVE_VL_MAX: usize = 256;
+
+/// Z0-Z31 of `VL` bytes, then P0-P15 and FFR of `VL / 8` bytes.
+const DATA_SIZE: usize = 32 * SVE_VL_MAX + 17 * (SVE_VL_MAX / 8);
+
+/// Size of `struct user_sve_header` of `NT_ARM_SVE`, before the registers.
+const REGSET_HEADER_SIZE: usize = 16;
+/// `SVE_PT_REGS_SVE` flag of the header, for registers in SVE format
+const REGSET_FLAG_SVE: u16 = 1;
+/// Largest size of `NT_ARM_SVE`
+const REGSET_MAX: usize = regset_layout(SVE_VL_MAX).1;
+
+/// Exception class of SVE access trapped by `CPACR_EL1.ZEN`.
+pub(super) const EC_SVE: usize = 0x19;
+
+/// `CPACR_EL1.ZEN` field
+const ZEN: usize = 3 << 16;
+/// `CPACR_EL1.ZEN` value trapping EL0 only
+const ZEN_EL1: usize = 1 << 16;
+
+/// Vector length in bytes, 0 for no SVE.
+static VL: AtomicUsize = AtomicUsize::new(0);
+
+/// Enable SVE for the kernel with the largest vector length, if supported.
+pub(super) fn init() {
+    if !crate::cpu_features().contains(crate::CpuFeatures::SVE) {
+        return;
+    }
+    let vl: usize;
+    unsafe {
+        set_zen(ZEN_EL1);
+        // LEN is capped to the largest one supported, in `ZCR_EL1`
+        asm!(
+            "msr s3_0_c1_c2_0, {}",
+            "isb",
+            ".arch_extension sve",
+            "rdvl {}, #1",
+            in(reg) 0xfusize,
+            out(reg) vl,
+            options(nomem, nostack),
+        );
+    }
+    VL.store(vl, Ordering::Relaxed);
+}
+
+/// Get the vector length in bytes, or `None` if SVE is not supported.
+pub fn sve_vl() -> Option<usize> {
+    match VL.load(Ordering::Relaxed) {
+        0 => None,
+        vl => Some(vl),
+    }
+}
+
+/// SVE state of a user context, including FPSR, FPCR and the FP/SIMD
+/// registers as the low bits of Z0-Z31.
+///
+/// It is a plain `Pod` of the largest size, so it can be copied, stored or
+/// sent as bytes. A state of another vector length, e.g. from another
+/// machine, is converted on restore by truncating or zero extending each
+/// register.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct SveState {
+    /// Vector length in bytes of the saved registers, 0 if SVE is not used
+    vl: u64,
+    fpsr: u32,
+    fpcr: u32,
+    /// Z0-Z31, then P0-P15 and FFR, of `vl`
+    data: [u8; DATA_SIZE],
+}
+
+unsafe impl pod::Pod for SveState {}
+impl_bytemuck!(SveState);
+
+impl Default for SveState {
+    /// A context that has not used SVE.
+    fn default() -> Self {
+        SveState {
+            vl: 0,
+            fpsr: 0,
+            fpcr: 0,
+            data: [0; DATA_SIZE],
+        }
+    }
+}
+
+impl fmt::Debug for SveState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SveState")
+            .field("vl", &self.vl)
+            .field("fpsr", &self.fpsr)
+            .field("fpcr", &self.fpcr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SveState {
+    /// Whether the context has used SVE, so the state is switched.
+    pub fn is_active(&self) -> bool {
+        self.vl != 0
+    }
+
+    /// Vector length in bytes of the saved registers, 0 if not used.
+    pub fn vl(&self) -> usize {
+        self.vl as usize
+    }
+
+    /// Get the bytes of Z register `n`.
+    pub fn z(&self, n: usize) -> &[u8] {
+        let vl = self.vl();
+        &self.data[n * vl..(n + 1) * vl]
+    }
+
+    /// Get the bytes of P register `n`, or FFR as 16.
+    pub fn p(&self, n: usize) -> &[u8] {
+        let (vl, pl) = (self.vl(), self.vl() / 8);
+        &self.data[32 * vl + n * pl..32 * vl + (n + 1) * pl]
+    }
+
+    /// Read `NT_ARM_SVE` from byte `offset` into `buf`, and return the
+    /// number of bytes read, less than `buf.len()` at the end of the set.
+    ///
+    /// The registers are in SVE format of [`vl()`](Self::vl), after a
+    /// `struct user_sve_header`. Return `None` if the context has not used
+    /// SVE, as its FP/SIMD registers are not kept here.
+    pub fn read_regset(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        if !is_valid_vl(self.vl()) {
+            return None;
+        }
+        let mut regs = [0; REGSET_MAX];
+        let size = self.to_regset(&mut regs);
+        Some(crate::ptrace::read_bytes(&regs[..size], offset, buf))
+    }
+
+    /// Write `data` into `NT_ARM_SVE` from byte `offset`, leaving the other
+    /// bytes, and return the number of bytes written.
+    ///
+    /// The registers are taken in the vector length of the header, and
+    /// converted on restore. Return `None` and leave the state if the
+    /// context has not used SVE, or the header is not in SVE format of a
+    /// valid vector length.
+    pub fn write_regset(&mut self, offset: usize, data: &[u8]) -> Option<usize> {
+        if !is_valid_vl(self.vl()) {
+            return None;
+        }
+        let mut regs = [0; REGSET_MAX];
+        self.to_regset(&mut regs);
+        let len = crate::ptrace::write_bytes(&mut regs, offset, data);
+        let vl = u16::from_le_bytes([regs[8], regs[9]]) as usize;
+        let flags = u16::from_le_bytes([regs[12], regs[13]]);
+        if flags & REGSET_FLAG_SVE == 0 || !is_valid_vl(vl) {
+            return None;
+        }
+        let (fpsr, _) = regset_layout(vl);
+        let size = 32 * vl + 17 * (vl / 8);
+        self.vl = vl as u64;
+        self.data[..size].copy_from_slice(&regs[REGSET_HEADER_SIZE..][..size]);
+        self.data[size..].fill(0);
+        self.fpsr = u32::from_le_bytes(regs[fpsr..fpsr + 4].try_into().unwrap());
+        self.fpcr = u32::from_le_bytes(regs[fpsr + 4..fpsr + 8].try_into().unwrap());
+        Some(len)
+    }
+
+    /// Fill `regs` with `NT_ARM_SVE` of a valid `vl()`, and return its size.
+    fn to_regset(&self, regs: &mut [u8; REGSET_MAX]) -> usize {
+        let vl = self.vl();
+        let max_vl = sve_vl().unwrap_or(vl);
+        let (fpsr, size) = regset_layout(vl);
+        regs[0..4].copy_from_slice(&(size as u32).to_le_bytes());
+        regs[4..8].copy_from_slice(&(regset_layout(max_vl).1 as u32).to_le_bytes());
+        regs[8..10].copy_from_slice(&(vl as u16).to_le_bytes());
+        regs[10..12].copy_from_slice(&(max_vl as u16).to_le_bytes());
+        regs[12..14].copy_from_slice(&REGSET_FLAG_SVE.to_le_bytes());
+        let data = 32 * vl + 17 * (vl / 8);
+        regs[REGSET_HEADER_SIZE..][..data].copy_from_slice(&self.data[..data]);
+        regs[fpsr..fpsr + 4].copy_from_slice(&self.fpsr.to_le_bytes());
+        regs[fpsr + 4..fpsr + 8].copy_from_slice(&self.fpcr.to_le_bytes());
+        size
+    }
+
+    /// Save the current SVE state into `self`, with SVE enabled for EL1.
+    fn save(&mut self) {
+        self.vl = VL.load(Ordering::Relaxed) as u64;
+        let (fpsr, fpcr): (usize, usize);
+        unsafe {
+            asm!(
+                ".arch_extension sve",
+                "addvl {1}, {0}, #32",
+                ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "str z\\i, [{0}, #\\i, mul vl]",
+                ".endr",
+                ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+                "str p\\i, [{1}, #\\i, mul vl]",
+                ".endr",
+                // P0 is the scratch for FFR, and loaded back after it
+                "rdffr p0.b",
+                "str p0, [{1}, #16, mul vl]",
+                "ldr p0, [{1}]",
+                "mrs {2}, fpsr",
+                "mrs {3}, fpcr",
+                in(reg) self.data.as_mut_ptr(),
+                out(reg) _,
+                out(reg) fpsr,
+                out(reg) fpcr,
+                options(nostack),
+            );
+        }
+        self.fpsr = fpsr as u32;
+        self.fpcr = fpcr as u32;
+    }
+
+    /// Load the SVE state from `self`, with SVE enabled for EL1.
+    ///
+    /// The kernel is built without SVE, so only the FP/SIMD registers in the
+    /// low bits of Z0-Z31 are declared clobbered, and it keeps nothing in P
+    /// registers and FFR. It is inlined into the frame going to user, so the
+    /// callee-saved D8-D15 are only restored over the user state by that
+    /// frame after the run, when the state is saved already.
+    #[inline(always)]
+    fn restore(&mut self) {
+        let vl = VL.load(Ordering::Relaxed);
+        if self.vl() != vl {
+            self.convert(vl);
+        }
+        unsafe {
+            asm!(
+                ".arch_extension sve",
+                "addvl {1}, {0}, #32",
+                ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "ldr z\\i, [{0}, #\\i, mul vl]",
+                ".endr",
+                "ldr p0, [{1}, #16, mul vl]",
+                "wrffr p0.b",
+                ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+                "ldr p\\i, [{1}, #\\i, mul vl]",
+                ".endr",
+                "msr fpsr, {2}",
+                "msr fpcr, {3}",
+                in(reg) self.data.as_ptr(),
+                out(reg) _,
+                in(reg) self.fpsr as usize,
+                in(reg) self.fpcr as usize,
+                out("v0") _, out("v1") _, out("v2") _, out("v3") _,
+                out("v4") _, out("v5") _, out("v6") _, out("v7") _,
+                out("v8") _, out("v9") _, out("v10") _, out("v11") _,
+                out("v12") _, out("v13") _, out("v14") _, out("v15") _,
+                out("v16") _, out("v17") _, out("v18") _, out("v19") _,
+                out("v20") _, out("v21") _, out("v22") _, out("v23") _,
+                out("v24") _, out("v25") _, out("v26") _, out("v27") _,
+                out("v28") _, out("v29") _, out("v30") _, out("v31") _,
+                options(nostack),
+            );
+        }
+    }
+
+    /// Convert the registers to vector length `vl` in place, by truncating or
+    /// zero extending each one. An invalid length is taken as all zero.
+    fn convert(&mut self, vl: usize) {
+        let old = self.vl();
+        if !is_valid_vl(old) {
+            self.data.fill(0);
+        } else {
+            // offset and size of Z0-Z31, then P0-P15 and FFR
+            let reg = |n: usize, vl: usize| match n {
+                0..=31 => (n * vl, vl),
+                _ => (32 * vl + (n - 32) * (vl / 8), vl / 8),
+            };
+            let data = &mut self.data;
+            let mut move_reg = |n: usize| {
+                let ((src, old_size), (dst, size)) = (reg(n, old), reg(n, vl));
+                let len = old_size.min(size);
+                data.copy_within(src..src + len, dst);
+                data[dst + len..dst + size].fill(0);
+            };
+            // move away from the registers not moved yet
+            match vl > old {
+                true => (0..49).rev().for_each(&mut move_reg),
+                false => (0..49).for_each(&mut move_reg),
+            }
+            self.data[reg(49, vl).0..].fill(0);
+        }
+        self.vl = vl as u64;
+    }
+
+    /// Clear the bits beyond the FP/SIMD registers: the high bits of Z
+    /// registers, P registers and FFR, as the state on first use.
+    fn clear_high(&mut self) {
+        let vl = self.vl();
+        for n in 0..32 {
+            self.data[n * vl + 16..(n + 1) * vl].fill(0);
+        }
+        self.data[32 * vl..].fill(0);
+    }
+}
+
+/// Whether `vl` is a vector length of saved registers.
+fn is_valid_vl(vl: usize) -> bool {
+    vl != 0 && vl % 16 == 0 && vl <= SVE_VL_MAX
+}
+
+/// Offset of FPSR and the size of `NT_ARM_SVE` for vector length `vl`: Z0-Z31,
+/// P0-P15 and FFR after the header, then FPSR and FPCR 16 bytes aligned.
+const fn regset_layout(vl: usize) -> (usize, usize) {
+    let fpsr = (REGSET_HEADER_SIZE + 32 * vl + 17 * (vl / 8) + 15) & !15;
+    (fpsr, (fpsr + 8 + 15) & !15)
+}
+
+/// Set `CPACR_EL1.ZEN` to `zen`.
+unsafe fn set_zen(zen: usize) {
+    let cpacr: usize;
+    asm!("mrs {}, cpacr_el1", out(reg) cpacr, options(nomem, nostack));
+    asm!("msr cpacr_el1, {}", "isb", in(reg) (cpacr & !ZEN) | zen, options(nomem, nostack));
+}
+
+/// Whether SVE is enabled for user by `CPACR_EL1.ZEN`.
+fn user_enabled() -> bool {
+    let cpacr: usize;
+    unsafe { asm!("mrs {}, cpacr_el1", out(reg) cpacr, options(nomem, nostack)) };
+    cpacr & ZEN == ZEN
+}
+
+/// Load `sve` and enable SVE for user if the context has used it.
+#[inline(always)]
+pub(super) fn begin(sve: &mut SveState) {
+    if sve.is_active() && sve_vl().is_some() {
+        unsafe { set_zen(ZEN) };
+        sve.restore();
+    }
+}
+
+/// Handle the SVE access trap from user, by enabling SVE on first use.
+///
+/// Return `false` if SVE is not supported or enabled already, then the
+/// trap should be reported to the kernel.
+#[inline(always)]
+pub(super) fn handle(sve: &mut SveState) -> bool {
+    if sve_vl().is_none() || user_enabled() {
+        return false;
+    }
+    sve.save();
+    sve.clear_high();
+    sve.restore();
+    unsafe { set_zen(ZEN) };
+    true
+}
+
+/// Save the state to `sve` if the context has used SVE, and disable SVE
+/// for user.
+pub(super) fn end(sve: &mut SveState) {
+    if user_enabled() {
+        sve.save();
+        unsafe { set_zen(ZEN_EL1) };
+    }
+}
@@ -0,0 +1,42 @@
+//! EL1 physical timer.
+//!
+//! An IRQ is reported as `TrapReason::Timer` if the timer condition is met
+//! when `trap_reason()` is called, so call it before [`set_deadline`] or
+//! [`clear`], which acknowledge the timer.
+
+use core::arch::asm;
+
+/// `CNTP_CTL_EL0.ENABLE`
+const ENABLE: usize = 1 << 0;
+/// `CNTP_CTL_EL0.IMASK`
+const IMASK: usize = 1 << 1;
+/// `CNTP_CTL_EL0.ISTATUS`
+const ISTATUS: usize = 1 << 2;
+
+/// Current time in ticks of `CNTPCT_EL0`.
+pub fn now() -> u64 {
+    let time: u64;
+    unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) time, options(nomem, nostack)) };
+    time
+}
+
+/// Fire the timer when `CNTPCT_EL0` reaches `deadline`, and clear the
+/// pending timer interrupt.
+pub fn set_deadline(deadline: u64) {
+    unsafe {
+        asm!("msr cntp_cval_el0, {}", in(reg) deadline, options(nomem, nostack));
+        asm!("msr cntp_ctl_el0, {}", "isb", in(reg) ENABLE, options(nomem, nostack));
+    }
+}
+
+/// Disarm the timer, and clear the pending timer interrupt.
+pub fn clear() {
+    unsafe { asm!("msr cntp_ctl_el0, xzr", "isb", options(nomem, nostack)) };
+}
+
+/// Whether the timer is enabled, unmasked and its condition is met.
+pub fn is_pending() -> bool {
+    let ctl: usize;
+    unsafe { asm!("mrs {}, cntp_ctl_el0", out(reg) ctl, options(nomem, nostack)) };
+    ctl & (ENABLE | IMASK | ISTATUS) == ENABLE | ISTATUS
+}
@@ -1,21 +1,55 @@
 use super::*;
+use crate::TrapInfo;
 use core::arch::{asm, global_asm};
 
 global_asm!(include_str!("trap.S"));
 
 /// Initialize interrupt handling for the current HART.
 ///
+/// Always return `Ok` on this architecture, see
+/// [`TrapInitError`](crate::TrapInitError).
+///
 /// # Safety
 ///
 /// This function will:
 /// - Set `vbar_el1` to internal exception vector.
+/// - Enable SVE for EL1 with the largest vector length if supported, with
+///   feature `sve`.
 ///
 /// You **MUST NOT** modify these registers later.
-pub unsafe fn init() {
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
     // Set the exception vector address
     asm!("msr VBAR_EL1, {}", in(reg) __vectors as usize);
+    #[cfg(feature = "sve")]
+    super::sve::init();
+    Ok(())
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    unimplemented!("TRAP: tf={:#x?}", tf);
 }
 
+#[no_mangle]
+extern "C" fn trap_dispatch(tf: &mut TrapFrame) {
+    if tf.trap_num >> 16 == IRQ {
+        crate::intc::handle(IRQ, |irq| {
+            tf.__reserved = irq;
+            #[cfg(feature = "trace")]
+            crate::trace::emit(crate::trace::TrapEvent::KernelIrqBegin(irq));
+            trap_handler(tf);
+            #[cfg(feature = "trace")]
+            crate::trace::emit(crate::trace::TrapEvent::KernelIrqEnd(irq));
+        });
+    } else {
+        trap_handler(tf);
+    }
+}
+
+/// Kind of IRQ in trap num
+const IRQ: usize = 1;
+
 /// Trap frame of kernel interrupt
 ///
 /// # Trap handler
@@ -30,12 +64,14 @@ pub unsafe fn init() {
 ///     println!("TRAP! tf: {:#x?}", tf);
 /// }
 /// ```
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct TrapFrame {
     /// Trap num: Source and Kind
     pub trap_num: usize,
-    /// Reserved for internal use
+    /// Reserved for internal use, the IRQ number claimed for an IRQ
     pub __reserved: usize,
     /// Exception Link Register, elr_el1
     pub elr: usize,
@@ -50,11 +86,41 @@ pub struct TrapFrame {
     pub general: GeneralRegs,
 }
 
+impl_bytemuck!(TrapFrame);
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    ///
+    /// The syndrome is read from `esr_el1` and `far_el1`, so this must be
+    /// called in the trap handler before exceptions are unmasked.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        let (esr, far): (usize, usize);
+        unsafe {
+            asm!("mrs {}, esr_el1", out(reg) esr);
+            asm!("mrs {}, far_el1", out(reg) far);
+        }
+        match decode(self.trap_num, esr, far) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+
+    /// Get the IRQ number claimed from the interrupt controller if the trap
+    /// is an IRQ, see [`crate::gic`].
+    pub fn irq(&self) -> Option<usize> {
+        (self.trap_num >> 16 == IRQ && crate::intc::controller().is_some()).then(|| self.__reserved)
+    }
+}
+
 impl UserContext {
     /// Go to user space with the context, and come back when a trap occurs.
     ///
     /// On return, the context will be reset to the status before the trap.
-    /// Trap reason and error code will be returned.
+    /// Trap reason will be placed at `trap_num`, `esr` and `far`.
+    ///
+    /// With an interrupt controller set in [`crate::intc`], e.g. the GIC,
+    /// an IRQ is completed before returning, with the IRQ number in `esr`,
+    /// and a spurious one goes back to user.
     ///
     /// # Example
     /// ```no_run
@@ -75,7 +141,112 @@ impl UserContext {
     /// println!("back from user: {:#x?}", context);
     /// ```
     pub fn run(&mut self) {
-        unsafe { run_user(self) }
+        #[cfg(feature = "sve")]
+        self.run_timed(false, None);
+        #[cfg(not(feature = "sve"))]
+        self.run_timed(false);
+    }
+
+    /// Go to user space like [`run`](Self::run), and switch the SVE state
+    /// with `sve` lazily, see [`SveState`].
+    ///
+    /// The first SVE instruction of a context not using SVE yet is handled
+    /// inside, and never reported to the kernel.
+    #[cfg(feature = "sve")]
+    pub fn run_with_sve(&mut self, sve: &mut SveState) {
+        self.run_timed(false, Some(sve));
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(
+        &mut self,
+        timed: bool,
+        #[cfg(feature = "sve")] mut sve: Option<&mut SveState>,
+    ) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        #[cfg(feature = "pauth")]
+        let kernel_keys = super::pauth::read();
+        #[cfg(feature = "pauth")]
+        unsafe {
+            super::pauth::write(&self.pauth)
+        }
+        #[cfg(feature = "mte")]
+        let kernel_gcr = super::mte::begin(&self.mte);
+        // load the SVE state last, see `SveState::restore()`
+        #[cfg(feature = "sve")]
+        if let Some(sve) = sve.as_deref_mut() {
+            super::sve::begin(sve);
+        }
+        loop {
+            unsafe {
+                // read-only in user, so it is not saved back
+                asm!("msr tpidrro_el0, {}", in(reg) self.tpidrro);
+                run_user(self);
+                // exceptions are still masked, so they belong to this trap
+                asm!("mrs {}, esr_el1", out(reg) self.esr);
+                asm!("mrs {}, far_el1", out(reg) self.far);
+            }
+            #[cfg(feature = "sve")]
+            if let Some(sve) = sve.as_deref_mut() {
+                let ec = (self.esr >> 26) & 0x3f;
+                if self.trap_num >> 16 == 0 && ec == super::sve::EC_SVE && super::sve::handle(sve) {
+                    continue;
+                }
+            }
+            if self.trap_num >> 16 != IRQ || crate::intc::controller().is_none() {
+                break;
+            }
+            // acknowledge the IRQ, and go back to user on spurious ones
+            let mut claimed = None;
+            crate::intc::handle(IRQ, |irq| claimed = Some(irq));
+            if let Some(irq) = claimed {
+                self.esr = irq;
+                break;
+            }
+        }
+        #[cfg(feature = "mte")]
+        super::mte::end(&mut self.mte, kernel_gcr);
+        #[cfg(feature = "pauth")]
+        unsafe {
+            super::pauth::write(&kernel_keys)
+        }
+        #[cfg(feature = "sve")]
+        if let Some(sve) = sve {
+            super::sve::end(sve);
+        }
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), but trap after executing
+    /// one instruction, by software step (`MDSCR_EL1.SS` and `SPSR_EL1.SS`).
+    ///
+    /// `trap_reason()` returns `TrapReason::SingleStep` if the instruction
+    /// completes without other traps. The OS lock is cleared to enable
+    /// debug exceptions.
+    pub fn run_single_step(&mut self) {
+        const SPSR_SS: usize = 1 << 21;
+        const MDSCR_SS: usize = 1 << 0;
+        unsafe {
+            asm!("msr oslar_el1, xzr");
+            let mdscr: usize;
+            asm!("mrs {}, mdscr_el1", out(reg) mdscr);
+            asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr | MDSCR_SS);
+            self.spsr |= SPSR_SS;
+            self.run();
+            self.spsr &= !SPSR_SS;
+            asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr & !MDSCR_SS);
+        }
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        #[cfg(feature = "sve")]
+        let cycles = self.run_timed(true, None);
+        #[cfg(not(feature = "sve"))]
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
     }
 }
 
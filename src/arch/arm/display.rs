@@ -0,0 +1,89 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Bits in `CPSR`, besides the mode in bits 0 to 4
+const CPSR_BITS: &[(usize, &str)] = &[
+    (1 << 5, "T"),
+    (1 << 6, "F"),
+    (1 << 7, "I"),
+    (1 << 8, "A"),
+    (1 << 9, "E"),
+    (1 << 27, "Q"),
+    (1 << 28, "V"),
+    (1 << 29, "C"),
+    (1 << 30, "Z"),
+    (1 << 31, "N"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 15] {
+        [
+            ("r0", self.r0),
+            ("r1", self.r1),
+            ("r2", self.r2),
+            ("r3", self.r3),
+            ("r4", self.r4),
+            ("r5", self.r5),
+            ("r6", self.r6),
+            ("r7", self.r7),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("sp", self.sp),
+            ("lr", self.lr),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+/// Write the registers shared by `UserContext` and `TrapFrame`.
+fn write_trap_regs(
+    f: &mut fmt::Formatter,
+    general: &GeneralRegs,
+    pc: usize,
+    fsr: usize,
+    far: usize,
+    cpsr: usize,
+) -> fmt::Result {
+    write!(f, "{}", general)?;
+    write_regs(f, &[("pc", pc), ("fsr", fsr), ("far", far)])?;
+    write_flags(f, "cpsr", cpsr, CPSR_BITS)
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("pc", self.pc),
+            ("cpsr", self.cpsr),
+            ("fsr", self.fsr),
+            ("far", self.far),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, self.pc, self.fsr, self.far, self.cpsr)?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, self.pc, self.fsr, self.far, self.cpsr)
+    }
+}
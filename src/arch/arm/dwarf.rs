@@ -0,0 +1,37 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    unsafe {
+        Some(match index.0 {
+            // r0 to r12, sp, lr are in order
+            n @ 0..=14 => addr_of!((*cx).general).cast::<usize>().add(n as usize),
+            15 => addr_of!((*cx).pc),
+            _ => return None,
+        })
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            None => false,
+        }
+    }
+}
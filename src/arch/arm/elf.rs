@@ -0,0 +1,6 @@
+//! ELF header fields of core files.
+
+/// `EM_ARM`
+pub(crate) const ELF_MACHINE: u16 = 40;
+/// `EF_ARM_EABI_VER5`
+pub(crate) const ELF_FLAGS: u32 = 0x0500_0000;
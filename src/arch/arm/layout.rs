@@ -0,0 +1,56 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+use super::{TrapFrame, UserContext};
+
+layout!(UserContext {
+    USER_CONTEXT_TRAP_NUM_OFFSET = 0 => trap_num,
+    USER_CONTEXT_FSR_OFFSET = 1 => fsr,
+    USER_CONTEXT_FAR_OFFSET = 2 => far,
+    USER_CONTEXT_TLS_OFFSET = 3 => tls,
+    USER_CONTEXT_R0_OFFSET = 4 => general.r0,
+    USER_CONTEXT_R1_OFFSET = 5 => general.r1,
+    USER_CONTEXT_R2_OFFSET = 6 => general.r2,
+    USER_CONTEXT_R3_OFFSET = 7 => general.r3,
+    USER_CONTEXT_R4_OFFSET = 8 => general.r4,
+    USER_CONTEXT_R5_OFFSET = 9 => general.r5,
+    USER_CONTEXT_R6_OFFSET = 10 => general.r6,
+    USER_CONTEXT_R7_OFFSET = 11 => general.r7,
+    USER_CONTEXT_R8_OFFSET = 12 => general.r8,
+    USER_CONTEXT_R9_OFFSET = 13 => general.r9,
+    USER_CONTEXT_R10_OFFSET = 14 => general.r10,
+    USER_CONTEXT_R11_OFFSET = 15 => general.r11,
+    USER_CONTEXT_R12_OFFSET = 16 => general.r12,
+    USER_CONTEXT_SP_OFFSET = 17 => general.sp,
+    USER_CONTEXT_LR_OFFSET = 18 => general.lr,
+    USER_CONTEXT_PC_OFFSET = 19 => pc,
+    USER_CONTEXT_CPSR_OFFSET = 20 => cpsr,
+});
+
+layout!(TrapFrame {
+    TRAP_FRAME_TRAP_NUM_OFFSET = 0 => trap_num,
+    TRAP_FRAME_FSR_OFFSET = 1 => fsr,
+    TRAP_FRAME_FAR_OFFSET = 2 => far,
+    TRAP_FRAME_TLS_OFFSET = 3 => tls,
+    TRAP_FRAME_R0_OFFSET = 4 => general.r0,
+    TRAP_FRAME_R1_OFFSET = 5 => general.r1,
+    TRAP_FRAME_R2_OFFSET = 6 => general.r2,
+    TRAP_FRAME_R3_OFFSET = 7 => general.r3,
+    TRAP_FRAME_R4_OFFSET = 8 => general.r4,
+    TRAP_FRAME_R5_OFFSET = 9 => general.r5,
+    TRAP_FRAME_R6_OFFSET = 10 => general.r6,
+    TRAP_FRAME_R7_OFFSET = 11 => general.r7,
+    TRAP_FRAME_R8_OFFSET = 12 => general.r8,
+    TRAP_FRAME_R9_OFFSET = 13 => general.r9,
+    TRAP_FRAME_R10_OFFSET = 14 => general.r10,
+    TRAP_FRAME_R11_OFFSET = 15 => general.r11,
+    TRAP_FRAME_R12_OFFSET = 16 => general.r12,
+    TRAP_FRAME_SP_OFFSET = 17 => general.sp,
+    TRAP_FRAME_LR_OFFSET = 18 => general.lr,
+    TRAP_FRAME_PC_OFFSET = 19 => pc,
+    TRAP_FRAME_CPSR_OFFSET = 20 => cpsr,
+});
@@ -0,0 +1,59 @@
+//! Register layouts of Linux ABI, for `ptrace` and core dump.
+
+use super::{GeneralRegs, UserContext};
+
+/// `struct pt_regs` of `NT_PRSTATUS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    /// r0 to r12, sp, lr, pc
+    pub regs: [usize; 16],
+    pub cpsr: usize,
+    pub orig_r0: usize,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+
+impl GeneralRegs {
+    /// Registers r0 to r12, sp and lr.
+    fn to_array(self) -> [usize; 15] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.sp, self.lr,
+        ]
+    }
+
+    /// Set registers r0 to r12, sp and lr.
+    fn set_array(&mut self, r: &[usize; 15]) {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.sp, self.lr,
+        ] = *r;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of `pt_regs`.
+    ///
+    /// `orig_r0` is reported as the current `r0`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        let mut regs = [0; 16];
+        regs[..15].copy_from_slice(&self.general.to_array());
+        regs[15] = self.pc;
+        UserRegs {
+            regs,
+            cpsr: self.cpsr,
+            orig_r0: self.general.r0,
+        }
+    }
+
+    /// Set registers from the layout of `pt_regs`.
+    ///
+    /// `cpsr` and `orig_r0` are ignored.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        let mut general = [0; 15];
+        general.copy_from_slice(&regs.regs[..15]);
+        self.general.set_array(&general);
+        self.pc = regs.regs[15];
+    }
+}
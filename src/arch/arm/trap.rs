@@ -0,0 +1,326 @@
+use crate::{PageFaultFlags, PageFaultInfo, TrapInfo, TrapReason};
+use core::arch::{asm, global_asm};
+
+global_asm!(include_str!("trap.S"));
+
+/// Initialize interrupt handling for the current CPU.
+///
+/// The kernel runs in Supervisor mode. Exceptions taken to other modes are
+/// switched to Supervisor mode at the vectors, so their banked `sp` need not
+/// be set up.
+///
+/// Always return `Ok` on this architecture, see
+/// [`TrapInitError`](crate::TrapInitError).
+///
+/// # Safety
+///
+/// This function will:
+/// - Clear `SCTLR.V` to use `VBAR` for the vectors.
+/// - Set `VBAR` to internal exception vector.
+///
+/// You **MUST NOT** modify these registers later.
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
+    // Clear SCTLR.V
+    let mut sctlr: usize;
+    asm!("mrc p15, 0, {}, c1, c0, 0", out(reg) sctlr);
+    sctlr &= !(1 << 13);
+    asm!("mcr p15, 0, {}, c1, c0, 0", in(reg) sctlr);
+    // Set the exception vector address
+    asm!("mcr p15, 0, {}, c12, c0, 0", "isb", in(reg) __vectors as usize);
+    Ok(())
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    unimplemented!("TRAP: tf={:#x?}", tf);
+}
+
+/// Trap numbers, the offset of the vector in words
+const UNDEFINED: usize = 1;
+const SVC: usize = 2;
+const PREFETCH_ABORT: usize = 3;
+const DATA_ABORT: usize = 4;
+
+/// Decode trap num, `fsr` and `far` in the short-descriptor format.
+fn decode(trap_num: usize, fsr: usize, far: usize, user: bool) -> TrapReason {
+    // fault status, FS[4] is bit 10
+    let fs = (fsr & 0xf) | (fsr >> 6 & 0x10);
+    let page_fault = |mut flags: PageFaultFlags| {
+        flags.set(PageFaultFlags::USER, user);
+        // domain fault or permission fault
+        flags.set(PageFaultFlags::PRESENT, matches!(fs, 0x9 | 0xb | 0xd | 0xf));
+        TrapReason::PageFault { addr: far, flags }
+    };
+    match trap_num {
+        UNDEFINED => TrapReason::IllegalInstruction,
+        SVC => TrapReason::Syscall,
+        // debug event: breakpoint or watchpoint
+        PREFETCH_ABORT | DATA_ABORT if fs == 0x2 => TrapReason::Breakpoint,
+        PREFETCH_ABORT => page_fault(PageFaultFlags::EXECUTE),
+        DATA_ABORT if fs == 0x1 => TrapReason::Misaligned,
+        // WnR
+        DATA_ABORT if fsr & (1 << 11) != 0 => page_fault(PageFaultFlags::WRITE),
+        DATA_ABORT => page_fault(PageFaultFlags::empty()),
+        // IRQ, FIQ
+        6 | 7 => TrapReason::Interrupt(trap_num),
+        _ => TrapReason::Unknown(trap_num),
+    }
+}
+
+/// Trap frame of kernel interrupt
+///
+/// `sp` and `lr` in `general` are those of Supervisor mode.
+///
+/// # Trap handler
+///
+/// You need to define a handler function like this:
+///
+/// ```no_run
+/// use trapframe::TrapFrame;
+///
+/// #[no_mangle]
+/// pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+///     println!("TRAP! tf: {:#x?}", tf);
+/// }
+/// ```
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct TrapFrame {
+    /// Trap number: the offset of the vector in words
+    pub trap_num: usize,
+    /// `IFSR` or `DFSR` of an abort, saved on trap
+    pub fsr: usize,
+    /// `IFAR` or `DFAR` of an abort, saved on trap
+    pub far: usize,
+    /// `TPIDRURO`, the thread pointer of user
+    pub tls: usize,
+    /// General registers
+    pub general: GeneralRegs,
+    /// The address to return to
+    pub pc: usize,
+    /// `CPSR` to return with, from the `SPSR` of the exception mode
+    pub cpsr: usize,
+}
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        // CPSR.M = User
+        let user = self.cpsr & 0x1f == 0x10;
+        match decode(self.trap_num, self.fsr, self.far, user) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+}
+
+/// Saved registers on a trap.
+///
+/// `pc` is the address to return to, i.e. the faulting instruction on
+/// aborts, and the next instruction after `svc` or an undefined
+/// instruction.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct UserContext {
+    /// Trap number: the offset of the vector in words
+    pub trap_num: usize,
+    /// `IFSR` or `DFSR` of an abort, saved on trap
+    pub fsr: usize,
+    /// `IFAR` or `DFAR` of an abort, saved on trap
+    pub far: usize,
+    /// `TPIDRURO`, the thread pointer of user
+    pub tls: usize,
+    /// General registers
+    pub general: GeneralRegs,
+    /// The address to return to
+    pub pc: usize,
+    /// `CPSR` to return with, from the `SPSR` of the exception mode
+    pub cpsr: usize,
+    /// Keep `user_cycles` 8 bytes aligned. Public only for struct literals
+    /// with `..Default::default()`.
+    #[doc(hidden)]
+    pub _pad_cycles: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
+}
+
+impl UserContext {
+    /// Go to user space with the context, and come back when a trap occurs.
+    ///
+    /// On return, the context will be reset to the status before the trap.
+    /// Trap reason will be placed at `trap_num`, `fsr` and `far`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use trapframe::{UserContext, GeneralRegs};
+    ///
+    /// // init user space context
+    /// let mut context = UserContext {
+    ///     general: GeneralRegs {
+    ///         sp: 0x10000,
+    ///         ..Default::default()
+    ///     },
+    ///     // User mode with IRQ and FIQ enabled
+    ///     cpsr: 0x10,
+    ///     pc: 0x1000,
+    ///     ..Default::default()
+    /// };
+    /// // go to user
+    /// context.run();
+    /// // back from user
+    /// println!("back from user: {:#x?}", context);
+    /// ```
+    pub fn run(&mut self) {
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        unsafe { run_user(self) };
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    ///
+    /// The counter is `CNTVCT` of the generic timer.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
+
+/// Read the virtual count of the generic timer.
+pub(crate) fn read_cycles() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe { asm!("mrrc p15, 1, {}, {}, c14", out(reg) lo, out(reg) hi) };
+    (hi as u64) << 32 | lo as u64
+}
+
+/// General registers
+///
+/// `sp` and `lr` are those of User mode.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct GeneralRegs {
+    pub r0: usize,
+    pub r1: usize,
+    pub r2: usize,
+    pub r3: usize,
+    pub r4: usize,
+    pub r5: usize,
+    pub r6: usize,
+    pub r7: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub sp: usize,
+    pub lr: usize,
+}
+
+unsafe impl pod::Pod for GeneralRegs {}
+unsafe impl pod::Pod for UserContext {}
+impl_bytemuck!(GeneralRegs, UserContext, TrapFrame);
+
+impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.trap_num, self.fsr, self.far, true)
+    }
+
+    /// Get number of syscall
+    pub fn get_syscall_num(&self) -> usize {
+        self.general.r7
+    }
+
+    /// Get return value of syscall
+    pub fn get_syscall_ret(&self) -> usize {
+        self.general.r0
+    }
+
+    /// Set return value of syscall
+    pub fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.r0 = ret;
+    }
+
+    /// Get syscall args
+    pub fn get_syscall_args(&self) -> [usize; 6] {
+        [
+            self.general.r0,
+            self.general.r1,
+            self.general.r2,
+            self.general.r3,
+            self.general.r4,
+            self.general.r5,
+        ]
+    }
+
+    /// Set instruction pointer
+    pub fn set_ip(&mut self, ip: usize) {
+        self.pc = ip;
+    }
+
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.pc
+    }
+
+    /// Set stack pointer
+    pub fn set_sp(&mut self, sp: usize) {
+        self.general.sp = sp;
+    }
+
+    /// Get stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.general.sp
+    }
+
+    /// Set tls pointer
+    pub fn set_tls(&mut self, tls: usize) {
+        self.tls = tls;
+    }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.tls
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs { tpidruro: self.tls }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.tls = regs.tpidruro;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// `TPIDRURO`, the thread pointer
+    pub tpidruro: usize,
+}
+
+#[allow(improper_ctypes)]
+extern "C" {
+    fn __vectors();
+    fn run_user(regs: &mut UserContext);
+}
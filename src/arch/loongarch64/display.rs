@@ -0,0 +1,94 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Bits in `PRMD`
+const PRMD_BITS: &[(usize, &str)] = &[(0b11, "PPLV"), (1 << 2, "PIE"), (1 << 3, "PWE")];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 31] {
+        [
+            ("ra", self.ra),
+            ("tp", self.tp),
+            ("sp", self.sp),
+            ("a0", self.a0),
+            ("a1", self.a1),
+            ("a2", self.a2),
+            ("a3", self.a3),
+            ("a4", self.a4),
+            ("a5", self.a5),
+            ("a6", self.a6),
+            ("a7", self.a7),
+            ("t0", self.t0),
+            ("t1", self.t1),
+            ("t2", self.t2),
+            ("t3", self.t3),
+            ("t4", self.t4),
+            ("t5", self.t5),
+            ("t6", self.t6),
+            ("t7", self.t7),
+            ("t8", self.t8),
+            ("r21", self.r21),
+            ("fp", self.fp),
+            ("s0", self.s0),
+            ("s1", self.s1),
+            ("s2", self.s2),
+            ("s3", self.s3),
+            ("s4", self.s4),
+            ("s5", self.s5),
+            ("s6", self.s6),
+            ("s7", self.s7),
+            ("s8", self.s8),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+/// Write the registers shared by `UserContext` and `TrapFrame`.
+fn write_trap_regs(
+    f: &mut fmt::Formatter,
+    general: &GeneralRegs,
+    prmd: usize,
+    era: usize,
+    estat: usize,
+    badv: usize,
+) -> fmt::Result {
+    write!(f, "{}", general)?;
+    write_regs(f, &[("era", era), ("estat", estat), ("badv", badv)])?;
+    write_flags(f, "prmd", prmd, PRMD_BITS)
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("era", self.era),
+            ("prmd", self.prmd),
+            ("estat", self.estat),
+            ("badv", self.badv),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, self.prmd, self.era, self.estat, self.badv)?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, self.prmd, self.era, self.estat, self.badv)
+    }
+}
@@ -0,0 +1,6 @@
+//! ELF header fields of core files.
+
+/// `EM_LOONGARCH`
+pub(crate) const ELF_MACHINE: u16 = 258;
+/// `EF_LOONGARCH_ABI_DOUBLE_FLOAT | EF_LOONGARCH_OBJABI_V1`
+pub(crate) const ELF_FLAGS: u32 = 0x43;
@@ -0,0 +1,86 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+use super::{TrapFrame, UserContext};
+
+layout!(UserContext {
+    USER_CONTEXT_ZERO_OFFSET = 0 => general.zero,
+    USER_CONTEXT_RA_OFFSET = 1 => general.ra,
+    USER_CONTEXT_TP_OFFSET = 2 => general.tp,
+    USER_CONTEXT_SP_OFFSET = 3 => general.sp,
+    USER_CONTEXT_A0_OFFSET = 4 => general.a0,
+    USER_CONTEXT_A1_OFFSET = 5 => general.a1,
+    USER_CONTEXT_A2_OFFSET = 6 => general.a2,
+    USER_CONTEXT_A3_OFFSET = 7 => general.a3,
+    USER_CONTEXT_A4_OFFSET = 8 => general.a4,
+    USER_CONTEXT_A5_OFFSET = 9 => general.a5,
+    USER_CONTEXT_A6_OFFSET = 10 => general.a6,
+    USER_CONTEXT_A7_OFFSET = 11 => general.a7,
+    USER_CONTEXT_T0_OFFSET = 12 => general.t0,
+    USER_CONTEXT_T1_OFFSET = 13 => general.t1,
+    USER_CONTEXT_T2_OFFSET = 14 => general.t2,
+    USER_CONTEXT_T3_OFFSET = 15 => general.t3,
+    USER_CONTEXT_T4_OFFSET = 16 => general.t4,
+    USER_CONTEXT_T5_OFFSET = 17 => general.t5,
+    USER_CONTEXT_T6_OFFSET = 18 => general.t6,
+    USER_CONTEXT_T7_OFFSET = 19 => general.t7,
+    USER_CONTEXT_T8_OFFSET = 20 => general.t8,
+    USER_CONTEXT_R21_OFFSET = 21 => general.r21,
+    USER_CONTEXT_FP_OFFSET = 22 => general.fp,
+    USER_CONTEXT_S0_OFFSET = 23 => general.s0,
+    USER_CONTEXT_S1_OFFSET = 24 => general.s1,
+    USER_CONTEXT_S2_OFFSET = 25 => general.s2,
+    USER_CONTEXT_S3_OFFSET = 26 => general.s3,
+    USER_CONTEXT_S4_OFFSET = 27 => general.s4,
+    USER_CONTEXT_S5_OFFSET = 28 => general.s5,
+    USER_CONTEXT_S6_OFFSET = 29 => general.s6,
+    USER_CONTEXT_S7_OFFSET = 30 => general.s7,
+    USER_CONTEXT_S8_OFFSET = 31 => general.s8,
+    USER_CONTEXT_PRMD_OFFSET = 32 => prmd,
+    USER_CONTEXT_ERA_OFFSET = 33 => era,
+    USER_CONTEXT_ESTAT_OFFSET = 34 => estat,
+    USER_CONTEXT_BADV_OFFSET = 35 => badv,
+});
+
+layout!(TrapFrame {
+    TRAP_FRAME_ZERO_OFFSET = 0 => general.zero,
+    TRAP_FRAME_RA_OFFSET = 1 => general.ra,
+    TRAP_FRAME_TP_OFFSET = 2 => general.tp,
+    TRAP_FRAME_SP_OFFSET = 3 => general.sp,
+    TRAP_FRAME_A0_OFFSET = 4 => general.a0,
+    TRAP_FRAME_A1_OFFSET = 5 => general.a1,
+    TRAP_FRAME_A2_OFFSET = 6 => general.a2,
+    TRAP_FRAME_A3_OFFSET = 7 => general.a3,
+    TRAP_FRAME_A4_OFFSET = 8 => general.a4,
+    TRAP_FRAME_A5_OFFSET = 9 => general.a5,
+    TRAP_FRAME_A6_OFFSET = 10 => general.a6,
+    TRAP_FRAME_A7_OFFSET = 11 => general.a7,
+    TRAP_FRAME_T0_OFFSET = 12 => general.t0,
+    TRAP_FRAME_T1_OFFSET = 13 => general.t1,
+    TRAP_FRAME_T2_OFFSET = 14 => general.t2,
+    TRAP_FRAME_T3_OFFSET = 15 => general.t3,
+    TRAP_FRAME_T4_OFFSET = 16 => general.t4,
+    TRAP_FRAME_T5_OFFSET = 17 => general.t5,
+    TRAP_FRAME_T6_OFFSET = 18 => general.t6,
+    TRAP_FRAME_T7_OFFSET = 19 => general.t7,
+    TRAP_FRAME_T8_OFFSET = 20 => general.t8,
+    TRAP_FRAME_R21_OFFSET = 21 => general.r21,
+    TRAP_FRAME_FP_OFFSET = 22 => general.fp,
+    TRAP_FRAME_S0_OFFSET = 23 => general.s0,
+    TRAP_FRAME_S1_OFFSET = 24 => general.s1,
+    TRAP_FRAME_S2_OFFSET = 25 => general.s2,
+    TRAP_FRAME_S3_OFFSET = 26 => general.s3,
+    TRAP_FRAME_S4_OFFSET = 27 => general.s4,
+    TRAP_FRAME_S5_OFFSET = 28 => general.s5,
+    TRAP_FRAME_S6_OFFSET = 29 => general.s6,
+    TRAP_FRAME_S7_OFFSET = 30 => general.s7,
+    TRAP_FRAME_S8_OFFSET = 31 => general.s8,
+    TRAP_FRAME_PRMD_OFFSET = 32 => prmd,
+    TRAP_FRAME_ERA_OFFSET = 33 => era,
+    TRAP_FRAME_ESTAT_OFFSET = 34 => estat,
+    TRAP_FRAME_BADV_OFFSET = 35 => badv,
+});
@@ -0,0 +1,201 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::{GeneralRegs, UserContext};
+use crate::signal::{read_at, write_at, SigInfo};
+use core::mem::size_of;
+
+/// `struct user_pt_regs` of `NT_PRSTATUS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub regs: [usize; 32],
+    pub orig_a0: usize,
+    pub csr_era: usize,
+    pub csr_badv: usize,
+    pub reserved: [usize; 10],
+}
+
+/// `struct sigcontext`, the `uc_mcontext` of `ucontext_t`, without the
+/// extended contexts which follow at the next 16 bytes boundary.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub sc_pc: usize,
+    pub sc_regs: [usize; 32],
+    pub sc_flags: u32,
+    pub _pad: u32,
+}
+
+/// `struct ucontext`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    /// `sigset_t` of 64 bits, and the rest of 1024 bits
+    sigmask: u64,
+    _unused: [u64; 15],
+    /// `uc_mcontext` is 16 bytes aligned
+    _pad: usize,
+    mcontext: SigContext,
+    /// Null `struct sctx_info` terminating the extended contexts
+    end: [u64; 2],
+}
+
+/// `struct rt_sigframe`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+
+impl GeneralRegs {
+    /// Registers r0 to r31.
+    fn to_array(self) -> [usize; 32] {
+        [
+            0, self.ra, self.tp, self.sp, self.a0, self.a1, self.a2, self.a3, self.a4, self.a5,
+            self.a6, self.a7, self.t0, self.t1, self.t2, self.t3, self.t4, self.t5, self.t6,
+            self.t7, self.t8, self.r21, self.fp, self.s0, self.s1, self.s2, self.s3, self.s4,
+            self.s5, self.s6, self.s7, self.s8,
+        ]
+    }
+
+    /// Set registers r1 to r31.
+    fn set_array(&mut self, r: &[usize; 32]) {
+        [
+            _,
+            self.ra,
+            self.tp,
+            self.sp,
+            self.a0,
+            self.a1,
+            self.a2,
+            self.a3,
+            self.a4,
+            self.a5,
+            self.a6,
+            self.a7,
+            self.t0,
+            self.t1,
+            self.t2,
+            self.t3,
+            self.t4,
+            self.t5,
+            self.t6,
+            self.t7,
+            self.t8,
+            self.r21,
+            self.fp,
+            self.s0,
+            self.s1,
+            self.s2,
+            self.s3,
+            self.s4,
+            self.s5,
+            self.s6,
+            self.s7,
+            self.s8,
+        ] = *r;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of `user_pt_regs`.
+    ///
+    /// `orig_a0` is reported as the current `a0`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        UserRegs {
+            regs: self.general.to_array(),
+            orig_a0: self.general.a0,
+            csr_era: self.era,
+            csr_badv: self.badv,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `user_pt_regs`.
+    ///
+    /// `orig_a0` and `csr_badv` are ignored.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        self.general.set_array(&regs.regs);
+        self.era = regs.csr_era;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    pub fn get_sigcontext(&self) -> SigContext {
+        SigContext {
+            sc_pc: self.era,
+            sc_regs: self.general.to_array(),
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `rt_sigreturn`.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        self.general.set_array(&sc.sc_regs);
+        self.era = sc.sc_pc;
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `sp`. The frame
+    /// saves the registers and `mask` as the blocked signals, without
+    /// extended contexts since FP registers are not in the context. The
+    /// handler returns to `restorer`, which should call `rt_sigreturn`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.general.sp.checked_sub(stack.len())?;
+        let sp = self.general.sp.checked_sub(size_of::<RtSigFrame>())? & !15;
+        let frame = RtSigFrame {
+            info: *info,
+            uc: UContext {
+                sigmask: mask,
+                mcontext: self.get_sigcontext(),
+                ..Default::default()
+            },
+        };
+        write_at(stack, base, sp, &frame)?;
+
+        self.general.a0 = info.signo as usize;
+        self.general.a1 = sp;
+        self.general.a2 = sp + size_of::<SigInfo>();
+        self.general.ra = restorer;
+        self.general.sp = sp;
+        self.era = handler;
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.general.sp
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        self.set_sigcontext(&frame.uc.mcontext);
+        Some(frame.uc.sigmask)
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{PageFaultFlags, PageFaultInfo, TrapInfo, TrapReason};
+use core::arch::{asm, global_asm};
+
+global_asm!(include_str!("trap.S"));
+
+/// Initialize interrupt handling for the current CPU.
+///
+/// Always return `Ok` on this architecture, see
+/// [`TrapInitError`](crate::TrapInitError).
+///
+/// # Safety
+///
+/// This function will:
+/// - Set `SAVE0` to 0.
+/// - Set `ECFG.VS` to 0, so that all exceptions and interrupts share one entry.
+/// - Set `EENTRY` to internal exception vector.
+///
+/// TLB refill exception is not handled, whose entry is in `TLBRENTRY`.
+///
+/// You **MUST NOT** modify these registers later.
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
+    // Set SAVE0 register to 0, indicating to exception vector that we are
+    // presently executing in the kernel
+    asm!("csrwr {}, 0x30", inout(reg) 0usize => _);
+    // Set ECFG.VS to 0
+    asm!("csrxchg {}, {}, 0x4", inout(reg) 0usize => _, in(reg) 0x7usize << 16);
+    // Set the exception vector address
+    asm!("csrwr {}, 0xc", inout(reg) trap_entry as usize => _);
+    Ok(())
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    unimplemented!("TRAP: tf={:#x?}", tf);
+}
+
+/// Decode `ESTAT` and `BADV`.
+fn decode(estat: usize, badv: usize, user: bool) -> TrapReason {
+    let ecode = (estat >> 16) & 0x3f;
+    let page_fault = |mut flags: PageFaultFlags| {
+        flags.set(PageFaultFlags::USER, user);
+        TrapReason::PageFault { addr: badv, flags }
+    };
+    match ecode {
+        // interrupt: ESTAT.IS
+        0x0 => TrapReason::Interrupt(estat & 0x1fff),
+        // page invalid for load, store, fetch
+        0x1 => page_fault(PageFaultFlags::empty()),
+        0x2 => page_fault(PageFaultFlags::WRITE),
+        0x3 => page_fault(PageFaultFlags::EXECUTE),
+        // page modification, not readable, not executable, privilege
+        0x4 => page_fault(PageFaultFlags::WRITE | PageFaultFlags::PRESENT),
+        0x5 => page_fault(PageFaultFlags::PRESENT),
+        0x6 => page_fault(PageFaultFlags::EXECUTE | PageFaultFlags::PRESENT),
+        0x7 => page_fault(PageFaultFlags::PRESENT),
+        0x9 => TrapReason::Misaligned,
+        0xb => TrapReason::Syscall,
+        0xc => TrapReason::Breakpoint,
+        0xd | 0xe => TrapReason::IllegalInstruction,
+        _ => TrapReason::Unknown(ecode),
+    }
+}
+
+/// Trap frame of kernel interrupt
+///
+/// # Trap handler
+///
+/// You need to define a handler function like this:
+///
+/// ```no_run
+/// use trapframe::TrapFrame;
+///
+/// #[no_mangle]
+/// pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+///     println!("TRAP! tf: {:#x?}", tf);
+/// }
+/// ```
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct TrapFrame {
+    /// General registers
+    pub general: GeneralRegs,
+    /// Pre-exception Mode Information
+    pub prmd: usize,
+    /// Exception Return Address
+    pub era: usize,
+    /// Exception Status, saved on trap
+    pub estat: usize,
+    /// Bad Virtual Address, saved on trap
+    pub badv: usize,
+}
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        // prmd.PPLV != 0: from user
+        let user = self.prmd & 0x3 != 0;
+        match decode(self.estat, self.badv, user) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+}
+
+/// Saved registers on a trap.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct UserContext {
+    /// General registers
+    pub general: GeneralRegs,
+    /// Pre-exception Mode Information
+    pub prmd: usize,
+    /// Exception Return Address
+    pub era: usize,
+    /// Exception Status, saved on trap
+    pub estat: usize,
+    /// Bad Virtual Address, saved on trap
+    pub badv: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
+}
+
+impl UserContext {
+    /// Go to user space with the context, and come back when a trap occurs.
+    ///
+    /// On return, the context will be reset to the status before the trap.
+    /// Trap reason will be placed at `estat` and `badv`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use trapframe::{UserContext, GeneralRegs};
+    ///
+    /// // init user space context
+    /// let mut context = UserContext {
+    ///     general: GeneralRegs {
+    ///         sp: 0x10000,
+    ///         ..Default::default()
+    ///     },
+    ///     // PPLV = 3, PIE = 1
+    ///     prmd: 0x7,
+    ///     era: 0x1000,
+    ///     ..Default::default()
+    /// };
+    /// // go to user
+    /// context.run();
+    /// // back from user
+    /// println!("back from user: {:#x?}", context);
+    /// ```
+    pub fn run(&mut self) {
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        unsafe { run_user(self) };
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
+
+/// Read the stable counter.
+pub(crate) fn read_cycles() -> u64 {
+    let time: u64;
+    unsafe { asm!("rdtime.d {}, $zero", out(reg) time) };
+    time
+}
+
+/// General registers
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct GeneralRegs {
+    pub zero: usize,
+    pub ra: usize,
+    pub tp: usize,
+    pub sp: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub t7: usize,
+    pub t8: usize,
+    pub r21: usize,
+    pub fp: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+}
+
+unsafe impl pod::Pod for GeneralRegs {}
+unsafe impl pod::Pod for UserContext {}
+impl_bytemuck!(GeneralRegs, UserContext, TrapFrame);
+
+impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.estat, self.badv, true)
+    }
+
+    /// Get number of syscall
+    pub fn get_syscall_num(&self) -> usize {
+        self.general.a7
+    }
+
+    /// Get return value of syscall
+    pub fn get_syscall_ret(&self) -> usize {
+        self.general.a0
+    }
+
+    /// Set return value of syscall
+    pub fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.a0 = ret;
+    }
+
+    /// Get syscall args
+    pub fn get_syscall_args(&self) -> [usize; 6] {
+        [
+            self.general.a0,
+            self.general.a1,
+            self.general.a2,
+            self.general.a3,
+            self.general.a4,
+            self.general.a5,
+        ]
+    }
+
+    /// Set instruction pointer
+    pub fn set_ip(&mut self, ip: usize) {
+        self.era = ip;
+    }
+
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.era
+    }
+
+    /// Set stack pointer
+    pub fn set_sp(&mut self, sp: usize) {
+        self.general.sp = sp;
+    }
+
+    /// Get stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.general.sp
+    }
+
+    /// Set tls pointer
+    pub fn set_tls(&mut self, tls: usize) {
+        self.general.tp = tls;
+    }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.general.tp
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs {
+            tp: self.general.tp,
+        }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.general.tp = regs.tp;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// `tp`, the thread pointer
+    pub tp: usize,
+}
+
+#[allow(improper_ctypes)]
+extern "C" {
+    fn trap_entry();
+    fn run_user(regs: &mut UserContext);
+}
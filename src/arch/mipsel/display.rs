@@ -0,0 +1,127 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Bits in CP0 `Status`
+const STATUS_BITS: &[(usize, &str)] = &[
+    (1 << 0, "IE"),
+    (1 << 1, "EXL"),
+    (1 << 2, "ERL"),
+    (1 << 4, "UM"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 33] {
+        [
+            ("at", self.at),
+            ("v0", self.v0),
+            ("v1", self.v1),
+            ("a0", self.a0),
+            ("a1", self.a1),
+            ("a2", self.a2),
+            ("a3", self.a3),
+            ("t0", self.t0),
+            ("t1", self.t1),
+            ("t2", self.t2),
+            ("t3", self.t3),
+            ("t4", self.t4),
+            ("t5", self.t5),
+            ("t6", self.t6),
+            ("t7", self.t7),
+            ("s0", self.s0),
+            ("s1", self.s1),
+            ("s2", self.s2),
+            ("s3", self.s3),
+            ("s4", self.s4),
+            ("s5", self.s5),
+            ("s6", self.s6),
+            ("s7", self.s7),
+            ("t8", self.t8),
+            ("t9", self.t9),
+            ("k0", self.k0),
+            ("k1", self.k1),
+            ("gp", self.gp),
+            ("sp", self.sp),
+            ("fp", self.fp),
+            ("ra", self.ra),
+            ("hi", self.hi),
+            ("lo", self.lo),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+/// Write the registers shared by `UserContext` and `TrapFrame`.
+fn write_trap_regs(
+    f: &mut fmt::Formatter,
+    general: &GeneralRegs,
+    status: usize,
+    cause: usize,
+    epc: usize,
+    vaddr: usize,
+    tls: usize,
+) -> fmt::Result {
+    write!(f, "{}", general)?;
+    write_regs(
+        f,
+        &[
+            ("epc", epc),
+            ("cause", cause),
+            ("vaddr", vaddr),
+            ("tls", tls),
+        ],
+    )?;
+    write_flags(f, "status", status, STATUS_BITS)
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("epc", self.epc),
+            ("status", self.status),
+            ("cause", self.cause),
+            ("vaddr", self.vaddr),
+            ("tls", self.tls),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(
+            f,
+            &self.general,
+            self.status,
+            self.cause,
+            self.epc,
+            self.vaddr,
+            self.tls,
+        )?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(
+            f,
+            &self.general,
+            self.status,
+            self.cause,
+            self.epc,
+            self.vaddr,
+            self.tls,
+        )
+    }
+}
@@ -0,0 +1,44 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    unsafe {
+        let g = addr_of!((*cx).general);
+        Some(match index.0 {
+            // $1 to $31 are in order, after `hi` and `lo`
+            n @ 1..=31 => addr_of!((*g).at).add(n as usize - 1),
+            64 => addr_of!((*g).hi),
+            65 => addr_of!((*g).lo),
+            _ => return None,
+        })
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    ///
+    /// `$0` is always 0.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        if index.0 == 0 {
+            return Some(0);
+        }
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,10 @@
+//! ELF header fields of core files.
+
+/// `EM_MIPS`
+pub(crate) const ELF_MACHINE: u16 = 8;
+/// `EF_MIPS_ARCH_32 | EF_MIPS_ABI_O32`
+#[cfg(not(mips_r6))]
+pub(crate) const ELF_FLAGS: u32 = 0x5000_1000;
+/// `EF_MIPS_ARCH_32R6 | EF_MIPS_ABI_O32`
+#[cfg(mips_r6)]
+pub(crate) const ELF_FLAGS: u32 = 0x9000_1000;
@@ -0,0 +1,108 @@
+//! Conversion between context types and GDB register layouts of `gdbstub_arch`.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use gdbstub_arch::mips::reg::MipsCoreRegs;
+
+impl GeneralRegs {
+    /// Registers r0 to r31, followed by `lo` and `hi`.
+    fn to_gdb(self, regs: &mut MipsCoreRegs<u32>) {
+        regs.r = [
+            0, self.at, self.v0, self.v1, self.a0, self.a1, self.a2, self.a3, self.t0, self.t1,
+            self.t2, self.t3, self.t4, self.t5, self.t6, self.t7, self.s0, self.s1, self.s2,
+            self.s3, self.s4, self.s5, self.s6, self.s7, self.t8, self.t9, self.k0, self.k1,
+            self.gp, self.sp, self.fp, self.ra,
+        ]
+        .map(|r| r as u32);
+        regs.lo = self.lo as u32;
+        regs.hi = self.hi as u32;
+    }
+
+    /// Set registers r1 to r31, `lo` and `hi`.
+    fn set_gdb(&mut self, regs: &MipsCoreRegs<u32>) {
+        let r = regs.r.map(|r| r as usize);
+        [
+            _,
+            self.at,
+            self.v0,
+            self.v1,
+            self.a0,
+            self.a1,
+            self.a2,
+            self.a3,
+            self.t0,
+            self.t1,
+            self.t2,
+            self.t3,
+            self.t4,
+            self.t5,
+            self.t6,
+            self.t7,
+            self.s0,
+            self.s1,
+            self.s2,
+            self.s3,
+            self.s4,
+            self.s5,
+            self.s6,
+            self.s7,
+            self.t8,
+            self.t9,
+            self.k0,
+            self.k1,
+            self.gp,
+            self.sp,
+            self.fp,
+            self.ra,
+        ] = r;
+        self.lo = regs.lo as usize;
+        self.hi = regs.hi as usize;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of GDB `org.gnu.gdb.mips.cpu` and `cp0`.
+    ///
+    /// Floating-point registers are not part of the context and are
+    /// reported as 0.
+    pub fn get_gdb_regs(&self) -> MipsCoreRegs<u32> {
+        let mut regs = MipsCoreRegs::default();
+        self.general.to_gdb(&mut regs);
+        regs.pc = self.epc as u32;
+        regs.cp0.status = self.status as u32;
+        regs.cp0.cause = self.cause as u32;
+        regs.cp0.badvaddr = self.vaddr as u32;
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.mips.cpu`.
+    ///
+    /// CP0 and floating-point registers are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &MipsCoreRegs<u32>) {
+        self.general.set_gdb(regs);
+        self.epc = regs.pc as usize;
+    }
+}
+
+impl TrapFrame {
+    /// Get registers in the layout of GDB `org.gnu.gdb.mips.cpu` and `cp0`.
+    ///
+    /// Floating-point registers are not saved in the trap frame and are
+    /// reported as 0.
+    pub fn get_gdb_regs(&self) -> MipsCoreRegs<u32> {
+        let mut regs = MipsCoreRegs::default();
+        self.general.to_gdb(&mut regs);
+        regs.pc = self.epc as u32;
+        regs.cp0.status = self.status as u32;
+        regs.cp0.cause = self.cause as u32;
+        regs.cp0.badvaddr = self.vaddr as u32;
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.mips.cpu`.
+    ///
+    /// CP0 and floating-point registers are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &MipsCoreRegs<u32>) {
+        self.general.set_gdb(regs);
+        self.epc = regs.pc as usize;
+    }
+}
@@ -0,0 +1,90 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+use super::{TrapFrame, UserContext};
+
+layout!(UserContext {
+    USER_CONTEXT_TLS_OFFSET = 0 => tls,
+    USER_CONTEXT_STATUS_OFFSET = 2 => status,
+    USER_CONTEXT_CAUSE_OFFSET = 3 => cause,
+    USER_CONTEXT_EPC_OFFSET = 4 => epc,
+    USER_CONTEXT_VADDR_OFFSET = 5 => vaddr,
+    USER_CONTEXT_HI_OFFSET = 6 => general.hi,
+    USER_CONTEXT_LO_OFFSET = 7 => general.lo,
+    USER_CONTEXT_AT_OFFSET = 8 => general.at,
+    USER_CONTEXT_V0_OFFSET = 9 => general.v0,
+    USER_CONTEXT_V1_OFFSET = 10 => general.v1,
+    USER_CONTEXT_A0_OFFSET = 11 => general.a0,
+    USER_CONTEXT_A1_OFFSET = 12 => general.a1,
+    USER_CONTEXT_A2_OFFSET = 13 => general.a2,
+    USER_CONTEXT_A3_OFFSET = 14 => general.a3,
+    USER_CONTEXT_T0_OFFSET = 15 => general.t0,
+    USER_CONTEXT_T1_OFFSET = 16 => general.t1,
+    USER_CONTEXT_T2_OFFSET = 17 => general.t2,
+    USER_CONTEXT_T3_OFFSET = 18 => general.t3,
+    USER_CONTEXT_T4_OFFSET = 19 => general.t4,
+    USER_CONTEXT_T5_OFFSET = 20 => general.t5,
+    USER_CONTEXT_T6_OFFSET = 21 => general.t6,
+    USER_CONTEXT_T7_OFFSET = 22 => general.t7,
+    USER_CONTEXT_S0_OFFSET = 23 => general.s0,
+    USER_CONTEXT_S1_OFFSET = 24 => general.s1,
+    USER_CONTEXT_S2_OFFSET = 25 => general.s2,
+    USER_CONTEXT_S3_OFFSET = 26 => general.s3,
+    USER_CONTEXT_S4_OFFSET = 27 => general.s4,
+    USER_CONTEXT_S5_OFFSET = 28 => general.s5,
+    USER_CONTEXT_S6_OFFSET = 29 => general.s6,
+    USER_CONTEXT_S7_OFFSET = 30 => general.s7,
+    USER_CONTEXT_T8_OFFSET = 31 => general.t8,
+    USER_CONTEXT_T9_OFFSET = 32 => general.t9,
+    USER_CONTEXT_K0_OFFSET = 33 => general.k0,
+    USER_CONTEXT_K1_OFFSET = 34 => general.k1,
+    USER_CONTEXT_GP_OFFSET = 35 => general.gp,
+    USER_CONTEXT_SP_OFFSET = 36 => general.sp,
+    USER_CONTEXT_FP_OFFSET = 37 => general.fp,
+    USER_CONTEXT_RA_OFFSET = 38 => general.ra,
+});
+
+layout!(TrapFrame {
+    TRAP_FRAME_TLS_OFFSET = 0 => tls,
+    TRAP_FRAME_STATUS_OFFSET = 2 => status,
+    TRAP_FRAME_CAUSE_OFFSET = 3 => cause,
+    TRAP_FRAME_EPC_OFFSET = 4 => epc,
+    TRAP_FRAME_VADDR_OFFSET = 5 => vaddr,
+    TRAP_FRAME_HI_OFFSET = 6 => general.hi,
+    TRAP_FRAME_LO_OFFSET = 7 => general.lo,
+    TRAP_FRAME_AT_OFFSET = 8 => general.at,
+    TRAP_FRAME_V0_OFFSET = 9 => general.v0,
+    TRAP_FRAME_V1_OFFSET = 10 => general.v1,
+    TRAP_FRAME_A0_OFFSET = 11 => general.a0,
+    TRAP_FRAME_A1_OFFSET = 12 => general.a1,
+    TRAP_FRAME_A2_OFFSET = 13 => general.a2,
+    TRAP_FRAME_A3_OFFSET = 14 => general.a3,
+    TRAP_FRAME_T0_OFFSET = 15 => general.t0,
+    TRAP_FRAME_T1_OFFSET = 16 => general.t1,
+    TRAP_FRAME_T2_OFFSET = 17 => general.t2,
+    TRAP_FRAME_T3_OFFSET = 18 => general.t3,
+    TRAP_FRAME_T4_OFFSET = 19 => general.t4,
+    TRAP_FRAME_T5_OFFSET = 20 => general.t5,
+    TRAP_FRAME_T6_OFFSET = 21 => general.t6,
+    TRAP_FRAME_T7_OFFSET = 22 => general.t7,
+    TRAP_FRAME_S0_OFFSET = 23 => general.s0,
+    TRAP_FRAME_S1_OFFSET = 24 => general.s1,
+    TRAP_FRAME_S2_OFFSET = 25 => general.s2,
+    TRAP_FRAME_S3_OFFSET = 26 => general.s3,
+    TRAP_FRAME_S4_OFFSET = 27 => general.s4,
+    TRAP_FRAME_S5_OFFSET = 28 => general.s5,
+    TRAP_FRAME_S6_OFFSET = 29 => general.s6,
+    TRAP_FRAME_S7_OFFSET = 30 => general.s7,
+    TRAP_FRAME_T8_OFFSET = 31 => general.t8,
+    TRAP_FRAME_T9_OFFSET = 32 => general.t9,
+    TRAP_FRAME_K0_OFFSET = 33 => general.k0,
+    TRAP_FRAME_K1_OFFSET = 34 => general.k1,
+    TRAP_FRAME_GP_OFFSET = 35 => general.gp,
+    TRAP_FRAME_SP_OFFSET = 36 => general.sp,
+    TRAP_FRAME_FP_OFFSET = 37 => general.fp,
+    TRAP_FRAME_RA_OFFSET = 38 => general.ra,
+});
@@ -0,0 +1,235 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::{GeneralRegs, UserContext};
+use crate::signal::{read_at, write_at, SigInfo};
+use core::mem::size_of;
+
+/// `elf_gregset_t` of `NT_PRSTATUS`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub _pad0: [usize; 6],
+    pub regs: [usize; 32],
+    pub lo: usize,
+    pub hi: usize,
+    pub cp0_epc: usize,
+    pub cp0_badvaddr: usize,
+    pub cp0_status: usize,
+    pub cp0_cause: usize,
+    pub _pad1: usize,
+}
+
+/// `struct sigcontext` of o32 ABI, the `uc_mcontext` of `ucontext_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub sc_regmask: u32,
+    pub sc_status: u32,
+    pub sc_pc: u64,
+    pub sc_regs: [u64; 32],
+    pub sc_fpregs: [u64; 32],
+    pub sc_acx: u32,
+    pub sc_fpc_csr: u32,
+    pub sc_fpc_eir: u32,
+    pub sc_used_math: u32,
+    pub sc_dsp: u32,
+    pub _pad: u32,
+    pub sc_mdhi: u64,
+    pub sc_mdlo: u64,
+    pub sc_hi1: u32,
+    pub sc_lo1: u32,
+    pub sc_hi2: u32,
+    pub sc_lo2: u32,
+    pub sc_hi3: u32,
+    pub sc_lo3: u32,
+}
+
+/// `struct ucontext`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    _pad: usize,
+    mcontext: SigContext,
+    /// `sigset_t` of 128 bits
+    sigmask: [u32; 4],
+}
+
+/// `struct rt_sigframe`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    /// Argument save space for o32
+    ass: [u32; 4],
+    _pad: [u32; 2],
+    info: SigInfo,
+    uc: UContext,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+
+impl GeneralRegs {
+    /// Registers r0 to r31.
+    fn to_array(self) -> [usize; 32] {
+        [
+            0, self.at, self.v0, self.v1, self.a0, self.a1, self.a2, self.a3, self.t0, self.t1,
+            self.t2, self.t3, self.t4, self.t5, self.t6, self.t7, self.s0, self.s1, self.s2,
+            self.s3, self.s4, self.s5, self.s6, self.s7, self.t8, self.t9, self.k0, self.k1,
+            self.gp, self.sp, self.fp, self.ra,
+        ]
+    }
+
+    /// Set registers r1 to r31.
+    fn set_array(&mut self, r: [usize; 32]) {
+        [
+            _,
+            self.at,
+            self.v0,
+            self.v1,
+            self.a0,
+            self.a1,
+            self.a2,
+            self.a3,
+            self.t0,
+            self.t1,
+            self.t2,
+            self.t3,
+            self.t4,
+            self.t5,
+            self.t6,
+            self.t7,
+            self.s0,
+            self.s1,
+            self.s2,
+            self.s3,
+            self.s4,
+            self.s5,
+            self.s6,
+            self.s7,
+            self.t8,
+            self.t9,
+            self.k0,
+            self.k1,
+            self.gp,
+            self.sp,
+            self.fp,
+            self.ra,
+        ] = r;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of `elf_gregset_t`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        UserRegs {
+            regs: self.general.to_array(),
+            lo: self.general.lo,
+            hi: self.general.hi,
+            cp0_epc: self.epc,
+            cp0_badvaddr: self.vaddr,
+            cp0_status: self.status,
+            cp0_cause: self.cause,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `elf_gregset_t`.
+    ///
+    /// CP0 registers other than `epc` are ignored.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        self.general.set_array(regs.regs);
+        self.general.lo = regs.lo;
+        self.general.hi = regs.hi;
+        self.epc = regs.cp0_epc;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    ///
+    /// Floating-point and DSP registers are left 0 for the caller to fill.
+    pub fn get_sigcontext(&self) -> SigContext {
+        SigContext {
+            sc_status: self.status as u32,
+            sc_pc: self.epc as u64,
+            sc_regs: self.general.to_array().map(|r| r as u64),
+            sc_mdhi: self.general.hi as u64,
+            sc_mdlo: self.general.lo as u64,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `sigreturn`.
+    ///
+    /// `sc_status`, floating-point and DSP registers are ignored.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        self.general.set_array(sc.sc_regs.map(|r| r as usize));
+        self.general.hi = sc.sc_mdhi as usize;
+        self.general.lo = sc.sc_mdlo as usize;
+        self.epc = sc.sc_pc as usize;
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `sp`. The frame
+    /// saves the registers and `mask` as the blocked signals. The handler
+    /// returns to `restorer`, which should call `rt_sigreturn`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.general.sp.checked_sub(stack.len())?;
+        // leave 32 bytes below the user stack, as Linux does
+        let sp = self.general.sp.checked_sub(32 + size_of::<RtSigFrame>())? & !7;
+        let info_addr = sp + 24;
+        let frame = RtSigFrame {
+            info: *info,
+            uc: UContext {
+                mcontext: self.get_sigcontext(),
+                sigmask: [mask as u32, (mask >> 32) as u32, 0, 0],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        write_at(stack, base, sp, &frame)?;
+
+        self.general.a0 = info.signo as usize;
+        self.general.a1 = info_addr;
+        self.general.a2 = info_addr + size_of::<SigInfo>();
+        self.general.ra = restorer;
+        // position independent code expects its address in `t9`
+        self.general.t9 = handler;
+        self.general.sp = sp;
+        self.epc = handler;
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.general.sp
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        self.set_sigcontext(&frame.uc.mcontext);
+        let [lo, hi, ..] = frame.uc.sigmask;
+        Some((hi as u64) << 32 | lo as u64)
+    }
+}
@@ -1,3 +1,12 @@
+mod display;
+mod dwarf;
+mod elf;
+#[cfg(feature = "gdbstub")]
+mod gdb;
+mod layout;
+pub mod linux;
 mod trap;
 
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+pub use layout::*;
 pub use trap::*;
@@ -1,22 +1,92 @@
+use crate::{PageFaultFlags, PageFaultInfo, TrapInfo, TrapReason};
 use core::arch::{asm, global_asm};
 
+#[cfg(not(mips_r6))]
+global_asm!(
+    r"
+    .macro BRANCH_EQZ reg, label
+        beq \reg, $zero, \label
+        nop
+    .endm
+    .macro BRANCH label
+        b \label
+        nop
+    .endm
+    .macro JUMP_REG reg
+        jr \reg
+        nop
+    .endm
+    .macro SAVE_HILO hi, lo
+        mfhi $t0
+        sw $t0, \hi($sp)
+        mflo $t1
+        sw $t1, \lo($sp)
+    .endm
+    .macro RESTORE_HILO hi, lo
+        lw $t0, \hi($sp)
+        mthi $t0
+        lw $t1, \lo($sp)
+        mtlo $t1
+    .endm
+"
+);
+// release 6 has compact branches without delay slots, and no `hi` and
+// `lo`, whose slots are kept 0
+#[cfg(mips_r6)]
+global_asm!(
+    r"
+    .macro BRANCH_EQZ reg, label
+        beqzc \reg, \label
+    .endm
+    .macro BRANCH label
+        bc \label
+    .endm
+    .macro JUMP_REG reg
+        jrc \reg
+    .endm
+    .macro SAVE_HILO hi, lo
+        sw $zero, \hi($sp)
+        sw $zero, \lo($sp)
+    .endm
+    .macro RESTORE_HILO hi, lo
+    .endm
+"
+);
+
 global_asm!(include_str!("trap.S"));
 
 /// Initialize interrupt handling for the current HART.
 ///
+/// Always return `Ok` on this architecture, see
+/// [`TrapInitError`](crate::TrapInitError).
+///
 /// # Safety
 ///
 /// This function will:
-/// - Set `sscratch` to 0.
-/// - Set `stvec` to internal exception vector.
+/// - Set CP0 `EBase` to internal exception vector.
+/// - Clear CP0 `Config3.ISAOnExc` if present, so that exceptions are taken
+///   in MIPS32 mode, while user programs may run microMIPS code.
 ///
 /// You **MUST NOT** modify these registers later.
-pub unsafe fn init() {
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
     // Set cp0 ebase(15, 1) register to trap entry
     asm!(
         "mtc0 {trap_entry}, $15, 1",
         trap_entry = in(reg) trap_entry,
     );
+    // Config3 exists if Config, Config1 and Config2 have bit M set
+    const M: usize = 1 << 31;
+    const ISA_ON_EXC: usize = 1 << 16;
+    let (config, config1, config2): (usize, usize, usize);
+    asm!("mfc0 {}, $16, 0", out(reg) config);
+    asm!("mfc0 {}, $16, 1", out(reg) config1);
+    asm!("mfc0 {}, $16, 2", out(reg) config2);
+    if config & config1 & config2 & M != 0 {
+        let config3: usize;
+        asm!("mfc0 {}, $16, 3", out(reg) config3);
+        asm!("mtc0 {}, $16, 3", "ehb", in(reg) config3 & !ISA_ON_EXC);
+    }
+    Ok(())
 }
 
 #[no_mangle]
@@ -25,6 +95,35 @@ extern "C" fn trap_handler(tf: &mut TrapFrame) {
     unimplemented!("TRAP: tf={:#x?}", tf);
 }
 
+/// Decode CP0 cause and vaddr.
+fn decode(cause: usize, vaddr: usize, epc: usize, user: bool) -> TrapReason {
+    let exc_code = (cause >> 2) & 0x1f;
+    let page_fault = |mut flags: PageFaultFlags| {
+        flags.set(PageFaultFlags::USER, user);
+        TrapReason::PageFault { addr: vaddr, flags }
+    };
+    match exc_code {
+        // pending interrupts: cause.IP
+        0 => TrapReason::Interrupt((cause >> 8) & 0xff),
+        // TLB modified
+        1 => page_fault(PageFaultFlags::WRITE | PageFaultFlags::PRESENT),
+        // TLB load or fetch
+        // bit 0 of `epc` is set in microMIPS mode
+        2 if vaddr == (epc & !1) => page_fault(PageFaultFlags::EXECUTE),
+        2 => page_fault(PageFaultFlags::empty()),
+        // TLB store
+        3 => page_fault(PageFaultFlags::WRITE),
+        // address error: misaligned, or kernel address from user
+        4 | 5 if !user || vaddr < 0x8000_0000 => TrapReason::Misaligned,
+        4 => page_fault(PageFaultFlags::PRESENT),
+        5 => page_fault(PageFaultFlags::WRITE | PageFaultFlags::PRESENT),
+        8 => TrapReason::Syscall,
+        9 => TrapReason::Breakpoint,
+        10 => TrapReason::IllegalInstruction,
+        _ => TrapReason::Unknown(exc_code),
+    }
+}
+
 /// Trap frame of kernel interrupt
 ///
 /// # Trap handler
@@ -39,7 +138,9 @@ extern "C" fn trap_handler(tf: &mut TrapFrame) {
 ///     println!("TRAP! tf: {:#x?}", tf);
 /// }
 /// ```
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct TrapFrame {
     /// TLS
@@ -58,8 +159,20 @@ pub struct TrapFrame {
     pub general: GeneralRegs,
 }
 
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        match decode(self.cause, self.vaddr, self.epc, false) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+}
+
 /// Saved registers on a trap.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct UserContext {
     /// TLS
@@ -76,6 +189,14 @@ pub struct UserContext {
     pub vaddr: usize,
     /// General registers
     pub general: GeneralRegs,
+    /// Keep `user_cycles` 8 bytes aligned. Public only for struct literals
+    /// with `..Default::default()`.
+    #[doc(hidden)]
+    pub _pad_cycles: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
 }
 
 impl UserContext {
@@ -103,15 +224,62 @@ impl UserContext {
     /// println!("back from user: {:#x?}", context);
     /// ```
     pub fn run(&mut self) {
-        unsafe { run_user(self) }
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        // `UserLocal` is read-only in user, so it is not saved back
+        if has_user_local() {
+            unsafe { asm!("mtc0 {}, $4, 2", in(reg) self.tls) };
+        }
+        unsafe { run_user(self) };
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
+
+/// Read CP0 `Count`, which is 32 bits wide.
+pub(crate) fn read_cycles() -> u64 {
+    let count: u32;
+    unsafe { asm!("mfc0 {}, $9", out(reg) count) };
+    count as u64
+}
+
+/// Whether CP0 `UserLocal` is implemented, by `Config3.ULRI`.
+fn has_user_local() -> bool {
+    let (config1, config2, config3): (usize, usize, usize);
+    unsafe {
+        asm!("mfc0 {}, $16, 1", out(reg) config1);
+        if config1 & (1 << 31) == 0 {
+            return false;
+        }
+        asm!("mfc0 {}, $16, 2", out(reg) config2);
+        if config2 & (1 << 31) == 0 {
+            return false;
+        }
+        asm!("mfc0 {}, $16, 3", out(reg) config3);
     }
+    config3 & (1 << 13) != 0
 }
 
 /// General registers
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct GeneralRegs {
+    /// Always 0 on release 6, which has no `hi` and `lo`
     pub hi: usize,
+    /// Always 0 on release 6
     pub lo: usize,
     pub at: usize,
     pub v0: usize,
@@ -146,7 +314,14 @@ pub struct GeneralRegs {
     pub ra: usize,
 }
 
+impl_bytemuck!(GeneralRegs, UserContext, TrapFrame);
+
 impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.cause, self.vaddr, self.epc, true)
+    }
+
     /// Get number of syscall
     pub fn get_syscall_num(&self) -> usize {
         self.general.v0
@@ -191,6 +366,11 @@ impl UserContext {
         self.epc = ip;
     }
 
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.epc
+    }
+
     /// Set stack pointer
     pub fn set_sp(&mut self, sp: usize) {
         self.general.sp = sp;
@@ -205,6 +385,31 @@ impl UserContext {
     pub fn set_tls(&mut self, tls: usize) {
         self.tls = tls;
     }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.tls
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs { ulr: self.tls }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.tls = regs.ulr;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// CP0 `UserLocal`, the thread pointer read by `rdhwr $29`
+    pub ulr: usize,
 }
 
 #[allow(improper_ctypes)]
@@ -0,0 +1,120 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Bits in `MSR`
+const MSR_BITS: &[(usize, &str)] = &[
+    (1 << 0, "LE"),
+    (1 << 1, "RI"),
+    (1 << 4, "DR"),
+    (1 << 5, "IR"),
+    (1 << 9, "BE"),
+    (1 << 10, "SE"),
+    (1 << 12, "ME"),
+    (1 << 13, "FP"),
+    (1 << 14, "PR"),
+    (1 << 15, "EE"),
+    (1 << 63, "SF"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 32] {
+        [
+            ("r0", self.r0),
+            ("r1", self.r1),
+            ("r2", self.r2),
+            ("r3", self.r3),
+            ("r4", self.r4),
+            ("r5", self.r5),
+            ("r6", self.r6),
+            ("r7", self.r7),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+            ("r16", self.r16),
+            ("r17", self.r17),
+            ("r18", self.r18),
+            ("r19", self.r19),
+            ("r20", self.r20),
+            ("r21", self.r21),
+            ("r22", self.r22),
+            ("r23", self.r23),
+            ("r24", self.r24),
+            ("r25", self.r25),
+            ("r26", self.r26),
+            ("r27", self.r27),
+            ("r28", self.r28),
+            ("r29", self.r29),
+            ("r30", self.r30),
+            ("r31", self.r31),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+/// Registers shared by `UserContext` and `TrapFrame`, except `srr1`.
+macro_rules! trap_regs {
+    ($cx:expr) => {
+        [
+            ("lr", $cx.lr),
+            ("ctr", $cx.ctr),
+            ("xer", $cx.xer),
+            ("cr", $cx.cr),
+            ("srr0", $cx.srr0),
+            ("trap", $cx.trap),
+            ("dar", $cx.dar),
+            ("dsisr", $cx.dsisr),
+        ]
+    };
+}
+
+/// Write the registers shared by `UserContext` and `TrapFrame`.
+fn write_trap_regs(
+    f: &mut fmt::Formatter,
+    general: &GeneralRegs,
+    regs: &[(&str, usize)],
+    srr1: usize,
+) -> fmt::Result {
+    write!(f, "{}", general)?;
+    write_regs(f, regs)?;
+    write_flags(f, "srr1", srr1, MSR_BITS)
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general
+            .named()
+            .into_iter()
+            .chain(trap_regs!(self))
+            .chain([("srr1", self.srr1)])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, &trap_regs!(self), self.srr1)?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(f, &self.general, &trap_regs!(self), self.srr1)
+    }
+}
@@ -0,0 +1,6 @@
+//! ELF header fields of core files.
+
+/// `EM_PPC64`
+pub(crate) const ELF_MACHINE: u16 = 21;
+/// ELFv2 ABI
+pub(crate) const ELF_FLAGS: u32 = 2;
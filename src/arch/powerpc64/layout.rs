@@ -0,0 +1,96 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+use super::{TrapFrame, UserContext};
+
+layout!(UserContext {
+    USER_CONTEXT_R0_OFFSET = 0 => general.r0,
+    USER_CONTEXT_R1_OFFSET = 1 => general.r1,
+    USER_CONTEXT_R2_OFFSET = 2 => general.r2,
+    USER_CONTEXT_R3_OFFSET = 3 => general.r3,
+    USER_CONTEXT_R4_OFFSET = 4 => general.r4,
+    USER_CONTEXT_R5_OFFSET = 5 => general.r5,
+    USER_CONTEXT_R6_OFFSET = 6 => general.r6,
+    USER_CONTEXT_R7_OFFSET = 7 => general.r7,
+    USER_CONTEXT_R8_OFFSET = 8 => general.r8,
+    USER_CONTEXT_R9_OFFSET = 9 => general.r9,
+    USER_CONTEXT_R10_OFFSET = 10 => general.r10,
+    USER_CONTEXT_R11_OFFSET = 11 => general.r11,
+    USER_CONTEXT_R12_OFFSET = 12 => general.r12,
+    USER_CONTEXT_R13_OFFSET = 13 => general.r13,
+    USER_CONTEXT_R14_OFFSET = 14 => general.r14,
+    USER_CONTEXT_R15_OFFSET = 15 => general.r15,
+    USER_CONTEXT_R16_OFFSET = 16 => general.r16,
+    USER_CONTEXT_R17_OFFSET = 17 => general.r17,
+    USER_CONTEXT_R18_OFFSET = 18 => general.r18,
+    USER_CONTEXT_R19_OFFSET = 19 => general.r19,
+    USER_CONTEXT_R20_OFFSET = 20 => general.r20,
+    USER_CONTEXT_R21_OFFSET = 21 => general.r21,
+    USER_CONTEXT_R22_OFFSET = 22 => general.r22,
+    USER_CONTEXT_R23_OFFSET = 23 => general.r23,
+    USER_CONTEXT_R24_OFFSET = 24 => general.r24,
+    USER_CONTEXT_R25_OFFSET = 25 => general.r25,
+    USER_CONTEXT_R26_OFFSET = 26 => general.r26,
+    USER_CONTEXT_R27_OFFSET = 27 => general.r27,
+    USER_CONTEXT_R28_OFFSET = 28 => general.r28,
+    USER_CONTEXT_R29_OFFSET = 29 => general.r29,
+    USER_CONTEXT_R30_OFFSET = 30 => general.r30,
+    USER_CONTEXT_R31_OFFSET = 31 => general.r31,
+    USER_CONTEXT_LR_OFFSET = 32 => lr,
+    USER_CONTEXT_CTR_OFFSET = 33 => ctr,
+    USER_CONTEXT_XER_OFFSET = 34 => xer,
+    USER_CONTEXT_CR_OFFSET = 35 => cr,
+    USER_CONTEXT_SRR0_OFFSET = 36 => srr0,
+    USER_CONTEXT_SRR1_OFFSET = 37 => srr1,
+    USER_CONTEXT_TRAP_OFFSET = 38 => trap,
+    USER_CONTEXT_DAR_OFFSET = 39 => dar,
+    USER_CONTEXT_DSISR_OFFSET = 40 => dsisr,
+});
+
+layout!(TrapFrame {
+    TRAP_FRAME_R0_OFFSET = 0 => general.r0,
+    TRAP_FRAME_R1_OFFSET = 1 => general.r1,
+    TRAP_FRAME_R2_OFFSET = 2 => general.r2,
+    TRAP_FRAME_R3_OFFSET = 3 => general.r3,
+    TRAP_FRAME_R4_OFFSET = 4 => general.r4,
+    TRAP_FRAME_R5_OFFSET = 5 => general.r5,
+    TRAP_FRAME_R6_OFFSET = 6 => general.r6,
+    TRAP_FRAME_R7_OFFSET = 7 => general.r7,
+    TRAP_FRAME_R8_OFFSET = 8 => general.r8,
+    TRAP_FRAME_R9_OFFSET = 9 => general.r9,
+    TRAP_FRAME_R10_OFFSET = 10 => general.r10,
+    TRAP_FRAME_R11_OFFSET = 11 => general.r11,
+    TRAP_FRAME_R12_OFFSET = 12 => general.r12,
+    TRAP_FRAME_R13_OFFSET = 13 => general.r13,
+    TRAP_FRAME_R14_OFFSET = 14 => general.r14,
+    TRAP_FRAME_R15_OFFSET = 15 => general.r15,
+    TRAP_FRAME_R16_OFFSET = 16 => general.r16,
+    TRAP_FRAME_R17_OFFSET = 17 => general.r17,
+    TRAP_FRAME_R18_OFFSET = 18 => general.r18,
+    TRAP_FRAME_R19_OFFSET = 19 => general.r19,
+    TRAP_FRAME_R20_OFFSET = 20 => general.r20,
+    TRAP_FRAME_R21_OFFSET = 21 => general.r21,
+    TRAP_FRAME_R22_OFFSET = 22 => general.r22,
+    TRAP_FRAME_R23_OFFSET = 23 => general.r23,
+    TRAP_FRAME_R24_OFFSET = 24 => general.r24,
+    TRAP_FRAME_R25_OFFSET = 25 => general.r25,
+    TRAP_FRAME_R26_OFFSET = 26 => general.r26,
+    TRAP_FRAME_R27_OFFSET = 27 => general.r27,
+    TRAP_FRAME_R28_OFFSET = 28 => general.r28,
+    TRAP_FRAME_R29_OFFSET = 29 => general.r29,
+    TRAP_FRAME_R30_OFFSET = 30 => general.r30,
+    TRAP_FRAME_R31_OFFSET = 31 => general.r31,
+    TRAP_FRAME_LR_OFFSET = 32 => lr,
+    TRAP_FRAME_CTR_OFFSET = 33 => ctr,
+    TRAP_FRAME_XER_OFFSET = 34 => xer,
+    TRAP_FRAME_CR_OFFSET = 35 => cr,
+    TRAP_FRAME_SRR0_OFFSET = 36 => srr0,
+    TRAP_FRAME_SRR1_OFFSET = 37 => srr1,
+    TRAP_FRAME_TRAP_OFFSET = 38 => trap,
+    TRAP_FRAME_DAR_OFFSET = 39 => dar,
+    TRAP_FRAME_DSISR_OFFSET = 40 => dsisr,
+});
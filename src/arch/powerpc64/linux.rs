@@ -0,0 +1,82 @@
+//! Register layouts of Linux ABI, for `ptrace` and core dump.
+
+use super::{GeneralRegs, UserContext};
+
+/// `struct user_pt_regs` of `NT_PRSTATUS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub gpr: [usize; 32],
+    pub nip: usize,
+    pub msr: usize,
+    pub orig_gpr3: usize,
+    pub ctr: usize,
+    pub link: usize,
+    pub xer: usize,
+    pub ccr: usize,
+    pub softe: usize,
+    pub trap: usize,
+    pub dar: usize,
+    pub dsisr: usize,
+    pub result: usize,
+    pub reserved: [usize; 4],
+}
+
+unsafe impl pod::Pod for UserRegs {}
+
+impl GeneralRegs {
+    /// Registers r0 to r31.
+    fn to_array(self) -> [usize; 32] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.r13, self.r14, self.r15, self.r16,
+            self.r17, self.r18, self.r19, self.r20, self.r21, self.r22, self.r23, self.r24,
+            self.r25, self.r26, self.r27, self.r28, self.r29, self.r30, self.r31,
+        ]
+    }
+
+    /// Set registers r0 to r31.
+    fn set_array(&mut self, r: &[usize; 32]) {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7, self.r8,
+            self.r9, self.r10, self.r11, self.r12, self.r13, self.r14, self.r15, self.r16,
+            self.r17, self.r18, self.r19, self.r20, self.r21, self.r22, self.r23, self.r24,
+            self.r25, self.r26, self.r27, self.r28, self.r29, self.r30, self.r31,
+        ] = *r;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of `user_pt_regs`.
+    ///
+    /// `orig_gpr3` is reported as the current `r3`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        UserRegs {
+            gpr: self.general.to_array(),
+            nip: self.srr0,
+            msr: self.srr1,
+            orig_gpr3: self.general.r3,
+            ctr: self.ctr,
+            link: self.lr,
+            xer: self.xer,
+            ccr: self.cr,
+            softe: 1,
+            trap: self.trap,
+            dar: self.dar,
+            dsisr: self.dsisr,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `user_pt_regs`.
+    ///
+    /// `msr` and the fields saved on trap are ignored.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        self.general.set_array(&regs.gpr);
+        self.srr0 = regs.nip;
+        self.ctr = regs.ctr;
+        self.lr = regs.link;
+        self.xer = regs.xer;
+        self.cr = regs.ccr;
+    }
+}
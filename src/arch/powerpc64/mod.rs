@@ -0,0 +1,10 @@
+mod display;
+mod dwarf;
+mod elf;
+mod layout;
+pub mod linux;
+mod trap;
+
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+pub use layout::*;
+pub use trap::*;
@@ -0,0 +1,414 @@
+use super::layout::*;
+use crate::{PageFaultFlags, PageFaultInfo, TrapInfo, TrapInitError, TrapReason};
+use core::arch::asm;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+global_asm_equ!(
+    [
+        SCRATCH_CONTEXT = offset_of!(Scratch, context),
+        SCRATCH_KERNEL_SP = offset_of!(Scratch, kernel_sp),
+        SCRATCH_R12 = offset_of!(Scratch, r12),
+        SCRATCH_TRAP = offset_of!(Scratch, trap),
+        SCRATCH_CR = offset_of!(Scratch, cr),
+        LR = USER_CONTEXT_LR_OFFSET / 8,
+        CTR = USER_CONTEXT_CTR_OFFSET / 8,
+        XER = USER_CONTEXT_XER_OFFSET / 8,
+        CR = USER_CONTEXT_CR_OFFSET / 8,
+        SRR0 = USER_CONTEXT_SRR0_OFFSET / 8,
+        SRR1 = USER_CONTEXT_SRR1_OFFSET / 8,
+        TRAP = USER_CONTEXT_TRAP_OFFSET / 8,
+        DAR = USER_CONTEXT_DAR_OFFSET / 8,
+        DSISR = USER_CONTEXT_DSISR_OFFSET / 8,
+        // rounded up to 16 bytes
+        FRAME_WORDS = (core::mem::size_of::<TrapFrame>() / 8 + 1) & !1,
+    ],
+    include_str!("trap.S")
+);
+
+/// Per-CPU scratch area pointed to by `SPRG0`, used by the trap entry.
+#[repr(C)]
+struct Scratch {
+    /// The `UserContext` being run, or 0 in kernel
+    context: usize,
+    /// The kernel stack pointer in `run_user`
+    kernel_sp: usize,
+    /// `r12` of the interrupted code
+    r12: usize,
+    /// The interrupt vector
+    trap: usize,
+    /// `CR` of the interrupted code
+    cr: usize,
+}
+
+const EMPTY_SCRATCH: Scratch = Scratch {
+    context: 0,
+    kernel_sp: 0,
+    r12: 0,
+    trap: 0,
+    cr: 0,
+};
+
+static mut SCRATCH: [Scratch; crate::MAX_CPUS] = [EMPTY_SCRATCH; crate::MAX_CPUS];
+static SCRATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Initialize interrupt handling for the current CPU.
+///
+/// The interrupt vectors are in section `.text.trap_vectors`, which must be
+/// placed by the linker script at the interrupt vector base, i.e. physical
+/// address 0, or `0xc000_0000_0000_4000` for relocation-on interrupts with
+/// `LPCR.AIL` = 3, which are the only way to keep the MMU on in the
+/// handlers. Interrupts delivered with `HSRR0` and `HSRR1` are not handled.
+///
+/// # Safety
+///
+/// This function will:
+/// - Set `SPRG0` to a per-CPU scratch area, taken from a static array of
+///   [`MAX_CPUS`](crate::MAX_CPUS) entries.
+/// - Use `SPRG1` as a scratch register in the interrupt vectors.
+///
+/// You **MUST NOT** modify these registers later.
+///
+/// Return [`TrapInitError::TooManyCpus`] before changing any state if
+/// called on more than [`MAX_CPUS`](crate::MAX_CPUS) CPUs.
+pub unsafe fn init() -> Result<(), TrapInitError> {
+    let id = SCRATCH_COUNT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            (n < crate::MAX_CPUS).then(|| n + 1)
+        })
+        .map_err(|_| TrapInitError::TooManyCpus)?;
+    let scratch = addr_of!(SCRATCH[id]) as usize;
+    asm!("mtspr 272, {}", in(reg) scratch);
+    Ok(())
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    unimplemented!("TRAP: tf={:#x?}", tf);
+}
+
+/// `SRR1` bit of instruction storage interrupt and `DSISR` bit of data
+/// storage interrupt: protection violation
+const PROTECTION: usize = 1 << 27;
+/// `DSISR` bit: caused by a store
+const STORE: usize = 1 << 25;
+/// `SRR1` bits of program interrupt: illegal, privileged instruction, trap
+const ILLEGAL: usize = 1 << 19;
+const PRIVILEGED: usize = 1 << 18;
+const TRAP: usize = 1 << 17;
+/// `MSR.PR`, problem state
+const PR: usize = 1 << 14;
+
+/// Decode the interrupt vector and the saved registers.
+fn decode(
+    trap: usize,
+    srr0: usize,
+    srr1: usize,
+    dar: usize,
+    dsisr: usize,
+    user: bool,
+) -> TrapReason {
+    let page_fault = |addr: usize, status: usize, mut flags: PageFaultFlags| {
+        flags.set(PageFaultFlags::PRESENT, status & PROTECTION != 0);
+        flags.set(PageFaultFlags::USER, user);
+        TrapReason::PageFault { addr, flags }
+    };
+    match trap {
+        // data storage, data segment
+        0x300 | 0x380 => {
+            let write = if dsisr & STORE != 0 {
+                PageFaultFlags::WRITE
+            } else {
+                PageFaultFlags::empty()
+            };
+            page_fault(dar, dsisr, write)
+        }
+        // instruction storage, instruction segment
+        0x400 | 0x480 => page_fault(srr0, srr1, PageFaultFlags::EXECUTE),
+        0x600 => TrapReason::Misaligned,
+        0x700 if srr1 & TRAP != 0 => TrapReason::Breakpoint,
+        0x700 if srr1 & (ILLEGAL | PRIVILEGED) != 0 => TrapReason::IllegalInstruction,
+        0xc00 => TrapReason::Syscall,
+        0xd00 => TrapReason::SingleStep,
+        // external, decrementer, doorbell, performance monitor
+        0x500 | 0x900 | 0xa00 | 0xf00 => TrapReason::Interrupt(trap),
+        _ => TrapReason::Unknown(trap),
+    }
+}
+
+/// Trap frame of kernel interrupt
+///
+/// # Trap handler
+///
+/// You need to define a handler function like this:
+///
+/// ```no_run
+/// use trapframe::TrapFrame;
+///
+/// #[no_mangle]
+/// pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+///     println!("TRAP! tf: {:#x?}", tf);
+/// }
+/// ```
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct TrapFrame {
+    /// General registers
+    pub general: GeneralRegs,
+    /// Link Register
+    pub lr: usize,
+    /// Count Register
+    pub ctr: usize,
+    /// Fixed-Point Exception Register
+    pub xer: usize,
+    /// Condition Register
+    pub cr: usize,
+    /// Save/Restore Register 0, the address to return to
+    pub srr0: usize,
+    /// Save/Restore Register 1, the `MSR` to return with
+    pub srr1: usize,
+    /// Offset of the interrupt vector, saved on trap
+    pub trap: usize,
+    /// Data Address Register, saved on trap
+    pub dar: usize,
+    /// Data Storage Interrupt Status Register, saved on trap
+    pub dsisr: usize,
+}
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        let user = self.srr1 & PR != 0;
+        match decode(self.trap, self.srr0, self.srr1, self.dar, self.dsisr, user) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+}
+
+/// Saved registers on a trap.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct UserContext {
+    /// General registers
+    pub general: GeneralRegs,
+    /// Link Register
+    pub lr: usize,
+    /// Count Register
+    pub ctr: usize,
+    /// Fixed-Point Exception Register
+    pub xer: usize,
+    /// Condition Register
+    pub cr: usize,
+    /// Save/Restore Register 0, the address to return to
+    pub srr0: usize,
+    /// Save/Restore Register 1, the `MSR` to return with
+    pub srr1: usize,
+    /// Offset of the interrupt vector, saved on trap
+    pub trap: usize,
+    /// Data Address Register, saved on trap
+    pub dar: usize,
+    /// Data Storage Interrupt Status Register, saved on trap
+    pub dsisr: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
+}
+
+impl UserContext {
+    /// Go to user space with the context, and come back when a trap occurs.
+    ///
+    /// On return, the context will be reset to the status before the trap.
+    /// Trap reason will be placed at `trap`, `dar` and `dsisr`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use trapframe::{UserContext, GeneralRegs};
+    ///
+    /// // init user space context
+    /// let mut context = UserContext {
+    ///     general: GeneralRegs {
+    ///         r1: 0x10000,
+    ///         r12: 0x1000,
+    ///         ..Default::default()
+    ///     },
+    ///     // SF, EE, PR, ME, IR, DR, RI, LE
+    ///     srr1: 0x8000_0000_0000_d033,
+    ///     srr0: 0x1000,
+    ///     ..Default::default()
+    /// };
+    /// // go to user
+    /// context.run();
+    /// // back from user
+    /// println!("back from user: {:#x?}", context);
+    /// ```
+    pub fn run(&mut self) {
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        unsafe { run_user(self) };
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
+
+/// Read the time base.
+pub(crate) fn read_cycles() -> u64 {
+    let tb: u64;
+    unsafe { asm!("mftb {}", out(reg) tb) };
+    tb
+}
+
+/// General registers
+///
+/// `r1` is the stack pointer, `r2` the TOC pointer and `r13` the thread
+/// pointer.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct GeneralRegs {
+    pub r0: usize,
+    pub r1: usize,
+    pub r2: usize,
+    pub r3: usize,
+    pub r4: usize,
+    pub r5: usize,
+    pub r6: usize,
+    pub r7: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub r16: usize,
+    pub r17: usize,
+    pub r18: usize,
+    pub r19: usize,
+    pub r20: usize,
+    pub r21: usize,
+    pub r22: usize,
+    pub r23: usize,
+    pub r24: usize,
+    pub r25: usize,
+    pub r26: usize,
+    pub r27: usize,
+    pub r28: usize,
+    pub r29: usize,
+    pub r30: usize,
+    pub r31: usize,
+}
+
+unsafe impl pod::Pod for GeneralRegs {}
+unsafe impl pod::Pod for UserContext {}
+impl_bytemuck!(GeneralRegs, UserContext, TrapFrame);
+
+impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.trap, self.srr0, self.srr1, self.dar, self.dsisr, true)
+    }
+
+    /// Get number of syscall
+    pub fn get_syscall_num(&self) -> usize {
+        self.general.r0
+    }
+
+    /// Get return value of syscall
+    pub fn get_syscall_ret(&self) -> usize {
+        self.general.r3
+    }
+
+    /// Set return value of syscall
+    pub fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.r3 = ret;
+    }
+
+    /// Get syscall args
+    pub fn get_syscall_args(&self) -> [usize; 6] {
+        [
+            self.general.r3,
+            self.general.r4,
+            self.general.r5,
+            self.general.r6,
+            self.general.r7,
+            self.general.r8,
+        ]
+    }
+
+    /// Set instruction pointer
+    pub fn set_ip(&mut self, ip: usize) {
+        self.srr0 = ip;
+    }
+
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.srr0
+    }
+
+    /// Set stack pointer
+    pub fn set_sp(&mut self, sp: usize) {
+        self.general.r1 = sp;
+    }
+
+    /// Get stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.general.r1
+    }
+
+    /// Set tls pointer
+    pub fn set_tls(&mut self, tls: usize) {
+        self.general.r13 = tls;
+    }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.general.r13
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs {
+            r13: self.general.r13,
+        }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.general.r13 = regs.r13;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// `r13`, the thread pointer
+    pub r13: usize,
+}
+
+#[allow(improper_ctypes)]
+extern "C" {
+    fn run_user(regs: &mut UserContext);
+}
@@ -0,0 +1,102 @@
+//! Delegation of traps from M-mode to S-mode.
+//!
+//! The crate handles traps in S-mode, so the firmware must delegate the
+//! traps it expects by `medeleg` and `mideleg`. SBI implementations such
+//! as OpenSBI delegate at least [`Exceptions::EXPECTED`] and
+//! [`Interrupts::EXPECTED`].
+//!
+//! Kernels running the crate in M-mode, e.g. as firmware, program the
+//! delegation themselves by [`delegate`]. The CSRs are not accessible in
+//! S-mode.
+
+use bitflags::bitflags;
+use core::arch::asm;
+
+bitflags! {
+    /// Exceptions, as bits of `medeleg` by cause.
+    pub struct Exceptions: usize {
+        const INSTRUCTION_MISALIGNED = 1 << 0;
+        const INSTRUCTION_FAULT = 1 << 1;
+        const ILLEGAL_INSTRUCTION = 1 << 2;
+        const BREAKPOINT = 1 << 3;
+        const LOAD_MISALIGNED = 1 << 4;
+        const LOAD_FAULT = 1 << 5;
+        const STORE_MISALIGNED = 1 << 6;
+        const STORE_FAULT = 1 << 7;
+        const USER_ECALL = 1 << 8;
+        const SUPERVISOR_ECALL = 1 << 9;
+        const MACHINE_ECALL = 1 << 11;
+        const INSTRUCTION_PAGE_FAULT = 1 << 12;
+        const LOAD_PAGE_FAULT = 1 << 13;
+        const STORE_PAGE_FAULT = 1 << 15;
+
+        /// Exceptions taken by `UserContext::run()` and `TrapFrame`.
+        const EXPECTED = Self::INSTRUCTION_MISALIGNED.bits
+            | Self::INSTRUCTION_FAULT.bits
+            | Self::ILLEGAL_INSTRUCTION.bits
+            | Self::BREAKPOINT.bits
+            | Self::LOAD_MISALIGNED.bits
+            | Self::LOAD_FAULT.bits
+            | Self::STORE_MISALIGNED.bits
+            | Self::STORE_FAULT.bits
+            | Self::USER_ECALL.bits
+            | Self::INSTRUCTION_PAGE_FAULT.bits
+            | Self::LOAD_PAGE_FAULT.bits
+            | Self::STORE_PAGE_FAULT.bits;
+    }
+}
+
+bitflags! {
+    /// Interrupts, as bits of `mideleg` by cause.
+    pub struct Interrupts: usize {
+        const SUPERVISOR_SOFTWARE = 1 << 1;
+        const SUPERVISOR_TIMER = 1 << 5;
+        const SUPERVISOR_EXTERNAL = 1 << 9;
+
+        /// Interrupts taken by `UserContext::run()` and `TrapFrame`.
+        const EXPECTED = Self::SUPERVISOR_SOFTWARE.bits
+            | Self::SUPERVISOR_TIMER.bits
+            | Self::SUPERVISOR_EXTERNAL.bits;
+    }
+}
+
+/// Delegate `exceptions` and `interrupts` to S-mode, and keep others in
+/// M-mode.
+///
+/// Return the delegation that took effect, since unsupported bits of the
+/// CSRs are read-only.
+///
+/// # Safety
+///
+/// It must run in M-mode, and traps no longer delegated must be handled
+/// by the M-mode trap vector.
+pub unsafe fn delegate(exceptions: Exceptions, interrupts: Interrupts) -> (Exceptions, Interrupts) {
+    asm!("csrw medeleg, {}", in(reg) exceptions.bits());
+    asm!("csrw mideleg, {}", in(reg) interrupts.bits());
+    current()
+}
+
+/// Get the traps delegated to S-mode.
+///
+/// # Safety
+///
+/// It must run in M-mode.
+pub unsafe fn current() -> (Exceptions, Interrupts) {
+    let (medeleg, mideleg): (usize, usize);
+    asm!("csrr {}, medeleg", out(reg) medeleg);
+    asm!("csrr {}, mideleg", out(reg) mideleg);
+    (
+        Exceptions::from_bits_truncate(medeleg),
+        Interrupts::from_bits_truncate(mideleg),
+    )
+}
+
+/// Whether the traps expected by the crate are delegated to S-mode.
+///
+/// # Safety
+///
+/// It must run in M-mode.
+pub unsafe fn is_expected() -> bool {
+    let (exceptions, interrupts) = current();
+    exceptions.contains(Exceptions::EXPECTED) && interrupts.contains(Interrupts::EXPECTED)
+}
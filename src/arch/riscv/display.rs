@@ -0,0 +1,114 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Bits in `sstatus`
+const SSTATUS_BITS: &[(usize, &str)] = &[
+    (1 << 1, "SIE"),
+    (1 << 5, "SPIE"),
+    (1 << 8, "SPP"),
+    (1 << 18, "SUM"),
+    (1 << 19, "MXR"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 31] {
+        [
+            ("ra", self.ra),
+            ("sp", self.sp),
+            ("gp", self.gp),
+            ("tp", self.tp),
+            ("t0", self.t0),
+            ("t1", self.t1),
+            ("t2", self.t2),
+            ("s0", self.s0),
+            ("s1", self.s1),
+            ("a0", self.a0),
+            ("a1", self.a1),
+            ("a2", self.a2),
+            ("a3", self.a3),
+            ("a4", self.a4),
+            ("a5", self.a5),
+            ("a6", self.a6),
+            ("a7", self.a7),
+            ("s2", self.s2),
+            ("s3", self.s3),
+            ("s4", self.s4),
+            ("s5", self.s5),
+            ("s6", self.s6),
+            ("s7", self.s7),
+            ("s8", self.s8),
+            ("s9", self.s9),
+            ("s10", self.s10),
+            ("s11", self.s11),
+            ("t3", self.t3),
+            ("t4", self.t4),
+            ("t5", self.t5),
+            ("t6", self.t6),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+/// Write the registers shared by `UserContext` and `TrapFrame`.
+fn write_trap_regs(
+    f: &mut fmt::Formatter,
+    general: &GeneralRegs,
+    sstatus: usize,
+    sepc: usize,
+    scause: usize,
+    stval: usize,
+) -> fmt::Result {
+    write!(f, "{}", general)?;
+    write_regs(f, &[("sepc", sepc), ("scause", scause), ("stval", stval)])?;
+    write_flags(f, "sstatus", sstatus, SSTATUS_BITS)
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("sepc", self.sepc),
+            ("sstatus", self.sstatus),
+            ("scause", self.scause),
+            ("stval", self.stval),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(
+            f,
+            &self.general,
+            self.sstatus,
+            self.sepc,
+            self.scause,
+            self.stval,
+        )?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_trap_regs(
+            f,
+            &self.general,
+            self.sstatus,
+            self.sepc,
+            self.scause,
+            self.stval,
+        )
+    }
+}
@@ -0,0 +1,39 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    match index.0 {
+        // x0 to x31 are in order
+        n @ 0..=31 => Some(unsafe { addr_of!((*cx).general).cast::<usize>().add(n as usize) }),
+        _ => None,
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    ///
+    /// `x0` is always 0.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        if index.0 == 0 {
+            return Some(0);
+        }
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context or is `x0`.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) if index.0 != 0 => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            _ => false,
+        }
+    }
+}
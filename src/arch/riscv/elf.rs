@@ -0,0 +1,6 @@
+//! ELF header fields of core files.
+
+/// `EM_RISCV`
+pub(crate) const ELF_MACHINE: u16 = 243;
+/// `EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE`
+pub(crate) const ELF_FLAGS: u32 = 0x5;
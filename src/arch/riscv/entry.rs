@@ -0,0 +1,348 @@
+//! Trap entry and return, as naked functions.
+//!
+//! The trap CSRs are named by `xcsr!`, e.g. `sscratch`, or `mscratch` with
+//! feature `riscv_m_mode`.
+
+use super::layout::*;
+use super::trap::{trap_dispatch, trap_handler, INTERRUPT, SOFTWARE, TIMER};
+use super::{TrapFrame, UserContext};
+use core::arch::asm;
+use core::mem::size_of;
+
+/// Store `$reg` to word `$n`, or to the offset operand `$off`, above `sp`.
+#[cfg(target_arch = "riscv32")]
+macro_rules! store {
+    ($reg:literal, $n:literal) => {
+        concat!("sw ", $reg, ", ", $n, "*{xlenb}(sp)")
+    };
+    ($reg:literal, $off:ident) => {
+        concat!("sw ", $reg, ", {", stringify!($off), "}(sp)")
+    };
+}
+
+/// Load `$reg` from word `$n`, or from the offset operand `$off`, above `sp`.
+#[cfg(target_arch = "riscv32")]
+macro_rules! load {
+    ($reg:literal, $n:literal) => {
+        concat!("lw ", $reg, ", ", $n, "*{xlenb}(sp)")
+    };
+    ($reg:literal, $off:ident) => {
+        concat!("lw ", $reg, ", {", stringify!($off), "}(sp)")
+    };
+}
+
+/// Store `$reg` to word `$n`, or to the offset operand `$off`, above `sp`.
+#[cfg(target_arch = "riscv64")]
+macro_rules! store {
+    ($reg:literal, $n:literal) => {
+        concat!("sd ", $reg, ", ", $n, "*{xlenb}(sp)")
+    };
+    ($reg:literal, $off:ident) => {
+        concat!("sd ", $reg, ", {", stringify!($off), "}(sp)")
+    };
+}
+
+/// Load `$reg` from word `$n`, or from the offset operand `$off`, above `sp`.
+#[cfg(target_arch = "riscv64")]
+macro_rules! load {
+    ($reg:literal, $n:literal) => {
+        concat!("ld ", $reg, ", ", $n, "*{xlenb}(sp)")
+    };
+    ($reg:literal, $off:ident) => {
+        concat!("ld ", $reg, ", {", stringify!($off), "}(sp)")
+    };
+}
+
+/// Return from the trap.
+#[cfg(not(feature = "riscv_m_mode"))]
+macro_rules! xret {
+    () => {
+        "sret"
+    };
+}
+
+/// Return from the trap.
+#[cfg(feature = "riscv_m_mode")]
+macro_rules! xret {
+    () => {
+        "mret"
+    };
+}
+
+/// Keep `sstatus.SPP` of the saved `sstatus` in `t1`, non-zero if trapped
+/// from kernel.
+#[cfg(not(feature = "riscv_m_mode"))]
+macro_rules! from_kernel {
+    () => {
+        "andi t1, t1, 1 << 8"
+    };
+}
+
+/// Keep the high bit of `mstatus.MPP` of the saved `mstatus` in `t1`,
+/// non-zero if trapped from M-mode.
+#[cfg(feature = "riscv_m_mode")]
+macro_rules! from_kernel {
+    () => {
+        "srli t1, t1, 12\nandi t1, t1, 1"
+    };
+}
+
+/// Define a trap entry `$name`, which saves the trap frame, sets `t3` and
+/// `t4` to `scause` and `stval` by the instructions `$cause`, which may use
+/// the operand `code`, and jumps to `$dispatch` if trapped from kernel.
+macro_rules! trap_entry {
+    (
+        $(#[$attr:meta])*
+        fn $name:ident;
+        cause: { $($cause:tt)* }
+        dispatch: $dispatch:path;
+        $(code: $code:expr;)?
+    ) => {
+        $(#[$attr])*
+        #[naked]
+        #[repr(align(4))]
+        pub(super) unsafe extern "C" fn $name() -> ! {
+            asm!(
+                // If coming from userspace, preserve the user stack pointer and load
+                // the kernel stack pointer. If we came from the kernel, sscratch
+                // will contain 0, and we should continue on the current stack.
+                concat!("csrrw sp, ", xcsr!("scratch"), ", sp"),
+                "bnez sp, 1f",
+                // from kernel
+                concat!("csrr sp, ", xcsr!("scratch")),
+                "addi sp, sp, -{trap_frame_size}",
+                // sscratch = previous-sp, sp = kernel-sp
+                "1:",
+                // save general registers except sp(x2)
+                store!("x1", 1),
+                store!("x3", 3),
+                store!("x4", 4),
+                store!("x5", 5),
+                store!("x6", 6),
+                store!("x7", 7),
+                store!("x8", 8),
+                store!("x9", 9),
+                store!("x10", 10),
+                store!("x11", 11),
+                store!("x12", 12),
+                store!("x13", 13),
+                store!("x14", 14),
+                store!("x15", 15),
+                store!("x16", 16),
+                store!("x17", 17),
+                store!("x18", 18),
+                store!("x19", 19),
+                store!("x20", 20),
+                store!("x21", 21),
+                store!("x22", 22),
+                store!("x23", 23),
+                store!("x24", 24),
+                store!("x25", 25),
+                store!("x26", 26),
+                store!("x27", 27),
+                store!("x28", 28),
+                store!("x29", 29),
+                store!("x30", 30),
+                store!("x31", 31),
+                // save sp, sstatus, sepc, scause, stval
+                concat!("csrrw t0, ", xcsr!("scratch"), ", x0"), // sscratch = 0 (kernel)
+                concat!("csrr t1, ", xcsr!("status")),
+                concat!("csrr t2, ", xcsr!("epc")),
+                store!("t0", 2),
+                store!("t1", sstatus),
+                store!("t2", sepc),
+                $($cause)*
+                store!("t3", scause),
+                store!("t4", stval),
+                // clear sstatus.SUM, the kernel can not access user memory by accident
+                "li t5, 1 << 18",
+                concat!("csrc ", xcsr!("status"), ", t5"),
+                from_kernel!(),
+                "beqz t1, 2f",
+                // end of trap from kernel
+                "mv a0, sp", // first arg is TrapFrame
+                "la ra, {trap_return}", // set return address
+                "j {dispatch}",
+                // end of trap from user, load callee-saved registers
+                "2:",
+                load!("sp", 0),
+                load!("s0", 0),
+                load!("s1", 1),
+                load!("s2", 2),
+                load!("s3", 3),
+                load!("s4", 4),
+                load!("s5", 5),
+                load!("s6", 6),
+                load!("s7", 7),
+                load!("s8", 8),
+                load!("s9", 9),
+                load!("s10", 10),
+                load!("s11", 11),
+                load!("ra", 12),
+                // not callee-saved, but is used to store mhartid
+                load!("tp", 13),
+                "addi sp, sp, 14 * {xlenb}",
+                "ret",
+                xlenb = const size_of::<usize>(),
+                trap_frame_size = const size_of::<TrapFrame>(),
+                sstatus = const TRAP_FRAME_SSTATUS_OFFSET,
+                sepc = const TRAP_FRAME_SEPC_OFFSET,
+                scause = const TRAP_FRAME_SCAUSE_OFFSET,
+                stval = const TRAP_FRAME_STVAL_OFFSET,
+                trap_return = sym trap_return,
+                dispatch = sym $dispatch,
+                $(code = const $code,)?
+                options(noreturn),
+            )
+        }
+    };
+}
+
+trap_entry! {
+    /// Entry of all traps in direct mode of `stvec`, and of exceptions and
+    /// other interrupts in vectored mode.
+    fn trap_entry;
+    cause: {
+        concat!("csrr t3, ", xcsr!("cause")),
+        concat!("csrr t4, ", xcsr!("tval")),
+    }
+    dispatch: trap_dispatch;
+}
+
+trap_entry! {
+    /// Entry of timer interrupts in vectored mode, which goes to the handler
+    /// of the kernel directly, without reading `scause` and `stval`.
+    fn timer_entry;
+    cause: {
+        "li t3, 1",
+        "slli t3, t3, {xlenb} * 8 - 1",
+        "ori t3, t3, {code}",
+        "li t4, 0",
+    }
+    dispatch: trap_handler;
+    code: TIMER & !INTERRUPT;
+}
+
+trap_entry! {
+    /// Entry of software interrupts in vectored mode, which goes to the
+    /// handler of the kernel directly, without reading `scause` and `stval`.
+    fn software_entry;
+    cause: {
+        "li t3, 1",
+        "slli t3, t3, {xlenb} * 8 - 1",
+        "ori t3, t3, {code}",
+        "li t4, 0",
+    }
+    dispatch: trap_handler;
+    code: SOFTWARE & !INTERRUPT;
+}
+
+/// Vector table of `stvec` in vectored mode, for exceptions at the base and
+/// interrupt causes 1 to 15 at `base + 4 * cause`.
+#[naked]
+#[repr(align(256))]
+pub(super) unsafe extern "C" fn trap_vectors() -> ! {
+    asm!(
+        ".option push",
+        ".option norvc",
+        ".rept {software}",
+        "j {trap_entry}",
+        ".endr",
+        "j {software_entry}",
+        ".rept {timer} - {software} - 1",
+        "j {trap_entry}",
+        ".endr",
+        "j {timer_entry}",
+        ".rept 15 - {timer}",
+        "j {trap_entry}",
+        ".endr",
+        ".option pop",
+        software = const SOFTWARE & !INTERRUPT,
+        timer = const TIMER & !INTERRUPT,
+        trap_entry = sym trap_entry,
+        software_entry = sym software_entry,
+        timer_entry = sym timer_entry,
+        options(noreturn),
+    )
+}
+
+/// Go to user space with the context `regs`, and return on the next trap.
+#[naked]
+pub(super) unsafe extern "C" fn run_user(_regs: &mut UserContext) {
+    asm!(
+        // save callee-saved registers
+        "addi sp, sp, -14 * {xlenb}",
+        store!("s0", 0),
+        store!("s1", 1),
+        store!("s2", 2),
+        store!("s3", 3),
+        store!("s4", 4),
+        store!("s5", 5),
+        store!("s6", 6),
+        store!("s7", 7),
+        store!("s8", 8),
+        store!("s9", 9),
+        store!("s10", 10),
+        store!("s11", 11),
+        store!("ra", 12),
+        // not callee-saved, but is used to store mhartid
+        store!("tp", 13),
+        "mv t0, sp",
+        "mv sp, a0",
+        store!("t0", 0), // save kernel-sp
+        concat!("csrw ", xcsr!("scratch"), ", sp"), // sscratch = bottom of trap frame
+        "j {trap_return}",
+        xlenb = const size_of::<usize>(),
+        trap_return = sym trap_return,
+        options(noreturn),
+    )
+}
+
+/// Return to the trapped code with the trap frame at `sp`.
+#[naked]
+unsafe extern "C" fn trap_return() -> ! {
+    asm!(
+        load!("t0", sstatus),
+        load!("t1", sepc),
+        concat!("csrw ", xcsr!("status"), ", t0"),
+        concat!("csrw ", xcsr!("epc"), ", t1"),
+        // restore general registers except sp(x2)
+        load!("x1", 1),
+        load!("x3", 3),
+        load!("x4", 4),
+        load!("x5", 5),
+        load!("x6", 6),
+        load!("x7", 7),
+        load!("x8", 8),
+        load!("x9", 9),
+        load!("x10", 10),
+        load!("x11", 11),
+        load!("x12", 12),
+        load!("x13", 13),
+        load!("x14", 14),
+        load!("x15", 15),
+        load!("x16", 16),
+        load!("x17", 17),
+        load!("x18", 18),
+        load!("x19", 19),
+        load!("x20", 20),
+        load!("x21", 21),
+        load!("x22", 22),
+        load!("x23", 23),
+        load!("x24", 24),
+        load!("x25", 25),
+        load!("x26", 26),
+        load!("x27", 27),
+        load!("x28", 28),
+        load!("x29", 29),
+        load!("x30", 30),
+        load!("x31", 31),
+        // restore sp last
+        load!("x2", 2),
+        xret!(),
+        xlenb = const size_of::<usize>(),
+        sstatus = const USER_CONTEXT_SSTATUS_OFFSET,
+        sepc = const USER_CONTEXT_SEPC_OFFSET,
+        options(noreturn),
+    )
+}
@@ -0,0 +1,93 @@
+//! Conversion between context types and GDB register layouts of `gdbstub_arch`.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use gdbstub_arch::riscv::reg::RiscvCoreRegs;
+
+#[cfg(target_arch = "riscv32")]
+type Reg = u32;
+#[cfg(target_arch = "riscv64")]
+type Reg = u64;
+
+impl GeneralRegs {
+    /// Registers x0 to x31.
+    fn to_array(self) -> [Reg; 32] {
+        [
+            self.zero, self.ra, self.sp, self.gp, self.tp, self.t0, self.t1, self.t2, self.s0,
+            self.s1, self.a0, self.a1, self.a2, self.a3, self.a4, self.a5, self.a6, self.a7,
+            self.s2, self.s3, self.s4, self.s5, self.s6, self.s7, self.s8, self.s9, self.s10,
+            self.s11, self.t3, self.t4, self.t5, self.t6,
+        ]
+        .map(|x| x as Reg)
+    }
+
+    /// Set registers x1 to x31, `zero` is kept 0.
+    fn set_array(&mut self, x: &[Reg; 32]) {
+        let x = x.map(|x| x as usize);
+        [
+            _,
+            self.ra,
+            self.sp,
+            self.gp,
+            self.tp,
+            self.t0,
+            self.t1,
+            self.t2,
+            self.s0,
+            self.s1,
+            self.a0,
+            self.a1,
+            self.a2,
+            self.a3,
+            self.a4,
+            self.a5,
+            self.a6,
+            self.a7,
+            self.s2,
+            self.s3,
+            self.s4,
+            self.s5,
+            self.s6,
+            self.s7,
+            self.s8,
+            self.s9,
+            self.s10,
+            self.s11,
+            self.t3,
+            self.t4,
+            self.t5,
+            self.t6,
+        ] = x;
+    }
+}
+
+impl UserContext {
+    /// Get registers in the layout of GDB `org.gnu.gdb.riscv.cpu`.
+    pub fn get_gdb_regs(&self) -> RiscvCoreRegs<Reg> {
+        RiscvCoreRegs {
+            x: self.general.to_array(),
+            pc: self.sepc as Reg,
+        }
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.riscv.cpu`.
+    pub fn set_gdb_regs(&mut self, regs: &RiscvCoreRegs<Reg>) {
+        self.general.set_array(&regs.x);
+        self.sepc = regs.pc as usize;
+    }
+}
+
+impl TrapFrame {
+    /// Get registers in the layout of GDB `org.gnu.gdb.riscv.cpu`.
+    pub fn get_gdb_regs(&self) -> RiscvCoreRegs<Reg> {
+        RiscvCoreRegs {
+            x: self.general.to_array(),
+            pc: self.sepc as Reg,
+        }
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.riscv.cpu`.
+    pub fn set_gdb_regs(&mut self, regs: &RiscvCoreRegs<Reg>) {
+        self.general.set_array(&regs.x);
+        self.sepc = regs.pc as usize;
+    }
+}
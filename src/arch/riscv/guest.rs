@@ -0,0 +1,154 @@
+//! Hypervisor guest context, with the hypervisor extension (H).
+//!
+//! [`GuestContext`] is to VM guests what `UserContext` is to user programs:
+//! it holds the guest registers, and runs the guest in VS-mode until the
+//! next VM exit, i.e. a trap taken to HS-mode.
+//!
+//! Enabling the extension, G-stage page tables (`hgatp`), delegation by
+//! `hedeleg` / `hideleg` and virtual interrupts are left to the hypervisor.
+//! The VS-level CSRs other than `vsstatus` stay in the hardware, and must
+//! be switched by the hypervisor when switching guests.
+
+use super::GeneralRegs;
+use crate::PageFaultFlags;
+use core::arch::asm;
+
+extern "C" {
+    fn guest_entry(context: &mut GuestContext);
+}
+
+/// `sstatus.SPP`, entering VS-mode instead of VU-mode by `sret`
+const SPP: usize = 1 << 8;
+/// `hstatus.SPV`, entering the guest by `sret`
+const SPV: usize = 1 << 7;
+
+/// VM guest context
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct GuestContext {
+    /// General registers
+    pub general: GeneralRegs,
+    /// `sstatus` to enter the guest with, saved on exit
+    pub sstatus: usize,
+    /// Program counter of the guest
+    pub sepc: usize,
+    /// Supervisor Cause, saved on exit
+    pub scause: usize,
+    /// Supervisor Trap Value, saved on exit
+    pub stval: usize,
+    /// `hstatus` to enter the guest with, saved on exit
+    pub hstatus: usize,
+    /// Guest physical address of a guest-page fault, shifted right by 2
+    pub htval: usize,
+    /// Transformed trapping instruction, saved on exit
+    pub htinst: usize,
+    /// Status of the guest kernel, swapped with `vsstatus`
+    pub vsstatus: usize,
+}
+
+/// Reason of a VM exit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VmExitReason {
+    /// Exception in guest not delegated to it, with the cause
+    Exception(usize),
+    /// Page fault of VS-stage translation not delegated to the guest
+    PageFault { addr: usize, flags: PageFaultFlags },
+    /// Fault of G-stage translation
+    GuestPageFault {
+        /// Guest physical address
+        guest_phys_addr: usize,
+        /// Guest virtual address, if the fault is on one
+        addr: usize,
+        flags: PageFaultFlags,
+    },
+    /// `ecall` from VS-mode, e.g. SBI calls of the guest
+    Hypercall,
+    /// Instruction not allowed in VS-mode, to be emulated
+    VirtualInstruction,
+    /// Supervisor timer interrupt
+    Timer,
+    /// Supervisor external interrupt
+    ExternalInterrupt,
+    /// Other interrupts, with the cause
+    Interrupt(usize),
+}
+
+/// Information of a VM exit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VmExit {
+    pub reason: VmExitReason,
+    /// Whether the guest was in VS-mode, otherwise in VU-mode
+    pub from_supervisor: bool,
+    /// Transformed trapping instruction, 0 if not provided
+    pub instruction: usize,
+}
+
+impl GuestContext {
+    /// Create a context to start the guest at `entry` in VS-mode.
+    pub fn new(entry: usize) -> Self {
+        GuestContext {
+            sepc: entry,
+            sstatus: SPP,
+            ..Default::default()
+        }
+    }
+
+    /// Enter the guest by `sret`, and come back on VM exit.
+    ///
+    /// `hstatus.SPV` is set to enter the guest, in VS-mode if `sstatus.SPP`
+    /// is set, otherwise in VU-mode, both as saved on the last exit.
+    /// `vsstatus` is swapped, and other CSRs of the host are left as on
+    /// exit.
+    ///
+    /// # Safety
+    ///
+    /// The hypervisor extension must be present, and `hgatp` must be set
+    /// up for the guest. Interrupts must be disabled.
+    pub unsafe fn run(&mut self) -> VmExit {
+        let host_vsstatus: usize;
+        // vsstatus
+        asm!("csrrw {}, 0x200, {}", out(reg) host_vsstatus, in(reg) self.vsstatus);
+        self.hstatus |= SPV;
+        guest_entry(self);
+        asm!("csrrw {}, 0x200, {}", out(reg) self.vsstatus, in(reg) host_vsstatus);
+        VmExit {
+            reason: self.exit_reason(),
+            from_supervisor: self.sstatus & SPP != 0,
+            instruction: self.htinst,
+        }
+    }
+
+    /// Decode the reason of the last VM exit.
+    pub fn exit_reason(&self) -> VmExitReason {
+        const INTERRUPT: usize = 1 << (usize::BITS - 1);
+        let guest_page_fault = |flags: PageFaultFlags| VmExitReason::GuestPageFault {
+            guest_phys_addr: (self.htval << 2) | (self.stval & 3),
+            addr: self.stval,
+            flags,
+        };
+        let page_fault = |flags: PageFaultFlags| VmExitReason::PageFault {
+            addr: self.stval,
+            flags,
+        };
+        match self.scause {
+            c if c == INTERRUPT | 5 => VmExitReason::Timer,
+            c if c == INTERRUPT | 9 => VmExitReason::ExternalInterrupt,
+            c if c & INTERRUPT != 0 => VmExitReason::Interrupt(c & !INTERRUPT),
+            10 => VmExitReason::Hypercall,
+            12 => page_fault(PageFaultFlags::EXECUTE),
+            13 => page_fault(PageFaultFlags::empty()),
+            15 => page_fault(PageFaultFlags::WRITE),
+            20 => guest_page_fault(PageFaultFlags::EXECUTE),
+            21 => guest_page_fault(PageFaultFlags::empty()),
+            22 => VmExitReason::VirtualInstruction,
+            23 => guest_page_fault(PageFaultFlags::WRITE),
+            cause => VmExitReason::Exception(cause),
+        }
+    }
+
+    /// Skip the trapping instruction, e.g. after emulating a hypercall.
+    pub fn skip_instruction(&mut self) {
+        // compressed instructions do not trap as hypercalls
+        self.sepc += 4;
+    }
+}
@@ -0,0 +1,43 @@
+//! Inter-processor interrupts by SBI.
+//!
+//! An IPI is a supervisor software interrupt, reported as
+//! `TrapReason::Ipi(0)`. The payload is always 0, since the interrupt
+//! carries no data. It is pending until cleared by [`clear`].
+//!
+//! `sie.SSIE` should be set by the kernel to take the interrupt.
+
+use core::arch::asm;
+
+/// SBI IPI Extension, "sPI"
+const SBI_EXT_IPI: usize = 0x0073_5049;
+/// `sip.SSIP`
+const SSIP: usize = 1 << 1;
+
+/// Send an IPI to the harts in `hart_mask`, whose bit 0 is `hart_mask_base`.
+///
+/// Return the SBI error code, 0 on success.
+pub fn send(hart_mask: usize, hart_mask_base: usize) -> isize {
+    let error: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") hart_mask => error,
+            inlateout("a1") hart_mask_base => _,
+            in("a6") 0,
+            in("a7") SBI_EXT_IPI,
+        )
+    };
+    error
+}
+
+/// Send an IPI to each hart in `harts`.
+pub fn send_to(harts: impl IntoIterator<Item = usize>) {
+    for hart in harts {
+        send(1, hart);
+    }
+}
+
+/// Clear the pending IPI of the current hart.
+pub fn clear() {
+    unsafe { asm!("csrc sip, {}", in(reg) SSIP, options(nomem, nostack)) };
+}
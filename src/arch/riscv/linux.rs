@@ -0,0 +1,235 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::UserContext;
+use crate::signal::{read_at, write_at, SigInfo};
+use core::mem::size_of;
+
+/// `struct user_regs_struct` of `NT_PRSTATUS`, also `elf_gregset_t`.
+///
+/// `pc` takes the place of `zero`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub pc: usize,
+    pub ra: usize,
+    pub sp: usize,
+    pub gp: usize,
+    pub tp: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+}
+
+/// `struct sigcontext`, the `uc_mcontext` of `ucontext_t`, without the
+/// `sc_fpregs` union which follows.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub sc_regs: UserRegs,
+}
+
+/// `struct ucontext`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    /// `sigset_t` of 64 bits, and the rest of 1024 bits
+    sigmask: [u32; 2],
+    _unused: [u32; 30],
+    /// `uc_mcontext` is 16 bytes aligned
+    #[cfg(target_arch = "riscv32")]
+    _pad: [u32; 3],
+    #[cfg(target_arch = "riscv64")]
+    _pad: [u32; 2],
+    mcontext: SigContext,
+    /// `union __riscv_fp_state` as Q extension state, all 0 since FP
+    /// registers are not in the context
+    fpregs: [[u64; 32]; 2],
+    fcsr: u32,
+    _reserved: [u32; 3],
+}
+
+/// `struct rt_sigframe`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+
+impl UserContext {
+    /// Get registers in the layout of `user_regs_struct`.
+    pub fn get_user_regs(&self) -> UserRegs {
+        let g = &self.general;
+        UserRegs {
+            pc: self.sepc,
+            ra: g.ra,
+            sp: g.sp,
+            gp: g.gp,
+            tp: g.tp,
+            t0: g.t0,
+            t1: g.t1,
+            t2: g.t2,
+            s0: g.s0,
+            s1: g.s1,
+            a0: g.a0,
+            a1: g.a1,
+            a2: g.a2,
+            a3: g.a3,
+            a4: g.a4,
+            a5: g.a5,
+            a6: g.a6,
+            a7: g.a7,
+            s2: g.s2,
+            s3: g.s3,
+            s4: g.s4,
+            s5: g.s5,
+            s6: g.s6,
+            s7: g.s7,
+            s8: g.s8,
+            s9: g.s9,
+            s10: g.s10,
+            s11: g.s11,
+            t3: g.t3,
+            t4: g.t4,
+            t5: g.t5,
+            t6: g.t6,
+        }
+    }
+
+    /// Set registers from the layout of `user_regs_struct`.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        let g = &mut self.general;
+        self.sepc = regs.pc;
+        g.ra = regs.ra;
+        g.sp = regs.sp;
+        g.gp = regs.gp;
+        g.tp = regs.tp;
+        g.t0 = regs.t0;
+        g.t1 = regs.t1;
+        g.t2 = regs.t2;
+        g.s0 = regs.s0;
+        g.s1 = regs.s1;
+        g.a0 = regs.a0;
+        g.a1 = regs.a1;
+        g.a2 = regs.a2;
+        g.a3 = regs.a3;
+        g.a4 = regs.a4;
+        g.a5 = regs.a5;
+        g.a6 = regs.a6;
+        g.a7 = regs.a7;
+        g.s2 = regs.s2;
+        g.s3 = regs.s3;
+        g.s4 = regs.s4;
+        g.s5 = regs.s5;
+        g.s6 = regs.s6;
+        g.s7 = regs.s7;
+        g.s8 = regs.s8;
+        g.s9 = regs.s9;
+        g.s10 = regs.s10;
+        g.s11 = regs.s11;
+        g.t3 = regs.t3;
+        g.t4 = regs.t4;
+        g.t5 = regs.t5;
+        g.t6 = regs.t6;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    pub fn get_sigcontext(&self) -> SigContext {
+        SigContext {
+            sc_regs: self.get_user_regs(),
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `rt_sigreturn`.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        self.set_user_regs(&sc.sc_regs);
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `sp`. The frame
+    /// saves the registers and `mask` as the blocked signals. The handler
+    /// returns to `restorer`, which should call `rt_sigreturn`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.general.sp.checked_sub(stack.len())?;
+        let sp = self.general.sp.checked_sub(size_of::<RtSigFrame>())? & !15;
+        let frame = RtSigFrame {
+            info: *info,
+            uc: UContext {
+                sigmask: [mask as u32, (mask >> 32) as u32],
+                mcontext: self.get_sigcontext(),
+                ..Default::default()
+            },
+        };
+        write_at(stack, base, sp, &frame)?;
+
+        self.general.a0 = info.signo as usize;
+        self.general.a1 = sp;
+        self.general.a2 = sp + size_of::<SigInfo>();
+        self.general.ra = restorer;
+        self.general.sp = sp;
+        self.sepc = handler;
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.general.sp
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        self.set_sigcontext(&frame.uc.mcontext);
+        let [lo, hi] = frame.uc.sigmask;
+        Some((hi as u64) << 32 | lo as u64)
+    }
+}
@@ -1,3 +1,28 @@
+pub mod delegation;
+mod display;
+mod dwarf;
+mod elf;
+mod entry;
+#[cfg(feature = "gdbstub")]
+mod gdb;
+#[cfg(not(feature = "riscv_m_mode"))]
+mod guest;
+#[cfg(not(feature = "riscv_m_mode"))]
+pub mod ipi;
+mod layout;
+pub mod linux;
+#[cfg(feature = "riscv_plic")]
+pub mod plic;
+#[cfg(not(feature = "riscv_m_mode"))]
+pub mod timer;
 mod trap;
+#[cfg(feature = "alloc")]
+mod vector;
 
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+#[cfg(not(feature = "riscv_m_mode"))]
+pub use guest::{GuestContext, VmExit, VmExitReason};
+pub use layout::*;
 pub use trap::*;
+#[cfg(feature = "alloc")]
+pub use vector::{vlenb, VectorState};
@@ -0,0 +1,101 @@
+//! Platform-Level Interrupt Controller (PLIC) as the interrupt controller,
+//! see [`crate::intc`].
+//!
+//! The supervisor external interrupt is claimed from the PLIC on trap, and
+//! completed on return. It is reported as `TrapReason::External(irq)`, with
+//! the IRQ number in `stval`. A claim of 0 is spurious, and dropped.
+//!
+//! Before a controller is set by [`init`], the interrupt is not claimed,
+//! and reported as `TrapReason::Interrupt` with the cause number.
+
+use crate::intc::{self, InterruptController};
+
+/// Offset of priority registers, one for each source
+const PRIORITY: usize = 0;
+/// Offset of enable bits, 0x80 bytes for each context
+const ENABLE: usize = 0x2000;
+/// Offset of threshold and claim/complete registers, 0x1000 bytes for each context
+const CONTEXT: usize = 0x20_0000;
+
+/// A PLIC, accessed by MMIO.
+#[derive(Debug, Clone, Copy)]
+pub struct Plic {
+    base: usize,
+    context: fn() -> usize,
+}
+
+impl Plic {
+    /// Create with the virtual address of the registers, and a function to
+    /// get the PLIC context of supervisor mode on the current hart, which
+    /// is platform-specific.
+    ///
+    /// # Safety
+    ///
+    /// `base` must map the PLIC registers, uncached.
+    pub const unsafe fn new(base: usize, context: fn() -> usize) -> Self {
+        Plic { base, context }
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn claim_reg(&self) -> *mut u32 {
+        self.reg(CONTEXT + (self.context)() * 0x1000 + 4)
+    }
+
+    /// Set the priority of `irq`, 0 to disable it.
+    pub fn set_priority(&self, irq: usize, priority: u32) {
+        unsafe { self.reg(PRIORITY + irq * 4).write_volatile(priority) };
+    }
+
+    /// Set the priority threshold of the current hart.
+    pub fn set_threshold(&self, threshold: u32) {
+        let reg = self.reg(CONTEXT + (self.context)() * 0x1000);
+        unsafe { reg.write_volatile(threshold) };
+    }
+
+    /// Enable or disable `irq` on the current hart.
+    pub fn set_enabled(&self, irq: usize, enabled: bool) {
+        let reg = self.reg(ENABLE + (self.context)() * 0x80 + irq / 32 * 4);
+        let bit = 1 << (irq % 32);
+        unsafe {
+            let value = reg.read_volatile();
+            reg.write_volatile(if enabled { value | bit } else { value & !bit });
+        }
+    }
+}
+
+impl InterruptController for Plic {
+    fn claim(&self, _vector: usize) -> Option<usize> {
+        match unsafe { self.claim_reg().read_volatile() } {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    fn complete(&self, irq: usize) {
+        unsafe { self.claim_reg().write_volatile(irq as u32) };
+    }
+}
+
+/// The PLIC set by [`init`].
+static mut PLIC: Option<Plic> = None;
+
+/// Set the PLIC at `base` as the interrupt controller, and return it.
+///
+/// `sie.SEIE` should be set by the kernel to take the interrupt.
+///
+/// # Safety
+///
+/// See [`Plic::new`].
+///
+/// # Panics
+///
+/// Panics if called more than once.
+pub unsafe fn init(base: usize, context: fn() -> usize) -> &'static Plic {
+    assert!(PLIC.is_none(), "PLIC is already initialized");
+    let plic: &'static Plic = PLIC.insert(Plic::new(base, context));
+    intc::set_controller(plic);
+    plic
+}
@@ -0,0 +1,67 @@
+//! Supervisor timer by SBI.
+//!
+//! The supervisor timer interrupt is reported as `TrapReason::Timer`. It is
+//! pending until a new deadline is set, so [`set_deadline`] or [`clear`]
+//! also acknowledges it.
+
+use core::arch::asm;
+
+/// SBI Timer Extension, "TIME"
+const SBI_EXT_TIME: usize = 0x5449_4d45;
+
+/// Current time in ticks of the `time` CSR.
+pub fn now() -> u64 {
+    #[cfg(target_arch = "riscv64")]
+    {
+        let time: u64;
+        unsafe { asm!("rdtime {}", out(reg) time, options(nomem, nostack)) };
+        time
+    }
+    #[cfg(target_arch = "riscv32")]
+    loop {
+        let (hi, lo, hi2): (u32, u32, u32);
+        unsafe {
+            asm!(
+                "rdtimeh {0}", "rdtime {1}", "rdtimeh {2}",
+                out(reg) hi, out(reg) lo, out(reg) hi2,
+                options(nomem, nostack),
+            )
+        };
+        if hi == hi2 {
+            return (hi as u64) << 32 | lo as u64;
+        }
+    }
+}
+
+/// Fire the timer when `time` reaches `deadline`, and clear the pending
+/// timer interrupt.
+///
+/// `sie.STIE` should be set by the kernel to take the interrupt.
+pub fn set_deadline(deadline: u64) {
+    unsafe { sbi_set_timer(deadline) };
+}
+
+/// Disarm the timer, and clear the pending timer interrupt.
+pub fn clear() {
+    unsafe { sbi_set_timer(u64::MAX) };
+}
+
+/// `sbi_set_timer` of the SBI Timer Extension.
+unsafe fn sbi_set_timer(time: u64) {
+    #[cfg(target_arch = "riscv64")]
+    asm!(
+        "ecall",
+        inlateout("a0") time as usize => _,
+        lateout("a1") _,
+        in("a6") 0,
+        in("a7") SBI_EXT_TIME,
+    );
+    #[cfg(target_arch = "riscv32")]
+    asm!(
+        "ecall",
+        inlateout("a0") time as usize => _,
+        inlateout("a1") (time >> 32) as usize => _,
+        in("a6") 0,
+        in("a7") SBI_EXT_TIME,
+    );
+}
@@ -1,3 +1,6 @@
+use super::entry::{run_user, trap_entry, trap_vectors};
+use super::layout::*;
+use crate::{PageFaultFlags, PageFaultInfo, TrapInfo, TrapReason};
 use core::arch::{asm, global_asm};
 
 #[cfg(target_arch = "riscv32")]
@@ -25,33 +28,158 @@ global_asm!(
 "
 );
 
-global_asm!(include_str!("trap.S"));
+// `TrapFrame` shares the layout, and the kernel stack stays 16 bytes aligned
+const _: () = assert!(TRAP_FRAME_SSTATUS_OFFSET == USER_CONTEXT_SSTATUS_OFFSET);
+const _: () = assert!(core::mem::size_of::<TrapFrame>() % 16 == 0);
+// after the macros above
+#[cfg(not(feature = "riscv_m_mode"))]
+global_asm!(include_str!("guest.S"));
 
 /// Initialize interrupt handling for the current HART.
 ///
+/// Always return `Ok` on this architecture, see
+/// [`TrapInitError`](crate::TrapInitError).
+///
 /// # Safety
 ///
 /// This function will:
 /// - Set `sscratch` to 0.
-/// - Set `stvec` to internal exception vector.
+/// - Set `stvec` to internal exception vector, in direct mode.
+///   See [`init_vectored`] for vectored mode.
+///
+/// With feature `riscv_m_mode`, they are `mscratch` and `mtvec` instead.
 ///
 /// You **MUST NOT** modify these registers later.
-pub unsafe fn init() {
+///
+/// The traps must be delegated to S-mode, see [`delegation`](super::delegation).
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
     // Set sscratch register to 0, indicating to exception vector that we are
     // presently executing in the kernel
-    asm!("csrw sscratch, zero");
+    asm!(concat!("csrw ", xcsr!("scratch"), ", zero"));
     // Set the exception vector address
-    asm!("csrw stvec, {}", in(reg) trap_entry as usize);
+    asm!(concat!("csrw ", xcsr!("tvec"), ", {}"), in(reg) trap_entry as usize);
+    Ok(())
+}
+
+/// Initialize interrupt handling for the current HART like [`init`], with
+/// `stvec` in vectored mode.
+///
+/// Timer and software interrupts enter their own entries, which do not read
+/// `scause` and `stval`, and call `trap_handler` directly if trapped from
+/// kernel. Exceptions and other interrupts enter the same entry as in direct
+/// mode.
+///
+/// Return [`UnsupportedCpu`](crate::TrapInitError::UnsupportedCpu) and stay
+/// in direct mode if the HART does not support vectored mode.
+///
+/// # Safety
+///
+/// See [`init`].
+pub unsafe fn init_vectored() -> Result<(), crate::TrapInitError> {
+    /// `stvec.MODE` of vectored mode
+    const VECTORED: usize = 1;
+
+    asm!(concat!("csrw ", xcsr!("scratch"), ", zero"));
+    asm!(concat!("csrw ", xcsr!("tvec"), ", {}"), in(reg) trap_vectors as usize | VECTORED);
+    // MODE is WARL, unsupported modes do not stick
+    let stvec: usize;
+    asm!(concat!("csrr {}, ", xcsr!("tvec")), out(reg) stvec);
+    if stvec & 3 == VECTORED {
+        return Ok(());
+    }
+    asm!(concat!("csrw ", xcsr!("tvec"), ", {}"), in(reg) trap_entry as usize);
+    Err(crate::TrapInitError::UnsupportedCpu("vectored stvec"))
 }
 
 #[no_mangle]
 #[linkage = "weak"]
-extern "C" fn trap_handler(tf: &mut TrapFrame) {
+pub(super) extern "C" fn trap_handler(tf: &mut TrapFrame) {
     unimplemented!("TRAP: tf={:#x?}", tf);
 }
 
+pub(super) extern "C" fn trap_dispatch(tf: &mut TrapFrame) {
+    #[cfg(feature = "riscv_plic")]
+    if tf.scause == EXTERNAL && crate::intc::controller().is_some() {
+        crate::intc::handle(EXTERNAL & !INTERRUPT, |irq| {
+            tf.stval = irq;
+            #[cfg(feature = "trace")]
+            crate::trace::emit(crate::trace::TrapEvent::KernelIrqBegin(irq));
+            trap_handler(tf);
+            #[cfg(feature = "trace")]
+            crate::trace::emit(crate::trace::TrapEvent::KernelIrqEnd(irq));
+        });
+        return;
+    }
+    trap_handler(tf);
+}
+
+pub(super) const INTERRUPT: usize = 1 << (usize::BITS - 1);
+/// Supervisor external interrupt
+#[cfg(feature = "riscv_plic")]
+#[cfg(not(feature = "riscv_m_mode"))]
+const EXTERNAL: usize = INTERRUPT | 9;
+/// Machine external interrupt
+#[cfg(feature = "riscv_plic")]
+#[cfg(feature = "riscv_m_mode")]
+const EXTERNAL: usize = INTERRUPT | 11;
+
+/// Supervisor timer interrupt
+#[cfg(not(feature = "riscv_m_mode"))]
+pub(super) const TIMER: usize = INTERRUPT | 5;
+/// Machine timer interrupt
+#[cfg(feature = "riscv_m_mode")]
+pub(super) const TIMER: usize = INTERRUPT | 7;
+/// Supervisor software interrupt
+#[cfg(not(feature = "riscv_m_mode"))]
+pub(super) const SOFTWARE: usize = INTERRUPT | 1;
+/// Machine software interrupt
+#[cfg(feature = "riscv_m_mode")]
+pub(super) const SOFTWARE: usize = INTERRUPT | 3;
+
+/// Decode `scause` and `stval`.
+fn decode(scause: usize, stval: usize, user: bool) -> TrapReason {
+    if scause == TIMER {
+        return TrapReason::Timer;
+    }
+    if scause == SOFTWARE {
+        return TrapReason::Ipi(0);
+    }
+    // claimed IRQ in `stval`, 0 if not claimed without a controller
+    #[cfg(feature = "riscv_plic")]
+    if scause == EXTERNAL && stval != 0 {
+        return TrapReason::External(stval);
+    }
+    if scause & INTERRUPT != 0 {
+        return TrapReason::Interrupt(scause & !INTERRUPT);
+    }
+    let page_fault = |mut flags: PageFaultFlags| {
+        flags.set(PageFaultFlags::USER, user);
+        TrapReason::PageFault { addr: stval, flags }
+    };
+    match scause {
+        0 | 4 | 6 => TrapReason::Misaligned,
+        2 => TrapReason::IllegalInstruction,
+        3 => TrapReason::Breakpoint,
+        8 => TrapReason::Syscall,
+        // ecall from S-mode, to the firmware
+        #[cfg(feature = "riscv_m_mode")]
+        9 => TrapReason::Syscall,
+        // access fault
+        1 => page_fault(PageFaultFlags::EXECUTE | PageFaultFlags::PRESENT),
+        5 => page_fault(PageFaultFlags::PRESENT),
+        7 => page_fault(PageFaultFlags::WRITE | PageFaultFlags::PRESENT),
+        // page fault
+        12 => page_fault(PageFaultFlags::EXECUTE),
+        13 => page_fault(PageFaultFlags::empty()),
+        15 => page_fault(PageFaultFlags::WRITE),
+        _ => TrapReason::Unknown(scause),
+    }
+}
+
 /// Trap frame of kernel interrupt
 ///
+/// With feature `riscv_m_mode`, the `s` fields hold the `m` CSRs.
+///
 /// # Trap handler
 ///
 /// You need to define a handler function like this:
@@ -64,7 +192,9 @@ extern "C" fn trap_handler(tf: &mut TrapFrame) {
 ///     println!("TRAP! tf: {:#x?}", tf);
 /// }
 /// ```
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct TrapFrame {
     /// General registers
@@ -73,10 +203,36 @@ pub struct TrapFrame {
     pub sstatus: usize,
     /// Supervisor Exception Program Counter
     pub sepc: usize,
+    /// Supervisor Cause, saved on trap
+    pub scause: usize,
+    /// Supervisor Trap Value, saved on trap
+    pub stval: usize,
+}
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        // sstatus.SPP = 0: from user
+        #[cfg(not(feature = "riscv_m_mode"))]
+        let user = self.sstatus & (1 << 8) == 0;
+        // mstatus.MPP != 3: from S-mode or user
+        #[cfg(feature = "riscv_m_mode")]
+        let user = self.sstatus & (3 << 11) != 3 << 11;
+        match decode(self.scause, self.stval, user) {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
 }
 
 /// Saved registers on a trap.
-#[derive(Debug, Default, Clone, Copy)]
+///
+/// With feature `riscv_m_mode`, the `s` fields hold the `m` CSRs, e.g.
+/// `sstatus` holds `mstatus`, and `run()` goes by `mret` to the mode in
+/// `mstatus.MPP`, user by default.
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct UserContext {
     /// General registers
@@ -85,13 +241,30 @@ pub struct UserContext {
     pub sstatus: usize,
     /// Supervisor Exception Program Counter
     pub sepc: usize,
+    /// Supervisor Cause, saved on trap
+    pub scause: usize,
+    /// Supervisor Trap Value, saved on trap
+    pub stval: usize,
+    /// Keep `user_cycles` 8 bytes aligned. Public only for struct literals
+    /// with `..Default::default()`.
+    #[cfg(target_arch = "riscv32")]
+    #[doc(hidden)]
+    pub _pad_cycles: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
 }
 
 impl UserContext {
     /// Go to user space with the context, and come back when a trap occurs.
     ///
     /// On return, the context will be reset to the status before the trap.
-    /// Trap reason and error code will be returned.
+    /// Trap reason will be placed at `scause` and `stval`.
+    ///
+    /// With feature `riscv_plic`, an external interrupt is claimed and
+    /// completed before returning, with the IRQ number in `stval`, and a
+    /// spurious one goes back to user.
     ///
     /// # Example
     /// ```no_run
@@ -112,12 +285,73 @@ impl UserContext {
     /// println!("back from user: {:#x?}", context);
     /// ```
     pub fn run(&mut self) {
-        unsafe { run_user(self) }
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        unsafe { run_user(self) };
+        #[cfg(feature = "riscv_plic")]
+        while self.scause == EXTERNAL && crate::intc::controller().is_some() {
+            let mut claimed = None;
+            crate::intc::handle(EXTERNAL & !INTERRUPT, |irq| claimed = Some(irq));
+            match claimed {
+                Some(irq) => {
+                    self.stval = irq;
+                    break;
+                }
+                None => unsafe { run_user(self) },
+            }
+        }
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
+
+/// Read the 64-bit `cycle` counter.
+#[cfg(target_arch = "riscv64")]
+pub(crate) fn read_cycles() -> u64 {
+    let cycle: u64;
+    unsafe { asm!("rdcycle {}", out(reg) cycle) };
+    cycle
+}
+
+/// Read the 64-bit `cycle` counter.
+///
+/// On RV32 the counter is split into `cycle` and `cycleh`, so re-read if
+/// the low half overflows between the reads.
+#[cfg(target_arch = "riscv32")]
+pub(crate) fn read_cycles() -> u64 {
+    loop {
+        let (hi, lo, hi2): (u32, u32, u32);
+        unsafe {
+            asm!(
+                "rdcycleh {0}",
+                "rdcycle {1}",
+                "rdcycleh {2}",
+                out(reg) hi,
+                out(reg) lo,
+                out(reg) hi2,
+            )
+        };
+        if hi == hi2 {
+            return (hi as u64) << 32 | lo as u64;
+        }
     }
 }
 
 /// General registers
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct GeneralRegs {
     pub zero: usize,
@@ -154,7 +388,14 @@ pub struct GeneralRegs {
     pub t6: usize,
 }
 
+impl_bytemuck!(GeneralRegs, UserContext, TrapFrame);
+
 impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        decode(self.scause, self.stval, true)
+    }
+
     /// Get number of syscall
     pub fn get_syscall_num(&self) -> usize {
         self.general.a7
@@ -187,6 +428,11 @@ impl UserContext {
         self.sepc = ip;
     }
 
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.sepc
+    }
+
     /// Set stack pointer
     pub fn set_sp(&mut self, sp: usize) {
         self.general.sp = sp;
@@ -201,10 +447,31 @@ impl UserContext {
     pub fn set_tls(&mut self, tls: usize) {
         self.general.tp = tls;
     }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.general.tp
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs {
+            tp: self.general.tp,
+        }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.general.tp = regs.tp;
+    }
 }
 
-#[allow(improper_ctypes)]
-extern "C" {
-    fn trap_entry();
-    fn run_user(regs: &mut UserContext);
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// `tp`, the thread pointer
+    pub tp: usize,
 }
@@ -0,0 +1,188 @@
+//! Vector extension (V) state.
+//!
+//! The state is not part of `UserContext`, as its size depends on the CPU.
+//! Kernels enabling V for user programs keep a [`VectorState`] for each of
+//! them, and run them by [`UserContext::run_with_vector`], which only
+//! touches the vector unit when `sstatus.VS` says it is in use.
+//!
+//! A default state is empty, and is sized by `vlenb` on first use, with
+//! all registers cleared.
+//!
+//! Instructions are emitted as raw words, so that the crate does not require
+//! an assembler with V support.
+
+use super::UserContext;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+/// `sstatus.VS` field
+const SSTATUS_VS: usize = 3 << 9;
+const SSTATUS_VS_OFF: usize = 0;
+const SSTATUS_VS_INITIAL: usize = 1 << 9;
+const SSTATUS_VS_CLEAN: usize = 2 << 9;
+const SSTATUS_VS_DIRTY: usize = 3 << 9;
+
+/// Vector registers and CSRs.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct VectorState {
+    /// Vector registers v0-v31, `32 * vlenb` bytes
+    pub regs: Vec<u8>,
+    pub vstart: usize,
+    pub vcsr: usize,
+    pub vl: usize,
+    pub vtype: usize,
+}
+
+/// Get the length of a vector register in bytes.
+///
+/// The vector unit must be enabled in `sstatus`.
+pub fn vlenb() -> usize {
+    let vlenb: usize;
+    unsafe { asm!("csrr {}, 0xc22", out(reg) vlenb) };
+    vlenb
+}
+
+impl VectorState {
+    /// Create a state with all registers cleared.
+    ///
+    /// The vector unit must be enabled in `sstatus` to probe `vlenb`.
+    pub fn new() -> Self {
+        VectorState {
+            regs: vec![0; 32 * vlenb()],
+            ..Default::default()
+        }
+    }
+
+    /// Size `regs` for the vector length of the CPU, clearing it if it was
+    /// of another length.
+    ///
+    /// The vector unit must be enabled in `sstatus`.
+    fn fit(&mut self) {
+        let len = 32 * vlenb();
+        if self.regs.len() != len {
+            self.regs.clear();
+            self.regs.resize(len, 0);
+        }
+    }
+
+    /// Save the vector unit into `self`.
+    ///
+    /// # Safety
+    ///
+    /// The vector unit must be enabled in `sstatus`.
+    pub unsafe fn save(&mut self) {
+        self.fit();
+        asm!("csrr {}, 0x008", out(reg) self.vstart);
+        asm!("csrr {}, 0x00f", out(reg) self.vcsr);
+        asm!("csrr {}, 0xc20", out(reg) self.vl);
+        asm!("csrr {}, 0xc21", out(reg) self.vtype);
+        asm!(
+            "csrw 0x008, zero",
+            "csrr {step}, 0xc22",
+            "slli {step}, {step}, 3",
+            ".4byte 0xe2850027", // vs8r.v v0, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850427", // vs8r.v v8, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850827", // vs8r.v v16, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850c27", // vs8r.v v24, (a0)
+            step = out(reg) _,
+            inout("a0") self.regs.as_mut_ptr() => _,
+            options(nostack),
+        );
+    }
+
+    /// Load the vector unit from `self`.
+    ///
+    /// # Safety
+    ///
+    /// The vector unit must be enabled in `sstatus`.
+    pub unsafe fn restore(&mut self) {
+        self.fit();
+        asm!(
+            "csrr {step}, 0xc22",
+            "slli {step}, {step}, 3",
+            ".4byte 0xe2850007", // vl8re8.v v0, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850407", // vl8re8.v v8, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850807", // vl8re8.v v16, (a0)
+            "add a0, a0, {step}",
+            ".4byte 0xe2850c07", // vl8re8.v v24, (a0)
+            step = out(reg) _,
+            inout("a0") self.regs.as_ptr() => _,
+            options(nostack),
+        );
+        asm!(
+            ".4byte 0x8062f057", // vsetvl zero, t0, t1
+            in("t0") self.vl,
+            in("t1") self.vtype,
+            options(nostack),
+        );
+        asm!("csrw 0x00f, {}", in(reg) self.vcsr);
+        asm!("csrw 0x008, {}", in(reg) self.vstart);
+    }
+
+    /// Save the vector unit if the user has modified it, then mark it clean.
+    ///
+    /// Called after running user by [`UserContext::run_with_vector`].
+    pub fn save_lazy(&mut self, context: &mut UserContext) {
+        if context.sstatus & SSTATUS_VS != SSTATUS_VS_DIRTY {
+            return;
+        }
+        unsafe {
+            let sstatus = enable();
+            self.save();
+            restore_sstatus(sstatus);
+        }
+        context.sstatus = (context.sstatus & !SSTATUS_VS) | SSTATUS_VS_CLEAN;
+    }
+
+    /// Load the vector unit if the user has it enabled.
+    ///
+    /// Called before running user by [`UserContext::run_with_vector`].
+    pub fn restore_lazy(&mut self, context: &UserContext) {
+        if !context.vector_enabled() {
+            return;
+        }
+        unsafe {
+            let sstatus = enable();
+            self.restore();
+            restore_sstatus(sstatus);
+        }
+    }
+}
+
+impl UserContext {
+    /// Go to user space like [`run`](Self::run), and switch the vector state
+    /// with `vector` if the user has the vector unit enabled.
+    pub fn run_with_vector(&mut self, vector: &mut VectorState) {
+        vector.restore_lazy(self);
+        self.run();
+        vector.save_lazy(self);
+    }
+
+    /// Enable the vector unit for user, in the initial state.
+    pub fn enable_vector(&mut self) {
+        self.sstatus = (self.sstatus & !SSTATUS_VS) | SSTATUS_VS_INITIAL;
+    }
+
+    /// Whether the vector unit is enabled for user.
+    pub fn vector_enabled(&self) -> bool {
+        self.sstatus & SSTATUS_VS != SSTATUS_VS_OFF
+    }
+}
+
+/// Enable the vector unit in kernel, return the previous `sstatus`.
+unsafe fn enable() -> usize {
+    let sstatus: usize;
+    asm!("csrrs {}, sstatus, {}", out(reg) sstatus, in(reg) SSTATUS_VS_DIRTY);
+    sstatus
+}
+
+unsafe fn restore_sstatus(sstatus: usize) {
+    asm!("csrc sstatus, {}", in(reg) SSTATUS_VS);
+    asm!("csrs sstatus, {}", in(reg) sstatus & SSTATUS_VS);
+}
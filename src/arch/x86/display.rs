@@ -0,0 +1,117 @@
+//! Display registers in crash logs.
+
+use super::{GeneralRegs, TrapFrame, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext, TrapFrame);
+
+/// Flags in `EFLAGS`
+const EFLAGS_BITS: &[(usize, &str)] = &[
+    (1 << 0, "CF"),
+    (1 << 2, "PF"),
+    (1 << 4, "AF"),
+    (1 << 6, "ZF"),
+    (1 << 7, "SF"),
+    (1 << 8, "TF"),
+    (1 << 9, "IF"),
+    (1 << 10, "DF"),
+    (1 << 11, "OF"),
+    (1 << 14, "NT"),
+    (1 << 16, "RF"),
+    (1 << 17, "VM"),
+    (1 << 18, "AC"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 7] {
+        [
+            ("eax", self.eax),
+            ("ebx", self.ebx),
+            ("ecx", self.ecx),
+            ("edx", self.edx),
+            ("esi", self.esi),
+            ("edi", self.edi),
+            ("ebp", self.ebp),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())
+    }
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("eip", self.eip),
+            ("esp", self.esp),
+            ("eflags", self.eflags),
+            ("cs", self.cs),
+            ("ss", self.ss),
+            ("ds", self.ds),
+            ("es", self.es),
+            ("fs", self.fs),
+            ("gs", self.gs),
+            ("trap_num", self.trap_num),
+            ("error_code", self.error_code),
+            ("cr2", self.cr2),
+            ("tls", self.tls),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.general)?;
+        write_regs(
+            f,
+            &[
+                ("eip", self.eip),
+                ("esp", self.esp),
+                ("cs", self.cs),
+                ("ss", self.ss),
+                ("ds", self.ds),
+                ("es", self.es),
+                ("fs", self.fs),
+                ("gs", self.gs),
+                ("trap_num", self.trap_num),
+                ("error_code", self.error_code),
+                ("cr2", self.cr2),
+                ("tls", self.tls),
+            ],
+        )?;
+        write_flags(f, "eflags", self.eflags, EFLAGS_BITS)?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(
+            f,
+            &[
+                ("eax", self.eax),
+                ("ebx", self.ebx),
+                ("ecx", self.ecx),
+                ("edx", self.edx),
+                ("esi", self.esi),
+                ("edi", self.edi),
+                ("ebp", self.ebp),
+                ("eip", self.eip),
+                ("cs", self.cs),
+                ("ds", self.ds),
+                ("es", self.es),
+                ("fs", self.fs),
+                ("gs", self.gs),
+                ("trap_num", self.trap_num),
+                ("error_code", self.error_code),
+            ],
+        )?;
+        write_flags(f, "eflags", self.eflags, EFLAGS_BITS)
+    }
+}
@@ -0,0 +1,51 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    unsafe {
+        let g = addr_of!((*cx).general);
+        Some(match index.0 {
+            0 => addr_of!((*g).eax),
+            1 => addr_of!((*g).ecx),
+            2 => addr_of!((*g).edx),
+            3 => addr_of!((*g).ebx),
+            4 => addr_of!((*cx).esp),
+            5 => addr_of!((*g).ebp),
+            6 => addr_of!((*g).esi),
+            7 => addr_of!((*g).edi),
+            8 => addr_of!((*cx).eip),
+            9 => addr_of!((*cx).eflags),
+            40 => addr_of!((*cx).es),
+            41 => addr_of!((*cx).cs),
+            42 => addr_of!((*cx).ss),
+            43 => addr_of!((*cx).ds),
+            44 => addr_of!((*cx).fs),
+            45 => addr_of!((*cx).gs),
+            _ => return None,
+        })
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            None => false,
+        }
+    }
+}
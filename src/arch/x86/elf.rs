@@ -0,0 +1,5 @@
+//! ELF header fields of core files.
+
+/// `EM_386`
+pub(crate) const ELF_MACHINE: u16 = 3;
+pub(crate) const ELF_FLAGS: u32 = 0;
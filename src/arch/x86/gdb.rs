@@ -0,0 +1,91 @@
+//! Conversion between context types and GDB register layouts of `gdbstub_arch`.
+
+use super::{TrapFrame, UserContext};
+use gdbstub_arch::x86::reg::X86CoreRegs;
+
+impl UserContext {
+    /// Get registers in the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// Floating-point registers are not part of the context and are
+    /// reported as 0.
+    pub fn get_gdb_regs(&self) -> X86CoreRegs {
+        let mut regs = X86CoreRegs {
+            eax: self.general.eax as u32,
+            ecx: self.general.ecx as u32,
+            edx: self.general.edx as u32,
+            ebx: self.general.ebx as u32,
+            esp: self.esp as u32,
+            ebp: self.general.ebp as u32,
+            esi: self.general.esi as u32,
+            edi: self.general.edi as u32,
+            eip: self.eip as u32,
+            eflags: self.eflags as u32,
+            ..Default::default()
+        };
+        regs.segments.cs = self.cs as u32;
+        regs.segments.ss = self.ss as u32;
+        regs.segments.ds = self.ds as u32;
+        regs.segments.es = self.es as u32;
+        regs.segments.fs = self.fs as u32;
+        regs.segments.gs = self.gs as u32;
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// Segment selectors are ignored, since they are reset in `run()`.
+    pub fn set_gdb_regs(&mut self, regs: &X86CoreRegs) {
+        self.general.eax = regs.eax as usize;
+        self.general.ecx = regs.ecx as usize;
+        self.general.edx = regs.edx as usize;
+        self.general.ebx = regs.ebx as usize;
+        self.esp = regs.esp as usize;
+        self.general.ebp = regs.ebp as usize;
+        self.general.esi = regs.esi as usize;
+        self.general.edi = regs.edi as usize;
+        self.eip = regs.eip as usize;
+        self.eflags = regs.eflags as usize;
+    }
+}
+
+impl TrapFrame {
+    /// Get registers in the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// The CPU does not push `esp` and `ss` on a trap from kernel, so they
+    /// are reported as 0.
+    pub fn get_gdb_regs(&self) -> X86CoreRegs {
+        let mut regs = X86CoreRegs {
+            eax: self.eax as u32,
+            ecx: self.ecx as u32,
+            edx: self.edx as u32,
+            ebx: self.ebx as u32,
+            ebp: self.ebp as u32,
+            esi: self.esi as u32,
+            edi: self.edi as u32,
+            eip: self.eip as u32,
+            eflags: self.eflags as u32,
+            ..Default::default()
+        };
+        regs.segments.cs = self.cs as u32;
+        regs.segments.ds = self.ds as u32;
+        regs.segments.es = self.es as u32;
+        regs.segments.fs = self.fs as u32;
+        regs.segments.gs = self.gs as u32;
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// `esp` and segment selectors are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &X86CoreRegs) {
+        self.eax = regs.eax as usize;
+        self.ecx = regs.ecx as usize;
+        self.edx = regs.edx as usize;
+        self.ebx = regs.ebx as usize;
+        self.ebp = regs.ebp as usize;
+        self.esi = regs.esi as usize;
+        self.edi = regs.edi as usize;
+        self.eip = regs.eip as usize;
+        self.eflags = regs.eflags as usize;
+    }
+}
@@ -0,0 +1,141 @@
+//! Configure Global Descriptor Table (GDT) and Task State Segment (TSS)
+//!
+//! Each CPU has its own GDT and TSS, taken from static arrays of
+//! [`MAX_CPUS`](crate::MAX_CPUS) entries. The kernel runs with `fs` loaded
+//! with a per-CPU segment based at the TSS of the CPU, so that `trap.S` finds
+//! the kernel stack of `run_user` in `fs:[12]` (`TSS.esp1`).
+
+use crate::TrapInitError;
+use core::arch::asm;
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Kernel code segment selector.
+pub const KCODE_SELECTOR: u16 = 0x08;
+/// Kernel data segment selector.
+/// NOTICE: hard coded in `trap.S`
+pub const KDATA_SELECTOR: u16 = 0x10;
+/// User code segment selector.
+pub const UCODE_SELECTOR: u16 = 0x1b;
+/// User data segment selector.
+pub const UDATA_SELECTOR: u16 = 0x23;
+/// User TLS segment selector, loaded to `gs` when going to user.
+pub const UTLS_SELECTOR: u16 = 0x2b;
+/// TSS selector.
+const TSS_SELECTOR: u16 = 0x30;
+/// Per-CPU segment selector, loaded to `fs` in kernel.
+/// NOTICE: hard coded in `trap.S`
+const PERCPU_SELECTOR: u16 = 0x38;
+
+const UTLS_INDEX: usize = 5;
+const TSS_INDEX: usize = 6;
+const PERCPU_INDEX: usize = 7;
+const GDT_LEN: usize = 8;
+
+const KCODE32: u64 = 0x00cf9a00_0000ffff; // EXECUTABLE | USER_SEGMENT | PRESENT | 32-bit | 4K
+const KDATA32: u64 = 0x00cf9200_0000ffff; // DATA_WRITABLE | USER_SEGMENT | PRESENT | 32-bit | 4K
+const UCODE32: u64 = 0x00cffa00_0000ffff; // EXECUTABLE | USER_SEGMENT | USER_MODE | PRESENT | 32-bit | 4K
+const UDATA32: u64 = 0x00cff200_0000ffff; // DATA_WRITABLE | USER_SEGMENT | USER_MODE | PRESENT | 32-bit | 4K
+const TSS32: u64 = 0x00008900_00000000; // AVAILABLE_TSS | PRESENT
+const PERCPU32: u64 = 0x00409200_00000000; // DATA_WRITABLE | USER_SEGMENT | PRESENT | 32-bit | byte
+
+/// 32-bit Task State Segment.
+///
+/// Only `esp0` and `ss0` are used for switching stack from ring 3 to ring 0.
+/// `esp1` keeps the kernel stack of `run_user`, as ring 1 is not used.
+#[repr(C)]
+struct TaskStateSegment {
+    prev_task_link: u32,
+    esp0: u32,
+    ss0: u32,
+    esp1: u32,
+    _reserved: [u32; 21],
+    _trap: u16,
+    iomap_base: u16,
+}
+
+const EMPTY_GDT: [u64; GDT_LEN] = [0, KCODE32, KDATA32, UCODE32, UDATA32, UDATA32, 0, 0];
+
+const EMPTY_TSS: TaskStateSegment = TaskStateSegment {
+    prev_task_link: 0,
+    esp0: 0,
+    ss0: KDATA_SELECTOR as u32,
+    esp1: 0,
+    _reserved: [0; 21],
+    _trap: 0,
+    // no I/O permission bitmap
+    iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+static mut GDTS: [[u64; GDT_LEN]; crate::MAX_CPUS] = [EMPTY_GDT; crate::MAX_CPUS];
+static mut TSSES: [TaskStateSegment; crate::MAX_CPUS] = [EMPTY_TSS; crate::MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C, packed)]
+pub(super) struct DescriptorTablePointer {
+    pub limit: u16,
+    pub base: u32,
+}
+
+/// Set the base address of a segment descriptor.
+fn set_base(descriptor: u64, base: usize) -> u64 {
+    let base = base as u64;
+    (descriptor & !0xff00_00ff_ffff_0000) | (base & 0xff_ffff) << 16 | (base >> 24) << 56
+}
+
+/// Take the slot of GDT and TSS for the current CPU, without changing it
+/// if all [`MAX_CPUS`](crate::MAX_CPUS) are taken.
+pub fn take_slot() -> Result<usize, TrapInitError> {
+    CPU_COUNT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            (n < crate::MAX_CPUS).then(|| n + 1)
+        })
+        .map_err(|_| TrapInitError::TooManyCpus)
+}
+
+/// Init TSS & GDT of the current CPU in slot `id`, reload segment registers.
+pub unsafe fn init(id: usize) {
+    let tss = addr_of!(TSSES[id]) as usize;
+    let gdt = &mut GDTS[id];
+    gdt[TSS_INDEX] = set_base(TSS32 | (size_of::<TaskStateSegment>() as u64 - 1), tss);
+    gdt[PERCPU_INDEX] = set_base(PERCPU32 | (size_of::<TaskStateSegment>() as u64 - 1), tss);
+    let gdtp = DescriptorTablePointer {
+        limit: size_of::<[u64; GDT_LEN]>() as u16 - 1,
+        base: gdt.as_ptr() as u32,
+    };
+    asm!("lgdt [{}]", in(reg) &gdtp, options(readonly, nostack));
+    // reload cs by a far return, then other segments
+    asm!(
+        "push {cs}",
+        "lea {tmp}, [2f]",
+        "push {tmp}",
+        "retf",
+        "2:",
+        "mov ds, {ds:x}",
+        "mov es, {ds:x}",
+        "mov gs, {ds:x}",
+        "mov ss, {ds:x}",
+        "mov fs, {fs:x}",
+        cs = in(reg) KCODE_SELECTOR as u32,
+        ds = in(reg) KDATA_SELECTOR as u32,
+        fs = in(reg) PERCPU_SELECTOR as u32,
+        tmp = out(reg) _,
+    );
+    asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nostack));
+}
+
+/// Set the stack for trap from user, in the TSS of the current CPU.
+pub(super) unsafe fn set_kernel_stack(esp0: usize) {
+    // TSS.esp0
+    asm!("mov fs:[4], {}", in(reg) esp0, options(nostack, preserves_flags));
+}
+
+/// Set the base address of the user TLS segment, in the GDT of the current
+/// CPU.
+pub(super) unsafe fn set_user_tls(base: usize) {
+    let mut gdtp = DescriptorTablePointer { limit: 0, base: 0 };
+    asm!("sgdt [{}]", in(reg) &mut gdtp, options(nostack, preserves_flags));
+    let gdt = gdtp.base as *mut u64;
+    *gdt.add(UTLS_INDEX) = set_base(UDATA32, base);
+}
@@ -0,0 +1,70 @@
+use super::gdt::{DescriptorTablePointer, KCODE_SELECTOR};
+use core::arch::asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// 32-bit interrupt gate.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Entry {
+    offset_low: u16,
+    selector: u16,
+    _reserved: u8,
+    flags: u8,
+    offset_high: u16,
+}
+
+const MISSING: Entry = Entry {
+    offset_low: 0,
+    selector: 0,
+    _reserved: 0,
+    flags: 0,
+    offset_high: 0,
+};
+
+/// PRESENT | INTERRUPT_GATE_32
+const GATE_KERNEL: u8 = 0x8e;
+/// PRESENT | DPL3 | INTERRUPT_GATE_32
+const GATE_USER: u8 = 0xee;
+
+static mut IDT: [Entry; 256] = [MISSING; 256];
+
+/// 0 for empty, 1 for being filled, 2 for filled
+static IDT_STATE: AtomicU8 = AtomicU8::new(0);
+
+/// Fill the IDT shared by all CPUs on the first call, and load it.
+pub unsafe fn init() {
+    extern "C" {
+        #[link_name = "__vectors"]
+        static VECTORS: [extern "C" fn(); 256];
+    }
+
+    if IDT_STATE
+        .compare_exchange(0, 1, Ordering::Acquire, Ordering::Acquire)
+        .is_ok()
+    {
+        for (i, entry) in IDT.iter_mut().enumerate() {
+            let vector = VECTORS[i] as usize;
+            *entry = Entry {
+                offset_low: vector as u16,
+                selector: KCODE_SELECTOR,
+                _reserved: 0,
+                // Enable user space `int3`, `into` and `int 0x80`
+                flags: match i {
+                    3 | 4 | 0x80 => GATE_USER,
+                    _ => GATE_KERNEL,
+                },
+                offset_high: (vector >> 16) as u16,
+            };
+        }
+        IDT_STATE.store(2, Ordering::Release);
+    }
+    while IDT_STATE.load(Ordering::Acquire) != 2 {
+        core::hint::spin_loop();
+    }
+    let idtp = DescriptorTablePointer {
+        limit: size_of::<[Entry; 256]>() as u16 - 1,
+        base: IDT.as_ptr() as u32,
+    };
+    asm!("lidt [{}]", in(reg) &idtp, options(readonly, nostack));
+}
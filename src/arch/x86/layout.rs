@@ -0,0 +1,50 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+use super::{TrapFrame, UserContext};
+
+layout!(UserContext {
+    USER_CONTEXT_EAX_OFFSET = 0 => general.eax,
+    USER_CONTEXT_EBX_OFFSET = 1 => general.ebx,
+    USER_CONTEXT_ECX_OFFSET = 2 => general.ecx,
+    USER_CONTEXT_EDX_OFFSET = 3 => general.edx,
+    USER_CONTEXT_ESI_OFFSET = 4 => general.esi,
+    USER_CONTEXT_EDI_OFFSET = 5 => general.edi,
+    USER_CONTEXT_EBP_OFFSET = 6 => general.ebp,
+    USER_CONTEXT_GS_OFFSET = 7 => gs,
+    USER_CONTEXT_FS_OFFSET = 8 => fs,
+    USER_CONTEXT_ES_OFFSET = 9 => es,
+    USER_CONTEXT_DS_OFFSET = 10 => ds,
+    USER_CONTEXT_TRAP_NUM_OFFSET = 11 => trap_num,
+    USER_CONTEXT_ERROR_CODE_OFFSET = 12 => error_code,
+    USER_CONTEXT_EIP_OFFSET = 13 => eip,
+    USER_CONTEXT_CS_OFFSET = 14 => cs,
+    USER_CONTEXT_EFLAGS_OFFSET = 15 => eflags,
+    USER_CONTEXT_ESP_OFFSET = 16 => esp,
+    USER_CONTEXT_SS_OFFSET = 17 => ss,
+    USER_CONTEXT_CR2_OFFSET = 18 => cr2,
+    USER_CONTEXT_TLS_OFFSET = 19 => tls,
+});
+
+layout!(TrapFrame {
+    TRAP_FRAME_EAX_OFFSET = 0 => eax,
+    TRAP_FRAME_EBX_OFFSET = 1 => ebx,
+    TRAP_FRAME_ECX_OFFSET = 2 => ecx,
+    TRAP_FRAME_EDX_OFFSET = 3 => edx,
+    TRAP_FRAME_ESI_OFFSET = 4 => esi,
+    TRAP_FRAME_EDI_OFFSET = 5 => edi,
+    TRAP_FRAME_EBP_OFFSET = 6 => ebp,
+    TRAP_FRAME_GS_OFFSET = 7 => gs,
+    TRAP_FRAME_FS_OFFSET = 8 => fs,
+    TRAP_FRAME_ES_OFFSET = 9 => es,
+    TRAP_FRAME_DS_OFFSET = 10 => ds,
+    TRAP_FRAME_TRAP_NUM_OFFSET = 11 => trap_num,
+    TRAP_FRAME_ERROR_CODE_OFFSET = 12 => error_code,
+    TRAP_FRAME_EIP_OFFSET = 13 => eip,
+    TRAP_FRAME_CS_OFFSET = 14 => cs,
+    TRAP_FRAME_EFLAGS_OFFSET = 15 => eflags,
+});
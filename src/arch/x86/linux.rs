@@ -0,0 +1,265 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::UserContext;
+use crate::signal::{read_at, write_at, SigInfo};
+use core::mem::size_of;
+
+/// Flags in `EFLAGS` that user can change by `ptrace` and `sigreturn`:
+/// CF, PF, AF, ZF, SF, TF, DF, OF, RF, AC
+const EFLAGS_USER_MASK: usize = 0x50dd5;
+
+/// `struct user_regs_struct` of `PTRACE_GETREGS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub ebx: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub ebp: usize,
+    pub eax: usize,
+    pub xds: usize,
+    pub xes: usize,
+    pub xfs: usize,
+    pub xgs: usize,
+    pub orig_eax: usize,
+    pub eip: usize,
+    pub xcs: usize,
+    pub eflags: usize,
+    pub esp: usize,
+    pub xss: usize,
+}
+
+/// `struct sigcontext`, the `uc_mcontext` of `ucontext_t`.
+///
+/// Segment selectors take the low 16 bits of their words.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub gs: usize,
+    pub fs: usize,
+    pub es: usize,
+    pub ds: usize,
+    pub edi: usize,
+    pub esi: usize,
+    pub ebp: usize,
+    pub esp: usize,
+    pub ebx: usize,
+    pub edx: usize,
+    pub ecx: usize,
+    pub eax: usize,
+    pub trapno: usize,
+    pub err: usize,
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+    pub esp_at_signal: usize,
+    pub ss: usize,
+    /// Pointer to the FSAVE / FXSAVE area in user memory, 0 for none
+    pub fpstate: usize,
+    pub oldmask: usize,
+    pub cr2: usize,
+}
+
+/// `struct ucontext`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    mcontext: SigContext,
+    sigmask: [u32; 2],
+}
+
+/// `struct rt_sigframe`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    pretcode: usize,
+    sig: usize,
+    pinfo: usize,
+    puc: usize,
+    info: SigInfo,
+    uc: UContext,
+    /// `movl $__NR_rt_sigreturn, %eax; int $0x80`, used without a restorer
+    retcode: [u8; 8],
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+
+impl UserContext {
+    /// Get registers in the layout of `user_regs_struct`.
+    ///
+    /// `orig_eax` is the syscall number, or -1 if not trapped by syscall.
+    pub fn get_user_regs(&self) -> UserRegs {
+        let g = &self.general;
+        UserRegs {
+            ebx: g.ebx,
+            ecx: g.ecx,
+            edx: g.edx,
+            esi: g.esi,
+            edi: g.edi,
+            ebp: g.ebp,
+            eax: g.eax,
+            xds: self.ds,
+            xes: self.es,
+            xfs: self.fs,
+            xgs: self.gs,
+            orig_eax: if self.trap_num == 0x80 {
+                g.eax
+            } else {
+                usize::MAX
+            },
+            eip: self.eip,
+            xcs: self.cs,
+            eflags: self.eflags,
+            esp: self.esp,
+            xss: self.ss,
+        }
+    }
+
+    /// Set registers from the layout of `user_regs_struct`.
+    ///
+    /// Only user flags in `eflags` are taken. `orig_eax` and segment
+    /// selectors are ignored, since selectors are reset in `run()`.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        let g = &mut self.general;
+        g.ebx = regs.ebx;
+        g.ecx = regs.ecx;
+        g.edx = regs.edx;
+        g.esi = regs.esi;
+        g.edi = regs.edi;
+        g.ebp = regs.ebp;
+        g.eax = regs.eax;
+        self.eip = regs.eip;
+        self.eflags = (self.eflags & !EFLAGS_USER_MASK) | (regs.eflags & EFLAGS_USER_MASK);
+        self.esp = regs.esp;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    ///
+    /// `fpstate` and `oldmask` are left 0 for the caller to fill.
+    pub fn get_sigcontext(&self) -> SigContext {
+        let g = &self.general;
+        SigContext {
+            gs: self.gs,
+            fs: self.fs,
+            es: self.es,
+            ds: self.ds,
+            edi: g.edi,
+            esi: g.esi,
+            ebp: g.ebp,
+            esp: self.esp,
+            ebx: g.ebx,
+            edx: g.edx,
+            ecx: g.ecx,
+            eax: g.eax,
+            trapno: self.trap_num,
+            err: self.error_code,
+            eip: self.eip,
+            cs: self.cs,
+            eflags: self.eflags,
+            esp_at_signal: self.esp,
+            ss: self.ss,
+            cr2: self.cr2,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `sigreturn`.
+    ///
+    /// Only user flags in `eflags` are taken. Segment selectors and trap
+    /// information are ignored.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        let g = &mut self.general;
+        g.edi = sc.edi;
+        g.esi = sc.esi;
+        g.ebp = sc.ebp;
+        g.ebx = sc.ebx;
+        g.edx = sc.edx;
+        g.ecx = sc.ecx;
+        g.eax = sc.eax;
+        self.esp = sc.esp;
+        self.eip = sc.eip;
+        self.eflags = (self.eflags & !EFLAGS_USER_MASK) | (sc.eflags & EFLAGS_USER_MASK);
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `esp`. The frame
+    /// saves the registers and `mask` as the blocked signals. The handler
+    /// returns to `restorer`, which should call `rt_sigreturn`, or to the
+    /// code in the frame if `restorer` is 0. The handler receives arguments
+    /// in both the stack and `eax`, `edx`, `ecx` for `regparm(3)`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.esp.checked_sub(stack.len())?;
+        // `esp + 4` is 16 bytes aligned at the entry of handler
+        let sp = ((self.esp.checked_sub(size_of::<RtSigFrame>())? + 4) & !15).checked_sub(4)?;
+        let pinfo = sp + 16;
+        let puc = pinfo + size_of::<SigInfo>();
+        let retcode = puc + size_of::<UContext>();
+        let mut mcontext = self.get_sigcontext();
+        mcontext.oldmask = mask as usize;
+        let frame = RtSigFrame {
+            pretcode: if restorer != 0 { restorer } else { retcode },
+            sig: info.signo as usize,
+            pinfo,
+            puc,
+            info: *info,
+            uc: UContext {
+                mcontext,
+                sigmask: [mask as u32, (mask >> 32) as u32],
+                ..Default::default()
+            },
+            retcode: [0xb8, 173, 0, 0, 0, 0xcd, 0x80, 0],
+        };
+        write_at(stack, base, sp, &frame)?;
+
+        const TF: usize = 1 << 8;
+        const DF: usize = 1 << 10;
+        const RF: usize = 1 << 16;
+        self.general.eax = info.signo as usize;
+        self.general.edx = pinfo;
+        self.general.ecx = puc;
+        self.esp = sp;
+        self.eip = handler;
+        self.eflags &= !(TF | DF | RF);
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    ///
+    /// The handler has popped `pretcode` by returning to the restorer.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.esp - size_of::<usize>()
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        self.set_sigcontext(&frame.uc.mcontext);
+        let [lo, hi] = frame.uc.sigmask;
+        Some((hi as u64) << 32 | lo as u64)
+    }
+}
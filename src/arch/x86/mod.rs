@@ -0,0 +1,220 @@
+//! Protected mode (i686) support for bare-metal kernels.
+//!
+//! Trap from user switches to the stack in `TSS.esp0`, which points into the
+//! running `UserContext`, so the CPU and `trap.S` save user registers there
+//! directly. System call is `int 0x80`.
+
+mod display;
+mod dwarf;
+mod elf;
+#[cfg(feature = "gdbstub")]
+mod gdb;
+mod gdt;
+mod idt;
+mod layout;
+pub mod linux;
+mod trap;
+
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+pub use gdt::{KCODE_SELECTOR, KDATA_SELECTOR, UCODE_SELECTOR, UDATA_SELECTOR, UTLS_SELECTOR};
+pub use layout::*;
+pub use trap::TrapFrame;
+
+use crate::{PageFaultFlags, TrapReason};
+
+/// Initialize interrupt handling on x86, on each CPU.
+///
+/// Return [`TrapInitError::TooManyCpus`](crate::TrapInitError::TooManyCpus)
+/// before changing any state if called on more than
+/// [`MAX_CPUS`](crate::MAX_CPUS) CPUs.
+///
+/// # Safety
+///
+/// This function will:
+///
+/// - Disable interrupt.
+/// - Switch to a new flat [GDT] of the current CPU with kernel, user and
+///   user TLS segments, then reload all segment registers.
+///     - load `fs` with a per-CPU segment, which the kernel must keep
+/// - Switch to a new [TSS] of the current CPU for stack switching from
+///   ring 3.
+/// - Switch to a new [IDT], override the current one.
+///     - allow `int3`, `into` and `int 0x80` from user
+///
+/// [GDT]: https://wiki.osdev.org/GDT
+/// [IDT]: https://wiki.osdev.org/IDT
+/// [TSS]: https://wiki.osdev.org/Task_State_Segment
+pub unsafe fn init() -> Result<(), crate::TrapInitError> {
+    use log::info;
+    let id = gdt::take_slot()?;
+    info!("Initializing trapframe...");
+
+    core::arch::asm!("cli");
+    gdt::init(id);
+    info!("GDT initialization completed");
+    idt::init();
+    info!("IDT initialization completed");
+    Ok(())
+}
+
+/// Decode the error code of page fault.
+fn page_fault_flags(error_code: usize) -> PageFaultFlags {
+    let mut flags = PageFaultFlags::empty();
+    flags.set(PageFaultFlags::PRESENT, error_code & 0x1 != 0);
+    flags.set(PageFaultFlags::WRITE, error_code & 0x2 != 0);
+    flags.set(PageFaultFlags::USER, error_code & 0x4 != 0);
+    flags.set(PageFaultFlags::EXECUTE, error_code & 0x10 != 0);
+    flags
+}
+
+/// Read the time stamp counter.
+pub(crate) fn read_cycles() -> u64 {
+    unsafe { core::arch::x86::_rdtsc() }
+}
+
+/// User space context
+///
+/// Fields from `general` to `ss` are saved on trap in the order of pushing,
+/// the segment selectors are reset in `run()`.
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct UserContext {
+    pub general: GeneralRegs,
+    pub gs: usize,
+    pub fs: usize,
+    pub es: usize,
+    pub ds: usize,
+    pub trap_num: usize,
+    pub error_code: usize,
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+    pub esp: usize,
+    pub ss: usize,
+    /// Faulting address of the last page fault, from `CR2`
+    pub cr2: usize,
+    /// Base address of the user TLS segment in `gs`
+    pub tls: usize,
+    /// Counter ticks spent in user by `run()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
+}
+
+/// General registers
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct GeneralRegs {
+    pub eax: usize,
+    pub ebx: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub ebp: usize,
+}
+
+unsafe impl pod::Pod for GeneralRegs {}
+unsafe impl pod::Pod for UserContext {}
+impl_bytemuck!(GeneralRegs, UserContext);
+
+impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        match self.trap_num {
+            0x80 => TrapReason::Syscall,
+            1 => TrapReason::SingleStep,
+            2 => TrapReason::Nmi,
+            3 => TrapReason::Breakpoint,
+            6 => TrapReason::IllegalInstruction,
+            14 => TrapReason::PageFault {
+                addr: self.cr2,
+                flags: page_fault_flags(self.error_code),
+            },
+            17 => TrapReason::Misaligned,
+            32..=255 => TrapReason::Interrupt(self.trap_num),
+            _ => TrapReason::Unknown(self.trap_num),
+        }
+    }
+
+    /// Get number of syscall
+    pub fn get_syscall_num(&self) -> usize {
+        self.general.eax
+    }
+
+    /// Get return value of syscall
+    pub fn get_syscall_ret(&self) -> usize {
+        self.general.eax
+    }
+
+    /// Set return value of syscall
+    pub fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.eax = ret;
+    }
+
+    /// Get syscall args
+    pub fn get_syscall_args(&self) -> [usize; 6] {
+        [
+            self.general.ebx,
+            self.general.ecx,
+            self.general.edx,
+            self.general.esi,
+            self.general.edi,
+            self.general.ebp,
+        ]
+    }
+
+    /// Set instruction pointer
+    pub fn set_ip(&mut self, ip: usize) {
+        self.eip = ip;
+    }
+
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.eip
+    }
+
+    /// Set stack pointer
+    pub fn set_sp(&mut self, sp: usize) {
+        self.esp = sp;
+    }
+
+    /// Get stack pointer
+    pub fn get_sp(&self) -> usize {
+        self.esp
+    }
+
+    /// Set tls pointer
+    pub fn set_tls(&mut self, tls: usize) {
+        self.tls = tls;
+    }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.tls
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs { gsbase: self.tls }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.tls = regs.gsbase;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// Base of the user TLS segment in `gs`, the thread pointer
+    pub gsbase: usize,
+}
@@ -0,0 +1,168 @@
+use super::gdt::{self, UCODE_SELECTOR, UDATA_SELECTOR, UTLS_SELECTOR};
+use super::UserContext;
+use crate::{PageFaultInfo, TrapInfo};
+use core::arch::{asm, global_asm};
+
+global_asm!(include_str!("trap.S"));
+global_asm!(include_str!(concat!(env!("OUT_DIR"), "/vector.S")));
+
+/// Trap frame of kernel interrupt
+///
+/// # Trap handler
+///
+/// You need to define a handler function like this:
+///
+/// ```no_run
+/// use trapframe::TrapFrame;
+///
+/// #[no_mangle]
+/// extern "C" fn trap_handler(tf: &mut TrapFrame) {
+///     match tf.trap_num {
+///         3 => {
+///             println!("TRAP: BreakPoint");
+///         }
+///         _ => panic!("TRAP: {:#x?}", tf),
+///     }
+/// }
+/// ```
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct TrapFrame {
+    // Pushed by 'trap.S'
+    pub eax: usize,
+    pub ebx: usize,
+    pub ecx: usize,
+    pub edx: usize,
+    pub esi: usize,
+    pub edi: usize,
+    pub ebp: usize,
+    pub gs: usize,
+    pub fs: usize,
+    pub es: usize,
+    pub ds: usize,
+
+    // Pushed by 'vector.S'
+    pub trap_num: usize,
+    pub error_code: usize,
+
+    // Pushed by CPU
+    pub eip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+}
+
+impl_bytemuck!(TrapFrame);
+
+impl TrapFrame {
+    /// Get information of the trap if it is a page fault.
+    ///
+    /// The faulting address is read from `CR2`, so this must be called in
+    /// the trap handler before interrupts are enabled.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        if self.trap_num != 14 {
+            return None;
+        }
+        Some(PageFaultInfo::new(
+            read_cr2(),
+            super::page_fault_flags(self.error_code),
+        ))
+    }
+}
+
+fn read_cr2() -> usize {
+    let cr2: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack)) };
+    cr2
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    unimplemented!("TRAP: tf={:#x?}", tf);
+}
+
+extern "C" {
+    fn run_user(regs: &mut UserContext);
+}
+
+impl UserContext {
+    /// Go to user space with the context, and come back when a trap occurs.
+    ///
+    /// On return, the context will be reset to the status before the trap.
+    /// Trap reason and error code will be placed at `trap_num` and `error_code`.
+    /// For page fault, the faulting address will be placed at `cr2`.
+    ///
+    /// If the trap was triggered by `int 0x80`, the `trap_num` will be set to `0x80`.
+    ///
+    /// Segment registers are always set to the user segments,
+    /// and the base of `gs` is set to `tls`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use trapframe::UserContext;
+    ///
+    /// // init user space context
+    /// let mut context = UserContext {
+    ///     eip: 0x1000,
+    ///     esp: 0x10000,
+    ///     eflags: 0x202,
+    ///     ..Default::default()
+    /// };
+    /// // go to user
+    /// context.run();
+    /// // back from user
+    /// println!("back from user: {:#x?}", context);
+    /// ```
+    pub fn run(&mut self) {
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        let run = self.before_user(timed);
+        debug_assert!(
+            !crate::user_access::flag(),
+            "go to user with a UserAccess guard"
+        );
+        self.cs = UCODE_SELECTOR as usize;
+        self.ss = UDATA_SELECTOR as usize;
+        self.ds = UDATA_SELECTOR as usize;
+        self.es = UDATA_SELECTOR as usize;
+        self.fs = UDATA_SELECTOR as usize;
+        self.gs = UTLS_SELECTOR as usize;
+        unsafe {
+            gdt::set_user_tls(self.tls);
+            // the CPU pushes the trap frame below `cr2`
+            gdt::set_kernel_stack(core::ptr::addr_of!(self.cr2) as usize);
+            run_user(self);
+        }
+        // interrupts are still disabled, so CR2 belongs to this trap
+        if self.trap_num == 14 {
+            self.cr2 = read_cr2();
+        }
+        self.after_user_trap(run)
+    }
+
+    /// Go to user space like [`run`](Self::run), but trap after executing
+    /// one instruction, by setting `EFLAGS.TF`.
+    ///
+    /// `trap_reason()` returns `TrapReason::SingleStep` if the instruction
+    /// completes without other traps. `TF` is restored on return.
+    pub fn run_single_step(&mut self) {
+        const TF: usize = 1 << 8;
+        let tf = self.eflags & TF;
+        self.eflags |= TF;
+        self.run();
+        self.eflags = (self.eflags & !TF) | tf;
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
+    }
+}
@@ -0,0 +1,68 @@
+//! Local APIC as the interrupt controller, see [`crate::intc`].
+//!
+//! The crate acknowledges vectors from `FIRST_EXTERNAL_VECTOR`, except the
+//! legacy syscall vector `0x80`. The spurious vector, as in the spurious
+//! interrupt vector register, is dropped without an EOI.
+
+use crate::intc::InterruptController;
+use x86_64::registers::model_specific::Msr;
+
+/// EOI register of x2APIC
+const IA32_X2APIC_EOI: u32 = 0x80b;
+/// Offset of EOI register of xAPIC
+const XAPIC_EOI: usize = 0xb0;
+
+/// Local APIC in x2APIC mode, accessed by MSRs.
+#[derive(Debug, Clone, Copy)]
+pub struct X2Apic {
+    spurious_vector: u8,
+}
+
+impl X2Apic {
+    /// Create with the spurious interrupt vector.
+    pub const fn new(spurious_vector: u8) -> Self {
+        X2Apic { spurious_vector }
+    }
+}
+
+impl InterruptController for X2Apic {
+    fn claim(&self, vector: usize) -> Option<usize> {
+        (vector != self.spurious_vector as usize).then(|| vector)
+    }
+
+    fn complete(&self, _irq: usize) {
+        unsafe { Msr::new(IA32_X2APIC_EOI).write(0) };
+    }
+}
+
+/// Local APIC in xAPIC mode, accessed by MMIO.
+#[derive(Debug, Clone, Copy)]
+pub struct XApic {
+    base: usize,
+    spurious_vector: u8,
+}
+
+impl XApic {
+    /// Create with the virtual address of the registers and the spurious
+    /// interrupt vector.
+    ///
+    /// # Safety
+    ///
+    /// `base` must map the local APIC registers of every CPU, uncached.
+    pub const unsafe fn new(base: usize, spurious_vector: u8) -> Self {
+        XApic {
+            base,
+            spurious_vector,
+        }
+    }
+}
+
+impl InterruptController for XApic {
+    fn claim(&self, vector: usize) -> Option<usize> {
+        (vector != self.spurious_vector as usize).then(|| vector)
+    }
+
+    fn complete(&self, _irq: usize) {
+        unsafe { ((self.base + XAPIC_EOI) as *mut u32).write_volatile(0) };
+    }
+}
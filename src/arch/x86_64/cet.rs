@@ -0,0 +1,41 @@
+//! Control-flow Enforcement Technology (CET).
+//!
+//! All indirect branch targets in the assembly of this crate, including
+//! the trap vectors, `syscall_entry` and the `syscall_fn_entry` variants,
+//! begin with `endbr64`, so they work with indirect branch tracking (IBT)
+//! enabled.
+//!
+//! Supervisor shadow stacks need cooperation from trap entries and exits,
+//! enabled by [`enable_shadow_stack`]. On the way to user, `run()` turns the
+//! return address on the shadow stack into a supervisor shadow stack token,
+//! and points `IA32_PL0_SSP` to it. The CPU switches to the token on traps
+//! from user (`setssbsy` for `syscall`), where the return address is put
+//! back, so `run()` returns on the shadow stack it was called on.
+//!
+//! Vectors with IST should not have their own shadow stacks in
+//! `IA32_INTERRUPT_SSP_TABLE` when trapped from user.
+
+use x86_64::registers::control::Cr4;
+
+/// `CR4.CET`
+const CR4_CET: u64 = 1 << 23;
+
+/// Whether supervisor shadow stack is enabled, read by `syscall.S`.
+#[no_mangle]
+static mut SHADOW_STACK: u8 = 0;
+
+/// Keep the supervisor shadow stack consistent in `UserContext::run()`.
+///
+/// # Safety
+///
+/// The kernel must have enabled supervisor shadow stack with `CR4.CET` and
+/// `IA32_S_CET.SH_STK_EN`, and allowed `wrss` by `IA32_S_CET.WR_SHSTK_EN`.
+pub unsafe fn enable_shadow_stack() {
+    assert!(Cr4::read_raw() & CR4_CET != 0, "CET is not enabled in CR4");
+    SHADOW_STACK = 1;
+}
+
+/// Whether [`enable_shadow_stack`] has been called.
+pub fn shadow_stack_enabled() -> bool {
+    unsafe { SHADOW_STACK != 0 }
+}
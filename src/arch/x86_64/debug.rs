@@ -0,0 +1,167 @@
+//! Debug registers for hardware breakpoints and watchpoints.
+
+/// Condition of a hardware breakpoint, the `R/W` field of `DR7`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BreakpointKind {
+    /// Break on instruction execution, the length must be 1 byte
+    Execute = 0b00,
+    /// Break on data writes
+    Write = 0b01,
+    /// Break on data reads or writes
+    ReadWrite = 0b11,
+}
+
+/// Length of a hardware breakpoint, the `LEN` field of `DR7`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BreakpointLen {
+    Byte1 = 0b00,
+    Byte2 = 0b01,
+    Byte8 = 0b10,
+    Byte4 = 0b11,
+}
+
+impl BreakpointLen {
+    fn bytes(self) -> usize {
+        match self {
+            BreakpointLen::Byte1 => 1,
+            BreakpointLen::Byte2 => 2,
+            BreakpointLen::Byte4 => 4,
+            BreakpointLen::Byte8 => 8,
+        }
+    }
+}
+
+/// Debug registers of a user program.
+///
+/// They are loaded before going to user in `UserContext::run()` if any
+/// breakpoint is enabled, and `DR7` is cleared after coming back, so that
+/// the breakpoints never fire in kernel. `dr6` is saved on every debug
+/// exception (`trap_num` 1).
+///
+/// Only local enable bits are used.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C)]
+pub struct DebugRegs {
+    /// Breakpoint addresses `DR0`-`DR3`
+    pub addr: [usize; 4],
+    /// Debug status `DR6`
+    pub dr6: usize,
+    /// Debug control `DR7`
+    pub dr7: usize,
+}
+
+unsafe impl pod::Pod for DebugRegs {}
+impl_bytemuck!(DebugRegs);
+
+impl DebugRegs {
+    /// Set hardware breakpoint `index` (0-3) at `addr`.
+    ///
+    /// Return false if `index` is invalid, `addr` is not aligned to `len`,
+    /// or `kind` is `Execute` but `len` is not 1 byte.
+    pub fn set_breakpoint(
+        &mut self,
+        index: usize,
+        addr: usize,
+        kind: BreakpointKind,
+        len: BreakpointLen,
+    ) -> bool {
+        if index >= 4 || addr % len.bytes() != 0 {
+            return false;
+        }
+        if kind == BreakpointKind::Execute && len != BreakpointLen::Byte1 {
+            return false;
+        }
+        self.addr[index] = addr;
+        let shift = 16 + index * 4;
+        self.dr7 &= !(0b1111 << shift);
+        self.dr7 |= ((len as usize) << 2 | kind as usize) << shift;
+        self.dr7 |= 1 << (index * 2);
+        true
+    }
+
+    /// Disable hardware breakpoint `index` (0-3).
+    pub fn clear_breakpoint(&mut self, index: usize) {
+        if index >= 4 {
+            return;
+        }
+        self.addr[index] = 0;
+        self.dr7 &= !(0b1111 << (16 + index * 4));
+        self.dr7 &= !(1 << (index * 2));
+    }
+
+    /// Whether any breakpoint is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.dr7 & 0x55 != 0
+    }
+
+    /// Get the bitmap of breakpoints hit by the last debug exception.
+    pub fn hit_breakpoints(&self) -> u8 {
+        (self.dr6 & 0xf) as u8
+    }
+
+    /// Load the debug registers from `self`.
+    #[cfg(baremetal)]
+    pub(super) unsafe fn load(&self) {
+        use core::arch::asm;
+        asm!("mov dr0, {}", in(reg) self.addr[0], options(nomem, nostack));
+        asm!("mov dr1, {}", in(reg) self.addr[1], options(nomem, nostack));
+        asm!("mov dr2, {}", in(reg) self.addr[2], options(nomem, nostack));
+        asm!("mov dr3, {}", in(reg) self.addr[3], options(nomem, nostack));
+        asm!("mov dr7, {}", in(reg) self.dr7, options(nomem, nostack));
+    }
+
+    /// Save `DR6` into `self` and reset it, clear `DR7` if `disable`.
+    #[cfg(baremetal)]
+    pub(super) unsafe fn save(&mut self, disable: bool) {
+        use core::arch::asm;
+        asm!("mov {}, dr6", out(reg) self.dr6, options(nomem, nostack));
+        // reserved bits are set
+        asm!("mov dr6, {}", in(reg) 0xffff_0ff0usize, options(nomem, nostack));
+        if disable {
+            asm!("mov dr7, {}", in(reg) 0usize, options(nomem, nostack));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_breakpoint() {
+        let mut regs = DebugRegs::default();
+        assert!(regs.set_breakpoint(0, 0x1001, BreakpointKind::Execute, BreakpointLen::Byte1));
+        // L0, and R/W0 = LEN0 = 0
+        assert_eq!(regs.dr7, 0b01);
+        assert!(regs.set_breakpoint(1, 0x2004, BreakpointKind::Write, BreakpointLen::Byte4));
+        // L1, R/W1 = 01, LEN1 = 11
+        assert_eq!(regs.dr7, 0b1101 << 20 | 0b0101);
+        assert!(regs.set_breakpoint(3, 0x3008, BreakpointKind::ReadWrite, BreakpointLen::Byte8));
+        // L3, R/W3 = 11, LEN3 = 10
+        assert_eq!(regs.dr7, 0b1011 << 28 | 0b1101 << 20 | 0b0100_0101);
+        assert_eq!(regs.addr, [0x1001, 0x2004, 0, 0x3008]);
+
+        // replace the condition and length of an enabled breakpoint
+        assert!(regs.set_breakpoint(1, 0x2002, BreakpointKind::ReadWrite, BreakpointLen::Byte2));
+        assert_eq!(regs.dr7, 0b1011 << 28 | 0b0111 << 20 | 0b0100_0101);
+
+        regs.clear_breakpoint(1);
+        regs.clear_breakpoint(3);
+        assert_eq!(regs.dr7, 0b01);
+        assert_eq!(regs.addr, [0x1001, 0, 0, 0]);
+        assert!(regs.is_enabled());
+        regs.clear_breakpoint(0);
+        assert!(!regs.is_enabled());
+    }
+
+    #[test]
+    fn set_breakpoint_invalid() {
+        let mut regs = DebugRegs::default();
+        assert!(!regs.set_breakpoint(4, 0x1000, BreakpointKind::Write, BreakpointLen::Byte1));
+        assert!(!regs.set_breakpoint(0, 0x1002, BreakpointKind::Write, BreakpointLen::Byte4));
+        assert!(!regs.set_breakpoint(0, 0x1000, BreakpointKind::Execute, BreakpointLen::Byte2));
+        assert_eq!(regs, DebugRegs::default());
+    }
+}
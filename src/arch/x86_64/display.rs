@@ -0,0 +1,125 @@
+//! Display registers in crash logs.
+
+#[cfg(baremetal)]
+use super::TrapFrame;
+use super::{GeneralRegs, UserContext};
+use crate::display::{write_flags, write_regs};
+use core::fmt;
+
+impl_debug_by_display!(GeneralRegs, UserContext);
+#[cfg(baremetal)]
+impl_debug_by_display!(TrapFrame);
+
+/// Flags in `RFLAGS`
+const RFLAGS_BITS: &[(usize, &str)] = &[
+    (1 << 0, "CF"),
+    (1 << 2, "PF"),
+    (1 << 4, "AF"),
+    (1 << 6, "ZF"),
+    (1 << 7, "SF"),
+    (1 << 8, "TF"),
+    (1 << 9, "IF"),
+    (1 << 10, "DF"),
+    (1 << 11, "OF"),
+    (1 << 14, "NT"),
+    (1 << 16, "RF"),
+    (1 << 17, "VM"),
+    (1 << 18, "AC"),
+];
+
+impl GeneralRegs {
+    /// Registers with their names, in the order of display.
+    pub(crate) fn named(&self) -> [(&'static str, usize); 19] {
+        [
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rbp", self.rbp),
+            ("rsp", self.rsp),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+            ("rip", self.rip),
+            ("fsbase", self.fsbase),
+            ("gsbase", self.gsbase),
+        ]
+    }
+}
+
+impl fmt::Display for GeneralRegs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(f, &self.named())?;
+        write_flags(f, "rflags", self.rflags, RFLAGS_BITS)
+    }
+}
+
+impl UserContext {
+    /// Registers with their names, for [`diff`](Self::diff).
+    pub(crate) fn named_regs(&self) -> impl Iterator<Item = (&'static str, usize)> {
+        self.general.named().into_iter().chain([
+            ("rflags", self.general.rflags),
+            ("trap_num", self.trap_num),
+            ("error_code", self.error_code),
+            ("cr2", self.cr2),
+            ("cs", self.cs),
+            ("ss", self.ss),
+        ])
+    }
+}
+
+impl fmt::Display for UserContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.general)?;
+        write_regs(
+            f,
+            &[
+                ("trap_num", self.trap_num),
+                ("error_code", self.error_code),
+                ("cr2", self.cr2),
+                ("cs", self.cs),
+                ("ss", self.ss),
+            ],
+        )?;
+        writeln!(f, "reason: {:x?}", self.trap_reason())
+    }
+}
+
+#[cfg(baremetal)]
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_regs(
+            f,
+            &[
+                ("rax", self.rax),
+                ("rbx", self.rbx),
+                ("rcx", self.rcx),
+                ("rdx", self.rdx),
+                ("rsi", self.rsi),
+                ("rdi", self.rdi),
+                ("rbp", self.rbp),
+                ("rsp", self.rsp),
+                ("r8", self.r8),
+                ("r9", self.r9),
+                ("r10", self.r10),
+                ("r11", self.r11),
+                ("r12", self.r12),
+                ("r13", self.r13),
+                ("r14", self.r14),
+                ("r15", self.r15),
+                ("rip", self.rip),
+                ("cs", self.cs),
+                ("trap_num", self.trap_num),
+                ("error_code", self.error_code),
+            ],
+        )?;
+        write_flags(f, "rflags", self.rflags, RFLAGS_BITS)
+    }
+}
@@ -0,0 +1,60 @@
+//! Register access by DWARF register numbers.
+
+use super::UserContext;
+use crate::dwarf::{RegIndex, RegPtr};
+use core::ptr::addr_of;
+
+/// Get the pointer to register `index` of `cx`.
+fn reg_ptr(cx: *const UserContext, index: RegIndex) -> RegPtr {
+    unsafe {
+        let g = addr_of!((*cx).general);
+        Some(match index.0 {
+            0 => addr_of!((*g).rax),
+            1 => addr_of!((*g).rdx),
+            2 => addr_of!((*g).rcx),
+            3 => addr_of!((*g).rbx),
+            4 => addr_of!((*g).rsi),
+            5 => addr_of!((*g).rdi),
+            6 => addr_of!((*g).rbp),
+            7 => addr_of!((*g).rsp),
+            8 => addr_of!((*g).r8),
+            9 => addr_of!((*g).r9),
+            10 => addr_of!((*g).r10),
+            11 => addr_of!((*g).r11),
+            12 => addr_of!((*g).r12),
+            13 => addr_of!((*g).r13),
+            14 => addr_of!((*g).r14),
+            15 => addr_of!((*g).r15),
+            // return address
+            16 => addr_of!((*g).rip),
+            49 => addr_of!((*g).rflags),
+            51 => addr_of!((*cx).cs),
+            52 => addr_of!((*cx).ss),
+            58 => addr_of!((*g).fsbase),
+            59 => addr_of!((*g).gsbase),
+            _ => return None,
+        })
+    }
+}
+
+impl UserContext {
+    /// Get register `index` by DWARF number, or `None` if it is not in the
+    /// context.
+    ///
+    /// `rip` is the return address column 16.
+    pub fn get_reg(&self, index: RegIndex) -> Option<usize> {
+        reg_ptr(self, index).map(|ptr| unsafe { *ptr })
+    }
+
+    /// Set register `index` by DWARF number, return false if it is not in
+    /// the context.
+    pub fn set_reg(&mut self, index: RegIndex, value: usize) -> bool {
+        match reg_ptr(self, index) {
+            Some(ptr) => {
+                unsafe { *(ptr as *mut usize) = value };
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,5 @@
+//! ELF header fields of core files.
+
+/// `EM_X86_64`
+pub(crate) const ELF_MACHINE: u16 = 62;
+pub(crate) const ELF_FLAGS: u32 = 0;
@@ -0,0 +1,258 @@
+//! Trap-and-emulate of privileged instructions of user.
+//!
+//! With `CR4.TSD` set by [`trap_user_counters`], `rdtsc` and `rdtscp` of
+//! user raise #GP, and so does `rdpmc` with `CR4.PCE` clear. The trap is
+//! decoded by [`UserContext::decode_gp`] as
+//! `TrapReason::EmulatableInstruction`, and the kernel completes it with
+//! the value of its choice by [`UserContext::emulate_counter`], e.g. a
+//! virtual time for a deterministic sandbox.
+//!
+//! Likewise, with CPUID faulting enabled by [`trap_user_cpuid`], `cpuid` of
+//! user is reported with its leaf and subleaf, and completed by
+//! [`UserContext::emulate_cpuid`] to present virtual CPU features.
+//!
+//! Other privileged instructions raising #GP, i.e. `rdmsr`, `wrmsr`,
+//! `hlt`, `cli`, `sti` and port I/O without permission, are decoded as
+//! `TrapReason::PrivilegedInstruction` with their operands and length, to
+//! be emulated or reported to the user as precise signals.
+//!
+//! `UserContext::trap_reason()` reports all of them as
+//! `TrapReason::Unknown(13)`, as it does not read user memory. The
+//! instruction is read by the `fetch` function of the kernel passed to
+//! `decode_gp()`, which should fail on unmapped or non-canonical addresses
+//! instead of faulting, e.g. by the user copy of the kernel, a byte at a
+//! time until the length decoder of [`UserContext::advance_pc`] completes
+//! the instruction.
+
+use super::{TrapFrame, UserContext};
+use crate::insn::x86 as insn;
+use crate::{EmulatableKind, PrivilegedKind, TrapReason};
+use core::arch::global_asm;
+use x86_64::registers::control::Cr4;
+use x86_64::registers::model_specific::Msr;
+
+/// `CR4.TSD`
+const CR4_TSD: u64 = 1 << 2;
+/// `CR4.PCE`
+const CR4_PCE: u64 = 1 << 8;
+
+/// `MSR_PLATFORM_INFO`
+const MSR_PLATFORM_INFO: u32 = 0xce;
+/// `MSR_PLATFORM_INFO.CPUID_FAULTING_SUPPORTED`
+const PLATFORM_INFO_CPUID_FAULT: u64 = 1 << 31;
+/// `MSR_MISC_FEATURES_ENABLES`
+const MSR_MISC_FEATURES_ENABLES: u32 = 0x140;
+/// `MSR_MISC_FEATURES_ENABLES.CPUID_FAULTING`
+const MISC_FEATURES_CPUID_FAULT: u64 = 1 << 0;
+
+/// Trap `rdtsc` and `rdtscp` of user if `tsc`, and `rdpmc` if `pmc`, on
+/// the current CPU.
+///
+/// Without `pmc`, `CR4.PCE` is left as is, as setting it would let user
+/// read the performance counters. Call it after [`init()`](super::init) on
+/// each CPU. The kernel can always read the counters.
+pub fn trap_user_counters(tsc: bool, pmc: bool) {
+    let mut cr4 = Cr4::read_raw();
+    match tsc {
+        true => cr4 |= CR4_TSD,
+        false => cr4 &= !CR4_TSD,
+    }
+    if pmc {
+        cr4 &= !CR4_PCE;
+    }
+    unsafe { Cr4::write_raw(cr4) };
+}
+
+// Read the MSR `edi` to `[rsi]` and return 1, or return 0 if `rdmsr` raises
+// #GP, resumed at `rdmsr_safe_fixup` by `fixup()`.
+global_asm!(
+    r#"
+.global rdmsr_safe_asm
+.global rdmsr_safe_insn
+.global rdmsr_safe_fixup
+rdmsr_safe_asm:
+    mov ecx, edi
+rdmsr_safe_insn:
+    rdmsr
+    shl rdx, 32
+    or rax, rdx
+    mov [rsi], rax
+    mov eax, 1
+    ret
+rdmsr_safe_fixup:
+    xor eax, eax
+    ret
+"#
+);
+
+extern "sysv64" {
+    fn rdmsr_safe_asm(msr: u32, value: &mut u64) -> bool;
+    fn rdmsr_safe_insn();
+    fn rdmsr_safe_fixup();
+}
+
+/// Read `msr`, or return `None` if it raises #GP, e.g. it is not
+/// implemented by the CPU or hypervisor.
+///
+/// The #GP is resumed by `trap_dispatch`, so call it after
+/// [`init()`](super::init).
+fn rdmsr_safe(msr: u32) -> Option<u64> {
+    let mut value = 0;
+    unsafe { rdmsr_safe_asm(msr, &mut value) }.then(|| value)
+}
+
+/// Resume a #GP of the kernel raised by `rdmsr_safe()` at its fixup.
+///
+/// Return `false` if the trap is not one.
+pub(super) fn fixup(tf: &mut TrapFrame) -> bool {
+    if tf.trap_num != 13 || tf.rip != rdmsr_safe_insn as usize {
+        return false;
+    }
+    tf.rip = rdmsr_safe_fixup as usize;
+    true
+}
+
+/// Whether the CPU supports CPUID faulting, on Intel CPUs and hypervisors
+/// presenting it, on any vendor.
+///
+/// `MSR_PLATFORM_INFO` is read with a fixup, as it may not exist. Call it
+/// after [`init()`](super::init).
+pub fn cpuid_faulting_supported() -> bool {
+    rdmsr_safe(MSR_PLATFORM_INFO).map_or(false, |info| info & PLATFORM_INFO_CPUID_FAULT != 0)
+}
+
+/// Trap `cpuid` of user by CPUID faulting if `enable`, on the current CPU.
+///
+/// Return `false` if CPUID faulting is not supported, then `cpuid` of user
+/// runs natively. Call it after [`init()`](super::init) on each CPU. The
+/// kernel can always execute `cpuid`.
+pub fn trap_user_cpuid(enable: bool) -> bool {
+    if !cpuid_faulting_supported() {
+        return false;
+    }
+    let mut msr = Msr::new(MSR_MISC_FEATURES_ENABLES);
+    unsafe {
+        let value = msr.read();
+        match enable {
+            true => msr.write(value | MISC_FEATURES_CPUID_FAULT),
+            false => msr.write(value & !MISC_FEATURES_CPUID_FAULT),
+        }
+    }
+    true
+}
+
+/// Decode the instruction of user at `rip` which raised #GP(0), read by
+/// `fetch`.
+fn decode(cx: &UserContext, mut fetch: impl FnMut(usize) -> Option<u8>) -> Option<TrapReason> {
+    // only read the bytes the CPU has fetched for the instruction
+    let mut code = [0u8; 15];
+    let mut insn = None;
+    for n in 0..code.len() {
+        code[n] = fetch(cx.general.rip.wrapping_add(n))?;
+        insn = insn::decode(&code[..=n], !cx.is_compat_mode());
+        if insn.is_some() {
+            break;
+        }
+    }
+    let insn = insn?;
+    let op = &code[insn.opcode..insn.len];
+    let g = &cx.general;
+    let emulatable = match *op {
+        [0x0f, 0x31] => Some(EmulatableKind::Rdtsc),
+        [0x0f, 0x33] => Some(EmulatableKind::Rdpmc),
+        [0x0f, 0x01, 0xf9] => Some(EmulatableKind::Rdtscp),
+        [0x0f, 0xa2] => Some(EmulatableKind::Cpuid {
+            leaf: g.rax as u32,
+            subleaf: g.rcx as u32,
+        }),
+        _ => None,
+    };
+    // without prefixes, as skipped by `EmulatableKind::insn_len()`
+    if let Some(kind) = emulatable {
+        return (insn.opcode == 0).then(|| TrapReason::EmulatableInstruction { kind });
+    }
+    let size = match op[0] & 1 {
+        0 => 1,
+        _ if insn.opsize16 => 2,
+        _ => 4,
+    };
+    let dx = g.rdx as u16;
+    let kind = match *op {
+        [0x0f, 0x32] => PrivilegedKind::Rdmsr { msr: g.rcx as u32 },
+        [0x0f, 0x30] => PrivilegedKind::Wrmsr {
+            msr: g.rcx as u32,
+            value: (g.rdx as u32 as u64) << 32 | g.rax as u32 as u64,
+        },
+        [0xf4] => PrivilegedKind::Hlt,
+        [0xfa] => PrivilegedKind::Cli,
+        [0xfb] => PrivilegedKind::Sti,
+        [0xe4 | 0xe5, port] => PrivilegedKind::In {
+            port: port as u16,
+            size,
+        },
+        [0xe6 | 0xe7, port] => PrivilegedKind::Out {
+            port: port as u16,
+            size,
+        },
+        [0xec | 0xed] => PrivilegedKind::In { port: dx, size },
+        [0xee | 0xef] => PrivilegedKind::Out { port: dx, size },
+        [0x6c | 0x6d] => PrivilegedKind::Ins {
+            port: dx,
+            size,
+            rep: insn.rep,
+        },
+        [0x6e | 0x6f] => PrivilegedKind::Outs {
+            port: dx,
+            size,
+            rep: insn.rep,
+        },
+        _ => return None,
+    };
+    Some(TrapReason::PrivilegedInstruction {
+        kind,
+        len: insn.len,
+    })
+}
+
+impl UserContext {
+    /// Decode the instruction of user which raised #GP(0), reading its bytes
+    /// by `fetch` from their addresses.
+    ///
+    /// Return `TrapReason::EmulatableInstruction` or
+    /// `TrapReason::PrivilegedInstruction`, or `None` if the last trap is
+    /// not #GP(0), the instruction is not one of them, or `fetch` fails.
+    pub fn decode_gp(&self, fetch: impl FnMut(usize) -> Option<u8>) -> Option<TrapReason> {
+        if self.trap_num != 13 || self.error_code != 0 {
+            return None;
+        }
+        decode(self, fetch)
+    }
+
+    /// Complete an emulated `rdtsc`, `rdtscp` or `rdpmc` with `value` in
+    /// `edx:eax`, and `aux` in `ecx` for `rdtscp`, then skip it.
+    pub fn emulate_counter(&mut self, kind: EmulatableKind, value: u64, aux: u32) {
+        let g = &mut self.general;
+        g.rax = value as u32 as usize;
+        g.rdx = (value >> 32) as usize;
+        if kind == EmulatableKind::Rdtscp {
+            g.rcx = aux as usize;
+        }
+        g.rip += kind.insn_len();
+    }
+
+    /// Complete an emulated `cpuid` with the result `[eax, ebx, ecx, edx]`,
+    /// then skip it by [`advance_pc()`](Self::advance_pc) from `code`
+    /// fetched at `rip`.
+    ///
+    /// Return the length of the instruction, or `None` with the context
+    /// unchanged if `code` can not be decoded.
+    pub fn emulate_cpuid(&mut self, code: &[u8], result: [u32; 4]) -> Option<usize> {
+        let len = self.advance_pc(code)?;
+        let g = &mut self.general;
+        g.rax = result[0] as usize;
+        g.rbx = result[1] as usize;
+        g.rcx = result[2] as usize;
+        g.rdx = result[3] as usize;
+        Some(len)
+    }
+}
@@ -0,0 +1,62 @@
+//! Translate host `SIGSEGV` and `SIGBUS` in user program of `run_fncall()`
+//! into page faults.
+//!
+//! When the signal arrives in user, `run_fncall()` returns with `trap_num`
+//! and `error_code` set to the trap number and error code reported by
+//! Linux, e.g. 14 and the page fault error code for a page fault, or 13
+//! for a general protection fault, and `cr2` set to the faulting address
+//! reported in `si_addr`. For `SIGBUS`, `trap_num` is always 14. All
+//! registers are kept, so the faulting instruction is executed again on
+//! next `run_fncall()`.
+//!
+//! Signals arriving in kernel are handled by the default action.
+
+use super::hostsig::{self, ENTRY_FAULT, REG_ERR, REG_TRAPNO};
+
+pub use super::hostsig::{init_thread, HostSignalError};
+
+const SIGBUS: i32 = 7;
+const SIGSEGV: i32 = 11;
+/// Never raised, only used to tell user from kernel by signal mask.
+const SIG_USER_MARKER: i32 = 64;
+
+/// Offset of `si_addr` in `siginfo_t`.
+const SI_ADDR: usize = 16;
+
+/// Catch `SIGSEGV` and `SIGBUS` in user, and report them as page faults.
+///
+/// The calling thread is initialized as [`init_thread`] does.
+///
+/// Return an error if a libc call fails or the layout of `ucontext_t` is
+/// not the expected one.
+///
+/// # Safety
+///
+/// Call it before spawning other threads, so that they inherit the blocked
+/// signal mask. Other threads calling `run_fncall()` must call [`init_thread`].
+///
+/// The kernel must not install other handlers for `SIGSEGV` and `SIGBUS`.
+pub unsafe fn enable() -> Result<(), HostSignalError> {
+    init_thread()?;
+    hostsig::check_layout(SIGSEGV)?;
+    hostsig::add_user_signal(SIG_USER_MARKER)?;
+    hostsig::set_handler(SIGSEGV, handler)?;
+    hostsig::set_handler(SIGBUS, handler)
+}
+
+extern "C" fn handler(signal: i32, info: *mut u8, uc: *mut u8) {
+    unsafe {
+        if !hostsig::in_user(uc) {
+            // fault in kernel, crash on return
+            hostsig::reset_handler(signal);
+            return;
+        }
+        let addr = *info.add(SI_ADDR).cast::<usize>();
+        let gregs = hostsig::gregs(uc);
+        let trap_num = match signal {
+            SIGSEGV => gregs[REG_TRAPNO],
+            _ => 14,
+        };
+        hostsig::redirect(uc, ENTRY_FAULT, &[trap_num, gregs[REG_ERR], addr]);
+    }
+}
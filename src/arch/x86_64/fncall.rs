@@ -3,12 +3,31 @@
 //! # Assumption
 //!
 //! This module suppose you are running kernel on Linux or macOS with glibc,
-//! and your user program is based on musl libc.
+//! or on Windows, and your user program is based on musl libc.
 //!
 //! Because we will store values in their pthread structure.
+//!
+//! For kernel on Linux with musl, enable feature `fncall_host_musl`,
+//! then values of kernel are stored in thread-local slots of this crate
+//! instead of the pthread structure.
+//!
+//! For user program on Linux with glibc, enable feature `fncall_user_glibc`,
+//! then kernel fsbase is stored in an unused slot of its `tcbhead_t`
+//! (`fs:56`) instead of `fs:48`, which is the pointer guard of glibc.
+//!
+//! On Windows, fsbase can not be set from user mode, so the user program
+//! runs with the fsbase of kernel, and `fsbase` in `UserContext` is kept
+//! as is. Kernel stack is stored in a TLS slot of the TEB allocated by
+//! `TlsAlloc` on the first call.
 
+#[cfg(feature = "fpu")]
+use super::FpState;
 use super::UserContext;
 use core::arch::global_asm;
+#[cfg(target_os = "linux")]
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(target_os = "windows")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 extern "sysv64" {
     /// The syscall entry of function call.
@@ -21,39 +40,301 @@ extern "sysv64" {
     /// syscall
     /// call syscall_fn_entry
     /// ```
+    ///
+    /// The `call` pushes the return address into the red zone of the user,
+    /// so the user code should be compiled with `-mno-red-zone`, or use
+    /// [`syscall_fn_entry_redzone`] instead.
     pub fn syscall_fn_entry();
 
-    fn syscall_fn_return(regs: &mut UserContext);
+    /// The syscall entry of function call, keeping the 128-byte red zone
+    /// below the stack pointer of the user intact.
+    ///
+    /// # Usage
+    ///
+    /// Replace `syscall` instruction by skipping the red zone and a `call`
+    /// instruction. The stack pointer is restored on return.
+    ///
+    /// ```asm
+    /// syscall
+    /// lea rsp, [rsp - 128]
+    /// call syscall_fn_entry_redzone
+    /// ```
+    pub fn syscall_fn_entry_redzone();
+
+    /// Return 0 for syscall, otherwise the kind of signal entry, see `hostsig.rs`.
+    fn syscall_fn_return(regs: &mut UserContext) -> usize;
 }
 
 impl UserContext {
     /// Go to user context by function return, within the same privilege level.
     ///
     /// User program should call `syscall_fn_entry()` to return back.
-    /// Trap reason and error code will always be set to 0x100 and 0.
+    /// Trap reason and error code will be set to 0x100 and 0.
+    ///
+    /// On Linux, if preemption is enabled by [`preempt::enable`](crate::preempt::enable),
+    /// it may also return when the timer fires, with trap reason set to
+    /// [`preempt::TRAP_NUM`](crate::preempt::TRAP_NUM).
+    ///
+    /// On Linux, if [`fault::enable`](crate::fault::enable) is called,
+    /// it also returns on `SIGSEGV` or `SIGBUS` in user, with the trap
+    /// number reported by Linux, see [`fault`](crate::fault).
+    ///
+    /// With feature `fpu`, the floating-point state is switched as in `run()`.
+    ///
+    /// All general registers are restored. `rip` and `rflags` are passed by
+    /// 16 bytes of the user stack below the red zone, at `rsp - 144`.
+    ///
+    /// On Linux, fsbase and gsbase are switched by `wrfsbase` and `wrgsbase`
+    /// if the kernel enables `FSGSBASE` for user space, otherwise by
+    /// `arch_prctl` syscall, where gsbase is only set if it is not 0.
+    /// On macOS and Windows, `gsbase` is kept as is.
+    ///
+    /// On Linux, if `fsbase` is 0, it is set to an initial TLS block of
+    /// this crate for the current thread before running, so that the slots
+    /// of the kernel at `fs:0` and `fs:48` can be written.
     pub fn run_fncall(&mut self) {
-        unsafe {
-            syscall_fn_return(self);
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        #[cfg(target_os = "linux")]
+        detect_fsgsbase();
+        #[cfg(target_os = "windows")]
+        alloc_tls_slot();
+        #[cfg(target_os = "linux")]
+        if self.general.fsbase == 0 {
+            self.general.fsbase = init_user_tls();
+        }
+        #[cfg(feature = "fpu")]
+        let mut kernel_fp = FpState::default();
+        #[cfg(feature = "fpu")]
+        {
+            kernel_fp.save();
+            self.fp.restore();
+        }
+        let run = self.before_user(false);
+        let _kind = unsafe { syscall_fn_return(self) };
+        #[cfg(feature = "fpu")]
+        {
+            self.fp.save();
+            kernel_fp.restore();
+        }
+        #[cfg(target_os = "linux")]
+        if _kind != 0 {
+            super::hostsig::restore(self, _kind);
+            self.after_user_trap(run);
+            return;
         }
         self.trap_num = 0x100;
         self.error_code = 0;
+        self.after_user_trap(run);
+    }
+}
+
+// Slot in user TLS to store kernel fsbase
+#[cfg(all(target_os = "linux", not(feature = "fncall_user_glibc")))]
+global_asm!(".equ USER_KERNEL_FSBASE, 48"); // musl: pthread.canary2
+#[cfg(all(target_os = "linux", feature = "fncall_user_glibc"))]
+global_asm!(".equ USER_KERNEL_FSBASE, 56"); // glibc: tcbhead.unused_vgetcpu_cache[0]
+
+/// Initial user TLS block of the current thread, for the user with
+/// `fsbase` 0, large enough for the slots in `tcbhead_t` written above.
+#[cfg(target_os = "linux")]
+#[thread_local]
+static mut INIT_USER_TLS: [usize; 8] = [0; 8];
+
+/// Get the initial user TLS block of the current thread, with its `fs:0`
+/// pointing to itself.
+#[cfg(target_os = "linux")]
+fn init_user_tls() -> usize {
+    unsafe {
+        let tls = INIT_USER_TLS.as_mut_ptr();
+        *tls = tls as usize;
+        tls as usize
+    }
+}
+
+/// Whether `wrfsbase` is enabled in user mode by Linux (>= 5.9).
+///
+/// 0 for not detected yet, 1 for disabled, 2 for enabled.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+static FNCALL_FSGSBASE: AtomicU8 = AtomicU8::new(0);
+
+/// Detect `FSGSBASE` from `AT_HWCAP2` on the first call.
+#[cfg(target_os = "linux")]
+fn detect_fsgsbase() {
+    const AT_HWCAP2: u64 = 26;
+    const HWCAP2_FSGSBASE: u64 = 1 << 1;
+    extern "C" {
+        fn getauxval(ty: u64) -> u64;
+    }
+    if FNCALL_FSGSBASE.load(Ordering::Relaxed) == 0 {
+        let enabled = unsafe { getauxval(AT_HWCAP2) } & HWCAP2_FSGSBASE != 0;
+        FNCALL_FSGSBASE.store(if enabled { 2 } else { 1 }, Ordering::Relaxed);
     }
 }
 
+/// Offset from gsbase of the TLS slot in the TEB keeping kernel stack,
+/// 0 for not allocated yet.
+#[cfg(target_os = "windows")]
+#[no_mangle]
+static FNCALL_TLS_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate the TLS slot keeping kernel stack on the first call.
+#[cfg(target_os = "windows")]
+fn alloc_tls_slot() {
+    /// Offset of `TEB.TlsSlots`
+    const TLS_SLOTS: usize = 0x1480;
+    /// Number of slots in `TEB.TlsSlots`, the others are not in the TEB
+    const TLS_MINIMUM_AVAILABLE: u32 = 64;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn TlsAlloc() -> u32;
+        fn TlsFree(index: u32) -> i32;
+    }
+    if FNCALL_TLS_SLOT.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    let index = unsafe { TlsAlloc() };
+    assert!(
+        index < TLS_MINIMUM_AVAILABLE,
+        "no TLS slot in the TEB for run_fncall"
+    );
+    let slot = TLS_SLOTS + index as usize * 8;
+    if FNCALL_TLS_SLOT
+        .compare_exchange(0, slot, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+        unsafe { TlsFree(index) };
+    }
+}
+
+// Block or unblock signals handled in user if enabled, see `hostsig.rs`.
+// Clobber rax, rcx, rdx, rsi, rdi, r10, r11.
+#[cfg(target_os = "linux")]
+global_asm!(
+    r#"
+.macro USER_SIGPROCMASK how
+    cmp qword ptr [rip + FNCALL_USER_MASK], 0
+    je 4f
+    mov eax, 14             # SYS_rt_sigprocmask
+    mov edi, \how
+    lea rsi, [rip + FNCALL_USER_MASK]
+    xor edx, edx
+    mov r10d, 8             # sizeof(kernel sigset_t)
+    syscall
+4:
+.endm
+.macro USER_SIGNAL_BLOCK
+    USER_SIGPROCMASK 0      # SIG_BLOCK
+.endm
+.macro USER_SIGNAL_UNBLOCK
+    push rdi
+    USER_SIGPROCMASK 1      # SIG_UNBLOCK
+    pop rdi
+.endm
+
+.global fncall_asm_start
+.global fncall_asm_end
+.global fncall_preempt_entry
+.global fncall_fault_entry
+"#
+);
+#[cfg(not(target_os = "linux"))]
+global_asm!(
+    r#"
+.macro USER_SIGNAL_BLOCK
+.endm
+.macro USER_SIGNAL_UNBLOCK
+.endm
+.macro PUSH_KERNEL_GSBASE
+    push 0
+.endm
+.macro POP_USER_GSBASE
+.endm
+.macro SAVE_USER_GSBASE
+.endm
+.macro RESTORE_KERNEL_GSBASE
+.endm
+"#
+);
+
+// Set fsbase to rsi, by `wrfsbase` if enabled, otherwise by `arch_prctl`.
+// Clobber rax, rcx, rdi, r11.
+#[cfg(target_os = "linux")]
+global_asm!(
+    r#"
+.macro SET_FSBASE
+    cmp byte ptr [rip + FNCALL_FSGSBASE], 2
+    jne 2f
+    wrfsbase rsi
+    jmp 3f
+2:  mov eax, 158            # SYS_arch_prctl
+    mov edi, 0x1002         # SET_FS
+    syscall                 # set fsbase
+3:
+.endm
+"#
+);
+
+// Switch gsbase by `rdgsbase` and `wrgsbase` if enabled, otherwise by
+// `arch_prctl` only when it differs, since the user can not change it.
+// Kernel gsbase is kept on the kernel stack, taken as 0 if not enabled.
+// Clobber rax, rcx, rsi, rdi, r11.
+#[cfg(target_os = "linux")]
+global_asm!(
+    r#"
+.macro SET_GSBASE
+    cmp byte ptr [rip + FNCALL_FSGSBASE], 2
+    jne 2f
+    wrgsbase rsi
+    jmp 3f
+2:  mov eax, 158            # SYS_arch_prctl
+    mov edi, 0x1001         # SET_GS
+    syscall                 # set gsbase
+3:
+.endm
+.macro PUSH_KERNEL_GSBASE
+    xor eax, eax
+    cmp byte ptr [rip + FNCALL_FSGSBASE], 2
+    jne 5f
+    rdgsbase rax
+5:  push rax
+.endm
+.macro POP_USER_GSBASE      # rax = kernel gsbase
+    mov rsi, [rsp + 19*8]   # rsi = user gsbase
+    cmp rsi, rax
+    je 6f
+    SET_GSBASE
+6:
+.endm
+.macro SAVE_USER_GSBASE     # rsp = bottom of trap frame
+    cmp byte ptr [rip + FNCALL_FSGSBASE], 2
+    jne 5f
+    rdgsbase rax
+    mov [rsp + 19*8], rax
+5:
+.endm
+.macro RESTORE_KERNEL_GSBASE    # rbx = trap frame, rsi = kernel gsbase
+    cmp rsi, [rbx + 19*8]
+    je 6f
+    SET_GSBASE
+6:
+.endm
+"#
+);
+
 // User: (musl)
 // - fs:0  (pthread.self)       = user fsbase
-// - fs:48 (pthread.canary2)    = kernel fsbase
+// - fs:48 (pthread.canary2)    = kernel fsbase (fs:56 for glibc)
 //
 // Kernel: (glibc)
 // - fs:0  (pthread.self)       = kernel fsbase
-// - fs:64 (pthread.???)        = kernel stack
-// - fs:72 (pthread.???)        = init user fsbase
+// - fs:64 (tcbhead.unused_vgetcpu_cache[1]) = kernel stack
 //
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", not(feature = "fncall_host_musl")))]
 global_asm!(
     r#"
 .macro SWITCH_TO_KERNEL_STACK
-    mov rsp, fs:48          # rsp = kernel fsbase
+    mov rsp, fs:USER_KERNEL_FSBASE  # rsp = kernel fsbase
     mov rsp, [rsp + 64]     # rsp = kernel stack
 .endm
 .macro SAVE_KERNEL_STACK
@@ -63,25 +344,64 @@ global_asm!(
     push fs:0
 .endm
 .macro SWITCH_TO_KERNEL_FSBASE
-    mov eax, 158            # SYS_arch_prctl
-    mov edi, 0x1002         # SET_FS
-    mov rsi, fs:48          # rsi = kernel fsbase
-    syscall
+    mov rsi, fs:USER_KERNEL_FSBASE  # rsi = kernel fsbase
+    SET_FSBASE
 .endm
 .macro POP_USER_FSBASE
     mov rsi, [rsp + 18 * 8] # rsi = user fsbase
     mov rdx, fs:0           # rdx = kernel fsbase
-    test rsi, rsi
-    jnz 1f                  # if not 0, goto set
-0:  lea rsi, [rdx + 72]     # rsi = init user fsbase
-    mov [rsi], rsi          # user_fs:0 = user fsbase
-1:  mov eax, 158            # SYS_arch_prctl
-    mov edi, 0x1002         # SET_FS
-    syscall                 # set fsbase
-    mov fs:48, rdx          # user_fs:48 = kernel fsbase
+    SET_FSBASE
+    mov fs:USER_KERNEL_FSBASE, rdx  # user_fs:USER_KERNEL_FSBASE = kernel fsbase
+.endm
+
+.global syscall_fn_entry
+.global syscall_fn_entry_redzone
+.global syscall_fn_return
+"#
+);
+
+// User: (musl)
+// - fs:0  (pthread.self)       = user fsbase
+// - fs:48 (pthread.canary2)    = kernel fsbase (fs:56 for glibc)
+//
+// Kernel: (musl)
+// - fs:0  (pthread.self)       = kernel fsbase
+// - thread-local `fncall_kernel_stack`     = kernel stack
+//
+// The thread-local slots are addressed by offsets from kernel fsbase,
+// which requires the kernel to be linked as an executable.
+#[cfg(all(target_os = "linux", feature = "fncall_host_musl"))]
+global_asm!(
+    r#"
+.section .tbss,"awT",@nobits
+.balign 8
+fncall_kernel_stack:
+    .zero 8
+.text
+
+.macro SWITCH_TO_KERNEL_STACK
+    mov rsp, fs:USER_KERNEL_FSBASE  # rsp = kernel fsbase
+    mov rsp, [rsp + fncall_kernel_stack@tpoff]  # rsp = kernel stack
+.endm
+.macro SAVE_KERNEL_STACK
+    mov fs:[fncall_kernel_stack@tpoff], rsp
+.endm
+.macro PUSH_USER_FSBASE
+    push fs:0
+.endm
+.macro SWITCH_TO_KERNEL_FSBASE
+    mov rsi, fs:USER_KERNEL_FSBASE  # rsi = kernel fsbase
+    SET_FSBASE
+.endm
+.macro POP_USER_FSBASE
+    mov rsi, [rsp + 18 * 8] # rsi = user fsbase
+    mov rdx, fs:0           # rdx = kernel fsbase
+    SET_FSBASE
+    mov fs:USER_KERNEL_FSBASE, rdx  # user_fs:USER_KERNEL_FSBASE = kernel fsbase
 .endm
 
 .global syscall_fn_entry
+.global syscall_fn_entry_redzone
 .global syscall_fn_return
 "#
 );
@@ -135,27 +455,57 @@ global_asm!(
 
 .global _syscall_fn_entry
 .global syscall_fn_entry
+.global _syscall_fn_entry_redzone
+.global syscall_fn_entry_redzone
 .global _syscall_fn_return
 .set _syscall_fn_entry, syscall_fn_entry
+.set _syscall_fn_entry_redzone, syscall_fn_entry_redzone
 .set _syscall_fn_return, syscall_fn_return
 "#
 );
 
+// User & Kernel: (Windows)
+// - gs:FNCALL_TLS_SLOT (TEB.TlsSlots[i]) = kernel stack
+//
+// gsbase (TEB) is never changed, and fsbase can not be changed.
+#[cfg(target_os = "windows")]
 global_asm!(
     r#"
-syscall_fn_entry:
-    # save rsp
-    lea r11, [rsp + 8]      # save rsp to r11 (clobber)
+.macro SWITCH_TO_KERNEL_STACK
+    mov rsp, [rip + FNCALL_TLS_SLOT]
+    mov rsp, gs:[rsp]       # rsp = kernel stack
+.endm
+.macro SAVE_KERNEL_STACK    # clobber rax
+    mov rax, [rip + FNCALL_TLS_SLOT]
+    mov gs:[rax], rsp
+.endm
+.macro PUSH_USER_FSBASE
+    push [rsp - 8]          # keep fsbase in trap frame
+.endm
+.macro SWITCH_TO_KERNEL_FSBASE
+.endm
+.macro POP_USER_FSBASE
+.endm
+
+.global syscall_fn_entry
+.global syscall_fn_entry_redzone
+.global syscall_fn_return
+"#
+);
 
+global_asm!(
+    r#"
+// r11 = user rsp, the return address is at `r11 - rip` of the user stack
+.macro FN_ENTRY kind, rip=8
     SWITCH_TO_KERNEL_STACK
     pop rsp
     lea rsp, [rsp + 20*8]   # rsp = top of trap frame
 
     # push trap frame (struct GeneralRegs)
-    push 0                  # ignore gs_base
+    push [rsp - 8]          # keep gs_base, saved below if changeable
     PUSH_USER_FSBASE
     pushfq                  # push rflags
-    push [r11 - 8]          # push rip
+    push [r11 - \rip]       # push rip
     push r15
     push r14
     push r13
@@ -172,10 +522,14 @@ syscall_fn_entry:
     push rcx
     push rbx
     push rax
+    SAVE_USER_GSBASE
 
     # restore callee-saved registers
     SWITCH_TO_KERNEL_STACK
-    pop rbx
+    pop rbx                 # rbx = trap frame
+    pop rsi                 # rsi = kernel gsbase
+    RESTORE_KERNEL_GSBASE
+    popfq                   # restore rflags of kernel, clear DF and AC of user
     pop rbx
     pop rbp
     pop r12
@@ -184,12 +538,42 @@ syscall_fn_entry:
     pop r15
 
     SWITCH_TO_KERNEL_FSBASE
+    USER_SIGNAL_BLOCK
 
     # go back to Rust
+    mov eax, \kind
     ret
+.endm
+
+fncall_asm_start:
+fncall_preempt_entry:
+    # redirected by the signal handler, see `hostsig.rs`
+    mov [rsp - 8], r11      # stash r11
+    lea r11, [rsp + 8]      # save rsp to r11
+    FN_ENTRY 1
+
+fncall_fault_entry:
+    # redirected by the signal handler, see `hostsig.rs`
+    mov [rsp - 8], r11      # stash r11
+    lea r11, [rsp + 8]      # save rsp to r11
+    FN_ENTRY 2
+
+syscall_fn_entry:
+    endbr64
+    # save rsp
+    lea r11, [rsp + 8]      # save rsp to r11 (clobber)
+    FN_ENTRY 0
+
+syscall_fn_entry_redzone:
+    endbr64
+    # save rsp above the skipped red zone
+    lea r11, [rsp + 136]    # save rsp to r11 (clobber)
+    FN_ENTRY 0, 136         # rip at 8 + 128 bytes below rsp
 
-    # extern "sysv64" fn syscall_fn_return(&mut UserContext)
+    # extern "sysv64" fn syscall_fn_return(&mut UserContext) -> usize
 syscall_fn_return:
+    USER_SIGNAL_UNBLOCK
+
     # save callee-saved registers
     push r15
     push r14
@@ -197,13 +581,24 @@ syscall_fn_return:
     push r12
     push rbp
     push rbx
+    pushfq
+    PUSH_KERNEL_GSBASE
 
     push rdi
     SAVE_KERNEL_STACK
     mov rsp, rdi
 
+    POP_USER_GSBASE
     POP_USER_FSBASE
 
+    # copy rflags and rip below the red zone of user stack,
+    # so that all general registers can be restored
+    mov rax, [rsp + 7*8]    # rax = user rsp
+    mov rcx, [rsp + 17*8]
+    mov [rax - 144], rcx    # rflags
+    mov rcx, [rsp + 16*8]
+    mov [rax - 136], rcx    # rip
+
     # pop trap frame (struct GeneralRegs)
     pop rax
     pop rbx
@@ -221,10 +616,13 @@ syscall_fn_return:
     pop r13
     pop r14
     pop r15
-    pop r11                 # r11 = rip. FIXME: don't overwrite r11!
-    popfq                   # pop rflags
-    mov rsp, [rsp - 8*11]   # restore rsp
-    jmp r11                 # restore rip
+    mov rsp, [rsp - 9*8]    # rsp = user rsp
+    lea rsp, [rsp - 144]
+    popfq                   # restore rflags
+    # return instead of an indirect jump, which is vulnerable to
+    # branch target injection
+    ret 128                 # restore rip and rsp
+fncall_asm_end:
 "#
 );
 
@@ -235,6 +633,10 @@ mod tests {
 
     #[cfg(target_os = "macos")]
     global_asm!(".set _dump_registers, dump_registers");
+    #[cfg(target_os = "macos")]
+    global_asm!(".set _dump_flags, dump_flags");
+    #[cfg(target_os = "macos")]
+    global_asm!(".set _use_red_zone, use_red_zone");
 
     // Mock user program to dump registers at stack.
     global_asm!(
@@ -277,6 +679,58 @@ dump_registers:
 "#
     );
 
+    // Mock user program to dump rflags at stack, and set DF.
+    global_asm!(
+        r#"
+dump_flags:
+    pushfq
+    std
+    call syscall_fn_entry
+"#
+    );
+
+    // Mock user program to keep rdi and rsi in the red zone across syscalls,
+    // and load them to rax and rdx.
+    global_asm!(
+        r#"
+use_red_zone:
+    mov [rsp - 8], rdi
+    mov [rsp - 128], rsi
+    lea rsp, [rsp - 128]
+    call syscall_fn_entry_redzone
+    mov rax, [rsp - 8]
+    mov rdx, [rsp - 128]
+    lea rsp, [rsp - 128]
+    call syscall_fn_entry_redzone
+"#
+    );
+
+    // Mock user program to load gs:0 to rax.
+    #[cfg(target_os = "linux")]
+    global_asm!(
+        r#"
+load_gs:
+    mov rax, gs:0
+    call syscall_fn_entry
+"#
+    );
+
+    // Mock user program to load the byte at rdi to al.
+    #[cfg(target_os = "linux")]
+    global_asm!(
+        r#"
+read_rdi:
+    mov al, [rdi]
+    call syscall_fn_entry
+"#
+    );
+
+    fn current_rflags() -> usize {
+        let rflags: usize;
+        unsafe { core::arch::asm!("pushfq", "pop {}", out(reg) rflags) };
+        rflags
+    }
+
     #[test]
     fn run_fncall() {
         extern "sysv64" {
@@ -306,8 +760,7 @@ dump_registers:
                 fsbase: 0, // don't set to non-zero garbage value
                 gsbase: 0,
             },
-            trap_num: 0,
-            error_code: 0,
+            ..Default::default()
         };
         cx.run_fncall();
         // check restored registers
@@ -326,7 +779,7 @@ dump_registers:
                 r8: 8,
                 r9: 9,
                 r10: 10,
-                // skip r11
+                r11: 11,
                 r12: 12,
                 r13: 13,
                 r14: 14,
@@ -359,5 +812,130 @@ dump_registers:
         );
         assert_eq!(cx.trap_num, 0x100);
         assert_eq!(cx.error_code, 0);
+        assert_eq!(cx.trap_reason(), TrapReason::Syscall);
+    }
+
+    #[test]
+    fn run_fncall_rflags() {
+        extern "sysv64" {
+            fn dump_flags();
+        }
+        const CF: usize = 1 << 0;
+        const DF: usize = 1 << 10;
+        const AC: usize = 1 << 18;
+        let mut stack = [0u8; 0x1000];
+        let mut cx = UserContext {
+            general: GeneralRegs {
+                rsp: stack.as_mut_ptr() as usize + 0x1000,
+                rip: dump_flags as usize,
+                rflags: CF | DF | AC,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cx.run_fncall();
+        // user sees the flags from context
+        let rflags = unsafe { *(cx.general.rsp as *const usize) };
+        assert_eq!(rflags & (CF | DF | AC), CF | DF | AC);
+        // flags of user are saved
+        assert_eq!(cx.general.rflags & (DF | AC), DF | AC);
+        // flags of kernel are restored
+        assert_eq!(current_rflags() & (DF | AC), 0);
+    }
+
+    #[test]
+    fn run_fncall_redzone() {
+        extern "sysv64" {
+            fn use_red_zone();
+        }
+        let mut stack = [0u8; 0x1000];
+        let stack_top = stack.as_mut_ptr() as usize + 0x1000;
+        let mut cx = UserContext {
+            general: GeneralRegs {
+                rsp: stack_top,
+                rip: use_red_zone as usize,
+                rdi: 0x1234,
+                rsi: 0x5678,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cx.run_fncall();
+        // rsp is saved above the skipped red zone
+        assert_eq!(cx.general.rsp, stack_top);
+        cx.run_fncall();
+        assert_eq!(cx.general.rax, 0x1234);
+        assert_eq!(cx.general.rdx, 0x5678);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn run_fncall_gsbase() {
+        extern "sysv64" {
+            fn load_gs();
+        }
+        let mut stack = [0u8; 0x1000];
+        let tls = [0x1234usize];
+        let mut cx = UserContext {
+            general: GeneralRegs {
+                rsp: stack.as_mut_ptr() as usize + 0x1000,
+                rip: load_gs as usize,
+                gsbase: tls.as_ptr() as usize,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cx.run_fncall();
+        assert_eq!(cx.general.rax, 0x1234);
+        assert_eq!(cx.general.gsbase, tls.as_ptr() as usize);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn run_fncall_page_fault() {
+        extern "sysv64" {
+            fn read_rdi();
+        }
+        unsafe { fault::enable() }.unwrap();
+        let mut stack = [0u8; 0x1000];
+        let mut cx = UserContext {
+            general: GeneralRegs {
+                rsp: stack.as_mut_ptr() as usize + 0x1000,
+                rip: read_rdi as usize,
+                rdi: 0x10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        cx.run_fncall();
+        assert_eq!(cx.trap_num, 14);
+        // read of a page not present, in user
+        assert_eq!(cx.error_code, 0x4);
+        assert_eq!(cx.cr2, 0x10);
+        assert_eq!(
+            cx.trap_reason(),
+            TrapReason::PageFault {
+                addr: 0x10,
+                flags: PageFaultFlags::USER,
+            }
+        );
+        // the faulting instruction runs again
+        assert_eq!(cx.general.rip, read_rdi as usize);
+        let value = 0x5au8;
+        cx.general.rdi = &value as *const u8 as usize;
+        cx.run_fncall();
+        assert_eq!(cx.trap_num, 0x100);
+        assert_eq!(cx.general.rax as u8, 0x5a);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn preempt_unsupported_signal() {
+        const SIGUSR1: i32 = 10;
+        let result = unsafe { preempt::enable(SIGUSR1, 1000) };
+        assert_eq!(
+            result,
+            Err(preempt::HostSignalError::UnsupportedSignal(SIGUSR1))
+        );
     }
 }
@@ -0,0 +1,75 @@
+//! Floating-point and SIMD state.
+
+use core::arch::asm;
+
+/// x87 FPU, MMX and SSE state, saved by `fxsave64`.
+///
+/// Layout follows the 512-byte legacy region of FXSAVE.
+/// See [FXSAVE](https://www.felixcloutier.com/x86/fxsave) for details.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
+#[repr(C, align(16))]
+pub struct FpState {
+    data: [u8; 512],
+}
+
+unsafe impl pod::Pod for FpState {}
+impl_bytemuck!(FpState);
+
+impl Default for FpState {
+    /// The initial state after `fninit`, with all SSE exceptions masked.
+    fn default() -> Self {
+        let mut data = [0u8; 512];
+        // FCW
+        data[0..2].copy_from_slice(&0x037fu16.to_le_bytes());
+        // MXCSR
+        data[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        FpState { data }
+    }
+}
+
+impl FpState {
+    /// Save the current FPU state into `self`.
+    pub fn save(&mut self) {
+        unsafe {
+            asm!("fxsave64 [{}]", in(reg) self.data.as_mut_ptr(), options(nostack));
+        }
+    }
+
+    /// Load the FPU state from `self`.
+    pub fn restore(&self) {
+        unsafe {
+            asm!("fxrstor64 [{}]", in(reg) self.data.as_ptr(), options(nostack));
+        }
+    }
+
+    /// Get the raw bytes of the FXSAVE area.
+    pub fn as_bytes(&self) -> &[u8; 512] {
+        &self.data
+    }
+
+    /// Get the mutable raw bytes of the FXSAVE area.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; 512] {
+        &mut self.data
+    }
+}
+
+/// Serialized as a byte string of the FXSAVE area.
+#[cfg(feature = "serde")]
+impl serde::Serialize for FpState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.data)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FpState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let bytes = alloc::vec::Vec::<u8>::deserialize(deserializer)?;
+        let data = bytes.try_into().map_err(|bytes: alloc::vec::Vec<u8>| {
+            D::Error::invalid_length(bytes.len(), &"512 bytes")
+        })?;
+        Ok(FpState { data })
+    }
+}
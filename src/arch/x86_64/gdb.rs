@@ -0,0 +1,133 @@
+//! Conversion between context types and GDB register layouts of `gdbstub_arch`.
+
+use super::UserContext;
+use gdbstub_arch::x86::reg::X86_64CoreRegs;
+
+/// General registers in the order of GDB.
+macro_rules! gdb_regs {
+    ($regs:expr) => {
+        [
+            $regs.rax, $regs.rbx, $regs.rcx, $regs.rdx, $regs.rsi, $regs.rdi, $regs.rbp, $regs.rsp,
+            $regs.r8, $regs.r9, $regs.r10, $regs.r11, $regs.r12, $regs.r13, $regs.r14, $regs.r15,
+        ]
+    };
+}
+
+macro_rules! set_gdb_regs {
+    ($regs:expr, $gdb:expr) => {
+        let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15] =
+            $gdb.regs;
+        $regs.rax = rax as usize;
+        $regs.rbx = rbx as usize;
+        $regs.rcx = rcx as usize;
+        $regs.rdx = rdx as usize;
+        $regs.rsi = rsi as usize;
+        $regs.rdi = rdi as usize;
+        $regs.rbp = rbp as usize;
+        $regs.rsp = rsp as usize;
+        $regs.r8 = r8 as usize;
+        $regs.r9 = r9 as usize;
+        $regs.r10 = r10 as usize;
+        $regs.r11 = r11 as usize;
+        $regs.r12 = r12 as usize;
+        $regs.r13 = r13 as usize;
+        $regs.r14 = r14 as usize;
+        $regs.r15 = r15 as usize;
+        $regs.rip = $gdb.rip as usize;
+        $regs.rflags = $gdb.eflags as usize;
+    };
+}
+
+impl UserContext {
+    /// Get registers in the layout of GDB `org.gnu.gdb.i386.core` and `sse`.
+    ///
+    /// Only `cs` and `ss` of segment selectors are in the context, others
+    /// are reported as 0. x87 and SSE registers are only filled with
+    /// feature `fpu`.
+    pub fn get_gdb_regs(&self) -> X86_64CoreRegs {
+        let mut regs = X86_64CoreRegs {
+            regs: gdb_regs!(self.general).map(|r| r as u64),
+            rip: self.general.rip as u64,
+            eflags: self.general.rflags as u32,
+            ..Default::default()
+        };
+        regs.segments.cs = self.cs as u32;
+        regs.segments.ss = self.ss as u32;
+        #[cfg(feature = "fpu")]
+        fp_to_gdb(self.fp.as_bytes(), &mut regs);
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.i386.core` and `sse`.
+    ///
+    /// Segment selectors are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &X86_64CoreRegs) {
+        set_gdb_regs!(self.general, regs);
+        #[cfg(feature = "fpu")]
+        fp_from_gdb(regs, self.fp.as_bytes_mut());
+    }
+}
+
+#[cfg(baremetal)]
+impl super::TrapFrame {
+    /// Get registers in the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// Floating-point registers are not saved in the trap frame and are
+    /// reported as 0.
+    pub fn get_gdb_regs(&self) -> X86_64CoreRegs {
+        let mut regs = X86_64CoreRegs {
+            regs: gdb_regs!(self).map(|r| r as u64),
+            rip: self.rip as u64,
+            eflags: self.rflags as u32,
+            ..Default::default()
+        };
+        regs.segments.cs = self.cs as u32;
+        regs
+    }
+
+    /// Set registers from the layout of GDB `org.gnu.gdb.i386.core`.
+    ///
+    /// `rsp` is kept since the trap frame lives on that stack. Segment
+    /// selectors and floating-point registers are ignored.
+    pub fn set_gdb_regs(&mut self, regs: &X86_64CoreRegs) {
+        let rsp = self.rsp;
+        set_gdb_regs!(self, regs);
+        self.rsp = rsp;
+    }
+}
+
+/// Copy x87 and SSE registers from the FXSAVE area.
+#[cfg(feature = "fpu")]
+fn fp_to_gdb(area: &[u8; 512], regs: &mut X86_64CoreRegs) {
+    let u16_at = |i: usize| u16::from_le_bytes([area[i], area[i + 1]]) as u32;
+    let u32_at = |i: usize| u32::from_le_bytes(area[i..i + 4].try_into().unwrap());
+    regs.fpu.fctrl = u16_at(0);
+    regs.fpu.fstat = u16_at(2);
+    regs.fpu.ftag = area[4] as u32;
+    regs.fpu.fop = u16_at(6);
+    regs.fpu.fioff = u32_at(8);
+    regs.fpu.fooff = u32_at(16);
+    regs.mxcsr = u32_at(24);
+    for (i, st) in regs.st.iter_mut().enumerate() {
+        st.copy_from_slice(&area[32 + i * 16..][..10]);
+    }
+    for (i, xmm) in regs.xmm.iter_mut().enumerate() {
+        *xmm = u128::from_le_bytes(area[160 + i * 16..][..16].try_into().unwrap());
+    }
+}
+
+/// Copy x87 and SSE registers into the FXSAVE area.
+#[cfg(feature = "fpu")]
+fn fp_from_gdb(regs: &X86_64CoreRegs, area: &mut [u8; 512]) {
+    area[0..2].copy_from_slice(&(regs.fpu.fctrl as u16).to_le_bytes());
+    area[2..4].copy_from_slice(&(regs.fpu.fstat as u16).to_le_bytes());
+    area[4] = regs.fpu.ftag as u8;
+    area[6..8].copy_from_slice(&(regs.fpu.fop as u16).to_le_bytes());
+    area[24..28].copy_from_slice(&regs.mxcsr.to_le_bytes());
+    for (i, st) in regs.st.iter().enumerate() {
+        area[32 + i * 16..][..10].copy_from_slice(st);
+    }
+    for (i, xmm) in regs.xmm.iter().enumerate() {
+        area[160 + i * 16..][..16].copy_from_slice(&xmm.to_le_bytes());
+    }
+}
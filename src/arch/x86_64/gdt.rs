@@ -1,31 +1,228 @@
 //! Configure Global Descriptor Table (GDT)
 
-use alloc::boxed::Box;
-use alloc::vec::Vec;
+use super::memory::{self, MemoryKind};
+use super::TrapInitError;
+use crate::MAX_CPUS;
+use core::alloc::Layout;
 use core::arch::asm;
-use core::mem::size_of;
+use core::mem::{size_of, MaybeUninit};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use log::debug;
 
 use x86_64::instructions::tables::{lgdt, load_tss};
-use x86_64::registers::model_specific::{GsBase, Star};
+use x86_64::registers::model_specific::{GsBase, Msr, Star};
 use x86_64::structures::gdt::{Descriptor, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::DescriptorTablePointer;
 use x86_64::{PrivilegeLevel, VirtAddr};
 
 #[cfg(not(feature = "ioport_bitmap"))]
-type TSS = x86_64::structures::tss::TaskStateSegment;
+pub(super) type TSS = TaskStateSegment;
 #[cfg(feature = "ioport_bitmap")]
-type TSS = super::ioport::TSSWithPortBitmap;
+pub(super) type TSS = super::ioport::TSSWithPortBitmap;
+
+// Offsets in the TSS pointed by kernel `GSBASE`, for `gs`-relative accesses.
+// `TSSWithPortBitmap` starts with the `TaskStateSegment`.
+/// `TSS.sp0`, the kernel stack found by the trap and syscall entries.
+pub(super) const TSS_SP0_OFFSET: usize = offset_of!(TaskStateSegment, privilege_stack_table);
+/// `TSS.sp1`, scratch for the user `rsp` in the entries.
+pub(super) const TSS_SP1_OFFSET: usize = TSS_SP0_OFFSET + 8;
+/// `TSS.sp2`, the per-CPU pointer of the kernel.
+pub(super) const TSS_SP2_OFFSET: usize = TSS_SP0_OFFSET + 2 * 8;
+/// `TSS.reserved_2`, the address space last run for the Spectre mitigations.
+pub(super) const TSS_RESERVED_2_OFFSET: usize = TSS_SP0_OFFSET + 3 * 8;
+/// `TSS.ist[6]`, unused IST 7, the owner of FPU for feature `lazy_fpu`.
+#[cfg(feature = "lazy_fpu")]
+pub(super) const TSS_IST7_OFFSET: usize =
+    offset_of!(TaskStateSegment, interrupt_stack_table) + 6 * 8;
+/// `TSS.reserved_3`, the nesting count.
+pub(super) const TSS_RESERVED_3_OFFSET: usize =
+    offset_of!(TaskStateSegment, interrupt_stack_table) + 7 * 8;
+
+/// IST index for NMI.
+pub const NMI_IST_INDEX: u16 = 0;
+/// IST index for double fault.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 1;
+/// IST index for machine check.
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+/// Size of each IST stack.
+/// NOTICE: hard coded in `trap.S`
+pub(super) const IST_STACK_SIZE: usize = 0x4000;
+
+/// Size reserved above the NMI stack, see `__nmi_entry` in `trap.S`.
+pub(super) const NMI_RESERVED_SIZE: u64 = 32;
+
+/// Maximum number of entries in a GDT, including the ones copied from the
+/// GDT before [`init`].
+const MAX_GDT_ENTRIES: usize = 32;
+
+/// GDT of each CPU, without a memory provider.
+static mut GDTS: [[u64; MAX_GDT_ENTRIES]; MAX_CPUS] = [[0; MAX_GDT_ENTRIES]; MAX_CPUS];
+/// Number of slots taken in [`GDTS`].
+static GDT_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// TSS of each CPU, without a memory provider.
+static mut TSSS: MaybeUninit<[TSS; MAX_CPUS]> = MaybeUninit::uninit();
+/// Number of slots taken in [`TSSS`].
+static TSS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The GDT built by [`init`], shared by all CPUs except the TSS entry.
+static mut GDT: &[u64] = &[];
+/// Index of the TSS entry in [`GDT`].
+static mut TSS_INDEX: usize = 0;
+
+/// Check the prerequisites of [`init`], or of [`init_ap`] if `ap`.
+pub fn check(ap: bool) -> Result<(), TrapInitError> {
+    let initialized = unsafe { !GDT.is_empty() };
+    if ap && !initialized {
+        return Err(TrapInitError::NotInitialized);
+    }
+    if !ap && initialized {
+        return Err(TrapInitError::AlreadyInitialized);
+    }
+    if !ap {
+        let entry_count = current_entry_count();
+        if entry_count + 7 > MAX_GDT_ENTRIES {
+            return Err(TrapInitError::GdtFull(entry_count));
+        }
+    }
+    // without a memory provider, a GDT and a TSS are taken from the static arrays
+    let taken = GDT_COUNT
+        .load(Ordering::Relaxed)
+        .max(TSS_COUNT.load(Ordering::Relaxed));
+    if taken >= MAX_CPUS {
+        return Err(TrapInitError::TooManyCpus);
+    }
+    Ok(())
+}
+
+/// Number of entries in the current GDT.
+fn current_entry_count() -> usize {
+    let gdtp = unsafe { sgdt() };
+    (gdtp.limit + 1) as usize / size_of::<u64>()
+}
 
 /// Init TSS & GDT.
-pub fn init() {
+pub fn init() -> Result<(), TrapInitError> {
+    let tss = new_tss()?;
+    let (tss0, tss1) = tss_descriptor(tss);
+
+    unsafe {
+        // get current GDT
+        let gdtp = sgdt();
+        let entry_count = current_entry_count();
+        let old_gdt = core::slice::from_raw_parts(gdtp.base.as_ptr::<u64>(), entry_count);
+
+        // build new GDT with 7 more entries
+        //
+        // NOTICE: for fast syscall:
+        //   STAR[47:32] = K_CS   = K_SS - 8
+        //   STAR[63:48] = U_CS32 = U_SS32 - 8 = U_CS - 16
+        let gdt = alloc_gdt(entry_count + 7);
+        gdt[..entry_count].copy_from_slice(old_gdt);
+        gdt[entry_count..]
+            .copy_from_slice(&[tss0, tss1, KCODE64, KDATA64, UCODE32, UDATA32, UCODE64]);
+        let gdt: &'static [u64] = gdt;
+        debug!("new gdt:{:x?}, entry_count:{}", gdt, gdt.len());
+        GDT = gdt;
+        TSS_INDEX = entry_count;
+        load(gdt, tss);
+
+        let sysret = SegmentSelector::new(entry_count as u16 + 4, PrivilegeLevel::Ring3).0;
+        USER_CS32 = sysret;
+        USER_SS = sysret + 8;
+        USER_CS = sysret + 16;
+    }
+    Ok(())
+}
+
+/// Init TSS for an application processor, and a GDT copied from the one
+/// built by [`init`].
+///
+/// Each CPU needs its own GDT, since the TSS entry is marked busy once
+/// loaded. The segment selectors are the same on all CPUs.
+pub fn init_ap() -> Result<(), TrapInitError> {
+    if unsafe { GDT.is_empty() } {
+        return Err(TrapInitError::NotInitialized);
+    }
+    let tss = new_tss()?;
+    let (tss0, tss1) = tss_descriptor(tss);
+    unsafe {
+        let gdt = alloc_gdt(GDT.len());
+        gdt.copy_from_slice(GDT);
+        gdt[TSS_INDEX] = tss0;
+        gdt[TSS_INDEX + 1] = tss1;
+        load(gdt, tss);
+    }
+    Ok(())
+}
+
+/// Take a slot in a per-CPU static array, counted by `count`.
+pub(super) fn take_slot(count: &AtomicUsize) -> usize {
+    let slot = count.fetch_add(1, Ordering::Relaxed);
+    assert!(slot < MAX_CPUS, "more than MAX_CPUS ({}) CPUs", MAX_CPUS);
+    slot
+}
+
+/// Allocate a GDT of `len` entries for the current CPU.
+fn alloc_gdt(len: usize) -> &'static mut [u64] {
+    let layout = Layout::array::<u64>(len).unwrap();
+    match memory::alloc(MemoryKind::Gdt, layout) {
+        Some(addr) => unsafe { core::slice::from_raw_parts_mut(addr as *mut u64, len) },
+        None => unsafe { &mut GDTS[take_slot(&GDT_COUNT)][..len] },
+    }
+}
+
+/// Allocate TSS with kernel stacks for the current CPU.
+///
+/// The stack for trap from user is allocated and checked first, so that a
+/// misaligned one is reported before taking a TSS slot.
+fn new_tss() -> Result<&'static TSS, TrapInitError> {
     // allocate stack for trap from user
     // set the stack top to TSS
     // so that when trap from ring3 to ring0, CPU can switch stack correctly
-    let mut tss = Box::new(TSS::new());
-    let trap_stack_top = Box::leak(Box::new([0u8; 0x1000])).as_ptr() as u64 + 0x1000;
+    #[cfg(not(feature = "kpti"))]
+    let trap_stack_top = super::stack_guard::alloc_stack(MemoryKind::KernelStack, 0x1000) as u64;
+    // with KPTI, it is the trampoline stack and never changed
+    #[cfg(feature = "kpti")]
+    let trap_stack_top = super::kpti::new_trampoline_stack();
+    if trap_stack_top % 16 != 0 {
+        return Err(TrapInitError::MisalignedStack(trap_stack_top as usize));
+    }
+    let tss = match memory::alloc(MemoryKind::Tss, Layout::new::<TSS>()) {
+        Some(addr) => addr as *mut TSS,
+        None => unsafe { (TSSS.as_mut_ptr() as *mut TSS).add(take_slot(&TSS_COUNT)) },
+    };
+    let tss = unsafe {
+        tss.write(TSS::new());
+        &mut *tss
+    };
     tss.privilege_stack_table[0] = VirtAddr::new(trap_stack_top);
-    let tss: &'static _ = Box::leak(tss);
+    // allocate dedicated stacks for critical exceptions
+    // so that they can be handled even if the kernel stack is broken
+    for index in [
+        NMI_IST_INDEX,
+        DOUBLE_FAULT_IST_INDEX,
+        MACHINE_CHECK_IST_INDEX,
+    ] {
+        let stack_top =
+            super::stack_guard::alloc_stack(MemoryKind::IstStack, IST_STACK_SIZE) as u64;
+        tss.interrupt_stack_table[index as usize] = VirtAddr::new(stack_top).align_down(16u64);
+    }
+    // reserve words above the NMI stack for `__nmi_entry`
+    let nmi_stack_top = tss.interrupt_stack_table[NMI_IST_INDEX as usize] - NMI_RESERVED_SIZE;
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = nmi_stack_top;
+    let tss: &'static _ = tss;
+    unsafe {
+        // [top + 0]: NMI latched, [top + 8]: kernel gsbase
+        let reserved = nmi_stack_top.as_mut_ptr::<u64>();
+        *reserved.add(1) = tss as *const _ as u64;
+    }
+    Ok(tss)
+}
+
+/// Get the system segment descriptor of `tss`.
+fn tss_descriptor(tss: &'static TSS) -> (u64, u64) {
     let (tss0, tss1) = match Descriptor::tss_segment(tss) {
         Descriptor::SystemSegment(tss0, tss1) => (tss0, tss1),
         _ => unreachable!(),
@@ -33,68 +230,43 @@ pub fn init() {
     // Extreme hack: the segment limit assumed by x86_64 does not include the port bitmap.
     #[cfg(feature = "ioport_bitmap")]
     let tss0 = (tss0 & !0xFFFF) | (size_of::<TSS>() as u64);
+    (tss0, tss1)
+}
 
-    unsafe {
-        // get current GDT
-        let gdtp = sgdt();
-        let entry_count = (gdtp.limit + 1) as usize / size_of::<u64>();
-        let old_gdt = &*core::ptr::slice_from_raw_parts(
-            gdtp.base.as_ptr::<u8>(),
-            entry_count * size_of::<u64>(),
-        );
+/// Load `gdt` and `tss` on the current CPU, set `GSBASE` and `STAR`.
+unsafe fn load(gdt: &'static [u64], tss: &'static TSS) {
+    // load new GDT and TSS
+    lgdt(&DescriptorTablePointer {
+        limit: (gdt.len() * size_of::<u64>()) as u16 - 1,
+        base: VirtAddr::new(gdt.as_ptr() as _),
+    });
+    load_tss(SegmentSelector::new(
+        TSS_INDEX as u16,
+        PrivilegeLevel::Ring0,
+    ));
 
-        // allocate new GDT with 7 more entries
-        //
-        // NOTICE: for fast syscall:
-        //   STAR[47:32] = K_CS   = K_SS - 8
-        //   STAR[63:48] = U_CS32 = U_SS32 - 8 = U_CS - 16
-        let mut gdt = Vec::from(old_gdt);
-        let bytes = {
-            let mut bytes_collecter = Vec::new();
-            bytes_collecter.extend(tss0.to_le_bytes());
-            bytes_collecter.extend(tss1.to_le_bytes());
-            bytes_collecter.extend(KCODE64.to_le_bytes());
-            bytes_collecter.extend(KDATA64.to_le_bytes());
-            bytes_collecter.extend(UCODE32.to_le_bytes());
-            bytes_collecter.extend(UDATA32.to_le_bytes());
-            bytes_collecter.extend(UCODE64.to_le_bytes());
-            bytes_collecter
-        };
-
-        gdt.extend(bytes);
-        let gdt = Vec::leak(gdt);
-        debug!(
-            "new gdt:{:x?}, entry_count:{}",
-            gdt,
-            gdt.len() / size_of::<u64>()
-        );
-        // load new GDT and TSS
-        lgdt(&DescriptorTablePointer {
-            limit: gdt.len() as u16 - 1,
-            base: VirtAddr::new(gdt.as_ptr() as _),
-        });
-        load_tss(SegmentSelector::new(
-            entry_count as u16,
-            PrivilegeLevel::Ring0,
-        ));
-
-        // for fast syscall:
-        // store address of TSS to kernel_gsbase
-        #[allow(const_item_mutation)]
-        GsBase::MSR.write(tss as *const _ as u64);
+    // for fast syscall:
+    // store address of TSS to kernel_gsbase
+    #[allow(const_item_mutation)]
+    GsBase::MSR.write(tss as *const _ as u64);
 
-        let sysret = SegmentSelector::new(entry_count as u16 + 4, PrivilegeLevel::Ring3).0;
-        let syscall = SegmentSelector::new(entry_count as u16 + 2, PrivilegeLevel::Ring0).0;
-        Star::write_raw(sysret, syscall);
+    let sysret = SegmentSelector::new(TSS_INDEX as u16 + 4, PrivilegeLevel::Ring3).0;
+    let syscall = SegmentSelector::new(TSS_INDEX as u16 + 2, PrivilegeLevel::Ring0).0;
+    Star::write_raw(sysret, syscall);
 
-        USER_SS = sysret + 8;
-        USER_CS = sysret + 16;
-    }
+    // for sysenter from compatibility mode:
+    //   SYSENTER_CS  = K_CS = K_SS - 8
+    //   SYSENTER_ESP = the stack of TSS.sp0 before any `run()`
+    Msr::new(IA32_SYSENTER_CS).write(syscall as u64);
+    Msr::new(IA32_SYSENTER_ESP).write(tss.privilege_stack_table[0].as_u64());
 }
 
+const IA32_SYSENTER_CS: u32 = 0x174;
+const IA32_SYSENTER_ESP: u32 = 0x175;
+
 /// Get current GDT register
 #[inline]
-unsafe fn sgdt() -> DescriptorTablePointer {
+pub(super) unsafe fn sgdt() -> DescriptorTablePointer {
     let mut gdt = DescriptorTablePointer {
         limit: 0,
         base: VirtAddr::zero(),
@@ -103,10 +275,12 @@ unsafe fn sgdt() -> DescriptorTablePointer {
     gdt
 }
 
-#[no_mangle]
-static mut USER_SS: u16 = 0;
-#[no_mangle]
-static mut USER_CS: u16 = 0;
+/// User data segment, for both 64-bit and compatibility mode.
+pub(super) static mut USER_SS: u16 = 0;
+/// User code segment of 64-bit mode.
+pub(super) static mut USER_CS: u16 = 0;
+/// User code segment of 32-bit compatibility mode.
+pub(super) static mut USER_CS32: u16 = 0;
 
 const KCODE64: u64 = 0x00209800_00000000; // EXECUTABLE | USER_SEGMENT | PRESENT | LONG_MODE
 const UCODE64: u64 = 0x0020F800_00000000; // EXECUTABLE | USER_SEGMENT | USER_MODE | PRESENT | LONG_MODE
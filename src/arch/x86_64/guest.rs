@@ -0,0 +1,293 @@
+//! Hypervisor guest context.
+//!
+//! [`GuestContext`] is to VM guests what `UserContext` is to user programs:
+//! it holds the guest registers not kept in the VMCS, and runs the guest
+//! until the next VM exit.
+//!
+//! Both Intel VMX ([`GuestContext::run_vmx`]) and AMD SVM
+//! ([`GuestContext::run_svm`]) are supported, and exits of both are decoded
+//! into [`VmExitReason`].
+//!
+//! Enabling virtualization, allocating and configuring the VMCS or VMCB are
+//! left to the hypervisor. Guest `rsp`, `rip` and `rflags` live in them.
+
+use core::arch::asm;
+use x86_64::registers::model_specific::{FsBase, GsBase, KernelGsBase};
+
+// `svm_entry` takes `GuestRegs`, which is the first field of `GuestContext`
+global_asm_equ!(
+    [
+        GUEST_RAX_OFFSET = offset_of!(GuestContext, regs.rax),
+        GUEST_RBX_OFFSET = offset_of!(GuestContext, regs.rbx),
+        GUEST_RCX_OFFSET = offset_of!(GuestContext, regs.rcx),
+        GUEST_RDX_OFFSET = offset_of!(GuestContext, regs.rdx),
+        GUEST_RSI_OFFSET = offset_of!(GuestContext, regs.rsi),
+        GUEST_RDI_OFFSET = offset_of!(GuestContext, regs.rdi),
+        GUEST_RBP_OFFSET = offset_of!(GuestContext, regs.rbp),
+        GUEST_R8_OFFSET = offset_of!(GuestContext, regs.r8),
+        GUEST_R9_OFFSET = offset_of!(GuestContext, regs.r9),
+        GUEST_R10_OFFSET = offset_of!(GuestContext, regs.r10),
+        GUEST_R11_OFFSET = offset_of!(GuestContext, regs.r11),
+        GUEST_R12_OFFSET = offset_of!(GuestContext, regs.r12),
+        GUEST_R13_OFFSET = offset_of!(GuestContext, regs.r13),
+        GUEST_R14_OFFSET = offset_of!(GuestContext, regs.r14),
+        GUEST_R15_OFFSET = offset_of!(GuestContext, regs.r15),
+        GUEST_CR2_OFFSET = offset_of!(GuestContext, regs.cr2),
+        GUEST_LAUNCHED_OFFSET = offset_of!(GuestContext, launched),
+    ],
+    include_str!("vmx.S"),
+    include_str!("svm.S"),
+);
+const _: () = assert!(offset_of!(GuestContext, regs) == 0);
+
+extern "sysv64" {
+    fn vmx_entry(context: &mut GuestContext) -> usize;
+    fn svm_entry(regs: &mut GuestRegs, vmcb_paddr: usize);
+}
+
+/// Guest general registers, except those in the VMCS or VMCB.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct GuestRegs {
+    pub rax: usize,
+    pub rbx: usize,
+    pub rcx: usize,
+    pub rdx: usize,
+    pub rsi: usize,
+    pub rdi: usize,
+    pub rbp: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub cr2: usize,
+}
+
+/// VM guest context
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct GuestContext {
+    pub regs: GuestRegs,
+    /// Whether the current VMCS has been launched, so that the next entry
+    /// is `vmresume` instead of `vmlaunch`.
+    ///
+    /// Clear it after switching to a new VMCS. Unused by SVM.
+    pub launched: bool,
+}
+
+unsafe impl pod::Pod for GuestRegs {}
+
+/// Reason of a VM exit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VmExitReason {
+    /// Exception in guest, with the vector
+    Exception(u8),
+    /// Non-maskable interrupt
+    Nmi,
+    /// External interrupt
+    ExternalInterrupt,
+    /// Guest is ready to accept interrupts
+    InterruptWindow,
+    /// Triple fault or shutdown
+    Shutdown,
+    Cpuid,
+    Hlt,
+    Invlpg,
+    Rdtsc,
+    /// `vmcall` or `vmmcall`
+    Hypercall,
+    /// Access to control registers
+    CrAccess,
+    /// `in`, `out` and their string forms
+    IoInstruction,
+    MsrRead,
+    MsrWrite,
+    /// EPT violation or nested page fault
+    NestedPageFault,
+    Xsetbv,
+    /// Preemption timer expired
+    Timer,
+    /// Other exits, with the architecture-specific code
+    Unknown(u64),
+}
+
+/// Information of a VM exit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VmExit {
+    pub reason: VmExitReason,
+    /// Whether the exit is caused by a failed VM entry
+    pub entry_failure: bool,
+    /// Exit qualification on VMX, `EXITINFO1` on SVM
+    pub qualification: usize,
+    /// Length of the instruction causing the exit
+    pub instruction_len: usize,
+    /// Faulting guest physical address, for nested page fault
+    pub guest_phys_addr: usize,
+}
+
+/// VMCS fields used to decode a VM exit.
+mod vmcs {
+    pub const VM_INSTRUCTION_ERROR: u64 = 0x4400;
+    pub const EXIT_REASON: u64 = 0x4402;
+    pub const EXIT_INTERRUPTION_INFO: u64 = 0x4404;
+    pub const EXIT_INSTRUCTION_LEN: u64 = 0x440c;
+    pub const EXIT_QUALIFICATION: u64 = 0x6400;
+    pub const GUEST_PHYS_ADDR: u64 = 0x2400;
+}
+
+/// VMCB offsets used to enter the guest and decode a VM exit.
+mod vmcb {
+    pub const EXIT_CODE: usize = 0x70;
+    pub const EXIT_INFO1: usize = 0x78;
+    pub const EXIT_INFO2: usize = 0x80;
+    pub const NEXT_RIP: usize = 0xc8;
+    pub const RIP: usize = 0x578;
+    pub const RAX: usize = 0x5f8;
+    pub const CR2: usize = 0x640;
+}
+
+unsafe fn vmread(field: u64) -> usize {
+    let value: usize;
+    asm!("vmread {}, {}", out(reg) value, in(reg) field, options(nostack));
+    value
+}
+
+impl GuestContext {
+    /// Enter the guest by `vmlaunch` or `vmresume`, and come back on VM exit.
+    ///
+    /// On VM entry failure, return the VM-instruction error number,
+    /// or 0 if there is no current VMCS.
+    ///
+    /// # Safety
+    ///
+    /// VMX operation must be enabled, and the current VMCS must be set up
+    /// with valid guest and host state. `HOST_RSP` and `HOST_RIP` are set
+    /// by this function.
+    ///
+    /// Interrupts should be disabled.
+    pub unsafe fn run_vmx(&mut self) -> Result<VmExit, usize> {
+        if vmx_entry(self) != 0 {
+            // read error number only if there is a current VMCS
+            let mut vmcs_ptr = u64::MAX;
+            asm!("vmptrst [{}]", in(reg) &mut vmcs_ptr, options(nostack));
+            if vmcs_ptr == u64::MAX {
+                return Err(0);
+            }
+            return Err(vmread(vmcs::VM_INSTRUCTION_ERROR));
+        }
+        let exit_reason = vmread(vmcs::EXIT_REASON);
+        let basic = exit_reason as u16;
+        let reason = match basic {
+            0 => {
+                // exception or NMI, distinguished by interruption type
+                let info = vmread(vmcs::EXIT_INTERRUPTION_INFO);
+                match (info >> 8) & 0x7 {
+                    2 => VmExitReason::Nmi,
+                    _ => VmExitReason::Exception(info as u8),
+                }
+            }
+            1 => VmExitReason::ExternalInterrupt,
+            2 => VmExitReason::Shutdown,
+            7 => VmExitReason::InterruptWindow,
+            10 => VmExitReason::Cpuid,
+            12 => VmExitReason::Hlt,
+            14 => VmExitReason::Invlpg,
+            16 => VmExitReason::Rdtsc,
+            18 => VmExitReason::Hypercall,
+            28 => VmExitReason::CrAccess,
+            30 => VmExitReason::IoInstruction,
+            31 => VmExitReason::MsrRead,
+            32 => VmExitReason::MsrWrite,
+            48 => VmExitReason::NestedPageFault,
+            52 => VmExitReason::Timer,
+            55 => VmExitReason::Xsetbv,
+            _ => VmExitReason::Unknown(basic as u64),
+        };
+        Ok(VmExit {
+            reason,
+            entry_failure: exit_reason & (1 << 31) != 0,
+            qualification: vmread(vmcs::EXIT_QUALIFICATION),
+            instruction_len: vmread(vmcs::EXIT_INSTRUCTION_LEN),
+            guest_phys_addr: match reason {
+                VmExitReason::NestedPageFault => vmread(vmcs::GUEST_PHYS_ADDR),
+                _ => 0,
+            },
+        })
+    }
+
+    /// Enter the guest by `vmrun` with the VMCB, and come back on `#VMEXIT`.
+    ///
+    /// `rax` and `cr2` of the guest are copied to and from the VMCB.
+    /// Host callee-saved registers and `FSBASE`, `GSBASE`, `KERNEL_GSBASE`
+    /// are restored after exit, other state switched by `vmload` / `vmsave`
+    /// is left to the hypervisor.
+    ///
+    /// A failed VM entry is reported as `entry_failure` with
+    /// `VmExitReason::Unknown(u64::MAX)` (`VMEXIT_INVALID`).
+    ///
+    /// # Safety
+    ///
+    /// SVM must be enabled with a valid host save area in `VM_HSAVE_PA`.
+    /// `vmcb` must be the virtual address of the VMCB at `vmcb_paddr`,
+    /// set up with valid guest state.
+    pub unsafe fn run_svm(&mut self, vmcb: *mut u8, vmcb_paddr: usize) -> VmExit {
+        let field = |offset: usize| vmcb.add(offset).cast::<u64>();
+        field(vmcb::RAX).write_volatile(self.regs.rax as u64);
+        field(vmcb::CR2).write_volatile(self.regs.cr2 as u64);
+
+        let fsbase = FsBase::read();
+        let gsbase = GsBase::read();
+        let kernel_gsbase = KernelGsBase::read();
+        svm_entry(&mut self.regs, vmcb_paddr);
+        FsBase::write(fsbase);
+        GsBase::write(gsbase);
+        KernelGsBase::write(kernel_gsbase);
+
+        self.regs.rax = field(vmcb::RAX).read_volatile() as usize;
+        self.regs.cr2 = field(vmcb::CR2).read_volatile() as usize;
+        let exit_code = field(vmcb::EXIT_CODE).read_volatile();
+        let info1 = field(vmcb::EXIT_INFO1).read_volatile() as usize;
+        let info2 = field(vmcb::EXIT_INFO2).read_volatile() as usize;
+        let reason = match exit_code {
+            0x00..=0x1f => VmExitReason::CrAccess,
+            0x42 => VmExitReason::Nmi,
+            0x40..=0x5f => VmExitReason::Exception((exit_code - 0x40) as u8),
+            0x60 => VmExitReason::ExternalInterrupt,
+            0x61 => VmExitReason::Nmi,
+            0x64 => VmExitReason::InterruptWindow,
+            0x6e => VmExitReason::Rdtsc,
+            0x72 => VmExitReason::Cpuid,
+            0x78 => VmExitReason::Hlt,
+            0x79 => VmExitReason::Invlpg,
+            0x7b => VmExitReason::IoInstruction,
+            // EXITINFO1: 0 for rdmsr, 1 for wrmsr
+            0x7c if info1 == 0 => VmExitReason::MsrRead,
+            0x7c => VmExitReason::MsrWrite,
+            0x7f => VmExitReason::Shutdown,
+            0x81 => VmExitReason::Hypercall,
+            0x8d => VmExitReason::Xsetbv,
+            0x400 => VmExitReason::NestedPageFault,
+            _ => VmExitReason::Unknown(exit_code),
+        };
+        // next RIP is provided only with NRIP save
+        let next_rip = field(vmcb::NEXT_RIP).read_volatile();
+        let rip = field(vmcb::RIP).read_volatile();
+        VmExit {
+            reason,
+            entry_failure: exit_code == u64::MAX,
+            qualification: info1,
+            instruction_len: match next_rip {
+                0 => 0,
+                _ => next_rip.wrapping_sub(rip) as usize,
+            },
+            guest_phys_addr: match reason {
+                VmExitReason::NestedPageFault => info2,
+                _ => 0,
+            },
+        }
+    }
+}
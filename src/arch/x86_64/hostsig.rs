@@ -0,0 +1,286 @@
+//! Handle host signals arriving in user program of `run_fncall()`.
+//!
+//! Signals in [`FNCALL_USER_MASK`] are blocked in kernel, and only
+//! unblocked between going to user and coming back in `run_fncall()`.
+//! So a handler knows the signal arrived in user if they are unblocked in
+//! the interrupted context, and not in the switching code.
+//!
+//! Then the handler redirects the user program to call an internal entry,
+//! like `syscall_fn_entry` but keeping all registers, and `run_fncall()`
+//! returns with the context fixed up by [`restore`].
+//!
+//! Handlers run on an alternate signal stack, since the stack may point
+//! to `UserContext` during switching. The bottom of it is also the frame
+//! the user program is redirected with, so nothing is written to the user
+//! stack, which may be the faulting address.
+//!
+//! The handlers read `ucontext_t` by the layout of the Linux x86_64 signal
+//! frame, which is checked by raising a probe signal before installing them.
+
+use super::UserContext;
+use alloc::boxed::Box;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// Signals unblocked only in user, 0 for none.
+#[no_mangle]
+static FNCALL_USER_MASK: AtomicU64 = AtomicU64::new(0);
+
+/// Size of alternate signal stack.
+const SIGNAL_STACK_SIZE: usize = 0x4000;
+
+/// Size of the frame at the bottom of alternate signal stack, to redirect
+/// the user program with.
+const REDIRECT_FRAME_SIZE: usize = 8 * 8;
+
+const SIG_BLOCK: i32 = 0;
+const SIG_UNBLOCK: i32 = 1;
+const SIG_SETMASK: i32 = 2;
+const SIG_DFL: usize = 0;
+const SA_SIGINFO: i32 = 4;
+const SA_ONSTACK: i32 = 0x0800_0000;
+const SA_RESTART: i32 = 0x1000_0000;
+
+/// Offsets in `ucontext_t`.
+const UC_STACK_SP: usize = 16;
+const UC_STACK_SIZE: usize = 32;
+const UC_GREGS: usize = 40;
+const UC_FPSTATE: usize = 224;
+const UC_SIGMASK: usize = 296;
+/// Indexes of registers in `ucontext_t.uc_mcontext.gregs`.
+pub(super) const REG_RSP: usize = 15;
+pub(super) const REG_RIP: usize = 16;
+pub(super) const REG_CSGSFS: usize = 18;
+pub(super) const REG_ERR: usize = 19;
+pub(super) const REG_TRAPNO: usize = 20;
+
+/// Kind of entry returned from `syscall_fn_return`.
+pub(super) const ENTRY_PREEMPT: usize = 1;
+pub(super) const ENTRY_FAULT: usize = 2;
+
+/// `cs` of 64-bit user programs on Linux.
+const USER_CS: u16 = 0x33;
+
+/// Error of enabling host signal handling for `run_fncall()`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HostSignalError {
+    /// The signal can not be used for this purpose.
+    UnsupportedSignal(i32),
+    /// The named libc function failed.
+    Failed(&'static str),
+    /// `ucontext_t` passed to handlers is not laid out as expected.
+    UnknownLayout,
+}
+
+impl fmt::Display for HostSignalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedSignal(signal) => write!(f, "unsupported signal: {}", signal),
+            Self::Failed(func) => write!(f, "{} failed", func),
+            Self::UnknownLayout => write!(f, "unknown layout of ucontext_t"),
+        }
+    }
+}
+
+/// Return `Err(Failed(func))` if `ret` is not 0.
+pub(super) fn check(ret: i32, func: &'static str) -> Result<(), HostSignalError> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(HostSignalError::Failed(func)),
+    }
+}
+
+#[repr(C)]
+struct SigAction {
+    sa_sigaction: usize,
+    sa_mask: [u64; 16],
+    sa_flags: i32,
+    sa_restorer: usize,
+}
+
+#[repr(C)]
+struct SigAltStack {
+    ss_sp: *mut u8,
+    ss_flags: i32,
+    ss_size: usize,
+}
+
+extern "C" {
+    fn sigaction(sig: i32, act: *const SigAction, old: *mut SigAction) -> i32;
+    fn sigaltstack(ss: *const SigAltStack, old: *mut SigAltStack) -> i32;
+    fn pthread_sigmask(how: i32, set: *const [u64; 16], old: *mut [u64; 16]) -> i32;
+    fn raise(sig: i32) -> i32;
+
+    fn fncall_asm_start();
+    fn fncall_asm_end();
+    fn fncall_preempt_entry();
+    fn fncall_fault_entry();
+}
+
+/// Signal handler with `siginfo_t` and `ucontext_t`.
+pub(super) type Handler = extern "C" fn(signal: i32, info: *mut u8, uc: *mut u8);
+
+/// Block `signal` in kernel and unblock it only in user.
+///
+/// # Safety
+///
+/// Call it before spawning other threads, so that they inherit the blocked
+/// signal mask.
+pub(super) unsafe fn add_user_signal(signal: i32) -> Result<(), HostSignalError> {
+    let mut mask = [0u64; 16];
+    mask[0] = 1 << (signal - 1);
+    check(
+        pthread_sigmask(SIG_BLOCK, &mask, core::ptr::null_mut()),
+        "pthread_sigmask",
+    )?;
+    FNCALL_USER_MASK.fetch_or(mask[0], Ordering::Relaxed);
+    Ok(())
+}
+
+/// Install `handler` for `signal` on the alternate signal stack.
+pub(super) unsafe fn set_handler(signal: i32, handler: Handler) -> Result<(), HostSignalError> {
+    let mut mask = [0u64; 16];
+    mask[0] = 1 << (signal - 1);
+    let act = SigAction {
+        sa_sigaction: handler as usize,
+        sa_mask: mask,
+        sa_flags: SA_SIGINFO | SA_ONSTACK | SA_RESTART,
+        sa_restorer: 0,
+    };
+    check(sigaction(signal, &act, core::ptr::null_mut()), "sigaction")
+}
+
+/// Signal mask expected in the `ucontext_t` of the probe.
+static PROBE_MASK: AtomicU64 = AtomicU64::new(0);
+/// Result of the probe, 0 for not arrived, 1 for expected, 2 for unknown.
+static PROBE_RESULT: AtomicU8 = AtomicU8::new(0);
+
+extern "C" fn probe(_signal: i32, _info: *mut u8, uc: *mut u8) {
+    unsafe {
+        let sigmask = *uc.add(UC_SIGMASK).cast::<u64>();
+        let fpstate = *uc.add(UC_FPSTATE).cast::<usize>();
+        let cs = gregs(uc)[REG_CSGSFS] as u16;
+        let ss_sp = *uc.add(UC_STACK_SP).cast::<usize>();
+        let ss_size = *uc.add(UC_STACK_SIZE).cast::<usize>();
+        let expected = sigmask == PROBE_MASK.load(Ordering::Relaxed)
+            && fpstate > uc as usize
+            && cs == USER_CS
+            && (ss_sp..ss_sp + ss_size).contains(&(uc as usize));
+        PROBE_RESULT.store(if expected { 1 } else { 2 }, Ordering::Relaxed);
+    }
+}
+
+/// Check the layout of `ucontext_t` by raising `signal` on the calling
+/// thread with a probe handler, which is left installed.
+pub(super) unsafe fn check_layout(signal: i32) -> Result<(), HostSignalError> {
+    set_handler(signal, probe)?;
+    let mut mask = [0u64; 16];
+    mask[0] = 1 << (signal - 1);
+    let mut old = [0u64; 16];
+    check(
+        pthread_sigmask(SIG_UNBLOCK, &mask, &mut old),
+        "pthread_sigmask",
+    )?;
+    PROBE_MASK.store(old[0] & !mask[0], Ordering::Relaxed);
+    PROBE_RESULT.store(0, Ordering::Relaxed);
+    let raised = raise(signal);
+    check(
+        pthread_sigmask(SIG_SETMASK, &old, core::ptr::null_mut()),
+        "pthread_sigmask",
+    )?;
+    check(raised, "raise")?;
+    match PROBE_RESULT.load(Ordering::Relaxed) {
+        1 => Ok(()),
+        _ => Err(HostSignalError::UnknownLayout),
+    }
+}
+
+/// Restore the default action of `signal`.
+pub(super) unsafe fn reset_handler(signal: i32) {
+    let act = SigAction {
+        sa_sigaction: SIG_DFL,
+        sa_mask: [0; 16],
+        sa_flags: 0,
+        sa_restorer: 0,
+    };
+    sigaction(signal, &act, core::ptr::null_mut());
+}
+
+/// Allocate the alternate signal stack for the calling thread.
+///
+/// Every thread calling `run_fncall()` with signals handled in user needs it.
+///
+/// # Safety
+///
+/// The previous alternate signal stack of the thread is replaced.
+pub unsafe fn init_thread() -> Result<(), HostSignalError> {
+    let stack = Box::leak(Box::new([0u8; SIGNAL_STACK_SIZE]));
+    let ss = SigAltStack {
+        ss_sp: stack.as_mut_ptr(),
+        ss_flags: 0,
+        ss_size: SIGNAL_STACK_SIZE,
+    };
+    check(sigaltstack(&ss, core::ptr::null_mut()), "sigaltstack")
+}
+
+/// Get the registers saved in `ucontext_t`.
+pub(super) unsafe fn gregs<'a>(uc: *mut u8) -> &'a mut [usize; 23] {
+    &mut *uc.add(UC_GREGS).cast::<[usize; 23]>()
+}
+
+/// Whether the signal arrived in user.
+pub(super) unsafe fn in_user(uc: *mut u8) -> bool {
+    let sigmask = *uc.add(UC_SIGMASK).cast::<u64>();
+    let user_mask = FNCALL_USER_MASK.load(Ordering::Relaxed);
+    let rip = gregs(uc)[REG_RIP];
+    user_mask != 0
+        && sigmask & user_mask == 0
+        && !(fncall_asm_start as usize..fncall_asm_end as usize).contains(&rip)
+}
+
+/// Redirect the user program to the entry of `kind`, with the user stack
+/// pointer and `stash` pushed below the stashed `r11`.
+///
+/// The frame is at the bottom of the alternate signal stack the handler
+/// runs on, which is not used by the handler itself.
+///
+/// It may run with the fsbase of user, so it must not access thread-local
+/// variables of kernel.
+pub(super) unsafe fn redirect(uc: *mut u8, kind: usize, stash: &[usize]) {
+    debug_assert!(stash.len() <= 3);
+    let gregs = gregs(uc);
+    // push return address at the top of the frame, like `call`
+    let ss_sp = *uc.add(UC_STACK_SP).cast::<usize>();
+    let sp = ss_sp + REDIRECT_FRAME_SIZE - 8;
+    *(sp as *mut usize) = gregs[REG_RIP];
+    // [sp - 8] is for r11
+    *((sp - 16) as *mut usize) = gregs[REG_RSP];
+    for (i, &value) in stash.iter().enumerate() {
+        *((sp - 24 - i * 8) as *mut usize) = value;
+    }
+    gregs[REG_RSP] = sp;
+    gregs[REG_RIP] = match kind {
+        ENTRY_PREEMPT => fncall_preempt_entry as usize,
+        _ => fncall_fault_entry as usize,
+    };
+}
+
+/// Fix up the context saved by the entry of `kind`.
+pub(super) fn restore(context: &mut UserContext, kind: usize) {
+    // saved rsp is the top of the redirect frame, r11, user rsp and other
+    // values are stashed below the return address
+    let stash = |i: usize| unsafe { *((context.general.rsp - 16 - i * 8) as *const usize) };
+    context.general.r11 = stash(0);
+    match kind {
+        ENTRY_PREEMPT => {
+            context.trap_num = super::preempt::TRAP_NUM;
+            context.error_code = 0;
+        }
+        _ => {
+            context.trap_num = stash(2);
+            context.error_code = stash(3);
+            context.cr2 = stash(4);
+        }
+    }
+    context.general.rsp = stash(1);
+}
@@ -1,33 +1,73 @@
-use alloc::boxed::Box;
+use super::gdt::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+use super::memory::MemoryKind;
+use core::alloc::Layout;
 use core::arch::asm;
+use core::mem::MaybeUninit;
 use x86_64::structures::idt::*;
 use x86_64::structures::DescriptorTablePointer;
 use x86_64::{PrivilegeLevel, VirtAddr};
 
+/// Storage of [`IDT`], without a memory provider.
+static mut IDT_STORAGE: MaybeUninit<InterruptDescriptorTable> = MaybeUninit::uninit();
+/// The IDT built by [`init`], shared by all CPUs.
+static mut IDT: Option<&'static InterruptDescriptorTable> = None;
+
 pub fn init() {
     extern "C" {
         #[link_name = "__vectors"]
         static VECTORS: [extern "C" fn(); 256];
+        fn __nmi_entry();
     }
 
-    let idt = Box::leak(Box::new(InterruptDescriptorTable::new()));
+    let layout = Layout::new::<InterruptDescriptorTable>();
+    let idt = match super::memory::alloc(MemoryKind::Idt, layout) {
+        Some(addr) => unsafe {
+            let idt = addr as *mut InterruptDescriptorTable;
+            idt.write(InterruptDescriptorTable::new());
+            &mut *idt
+        },
+        None => unsafe { IDT_STORAGE.write(InterruptDescriptorTable::new()) },
+    };
     // let idt = sidt().base;
     let entries: &'static mut [Entry<HandlerFunc>; 256] =
         unsafe { core::mem::transmute_copy(&idt) };
     for i in 0..256 {
-        let opt = entries[i].set_handler_fn(unsafe { core::mem::transmute(VECTORS[i]) });
-        // Enable user space `int3` and `into`
-        if i == 3 || i == 4 {
+        // NMI has its own entry to handle nesting
+        let vector = match i {
+            2 => __nmi_entry as usize,
+            _ => unsafe { VECTORS[i] as usize },
+        };
+        let opt = entries[i].set_handler_fn(unsafe { core::mem::transmute(vector) });
+        // Enable user space `int3`, `into` and `int 0x80` for legacy syscalls
+        if i == 3 || i == 4 || i == 0x80 {
             opt.set_privilege_level(PrivilegeLevel::Ring3);
         }
+        // Switch to dedicated stacks for critical exceptions
+        let ist_index = match i {
+            2 => Some(NMI_IST_INDEX),
+            8 => Some(DOUBLE_FAULT_IST_INDEX),
+            18 => Some(MACHINE_CHECK_IST_INDEX),
+            _ => None,
+        };
+        if let Some(index) = ist_index {
+            unsafe {
+                opt.set_stack_index(index);
+            }
+        }
     }
     idt.load();
+    unsafe { IDT = Some(idt) };
+}
+
+/// Load the IDT built by [`init`] on an application processor.
+pub fn init_ap() {
+    unsafe { IDT.expect("IDT is not initialized by BSP").load() };
 }
 
 /// Get current IDT register
 #[allow(dead_code)]
 #[inline]
-fn sidt() -> DescriptorTablePointer {
+pub(super) fn sidt() -> DescriptorTablePointer {
     let mut dtp = DescriptorTablePointer {
         limit: 0,
         base: VirtAddr::zero(),
@@ -0,0 +1,148 @@
+//! Interrupt handler registration.
+//!
+//! Traps from kernel are first dispatched to the handler registered for
+//! the vector, and fall back to the `trap_handler` defined by the kernel.
+//! A #GP of the kernel probing an MSR which may not exist, see
+//! [`emulate::cpuid_faulting_supported()`](super::emulate::cpuid_faulting_supported),
+//! is resumed before dispatching.
+//!
+//! Traps from user are not dispatched here, they are returned from
+//! `UserContext::run()` with the vector in `trap_num`.
+//!
+//! External interrupts are acknowledged around the handler if an interrupt
+//! controller is set, see [`crate::intc`].
+
+use super::TrapFrame;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::Cr2;
+
+/// Handler of an interrupt vector.
+pub type InterruptHandler = fn(&mut TrapFrame);
+
+/// Number of IDT vectors.
+pub const NUM_VECTORS: usize = 256;
+
+/// First vector not reserved for exceptions.
+pub const FIRST_EXTERNAL_VECTOR: usize = 32;
+
+/// Whether `vector` is an external interrupt, to be acknowledged by the
+/// interrupt controller.
+pub(super) fn is_external(vector: usize) -> bool {
+    (FIRST_EXTERNAL_VECTOR..NUM_VECTORS).contains(&vector) && vector != 0x80
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Handlers for each vector, 0 for not registered.
+static HANDLERS: [AtomicUsize; NUM_VECTORS] = [NO_HANDLER; NUM_VECTORS];
+
+/// Register `handler` for `vector`.
+///
+/// Return false if `vector` is invalid or already has a handler.
+pub fn register_handler(vector: usize, handler: InterruptHandler) -> bool {
+    match HANDLERS.get(vector) {
+        Some(slot) => slot
+            .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok(),
+        None => false,
+    }
+}
+
+/// Unregister the handler of `vector`, return it if exists.
+pub fn unregister_handler(vector: usize) -> Option<InterruptHandler> {
+    let raw = HANDLERS.get(vector)?.swap(0, Ordering::AcqRel);
+    to_handler(raw)
+}
+
+/// Allocate a free vector from external vectors and register `handler` for it.
+///
+/// Return the vector, or `None` if all vectors are in use.
+pub fn allocate_vector(handler: InterruptHandler) -> Option<usize> {
+    (FIRST_EXTERNAL_VECTOR..NUM_VECTORS).find(|&vector| register_handler(vector, handler))
+}
+
+/// Get the handler of `vector`.
+pub fn handler(vector: usize) -> Option<InterruptHandler> {
+    to_handler(HANDLERS.get(vector)?.load(Ordering::Acquire))
+}
+
+fn to_handler(raw: usize) -> Option<InterruptHandler> {
+    if raw == 0 {
+        return None;
+    }
+    Some(unsafe { core::mem::transmute::<usize, InterruptHandler>(raw) })
+}
+
+/// Handler of double fault in kernel.
+///
+/// It runs on a dedicated stack, and receives whether the double fault is
+/// likely caused by kernel stack overflow. It must not return.
+pub type DoubleFaultHandler = fn(tf: &mut TrapFrame, stack_overflow: bool) -> !;
+
+static DOUBLE_FAULT_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the handler of double fault in kernel.
+///
+/// Without a handler, a double fault in kernel panics on its dedicated stack.
+pub fn set_double_fault_handler(handler: DoubleFaultHandler) {
+    DOUBLE_FAULT_HANDLER.store(handler as usize, Ordering::Release);
+}
+
+/// Size of the frame pushed by the CPU for a page fault, with error code.
+const PAGE_FAULT_FRAME_SIZE: usize = 6 * 8;
+
+fn double_fault(tf: &mut TrapFrame) -> ! {
+    // RSP pushed by CPU, right after the trap frame
+    let rsp = unsafe { *(tf as *const TrapFrame).add(1).cast::<usize>() };
+    // The stack pointer, or the page fault frame the CPU failed to push
+    // below it, is in a guard page. CR2 may be stale from a page fault
+    // handled before, so it only counts if it is also right below the
+    // stack pointer.
+    let frame = rsp.wrapping_sub(PAGE_FAULT_FRAME_SIZE);
+    let cr2 = Cr2::read().as_u64() as usize;
+    let stack_overflow = super::stack_guard::contains(rsp)
+        || super::stack_guard::contains(frame)
+        || (frame..rsp).contains(&cr2);
+    match DOUBLE_FAULT_HANDLER.load(Ordering::Acquire) {
+        0 => panic!(
+            "double fault, stack overflow: {}, rsp: {:#x}, cr2: {:#x}, tf: {:#x?}",
+            stack_overflow, rsp, cr2, tf
+        ),
+        raw => {
+            let handler = unsafe { core::mem::transmute::<usize, DoubleFaultHandler>(raw) };
+            handler(tf, stack_overflow)
+        }
+    }
+}
+
+#[no_mangle]
+extern "sysv64" fn trap_dispatch(tf: &mut TrapFrame) {
+    extern "sysv64" {
+        fn trap_handler(tf: &mut TrapFrame);
+    }
+    debug_assert!(!crate::user_access::flag(), "AC is not cleared on trap");
+    if tf.trap_num == 8 {
+        double_fault(tf);
+    }
+    if super::emulate::fixup(tf) {
+        return;
+    }
+    let dispatch = |tf: &mut TrapFrame| match handler(tf.trap_num) {
+        Some(handler) => handler(tf),
+        None => unsafe { trap_handler(tf) },
+    };
+    super::nesting::enter_trap(|| {
+        if is_external(tf.trap_num) {
+            crate::intc::handle(tf.trap_num, |_irq| {
+                #[cfg(feature = "trace")]
+                crate::trace::emit(crate::trace::TrapEvent::KernelIrqBegin(_irq));
+                dispatch(tf);
+                #[cfg(feature = "trace")]
+                crate::trace::emit(crate::trace::TrapEvent::KernelIrqEnd(_irq));
+            });
+        } else {
+            dispatch(tf);
+        }
+    });
+}
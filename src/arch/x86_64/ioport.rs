@@ -1,6 +1,15 @@
 //! I/O port permissions.
+//!
+//! The bitmap in the TSS of the current CPU can be set directly by
+//! [`set_permission`], which applies to every context run on the CPU. For
+//! permissions of each context, e.g. for DOS emulators or user drivers, keep
+//! an [`IoBitmap`] with the context and run it by
+//! [`UserContext::run_with_io_bitmap`], which gets only the ports of its
+//! bitmap. Only the bytes up to the highest port granted by either are
+//! copied into the TSS, so a few low ports are cheap.
 
-use core::ops::{Deref, DerefMut};
+use super::UserContext;
+use core::ops::{Deref, DerefMut, RangeInclusive};
 use x86_64::registers::model_specific::GsBase;
 use x86_64::structures::tss::TaskStateSegment;
 
@@ -12,6 +21,14 @@ pub(super) struct TSSWithPortBitmap {
     /// Bit in port_bitmap: 0 indicates accessible, 1 indicated inaccessible.
     /// Follow linux, add one extra element.
     port_bitmap: [u8; 1 + Self::BITMAP_VALID_SIZE],
+    /// Bytes of `port_bitmap` which may grant ports on the CPU, by
+    /// `set_permission` or `bitmap`. The others deny all.
+    granted: usize,
+    /// Bytes of `port_bitmap` replaced by an `IoBitmap`, to be restored
+    /// from `saved` after running the context.
+    installed: usize,
+    /// Bytes of `port_bitmap` before an `IoBitmap` was installed.
+    saved: [u8; Self::BITMAP_VALID_SIZE],
 }
 
 impl Deref for TSSWithPortBitmap {
@@ -39,19 +56,26 @@ impl TSSWithPortBitmap {
         let mut tss = Self {
             tss: TaskStateSegment::new(),
             port_bitmap: [DENY_ALL; 1 + Self::BITMAP_VALID_SIZE],
+            granted: 0,
+            installed: 0,
+            saved: [DENY_ALL; Self::BITMAP_VALID_SIZE],
         };
         tss.iomap_base = core::mem::size_of::<TaskStateSegment>() as u16;
         tss
     }
 }
 
+/// Get the TSS of the current CPU.
+fn current_tss() -> &'static mut TSSWithPortBitmap {
+    unsafe { &mut *(GsBase::MSR.read() as *mut TSSWithPortBitmap) }
+}
+
 /// Get ioport bitmap.
 pub fn bitmap() -> &'static mut [u8] {
-    unsafe {
-        let gsbase = GsBase::MSR.read();
-        let tss = &mut *(gsbase as *mut TSSWithPortBitmap);
-        &mut tss.port_bitmap[..]
-    }
+    let tss = current_tss();
+    // any port may be granted through it
+    tss.granted = TSSWithPortBitmap::BITMAP_VALID_SIZE;
+    &mut tss.port_bitmap[..]
 }
 
 /// Get ioport permission.
@@ -66,10 +90,125 @@ pub fn get_permission(port: u16) -> bool {
 
 /// Set ioport permission.
 pub fn set_permission(port: u16, allow: bool) {
-    let bitmap = bitmap();
+    let tss = current_tss();
     let idx: usize = (port >> 3) as usize;
     let bit: u8 = (port & 0x7) as u8;
     let deny: u8 = if allow { 0 } else { 1 };
-    bitmap[idx] &= !(1 << bit);
-    bitmap[idx] |= deny << bit;
+    tss.port_bitmap[idx] &= !(1 << bit);
+    tss.port_bitmap[idx] |= deny << bit;
+    if allow {
+        tss.granted = tss.granted.max(idx + 1);
+    }
+}
+
+/// Size in bytes of a bitmap of all ports.
+const IO_BITMAP_SIZE: usize = 0x10000 / 8;
+
+/// I/O permission bitmap of a user context, with all ports denied by
+/// default.
+#[derive(Clone)]
+pub struct IoBitmap {
+    /// Bit in bits: 0 indicates accessible, 1 indicates inaccessible.
+    bits: [u8; IO_BITMAP_SIZE],
+    /// Bytes up to the highest granted port
+    len: usize,
+}
+
+impl Default for IoBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for IoBitmap {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let granted = (0..=u16::MAX).filter(|&port| self.is_granted(port)).count();
+        f.debug_struct("IoBitmap")
+            .field("granted", &granted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl IoBitmap {
+    /// Create a bitmap denying all ports.
+    pub const fn new() -> Self {
+        IoBitmap {
+            bits: [!0; IO_BITMAP_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Allow access to `ports`.
+    pub fn grant(&mut self, ports: RangeInclusive<u16>) {
+        if ports.is_empty() {
+            return;
+        }
+        let end = *ports.end() as usize / 8 + 1;
+        for port in ports {
+            self.bits[port as usize / 8] &= !(1 << (port % 8));
+        }
+        self.len = self.len.max(end);
+    }
+
+    /// Deny access to `ports`.
+    pub fn revoke(&mut self, ports: RangeInclusive<u16>) {
+        for port in ports {
+            self.bits[port as usize / 8] |= 1 << (port % 8);
+        }
+        while self.len > 0 && self.bits[self.len - 1] == !0 {
+            self.len -= 1;
+        }
+    }
+
+    /// Deny access to all ports.
+    pub fn revoke_all(&mut self) {
+        self.bits[..self.len].fill(!0);
+        self.len = 0;
+    }
+
+    /// Whether access to `port` is allowed.
+    pub fn is_granted(&self, port: u16) -> bool {
+        self.bits[port as usize / 8] & (1 << (port % 8)) == 0
+    }
+
+    /// Load the bitmap into the TSS of the current CPU, saving the
+    /// permissions of the CPU to be restored by [`uninstall`].
+    ///
+    /// The ports granted on the CPU but not by the bitmap are denied.
+    fn install(&self) {
+        let tss = current_tss();
+        // the last byte of the TSS bitmap stays as the terminator, and the
+        // bytes beyond both `len` and `granted` deny all in both
+        let len = self
+            .len
+            .max(tss.granted)
+            .min(TSSWithPortBitmap::BITMAP_VALID_SIZE);
+        tss.saved[..len].copy_from_slice(&tss.port_bitmap[..len]);
+        tss.port_bitmap[..len].copy_from_slice(&self.bits[..len]);
+        tss.installed = len;
+    }
+}
+
+/// Restore the permissions of the current CPU saved by
+/// [`IoBitmap::install`].
+fn uninstall() {
+    let tss = current_tss();
+    let installed = tss.installed;
+    tss.port_bitmap[..installed].copy_from_slice(&tss.saved[..installed]);
+    tss.installed = 0;
+}
+
+impl UserContext {
+    /// Go to user with the I/O permissions of `bitmap`, like
+    /// [`run`](Self::run).
+    ///
+    /// The context gets only the ports granted by `bitmap`, not those set
+    /// by [`set_permission`] on the CPU. The permissions of the CPU are
+    /// restored when it returns, so other contexts run by `run()` do not
+    /// get the ports of `bitmap`.
+    pub fn run_with_io_bitmap(&mut self, bitmap: &IoBitmap) {
+        bitmap.install();
+        self.run();
+        uninstall();
+    }
 }
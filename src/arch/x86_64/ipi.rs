@@ -0,0 +1,85 @@
+//! Inter-processor interrupts by x2APIC.
+//!
+//! A range of vectors is reserved for IPIs by [`set_vectors`], each for a
+//! payload. The receiver gets `TrapReason::Ipi(payload)` from
+//! `trap_reason()` if it was in user, or the vector in `TrapFrame` if it
+//! was in kernel, which can be decoded by [`payload`].
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+
+/// Interrupt Command Register of x2APIC
+const IA32_X2APIC_ICR: u32 = 0x830;
+/// Destination shorthand: all excluding self
+const ALL_EXCLUDING_SELF: u64 = 0b11 << 18;
+
+/// First vector for IPIs, and number of them.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Reserve `count` vectors from `base` for IPIs, with payloads from 0.
+///
+/// Return false if the vectors are not all external vectors, or include the
+/// legacy syscall vector `0x80`.
+pub fn set_vectors(base: u8, count: u8) -> bool {
+    let base = base as usize;
+    let count = count as usize;
+    if base < super::interrupt::FIRST_EXTERNAL_VECTOR
+        || base + count > super::interrupt::NUM_VECTORS
+        || (base..base + count).contains(&0x80)
+    {
+        return false;
+    }
+    BASE.store(base, Ordering::Relaxed);
+    COUNT.store(count, Ordering::Relaxed);
+    true
+}
+
+/// Get the payload of an IPI by its `vector`, or `None` if it is not an IPI.
+pub fn payload(vector: usize) -> Option<usize> {
+    let index = vector.wrapping_sub(BASE.load(Ordering::Relaxed));
+    if index < COUNT.load(Ordering::Relaxed) {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// Get the vector of `payload`.
+fn vector(payload: usize) -> u64 {
+    assert!(
+        payload < COUNT.load(Ordering::Relaxed),
+        "no vector for IPI payload {}",
+        payload
+    );
+    (BASE.load(Ordering::Relaxed) + payload) as u64
+}
+
+/// Send an IPI with `payload` to the CPU of x2APIC ID `apic_id`.
+///
+/// # Safety
+///
+/// The local APIC must be in x2APIC mode.
+pub unsafe fn send(apic_id: u32, payload: usize) {
+    Msr::new(IA32_X2APIC_ICR).write((apic_id as u64) << 32 | vector(payload));
+}
+
+/// Send an IPI with `payload` to each CPU in `apic_ids`.
+///
+/// # Safety
+///
+/// The local APIC must be in x2APIC mode.
+pub unsafe fn send_to(apic_ids: impl IntoIterator<Item = u32>, payload: usize) {
+    for apic_id in apic_ids {
+        send(apic_id, payload);
+    }
+}
+
+/// Send an IPI with `payload` to all CPUs except the current one.
+///
+/// # Safety
+///
+/// The local APIC must be in x2APIC mode.
+pub unsafe fn broadcast(payload: usize) {
+    Msr::new(IA32_X2APIC_ICR).write(ALL_EXCLUDING_SELF | vector(payload));
+}
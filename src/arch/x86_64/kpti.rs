@@ -0,0 +1,135 @@
+//! Kernel page-table isolation (KPTI), against Meltdown.
+//!
+//! With feature `kpti`, user programs can run with page tables that do not
+//! map the kernel. `UserContext::run()` switches `CR3` to
+//! [`UserContext::set_user_cr3`] right before going to user, and the trap
+//! entries switch back to the kernel page table before touching kernel memory.
+//!
+//! The code and data used between the switches must be mapped in every user
+//! page table, as supervisor-only pages:
+//!
+//! - the code returned by [`trampoline_text()`]
+//! - the per-CPU data returned by [`cpu_entry_area()`] on each CPU
+//!
+//! Instead of the kernel stack, `TSS.sp0` always points to a small per-CPU
+//! trampoline stack, which keeps the kernel `rsp` and `CR3` above its top.
+
+use super::gdt::{IST_STACK_SIZE, NMI_RESERVED_SIZE, TSS};
+use super::memory::{self, MemoryKind};
+use super::UserContext;
+use crate::MAX_CPUS;
+use core::alloc::Layout;
+use core::arch::asm;
+use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::AtomicUsize;
+use x86_64::registers::model_specific::GsBase;
+use x86_64::structures::DescriptorTablePointer;
+
+/// Size of the trampoline stack of each CPU.
+const STACK_SIZE: usize = 0x1000;
+
+/// Size reserved above the trampoline stack, see `syscall_return` in `syscall.S`.
+///
+/// `[top + 0]`: kernel `rsp`, `[top + 8]`: kernel `CR3`
+const RESERVED_SIZE: usize = 16;
+
+#[repr(C, align(4096))]
+struct TrampolineStack([u8; STACK_SIZE]);
+
+const EMPTY_STACK: TrampolineStack = TrampolineStack([0; STACK_SIZE]);
+
+/// Trampoline stack of each CPU, without a memory provider.
+static mut STACKS: [TrampolineStack; MAX_CPUS] = [EMPTY_STACK; MAX_CPUS];
+/// Number of slots taken in [`STACKS`].
+static STACK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate a trampoline stack for the current CPU, return its top.
+pub(super) fn new_trampoline_stack() -> u64 {
+    let base = match memory::alloc(
+        MemoryKind::TrampolineStack,
+        Layout::new::<TrampolineStack>(),
+    ) {
+        Some(addr) => addr,
+        None => unsafe { STACKS[super::gdt::take_slot(&STACK_COUNT)].0.as_ptr() as usize },
+    };
+    let top = base + STACK_SIZE - RESERVED_SIZE;
+    // traps before the first `run()` still find the kernel page table
+    let cr3: usize;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        *((top + 8) as *mut usize) = cr3;
+    }
+    top as u64
+}
+
+/// Get the address ranges of the trap entry and exit code.
+///
+/// They are placed in section `.text.trampoline`.
+pub fn trampoline_text() -> [Range<usize>; 3] {
+    extern "C" {
+        static __trampoline_syscall_start: u8;
+        static __trampoline_syscall_end: u8;
+        static __trampoline_trap_start: u8;
+        static __trampoline_trap_end: u8;
+        static __trampoline_vectors_start: u8;
+        static __trampoline_vectors_end: u8;
+    }
+    unsafe {
+        [
+            addr(&__trampoline_syscall_start)..addr(&__trampoline_syscall_end),
+            addr(&__trampoline_trap_start)..addr(&__trampoline_trap_end),
+            addr(&__trampoline_vectors_start)..addr(&__trampoline_vectors_end),
+        ]
+    }
+}
+
+/// Get the address ranges of the data of the current CPU, accessed by the
+/// CPU or the trampoline with the user page table: GDT, IDT, TSS,
+/// trampoline stack and IST stacks.
+///
+/// [`init()`](crate::init) or [`init_ap()`](crate::init_ap) must have been
+/// called on the current CPU.
+pub fn cpu_entry_area() -> [Range<usize>; 7] {
+    let tss = unsafe { &*(GsBase::read().as_u64() as *const TSS) };
+    let tss_start = tss as *const TSS as usize;
+    let sp0 = tss.privilege_stack_table[0].as_u64() as usize;
+    // cover the words reserved above the NMI stack
+    let reserved = NMI_RESERVED_SIZE as usize;
+    let ist = |index: usize| {
+        let top = tss.interrupt_stack_table[index].as_u64() as usize;
+        top + reserved - IST_STACK_SIZE..top + reserved
+    };
+    [
+        table(unsafe { super::gdt::sgdt() }),
+        table(super::idt::sidt()),
+        tss_start..tss_start + size_of::<TSS>(),
+        sp0 + RESERVED_SIZE - STACK_SIZE..sp0 + RESERVED_SIZE,
+        ist(0),
+        ist(1),
+        ist(2),
+    ]
+}
+
+fn addr(symbol: &u8) -> usize {
+    symbol as *const u8 as usize
+}
+
+fn table(dtp: DescriptorTablePointer) -> Range<usize> {
+    let base = dtp.base.as_u64() as usize;
+    base..base + dtp.limit as usize + 1
+}
+
+impl UserContext {
+    /// Set the page table to switch to on the way to user.
+    ///
+    /// 0 for staying in the current page table.
+    pub fn set_user_cr3(&mut self, cr3: usize) {
+        self.user_cr3 = cr3;
+    }
+
+    /// Get the page table to switch to on the way to user.
+    pub fn get_user_cr3(&self) -> usize {
+        self.user_cr3
+    }
+}
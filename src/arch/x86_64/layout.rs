@@ -0,0 +1,65 @@
+//! Offsets of fields of `UserContext` and `TrapFrame`, for kernels which
+//! access them in assembly.
+//!
+//! The layout is stable within a major version, so the offsets only change
+//! in a breaking release. Fields behind features are not covered. Each
+//! offset is checked against the structure at compile time.
+
+#[cfg(baremetal)]
+use super::TrapFrame;
+use super::UserContext;
+
+layout!(UserContext {
+    USER_CONTEXT_RAX_OFFSET = 0 => general.rax,
+    USER_CONTEXT_RBX_OFFSET = 1 => general.rbx,
+    USER_CONTEXT_RCX_OFFSET = 2 => general.rcx,
+    USER_CONTEXT_RDX_OFFSET = 3 => general.rdx,
+    USER_CONTEXT_RSI_OFFSET = 4 => general.rsi,
+    USER_CONTEXT_RDI_OFFSET = 5 => general.rdi,
+    USER_CONTEXT_RBP_OFFSET = 6 => general.rbp,
+    USER_CONTEXT_RSP_OFFSET = 7 => general.rsp,
+    USER_CONTEXT_R8_OFFSET = 8 => general.r8,
+    USER_CONTEXT_R9_OFFSET = 9 => general.r9,
+    USER_CONTEXT_R10_OFFSET = 10 => general.r10,
+    USER_CONTEXT_R11_OFFSET = 11 => general.r11,
+    USER_CONTEXT_R12_OFFSET = 12 => general.r12,
+    USER_CONTEXT_R13_OFFSET = 13 => general.r13,
+    USER_CONTEXT_R14_OFFSET = 14 => general.r14,
+    USER_CONTEXT_R15_OFFSET = 15 => general.r15,
+    USER_CONTEXT_RIP_OFFSET = 16 => general.rip,
+    USER_CONTEXT_RFLAGS_OFFSET = 17 => general.rflags,
+    USER_CONTEXT_FSBASE_OFFSET = 18 => general.fsbase,
+    USER_CONTEXT_GSBASE_OFFSET = 19 => general.gsbase,
+    USER_CONTEXT_TRAP_NUM_OFFSET = 20 => trap_num,
+    USER_CONTEXT_ERROR_CODE_OFFSET = 21 => error_code,
+    USER_CONTEXT_CR2_OFFSET = 22 => cr2,
+    USER_CONTEXT_CS_OFFSET = 23 => cs,
+    USER_CONTEXT_SS_OFFSET = 24 => ss,
+    USER_CONTEXT_DEBUG_OFFSET = 25 => debug,
+    USER_CONTEXT_FORCE_IRET_OFFSET = 31 => force_iret,
+});
+
+#[cfg(baremetal)]
+layout!(TrapFrame {
+    TRAP_FRAME_RAX_OFFSET = 0 => rax,
+    TRAP_FRAME_RBX_OFFSET = 1 => rbx,
+    TRAP_FRAME_RCX_OFFSET = 2 => rcx,
+    TRAP_FRAME_RDX_OFFSET = 3 => rdx,
+    TRAP_FRAME_RSI_OFFSET = 4 => rsi,
+    TRAP_FRAME_RDI_OFFSET = 5 => rdi,
+    TRAP_FRAME_RBP_OFFSET = 6 => rbp,
+    TRAP_FRAME_RSP_OFFSET = 7 => rsp,
+    TRAP_FRAME_R8_OFFSET = 8 => r8,
+    TRAP_FRAME_R9_OFFSET = 9 => r9,
+    TRAP_FRAME_R10_OFFSET = 10 => r10,
+    TRAP_FRAME_R11_OFFSET = 11 => r11,
+    TRAP_FRAME_R12_OFFSET = 12 => r12,
+    TRAP_FRAME_R13_OFFSET = 13 => r13,
+    TRAP_FRAME_R14_OFFSET = 14 => r14,
+    TRAP_FRAME_R15_OFFSET = 15 => r15,
+    TRAP_FRAME_TRAP_NUM_OFFSET = 17 => trap_num,
+    TRAP_FRAME_ERROR_CODE_OFFSET = 18 => error_code,
+    TRAP_FRAME_RIP_OFFSET = 19 => rip,
+    TRAP_FRAME_CS_OFFSET = 20 => cs,
+    TRAP_FRAME_RFLAGS_OFFSET = 21 => rflags,
+});
@@ -0,0 +1,70 @@
+//! Lazy switching of the floating-point state by `CR0.TS`.
+//!
+//! With feature `lazy_fpu`, `UserContext::run()` does not load `fp` before
+//! going to user. Instead it sets `CR0.TS` right before going to user and
+//! clears it right after coming back, so that the first FPU or SSE
+//! instruction of the user raises #NM (device not available), while the
+//! kernel never runs with it set. The trap is handled inside `run()` by
+//! loading `fp` and resuming the user, so it is never reported to the kernel.
+//!
+//! The state is saved back to `fp` only if the user has touched the FPU,
+//! so programs that never use it do not pay for switching it. Likewise,
+//! the state of the kernel is only saved and restored when the user state
+//! is loaded.
+//!
+//! The context that last loaded `fp` on the CPU is recorded as the owner of
+//! FPU, and is loaded eagerly on its next `run()` without setting `CR0.TS`,
+//! since it is likely to use FPU again. The owner is only a hint: a context
+//! reusing the address of a dropped one is at worst loaded eagerly.
+
+use super::gdt::TSS_IST7_OFFSET;
+use super::FpState;
+use core::arch::asm;
+
+/// Trap number of #NM.
+pub(super) const DEVICE_NOT_AVAILABLE: usize = 7;
+
+/// Load `fp` eagerly and return `true` if `owner` is the owner of FPU on
+/// the current CPU. Otherwise return `false`, and `CR0.TS` should be set
+/// when going to user.
+pub(super) fn begin(owner: usize, fp: &FpState, kernel: &mut FpState) -> bool {
+    let current: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{off}]",
+            out(reg) current,
+            off = const TSS_IST7_OFFSET,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    if current != owner {
+        return false;
+    }
+    kernel.save();
+    fp.restore();
+    true
+}
+
+/// Handle #NM from user by saving the state of the kernel to `kernel` and
+/// loading `fp`, and make `owner` the owner of FPU on the current CPU.
+pub(super) fn load(owner: usize, fp: &FpState, kernel: &mut FpState) {
+    kernel.save();
+    fp.restore();
+    unsafe {
+        asm!(
+            "mov gs:[{off}], {}",
+            in(reg) owner,
+            off = const TSS_IST7_OFFSET,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Save the state to `fp` and restore `kernel` unless loading `fp` is still
+/// `pending`, i.e. the user has not used FPU.
+pub(super) fn end(pending: bool, fp: &mut FpState, kernel: &FpState) {
+    if !pending {
+        fp.save();
+        kernel.restore();
+    }
+}
@@ -0,0 +1,474 @@
+//! Register layouts of Linux ABI, for `ptrace`, signal delivery and core dump.
+
+use super::UserContext;
+use crate::signal::{read_at, write_at, SigInfo};
+use core::mem::size_of;
+
+/// Flags in `RFLAGS` that user can change by `ptrace` and `rt_sigreturn`:
+/// CF, PF, AF, ZF, SF, TF, DF, OF, RF, AC
+const RFLAGS_USER_MASK: usize = 0x50dd5;
+
+/// `struct user_regs_struct` of `PTRACE_GETREGS`, also `elf_gregset_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct UserRegs {
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub rbp: usize,
+    pub rbx: usize,
+    pub r11: usize,
+    pub r10: usize,
+    pub r9: usize,
+    pub r8: usize,
+    pub rax: usize,
+    pub rcx: usize,
+    pub rdx: usize,
+    pub rsi: usize,
+    pub rdi: usize,
+    pub orig_rax: usize,
+    pub rip: usize,
+    pub cs: usize,
+    pub eflags: usize,
+    pub rsp: usize,
+    pub ss: usize,
+    pub fs_base: usize,
+    pub gs_base: usize,
+    pub ds: usize,
+    pub es: usize,
+    pub fs: usize,
+    pub gs: usize,
+}
+
+/// `struct sigcontext`, the `uc_mcontext` of `ucontext_t`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigContext {
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rbp: usize,
+    pub rbx: usize,
+    pub rdx: usize,
+    pub rax: usize,
+    pub rcx: usize,
+    pub rsp: usize,
+    pub rip: usize,
+    pub eflags: usize,
+    pub cs: u16,
+    pub gs: u16,
+    pub fs: u16,
+    pub ss: u16,
+    pub err: usize,
+    pub trapno: usize,
+    pub oldmask: usize,
+    pub cr2: usize,
+    /// Pointer to the FXSAVE / XSAVE area in user memory, 0 for none
+    pub fpstate: usize,
+    pub reserved1: [usize; 8],
+}
+
+/// `struct ucontext`
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    /// `stack_t`, all 0 for no alternate signal stack
+    stack: [usize; 3],
+    mcontext: SigContext,
+    sigmask: u64,
+}
+
+/// `struct rt_sigframe`, followed by the FXSAVE area with feature `fpu`.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct RtSigFrame {
+    pretcode: usize,
+    uc: UContext,
+    info: SigInfo,
+}
+
+unsafe impl pod::Pod for UserRegs {}
+unsafe impl pod::Pod for SigContext {}
+unsafe impl pod::Pod for UContext {}
+unsafe impl pod::Pod for RtSigFrame {}
+
+impl UserContext {
+    /// Get registers in the layout of `user_regs_struct`.
+    ///
+    /// Only `cs` and `ss` of segment selectors are in the context, others
+    /// are reported as 0. `orig_rax` is the syscall number, or -1 if not trapped by syscall.
+    pub fn get_user_regs(&self) -> UserRegs {
+        let g = &self.general;
+        UserRegs {
+            r15: g.r15,
+            r14: g.r14,
+            r13: g.r13,
+            r12: g.r12,
+            rbp: g.rbp,
+            rbx: g.rbx,
+            r11: g.r11,
+            r10: g.r10,
+            r9: g.r9,
+            r8: g.r8,
+            rax: g.rax,
+            rcx: g.rcx,
+            rdx: g.rdx,
+            rsi: g.rsi,
+            rdi: g.rdi,
+            orig_rax: if self.trap_num == 0x100 || self.is_legacy_syscall() {
+                g.rax
+            } else {
+                usize::MAX
+            },
+            rip: g.rip,
+            cs: self.cs,
+            eflags: g.rflags,
+            rsp: g.rsp,
+            ss: self.ss,
+            fs_base: g.fsbase,
+            gs_base: g.gsbase,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `user_regs_struct`.
+    ///
+    /// Only user flags in `eflags` are taken. `orig_rax` and segment
+    /// selectors are ignored.
+    pub fn set_user_regs(&mut self, regs: &UserRegs) {
+        let g = &mut self.general;
+        g.r15 = regs.r15;
+        g.r14 = regs.r14;
+        g.r13 = regs.r13;
+        g.r12 = regs.r12;
+        g.rbp = regs.rbp;
+        g.rbx = regs.rbx;
+        g.r11 = regs.r11;
+        g.r10 = regs.r10;
+        g.r9 = regs.r9;
+        g.r8 = regs.r8;
+        g.rax = regs.rax;
+        g.rcx = regs.rcx;
+        g.rdx = regs.rdx;
+        g.rsi = regs.rsi;
+        g.rdi = regs.rdi;
+        g.rip = regs.rip;
+        g.rflags = (g.rflags & !RFLAGS_USER_MASK) | (regs.eflags & RFLAGS_USER_MASK);
+        g.rsp = regs.rsp;
+        g.fsbase = regs.fs_base;
+        g.gsbase = regs.gs_base;
+    }
+
+    /// Get registers in the layout of `struct sigcontext`.
+    ///
+    /// `fpstate` and `oldmask` are left 0 for the caller to fill.
+    pub fn get_sigcontext(&self) -> SigContext {
+        let g = &self.general;
+        SigContext {
+            r8: g.r8,
+            r9: g.r9,
+            r10: g.r10,
+            r11: g.r11,
+            r12: g.r12,
+            r13: g.r13,
+            r14: g.r14,
+            r15: g.r15,
+            rdi: g.rdi,
+            rsi: g.rsi,
+            rbp: g.rbp,
+            rbx: g.rbx,
+            rdx: g.rdx,
+            rax: g.rax,
+            rcx: g.rcx,
+            rsp: g.rsp,
+            rip: g.rip,
+            eflags: g.rflags,
+            cs: self.cs as u16,
+            ss: self.ss as u16,
+            err: self.error_code,
+            trapno: self.trap_num,
+            cr2: self.cr2,
+            ..Default::default()
+        }
+    }
+
+    /// Set registers from the layout of `struct sigcontext`, as `rt_sigreturn`.
+    ///
+    /// Only user flags in `eflags` are taken. Segment selectors and trap
+    /// information are ignored.
+    pub fn set_sigcontext(&mut self, sc: &SigContext) {
+        let g = &mut self.general;
+        g.r8 = sc.r8;
+        g.r9 = sc.r9;
+        g.r10 = sc.r10;
+        g.r11 = sc.r11;
+        g.r12 = sc.r12;
+        g.r13 = sc.r13;
+        g.r14 = sc.r14;
+        g.r15 = sc.r15;
+        g.rdi = sc.rdi;
+        g.rsi = sc.rsi;
+        g.rbp = sc.rbp;
+        g.rbx = sc.rbx;
+        g.rdx = sc.rdx;
+        g.rax = sc.rax;
+        g.rcx = sc.rcx;
+        g.rsp = sc.rsp;
+        g.rip = sc.rip;
+        g.rflags = (g.rflags & !RFLAGS_USER_MASK) | (sc.eflags & RFLAGS_USER_MASK);
+    }
+
+    /// Push a signal frame for `handler` onto the user stack, as Linux does.
+    ///
+    /// `stack` is the user memory right below the current `rsp`. The frame
+    /// saves the registers, `mask` as the blocked signals, and the FPU state
+    /// with feature `fpu`. The handler returns to `restorer`, which should
+    /// call `rt_sigreturn`.
+    ///
+    /// Return the address of the frame, or `None` if `stack` is too small.
+    pub fn push_signal_frame(
+        &mut self,
+        stack: &mut [u8],
+        info: &SigInfo,
+        handler: usize,
+        restorer: usize,
+        mask: u64,
+    ) -> Option<usize> {
+        let base = self.general.rsp.checked_sub(stack.len())?;
+        // skip the red zone
+        let mut sp = self.general.rsp.checked_sub(128)?;
+        let mut mcontext = self.get_sigcontext();
+        mcontext.oldmask = mask as usize;
+        #[cfg(feature = "fpu")]
+        {
+            sp = sp.checked_sub(size_of::<super::FpState>())? & !63;
+            write_at(stack, base, sp, &self.fp)?;
+            mcontext.fpstate = sp;
+        }
+        sp = (sp.checked_sub(size_of::<RtSigFrame>())? & !15).checked_sub(8)?;
+        let frame = RtSigFrame {
+            pretcode: restorer,
+            uc: UContext {
+                mcontext,
+                sigmask: mask,
+                ..Default::default()
+            },
+            info: *info,
+        };
+        write_at(stack, base, sp, &frame)?;
+
+        const TF: usize = 1 << 8;
+        const DF: usize = 1 << 10;
+        const RF: usize = 1 << 16;
+        let g = &mut self.general;
+        g.rdi = info.signo as usize;
+        g.rsi = sp + size_of::<usize>() + size_of::<UContext>();
+        g.rdx = sp + size_of::<usize>();
+        g.rax = 0;
+        g.rsp = sp;
+        g.rip = handler;
+        g.rflags &= !(TF | DF | RF);
+        Some(sp)
+    }
+
+    /// Get the address of the signal frame when the user calls `rt_sigreturn`.
+    ///
+    /// The handler has popped `pretcode` by returning to the restorer.
+    pub fn signal_frame_addr(&self) -> usize {
+        self.general.rsp - size_of::<usize>()
+    }
+
+    /// Restore registers from the signal frame pushed by
+    /// [`push_signal_frame`](Self::push_signal_frame), as `rt_sigreturn`.
+    ///
+    /// `stack` is the user memory from [`signal_frame_addr`](Self::signal_frame_addr).
+    ///
+    /// Return the saved signal mask, or `None` if `stack` is too small.
+    pub fn restore_signal_frame(&mut self, stack: &[u8]) -> Option<u64> {
+        let base = self.signal_frame_addr();
+        let frame: RtSigFrame = read_at(stack, base, base)?;
+        #[cfg(feature = "fpu")]
+        if frame.uc.mcontext.fpstate != 0 {
+            let mut fp: super::FpState = read_at(stack, base, frame.uc.mcontext.fpstate)?;
+            // clear reserved bits of MXCSR, which fault in `fxrstor`
+            fp.as_bytes_mut()[26..28].fill(0);
+            self.fp = fp;
+        }
+        self.set_sigcontext(&frame.uc.mcontext);
+        Some(frame.uc.sigmask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneralRegs;
+
+    fn general_regs() -> GeneralRegs {
+        GeneralRegs {
+            rax: 1,
+            rbx: 2,
+            rcx: 3,
+            rdx: 4,
+            rsi: 5,
+            rdi: 6,
+            rbp: 7,
+            rsp: 0x7fff_0000,
+            r8: 8,
+            r9: 9,
+            r10: 10,
+            r11: 11,
+            r12: 12,
+            r13: 13,
+            r14: 14,
+            r15: 15,
+            rip: 0x40_1000,
+            rflags: 0x202,
+            fsbase: 0x7000_0000,
+            gsbase: 0x7000_1000,
+        }
+    }
+
+    #[test]
+    fn user_regs_round_trip() {
+        let mut cx = UserContext {
+            general: general_regs(),
+            cs: 0x33,
+            ss: 0x2b,
+            ..Default::default()
+        };
+        let regs = cx.get_user_regs();
+        assert_eq!((regs.rax, regs.r15, regs.rip), (1, 15, 0x40_1000));
+        assert_eq!((regs.cs, regs.ss, regs.ds), (0x33, 0x2b, 0));
+        assert_eq!((regs.fs_base, regs.gs_base), (0x7000_0000, 0x7000_1000));
+        // not trapped by syscall
+        assert_eq!(regs.orig_rax, usize::MAX);
+        cx.trap_num = 0x100;
+        assert_eq!(cx.get_user_regs().orig_rax, 1);
+
+        // segment selectors are not taken
+        let mut other = UserContext {
+            general: GeneralRegs {
+                rflags: 0x202,
+                ..Default::default()
+            },
+            cs: 0x33,
+            ss: 0x2b,
+            ..Default::default()
+        };
+        other.set_user_regs(&regs);
+        assert_eq!(other.general, cx.general);
+        assert_eq!(other.get_user_regs(), regs);
+    }
+
+    #[test]
+    fn set_user_regs_flags() {
+        let mut cx = UserContext::default();
+        cx.general.rflags = 0x202;
+        let regs = UserRegs {
+            eflags: !0,
+            ..Default::default()
+        };
+        cx.set_user_regs(&regs);
+        // IF and the reserved bit 1 are kept, IOPL and others are not taken
+        assert_eq!(cx.general.rflags, 0x202 | RFLAGS_USER_MASK);
+        cx.set_user_regs(&UserRegs::default());
+        assert_eq!(cx.general.rflags, 0x202);
+    }
+
+    #[test]
+    fn signal_frame_round_trip() {
+        const TF: usize = 1 << 8;
+        const DF: usize = 1 << 10;
+        const RF: usize = 1 << 16;
+        const HANDLER: usize = 0x40_2000;
+        const RESTORER: usize = 0x40_3000;
+        const MASK: u64 = 0x4001;
+        // unaligned stack pointer
+        let rsp = 0x7fff_0ff3;
+        let mut cx = UserContext {
+            general: GeneralRegs {
+                rsp,
+                rflags: 0x202 | TF | DF | RF | 1,
+                ..general_regs()
+            },
+            ..Default::default()
+        };
+        let saved = cx;
+        let mut stack = [0u8; 0x2000];
+        let base = rsp - stack.len();
+        let info = SigInfo::new(10, 0);
+        let sp = cx
+            .push_signal_frame(&mut stack, &info, HANDLER, RESTORER, MASK)
+            .unwrap();
+
+        // below the red zone, 16 bytes aligned before the return address
+        assert!(sp + size_of::<RtSigFrame>() <= rsp - 128);
+        assert_eq!((sp + 8) % 16, 0);
+        let frame: RtSigFrame = read_at(&stack, base, sp).unwrap();
+        assert_eq!(frame.pretcode, RESTORER);
+        assert_eq!(frame.uc.sigmask, MASK);
+        assert_eq!(frame.uc.mcontext.rip, 0x40_1000);
+        let g = &cx.general;
+        assert_eq!((g.rsp, g.rip, g.rax), (sp, HANDLER, 0));
+        assert_eq!(g.rdi, 10);
+        assert_eq!(g.rdx, sp + 8);
+        assert_eq!(g.rsi, sp + 8 + size_of::<UContext>());
+        assert_eq!(read_at::<SigInfo>(&stack, base, g.rsi), Some(info));
+        assert_eq!(g.rflags, 0x202 | 1);
+
+        #[cfg(feature = "fpu")]
+        {
+            let fpstate = frame.uc.mcontext.fpstate;
+            // 64 bytes aligned, between the frame and the red zone
+            assert_eq!(fpstate % 64, 0);
+            assert!(fpstate >= sp + size_of::<RtSigFrame>());
+            assert!(fpstate + size_of::<super::super::FpState>() <= rsp - 128);
+            // reserved bits of MXCSR set by user are cleared on return
+            stack[fpstate - base + 26..fpstate - base + 28].fill(0xff);
+        }
+
+        // the handler returns to the restorer, which calls `rt_sigreturn`
+        cx.general = GeneralRegs {
+            rsp: sp + 8,
+            rflags: cx.general.rflags,
+            ..Default::default()
+        };
+        let addr = cx.signal_frame_addr();
+        assert_eq!(addr, sp);
+        assert_eq!(cx.restore_signal_frame(&stack[addr - base..]), Some(MASK));
+        assert_eq!(
+            cx.general,
+            GeneralRegs {
+                fsbase: 0,
+                gsbase: 0,
+                ..saved.general
+            }
+        );
+        #[cfg(feature = "fpu")]
+        assert_eq!(cx.fp, saved.fp);
+    }
+
+    #[test]
+    fn signal_frame_too_small() {
+        let mut cx = UserContext {
+            general: general_regs(),
+            ..Default::default()
+        };
+        let saved = cx;
+        let mut stack = [0u8; 0x100];
+        let info = SigInfo::new(10, 0);
+        assert_eq!(cx.push_signal_frame(&mut stack, &info, 0, 0, 0), None);
+        assert_eq!(cx.general, saved.general);
+    }
+}
@@ -0,0 +1,92 @@
+//! Placement of the per-CPU tables and stacks.
+//!
+//! By default, the GDT, TSS, IDT and KPTI trampoline stacks are kept in
+//! static arrays, and the kernel stacks of the TSS are allocated from the
+//! heap, or a static pool without feature `alloc`.
+//!
+//! Kernels that need them in specific memory regions, e.g. to map them
+//! into each user page table, pass a [`MemoryProvider`] to
+//! [`init_with`](crate::init_with) and [`init_ap_with`](crate::init_ap_with).
+//! The provider is shared by all CPUs, set once by the first of them.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// What the memory is allocated for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MemoryKind {
+    /// Global Descriptor Table of a CPU
+    Gdt,
+    /// Interrupt Descriptor Table, shared by all CPUs
+    Idt,
+    /// Task State Segment of a CPU
+    Tss,
+    /// Stack of a CPU for traps from user, `TSS.sp0`
+    KernelStack,
+    /// Stack of a CPU for NMI, double fault or machine check
+    IstStack,
+    /// Trampoline stack of a CPU with feature `kpti`
+    TrampolineStack,
+}
+
+/// Allocator of the tables and stacks of the crate.
+pub trait MemoryProvider: Sync {
+    /// Allocate zeroed memory of `layout` for `kind`, and return its
+    /// virtual address. The memory is never freed.
+    ///
+    /// Return `None` to fall back to the default placement.
+    fn alloc(&self, kind: MemoryKind, layout: Layout) -> Option<NonNull<u8>>;
+}
+
+/// The provider, written once before [`PROVIDER_STATE`] becomes 2.
+static mut PROVIDER: Option<&'static dyn MemoryProvider> = None;
+/// 0 for not set, 1 for being set, 2 for set.
+static PROVIDER_STATE: AtomicU8 = AtomicU8::new(0);
+
+/// Set the provider of all CPUs used by the following allocations, if not
+/// set yet.
+///
+/// Return false if a different provider has been set.
+pub(super) fn set_provider(provider: &'static dyn MemoryProvider) -> bool {
+    if PROVIDER_STATE
+        .compare_exchange(0, 1, Ordering::Acquire, Ordering::Acquire)
+        .is_ok()
+    {
+        unsafe { PROVIDER = Some(provider) };
+        PROVIDER_STATE.store(2, Ordering::Release);
+        return true;
+    }
+    let current = loop {
+        if let Some(current) = self::provider() {
+            break current;
+        }
+        core::hint::spin_loop();
+    };
+    core::ptr::eq(
+        current as *const dyn MemoryProvider as *const u8,
+        provider as *const dyn MemoryProvider as *const u8,
+    )
+}
+
+/// Get the provider if set.
+fn provider() -> Option<&'static dyn MemoryProvider> {
+    match PROVIDER_STATE.load(Ordering::Acquire) {
+        2 => unsafe { PROVIDER },
+        _ => None,
+    }
+}
+
+/// Allocate memory of `layout` for `kind` by the provider, `None` if there
+/// is no provider or it falls back.
+pub(super) fn alloc(kind: MemoryKind, layout: Layout) -> Option<usize> {
+    let provider = provider()?;
+    let ptr = provider.alloc(kind, layout)?.as_ptr() as usize;
+    assert!(
+        ptr % layout.align() == 0,
+        "misaligned {:?} at {:#x}",
+        kind,
+        ptr
+    );
+    Some(ptr)
+}
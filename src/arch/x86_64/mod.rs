@@ -1,65 +1,335 @@
-#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[cfg(baremetal)]
+pub mod apic;
+#[cfg(baremetal)]
+pub mod cet;
+mod debug;
+mod display;
+mod dwarf;
+mod elf;
+#[cfg(baremetal)]
+pub mod emulate;
+#[cfg(all(fncall, target_os = "linux"))]
+pub mod fault;
+#[cfg(fncall)]
 mod fncall;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+mod fpu;
+#[cfg(feature = "gdbstub")]
+mod gdb;
+#[cfg(baremetal)]
 mod gdt;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+#[cfg(baremetal)]
+mod guest;
+#[cfg(all(fncall, target_os = "linux"))]
+mod hostsig;
+#[cfg(baremetal)]
 mod idt;
+#[cfg(baremetal)]
+pub mod interrupt;
 #[cfg(feature = "ioport_bitmap")]
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+#[cfg(baremetal)]
 pub mod ioport;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+#[cfg(baremetal)]
+pub mod ipi;
+#[cfg(feature = "kpti")]
+#[cfg(baremetal)]
+pub mod kpti;
+mod layout;
+#[cfg(feature = "lazy_fpu")]
+#[cfg(baremetal)]
+mod lazy_fpu;
+pub mod linux;
+#[cfg(baremetal)]
+pub mod memory;
+#[cfg(baremetal)]
+pub mod nesting;
+mod paging;
+#[cfg(baremetal)]
+pub mod percpu;
+#[cfg(all(fncall, target_os = "linux"))]
+pub mod preempt;
+#[cfg(feature = "spectre")]
+#[cfg(baremetal)]
+pub mod spectre;
+#[cfg(baremetal)]
+pub mod stack_guard;
+#[cfg(baremetal)]
 mod syscall;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+#[cfg(feature = "syscall_filter")]
+#[cfg(baremetal)]
+pub mod syscall_filter;
+#[cfg(baremetal)]
+pub mod timer;
+#[cfg(baremetal)]
 mod trap;
+mod xstate;
 
-#[cfg(any(target_os = "linux", target_os = "macos"))]
-pub use fncall::syscall_fn_entry;
-#[cfg(any(target_os = "none", target_os = "uefi"))]
+pub use debug::{BreakpointKind, BreakpointLen, DebugRegs};
+pub(crate) use elf::{ELF_FLAGS, ELF_MACHINE};
+#[cfg(fncall)]
+pub use fncall::{syscall_fn_entry, syscall_fn_entry_redzone};
+pub use fpu::FpState;
+#[cfg(baremetal)]
+pub use guest::{GuestContext, GuestRegs, VmExit, VmExitReason};
+pub use layout::*;
+pub use paging::{is_canonical, is_user_addr, is_user_data_addr, va_bits, LamMode};
+#[cfg(baremetal)]
 pub use trap::TrapFrame;
+pub use xstate::xsave_layout;
+#[cfg(feature = "alloc")]
+pub use xstate::ExtendedState;
+
+#[cfg(baremetal)]
+use crate::TrapInitError;
+use crate::{PageFaultFlags, TrapReason};
 
 /// Initialize interrupt handling on x86_64.
 ///
+/// Return an error before changing any state if a prerequisite checked by
+/// [`check_init()`] is not met. [`TrapInitError::MisalignedStack`] is only
+/// found after allocating the kernel stack, which is then leaked, but still
+/// before any table or register is changed.
+///
 /// # Safety
 ///
 /// This function will:
 ///
 /// - Disable interrupt.
 /// - Switch to a new [GDT], extend 7 more entries from the current one.
+///     - the GDT and TSS of each CPU are kept in static arrays for at most
+///       [`MAX_CPUS`](crate::MAX_CPUS) CPUs
 /// - Switch to a new [TSS], set `GSBASE` to its base address.
+///     - use [`percpu::set_percpu_ptr`] instead of writing `GSBASE`
+///     - allocate [IST] stacks for NMI, double fault and machine check,
+///       from a static pool without feature `alloc`
 /// - Switch to a new [IDT], override the current one.
 /// - Enable [`syscall`] instruction.
 ///     - set `EFER::SYSTEM_CALL_EXTENSIONS`
+/// - Enable `int 0x80` and `sysenter` for legacy syscalls of 32-bit programs.
+///     - set the `SYSENTER_CS`, `SYSENTER_ESP` and `SYSENTER_EIP` MSRs
+///     - a machine check between `sysenter` and `swapgs` of its entry is
+///       handled with user `GSBASE`, while NMI checks `GSBASE` by MSR
+/// - Enable [`xsave`] instruction if supported.
+///     - set `CR4::OSXSAVE`
+///     - enable x87, SSE, AVX and AVX-512 state in `XCR0` as far as supported
+///
+/// To run with supervisor shadow stacks of CET, also call
+/// [`cet::enable_shadow_stack`] after it. To trap `rdtsc` and `rdpmc` of
+/// user for emulation, call [`emulate::trap_user_counters`] after it, and
+/// [`emulate::trap_user_cpuid`] for `cpuid`.
+///
+/// To allocate the IST stacks with guard pages, call
+/// [`stack_guard::set_guard_fn`] before it. To place the tables and stacks
+/// in memory of the kernel's choice, call [`init_with`] instead.
 ///
 /// [GDT]: https://wiki.osdev.org/GDT
 /// [IDT]: https://wiki.osdev.org/IDT
 /// [TSS]: https://wiki.osdev.org/Task_State_Segment
+/// [IST]: https://www.kernel.org/doc/html/latest/x86/kernel-stacks.html
 /// [`syscall`]: https://www.felixcloutier.com/x86/syscall
+/// [`xsave`]: https://www.felixcloutier.com/x86/xsave
 ///
-#[cfg(any(target_os = "none", target_os = "uefi"))]
-pub unsafe fn init() {
+#[cfg(baremetal)]
+pub unsafe fn init() -> Result<(), TrapInitError> {
     use log::info;
+    check_init()?;
     info!("Initializing trapframe...");
 
     x86_64::instructions::interrupts::disable();
-    gdt::init();
+    gdt::init()?;
     info!("GDT initialization completed");
     idt::init();
     info!("IDT initialization completed");
     syscall::init();
     info!("Syscall related register initialization completed");
+    paging::init();
+    xstate::init();
+    info!("Extended state initialization completed");
+    Ok(())
+}
+
+/// Initialize interrupt handling on x86_64 like [`init()`], with the GDT,
+/// TSS, IDT and stacks allocated by `provider`.
+///
+/// The provider is shared by all CPUs, see [`init_ap_with()`].
+///
+/// # Safety
+///
+/// See [`init()`].
+#[cfg(baremetal)]
+pub unsafe fn init_with(
+    provider: &'static dyn memory::MemoryProvider,
+) -> Result<(), TrapInitError> {
+    check_init()?;
+    if !memory::set_provider(provider) {
+        return Err(TrapInitError::ProviderChanged);
+    }
+    init()
+}
+
+/// Initialize interrupt handling on an application processor.
+///
+/// Call it on each AP after [`init()`] has been called on the bootstrap
+/// processor. Return an error before changing any state if a prerequisite
+/// checked by [`check_init_ap()`] is not met, or after allocating the
+/// kernel stack for [`TrapInitError::MisalignedStack`] as [`init()`] does.
+///
+/// # Safety
+///
+/// This function will:
+///
+/// - Disable interrupt.
+/// - Switch to a copy of the GDT built by [`init()`], with a new TSS
+///   and IST stacks for this CPU, set `GSBASE` to the TSS.
+/// - Load the IDT built by [`init()`].
+/// - Enable `syscall` and `xsave` instructions as [`init()`] does.
+#[cfg(baremetal)]
+pub unsafe fn init_ap() -> Result<(), TrapInitError> {
+    use log::info;
+    check_init_ap()?;
+    info!("Initializing trapframe on AP...");
+
+    x86_64::instructions::interrupts::disable();
+    gdt::init_ap()?;
+    idt::init_ap();
+    syscall::init();
+    paging::init();
+    xstate::init();
+    info!("Trapframe initialization on AP completed");
+    Ok(())
+}
+
+/// Initialize interrupt handling on an application processor like
+/// [`init_ap()`], with the GDT, TSS and stacks allocated by `provider`.
+///
+/// The provider is shared by all CPUs and set by the first call of this
+/// function or [`init_with()`], so all of them must pass the same one.
+///
+/// # Safety
+///
+/// See [`init_ap()`].
+#[cfg(baremetal)]
+pub unsafe fn init_ap_with(
+    provider: &'static dyn memory::MemoryProvider,
+) -> Result<(), TrapInitError> {
+    check_init_ap()?;
+    if !memory::set_provider(provider) {
+        return Err(TrapInitError::ProviderChanged);
+    }
+    init_ap()
+}
+
+/// Check the prerequisites of [`init()`] on the current CPU, without
+/// changing any state.
+#[cfg(baremetal)]
+pub fn check_init() -> Result<(), TrapInitError> {
+    gdt::check(false)?;
+    syscall::check()
+}
+
+/// Check the prerequisites of [`init_ap()`] on the current CPU, without
+/// changing any state.
+#[cfg(baremetal)]
+pub fn check_init_ap() -> Result<(), TrapInitError> {
+    gdt::check(true)?;
+    syscall::check()
+}
+
+/// Decode the trap `trap_num` for both `UserContext` and `TrapFrame`, with
+/// `CR2` of a page fault and `DR6` of a debug trap.
+fn reason(trap_num: usize, error_code: usize, cr2: usize, dr6: usize) -> TrapReason {
+    #[cfg(baremetal)]
+    if let Some(payload) = ipi::payload(trap_num) {
+        return TrapReason::Ipi(payload);
+    }
+    match trap_num {
+        // DR6.BS: single step, otherwise hardware breakpoint
+        1 if dr6 & (1 << 14) != 0 => TrapReason::SingleStep,
+        1 => TrapReason::Breakpoint,
+        2 => TrapReason::Nmi,
+        3 => TrapReason::Breakpoint,
+        6 => TrapReason::IllegalInstruction,
+        14 => TrapReason::PageFault {
+            addr: cr2,
+            flags: page_fault_flags(error_code),
+        },
+        17 => TrapReason::Misaligned,
+        #[cfg(baremetal)]
+        n if timer::is_vector(n) => TrapReason::Timer,
+        32..=255 => TrapReason::Interrupt(trap_num),
+        _ => TrapReason::Unknown(trap_num),
+    }
+}
+
+/// Decode the error code of page fault.
+fn page_fault_flags(error_code: usize) -> PageFaultFlags {
+    let mut flags = PageFaultFlags::empty();
+    flags.set(PageFaultFlags::PRESENT, error_code & 0x1 != 0);
+    flags.set(PageFaultFlags::WRITE, error_code & 0x2 != 0);
+    flags.set(PageFaultFlags::USER, error_code & 0x4 != 0);
+    flags.set(PageFaultFlags::EXECUTE, error_code & 0x10 != 0);
+    flags
+}
+
+/// Read the time stamp counter.
+pub(crate) fn read_cycles() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
 }
 
 /// User space context
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct UserContext {
     pub general: GeneralRegs,
     pub trap_num: usize,
     pub error_code: usize,
+    /// Faulting address of the last page fault, from `CR2`, with up to
+    /// 57 significant bits under 5-level paging, see [`va_bits()`], and
+    /// possibly metadata bits under LAM, see [`LamMode`]
+    pub cr2: usize,
+    /// Code segment selector to go to user with, 0 for the standard 64-bit
+    /// user code segment, updated on trap
+    pub cs: usize,
+    /// Stack segment selector to go to user with, 0 for the standard user
+    /// data segment, updated on trap
+    pub ss: usize,
+    /// Debug registers, switched in `run()`
+    pub debug: DebugRegs,
+    /// Non-zero to go to user by `iret` in the next `run()`, even if it can
+    /// go by `sysret`, cleared on return
+    pub force_iret: usize,
+    /// Page table of the user, switched to by the trampoline with feature
+    /// `kpti`, 0 for staying in the kernel page table. Ignored without the
+    /// feature.
+    pub user_cr3: usize,
+    /// Keep `fp` 16 bytes aligned, present in all builds so that struct
+    /// literals do not depend on features. Public only for struct literals
+    /// with `..Default::default()`.
+    #[doc(hidden)]
+    pub _pad: usize,
+    /// Floating-point state, saved and restored around `run()`
+    ///
+    /// With feature `lazy_fpu`, it is only loaded when the user first uses
+    /// FPU in `run()`, or eagerly if the context was the last one to load it
+    /// on the CPU, and only saved if loaded.
+    #[cfg(feature = "fpu")]
+    pub fp: FpState,
+    /// Counter ticks spent in user by `run()` and `run_fncall()`, accumulated over runs with
+    /// feature `accounting`, by the same counter as `TrapInfo::user_cycles`.
+    /// Stays 0 without the feature.
+    pub user_cycles: u64,
+    /// Keep the size a multiple of the 16-byte alignment of `fp`, present in
+    /// all builds so that struct literals do not depend on features. Public
+    /// only for struct literals with `..Default::default()`.
+    #[doc(hidden)]
+    pub _pad_cycles: u64,
 }
 
 /// General registers
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct GeneralRegs {
     pub rax: usize,
@@ -86,8 +356,24 @@ pub struct GeneralRegs {
 
 unsafe impl pod::Pod for GeneralRegs {}
 unsafe impl pod::Pod for UserContext {}
+impl_bytemuck!(GeneralRegs, UserContext);
 
 impl UserContext {
+    /// Decode the reason of the last trap.
+    pub fn trap_reason(&self) -> TrapReason {
+        match self.trap_num {
+            0x100 => TrapReason::Syscall,
+            0x80 => TrapReason::LegacySyscall { sysenter: false },
+            0x101 => TrapReason::LegacySyscall { sysenter: true },
+            _ => reason(self.trap_num, self.error_code, self.cr2, self.debug.dr6),
+        }
+    }
+
+    /// Whether the last trap is a legacy syscall by `int 0x80` or `sysenter`.
+    pub(crate) fn is_legacy_syscall(&self) -> bool {
+        matches!(self.trap_num, 0x80 | 0x101)
+    }
+
     /// Get number of syscall
     pub fn get_syscall_num(&self) -> usize {
         self.general.rax
@@ -104,7 +390,14 @@ impl UserContext {
     }
 
     /// Get syscall args
+    ///
+    /// They are `ebx`, `ecx`, `edx`, `esi`, `edi` and `ebp` of i386 ABI for
+    /// legacy syscalls by `int 0x80` or `sysenter`.
     pub fn get_syscall_args(&self) -> [usize; 6] {
+        if self.is_legacy_syscall() {
+            let g = &self.general;
+            return [g.rbx, g.rcx, g.rdx, g.rsi, g.rdi, g.rbp];
+        }
         [
             self.general.rdi,
             self.general.rsi,
@@ -120,6 +413,11 @@ impl UserContext {
         self.general.rip = ip;
     }
 
+    /// Get instruction pointer
+    pub fn get_ip(&self) -> usize {
+        self.general.rip
+    }
+
     /// Set stack pointer
     pub fn set_sp(&mut self, sp: usize) {
         self.general.rsp = sp;
@@ -134,4 +432,35 @@ impl UserContext {
     pub fn set_tls(&mut self, tls: usize) {
         self.general.fsbase = tls;
     }
+
+    /// Get tls pointer
+    pub fn get_tls(&self) -> usize {
+        self.general.fsbase
+    }
+
+    /// Get registers for thread-local storage
+    pub fn get_tls_regs(&self) -> TlsRegs {
+        TlsRegs {
+            fsbase: self.general.fsbase,
+            gsbase: self.general.gsbase,
+        }
+    }
+
+    /// Set registers for thread-local storage
+    pub fn set_tls_regs(&mut self, regs: &TlsRegs) {
+        self.general.fsbase = regs.fsbase;
+        self.general.gsbase = regs.gsbase;
+    }
+}
+
+/// Registers for thread-local storage, switched in `run()`
+///
+/// The thread pointer among them is also accessed by `get_tls` and `set_tls`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TlsRegs {
+    /// Base of `fs`, the thread pointer of the System V ABI
+    pub fsbase: usize,
+    /// Base of `gs`, used by Wine and some threading libraries
+    pub gsbase: usize,
 }
@@ -0,0 +1,85 @@
+//! Nesting of kernel traps and preemption counting.
+//!
+//! The count of each CPU is kept in the reserved word of TSS at `gs:92`,
+//! which is not used by the CPU. As in Linux, its low bits count the
+//! nested [`preempt_disable`], and the bits from [`TRAP_SHIFT`] count the
+//! nested traps from kernel being handled, which are dispatched by the
+//! crate to the registered handler or `trap_handler`.
+//!
+//! Traps from user are returned from `UserContext::run()` to the kernel
+//! thread, so they are not counted.
+
+use super::gdt::TSS_RESERVED_3_OFFSET;
+use core::arch::asm;
+
+/// Shift of the trap depth in the count.
+pub const TRAP_SHIFT: u32 = 16;
+/// Mask of the preemption disabled depth in the count.
+pub const PREEMPT_MASK: usize = (1 << TRAP_SHIFT) - 1;
+
+/// Add `delta` to the count of the current CPU.
+fn add(delta: usize) {
+    unsafe {
+        asm!(
+            "add gs:[{off}], {}",
+            in(reg) delta,
+            off = const TSS_RESERVED_3_OFFSET,
+            options(nostack)
+        )
+    };
+}
+
+/// Get the count of the current CPU.
+///
+/// [`init()`](crate::init) must have been called on the current CPU.
+pub fn preempt_count() -> usize {
+    let count: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{off}]",
+            out(reg) count,
+            off = const TSS_RESERVED_3_OFFSET,
+            options(nostack, preserves_flags, readonly)
+        )
+    };
+    count
+}
+
+/// Get the depth of nested traps from kernel on the current CPU.
+pub fn trap_depth() -> usize {
+    preempt_count() >> TRAP_SHIFT
+}
+
+/// Whether the current CPU is handling a trap from kernel, e.g. an
+/// interrupt during a syscall handler.
+pub fn in_interrupt() -> bool {
+    trap_depth() != 0
+}
+
+/// Disable preemption of the current CPU, until [`preempt_enable`].
+pub fn preempt_disable() {
+    add(1);
+}
+
+/// Enable preemption disabled by [`preempt_disable`].
+pub fn preempt_enable() {
+    debug_assert!(
+        preempt_count() & PREEMPT_MASK != 0,
+        "preemption is not disabled"
+    );
+    add(usize::MAX);
+}
+
+/// Whether the current CPU can be preempted, i.e. not in a trap from
+/// kernel, preemption not disabled, and interrupts enabled.
+pub fn preemptible() -> bool {
+    preempt_count() == 0 && crate::irq::is_enabled()
+}
+
+/// Count a trap from kernel while running `f`.
+pub(super) fn enter_trap<T>(f: impl FnOnce() -> T) -> T {
+    add(1 << TRAP_SHIFT);
+    let ret = f();
+    add((1usize << TRAP_SHIFT).wrapping_neg());
+    ret
+}
@@ -0,0 +1,113 @@
+//! Virtual address width, 48 bits with 4-level paging or 57 bits with
+//! 5-level paging enabled by `CR4.LA57`, and linear address masking (LAM)
+//! of user data addresses by `CR3.LAM_U48` or `CR3.LAM_U57`.
+
+#[cfg(feature = "syscall_filter")]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// `CR4.LA57`
+#[cfg(baremetal)]
+const CR4_LA57: u64 = 1 << 12;
+
+/// Width of virtual addresses, probed by [`init`].
+static VA_BITS: AtomicU32 = AtomicU32::new(48);
+
+/// End of user addresses by [`is_user_addr`], above which `sysret` faults,
+/// read by `syscall.S`.
+#[cfg(feature = "syscall_filter")]
+#[no_mangle]
+static SYSRET_RIP_END: AtomicUsize = AtomicUsize::new(1 << 47);
+
+/// Probe the paging mode of the current CPU.
+#[cfg(baremetal)]
+pub(super) fn init() {
+    use x86_64::registers::control::Cr4;
+
+    let bits = if Cr4::read_raw() & CR4_LA57 != 0 {
+        57
+    } else {
+        48
+    };
+    VA_BITS.store(bits, Ordering::Relaxed);
+    #[cfg(feature = "syscall_filter")]
+    SYSRET_RIP_END.store(1 << (bits - 1), Ordering::Relaxed);
+}
+
+/// Get the width of virtual addresses, 57 with 5-level paging, otherwise 48.
+///
+/// It is probed by [`init()`](crate::init), and always 48 before that or in
+/// user space.
+pub fn va_bits() -> u32 {
+    VA_BITS.load(Ordering::Relaxed)
+}
+
+/// Whether `addr` is canonical, i.e. the bits above [`va_bits()`] are all
+/// copies of the highest bit below.
+pub fn is_canonical(addr: usize) -> bool {
+    let shift = usize::BITS - va_bits();
+    (((addr << shift) as isize) >> shift) as usize == addr
+}
+
+/// Whether `addr` is in the lower canonical half, where user space is.
+///
+/// LAM is not applied, as for instruction fetches. For data addresses, see
+/// [`is_user_data_addr()`].
+pub fn is_user_addr(addr: usize) -> bool {
+    addr < 1 << (va_bits() - 1)
+}
+
+/// Linear address masking of user data addresses.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LamMode {
+    /// No masking
+    Off,
+    /// Bits 62:57 are ignored, by `CR3.LAM_U57`
+    U57,
+    /// Bits 62:48 are ignored, by `CR3.LAM_U48`
+    U48,
+}
+
+impl LamMode {
+    /// Get the mode of the current address space from `CR3`.
+    ///
+    /// Always [`LamMode::Off`] in user space.
+    pub fn current() -> Self {
+        #[cfg(baremetal)]
+        {
+            /// `CR3.LAM_U57`
+            const LAM_U57: usize = 1 << 61;
+            /// `CR3.LAM_U48`
+            const LAM_U48: usize = 1 << 62;
+            let cr3: usize;
+            unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack)) };
+            if cr3 & LAM_U57 != 0 {
+                return LamMode::U57;
+            } else if cr3 & LAM_U48 != 0 {
+                return LamMode::U48;
+            }
+        }
+        LamMode::Off
+    }
+
+    /// Get the metadata bits ignored in user data addresses.
+    pub fn mask(self) -> usize {
+        match self {
+            LamMode::Off => 0,
+            LamMode::U57 => 0x7e00_0000_0000_0000,
+            LamMode::U48 => 0x7fff_0000_0000_0000,
+        }
+    }
+}
+
+/// Whether `addr` is a canonical user address for data accesses under
+/// `lam`, i.e. in the lower half once the metadata bits are ignored.
+///
+/// The half is 47 bits wide with `LAM_U48` even under 5-level paging.
+pub fn is_user_data_addr(addr: usize, lam: LamMode) -> bool {
+    let bits = match lam {
+        LamMode::U48 => 48,
+        _ => va_bits(),
+    };
+    addr & !lam.mask() < 1 << (bits - 1)
+}
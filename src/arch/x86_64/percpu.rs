@@ -0,0 +1,47 @@
+//! Per-CPU pointer of the kernel.
+//!
+//! In kernel, `GSBASE` points to the TSS of the current CPU, since the trap
+//! and syscall entries find the kernel stack by `swapgs` and `gs:4`
+//! (`TSS.sp0`). So the kernel must not write `GSBASE` or
+//! `IA32_KERNEL_GS_BASE` itself, and this is not a GS base.
+//!
+//! Instead, the per-CPU pointer of the kernel is kept in the `TSS.sp2` slot
+//! of each CPU at `gs:20`, which the CPU never uses as no code runs in ring
+//! 2, and can be read with a single `gs`-relative load.
+
+use core::arch::asm;
+
+/// Offset of `TSS.sp2` in the TSS, pointed by kernel `GSBASE`.
+pub const PERCPU_PTR_OFFSET: usize = super::gdt::TSS_SP2_OFFSET;
+
+/// Install `ptr` as the per-CPU pointer of the current CPU, in its
+/// `TSS.sp2`.
+///
+/// # Safety
+///
+/// [`init()`](crate::init) must have been called on the current CPU,
+/// and the caller must be in kernel with kernel `GSBASE` active.
+pub unsafe fn set_percpu_ptr(ptr: usize) {
+    asm!(
+        "mov gs:[{off}], {}",
+        in(reg) ptr,
+        off = const PERCPU_PTR_OFFSET,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Get the per-CPU pointer of the current CPU, 0 if not installed.
+///
+/// [`init()`](crate::init) must have been called on the current CPU.
+pub fn current_percpu_ptr() -> usize {
+    let ptr: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{off}]",
+            out(reg) ptr,
+            off = const PERCPU_PTR_OFFSET,
+            options(nostack, preserves_flags, readonly)
+        )
+    };
+    ptr
+}
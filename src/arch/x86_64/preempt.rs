@@ -0,0 +1,79 @@
+//! Preempt user program in `run_fncall()` by host timer signal.
+//!
+//! When the signal arrives in user, `run_fncall()` returns with `trap_num`
+//! set to [`TRAP_NUM`], and all registers are kept.
+
+use super::hostsig::{self, ENTRY_PREEMPT};
+
+pub use super::hostsig::{init_thread, HostSignalError};
+
+/// `trap_num` of `UserContext` preempted by timer.
+pub const TRAP_NUM: usize = 32;
+
+const SIGALRM: i32 = 14;
+const SIGVTALRM: i32 = 26;
+const ITIMER_REAL: i32 = 0;
+const ITIMER_VIRTUAL: i32 = 1;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct TimeVal {
+    tv_sec: i64,
+    tv_usec: i64,
+}
+
+#[repr(C)]
+struct ITimerVal {
+    it_interval: TimeVal,
+    it_value: TimeVal,
+}
+
+extern "C" {
+    fn setitimer(which: i32, new: *const ITimerVal, old: *mut ITimerVal) -> i32;
+}
+
+/// Enable preemption by `signal` (`SIGALRM` or `SIGVTALRM`), and start
+/// the corresponding interval timer every `interval_us` microseconds.
+///
+/// The calling thread is initialized as [`init_thread`] does.
+///
+/// Return an error before changing any state if `signal` is not supported,
+/// and an error if a libc call fails or the layout of `ucontext_t` is not
+/// the expected one.
+///
+/// # Safety
+///
+/// Call it before spawning other threads, so that they inherit the blocked
+/// signal mask. Other threads calling `run_fncall()` must call [`init_thread`].
+///
+/// The kernel must not install another handler for `signal`.
+pub unsafe fn enable(signal: i32, interval_us: u64) -> Result<(), HostSignalError> {
+    let which = match signal {
+        SIGALRM => ITIMER_REAL,
+        SIGVTALRM => ITIMER_VIRTUAL,
+        _ => return Err(HostSignalError::UnsupportedSignal(signal)),
+    };
+    init_thread()?;
+    hostsig::check_layout(signal)?;
+    hostsig::add_user_signal(signal)?;
+    hostsig::set_handler(signal, handler)?;
+
+    let interval = TimeVal {
+        tv_sec: (interval_us / 1_000_000) as i64,
+        tv_usec: (interval_us % 1_000_000) as i64,
+    };
+    let timer = ITimerVal {
+        it_interval: interval,
+        it_value: interval,
+    };
+    hostsig::check(setitimer(which, &timer, core::ptr::null_mut()), "setitimer")
+}
+
+extern "C" fn handler(_signal: i32, _info: *mut u8, uc: *mut u8) {
+    unsafe {
+        // ignore this tick if arrived in kernel or during switching
+        if hostsig::in_user(uc) {
+            hostsig::redirect(uc, ENTRY_PREEMPT, &[]);
+        }
+    }
+}
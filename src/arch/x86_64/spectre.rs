@@ -0,0 +1,137 @@
+//! Mitigations of Spectre variant 2 (branch target injection).
+//!
+//! With feature `spectre`, `UserContext::run()` may issue an indirect branch
+//! prediction barrier (IBPB) before going to user, so that a user can not
+//! steer the indirect branches of another one. Whether to issue it is a
+//! security policy of the kernel, set by [`set_ibpb_policy`].
+//!
+//! The address space of the last context run on each CPU, i.e. the page
+//! table it goes to user with, is kept in the reserved word of TSS at
+//! `gs:28`, which is not used by the CPU.
+
+use super::gdt::TSS_RESERVED_2_OFFSET;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::registers::control::Cr3;
+
+/// When to issue IBPB in `UserContext::run()`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IbpbPolicy {
+    /// Never, the default
+    Never,
+    /// When the address space differs from the one of the last context run
+    /// on this CPU, i.e. `user_cr3` with feature `kpti` if not 0, or the
+    /// current `CR3`
+    OnSwitch,
+    /// Before every run
+    Always,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(IbpbPolicy::Never as u8);
+
+const IA32_SPEC_CTRL: u32 = 0x48;
+const IA32_PRED_CMD: u32 = 0x49;
+const SPEC_CTRL_STIBP: u64 = 1 << 1;
+const PRED_CMD_IBPB: u64 = 1 << 0;
+const CR3_ADDR_MASK: usize = 0x000f_ffff_ffff_f000;
+
+/// Whether the CPU supports IBPB.
+pub fn has_ibpb() -> bool {
+    crate::cpu_features().contains(crate::CpuFeatures::IBPB)
+}
+
+/// Set when to issue IBPB in `UserContext::run()`.
+///
+/// Return `false` and keep the policy if IBPB is not supported.
+pub fn set_ibpb_policy(policy: IbpbPolicy) -> bool {
+    if policy != IbpbPolicy::Never && !has_ibpb() {
+        return false;
+    }
+    POLICY.store(policy as u8, Ordering::Relaxed);
+    true
+}
+
+/// Get when to issue IBPB in `UserContext::run()`.
+pub fn ibpb_policy() -> IbpbPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => IbpbPolicy::OnSwitch,
+        2 => IbpbPolicy::Always,
+        _ => IbpbPolicy::Never,
+    }
+}
+
+/// Issue IBPB on the current CPU.
+///
+/// # Safety
+///
+/// The CPU must support IBPB, see [`has_ibpb`].
+pub unsafe fn ibpb() {
+    wrmsr(IA32_PRED_CMD, PRED_CMD_IBPB);
+}
+
+/// Enable or disable single thread indirect branch predictors (STIBP) on
+/// the current CPU, which stops the sibling hyper-thread from steering
+/// indirect branches.
+///
+/// # Safety
+///
+/// The CPU must support STIBP, by `CPUID.(EAX=7,ECX=0):EDX[27]`.
+pub unsafe fn set_stibp(enable: bool) {
+    let value = rdmsr(IA32_SPEC_CTRL);
+    let value = if enable {
+        value | SPEC_CTRL_STIBP
+    } else {
+        value & !SPEC_CTRL_STIBP
+    };
+    wrmsr(IA32_SPEC_CTRL, value);
+}
+
+/// Issue IBPB before going to user with page table `user_cr3`, or the
+/// current one if 0, as the policy says.
+pub(super) fn before_run(user_cr3: usize) {
+    match ibpb_policy() {
+        IbpbPolicy::Never => {}
+        IbpbPolicy::Always => unsafe { ibpb() },
+        IbpbPolicy::OnSwitch => {
+            // PCID and flags do not tell address spaces apart
+            let current = match user_cr3 {
+                0 => Cr3::read().0.start_address().as_u64() as usize,
+                cr3 => cr3 & CR3_ADDR_MASK,
+            };
+            let last: usize;
+            unsafe {
+                asm!(
+                    "mov {}, gs:[{off}]",
+                    out(reg) last,
+                    off = const TSS_RESERVED_2_OFFSET,
+                    options(nostack, preserves_flags, readonly)
+                );
+                if last != current {
+                    asm!(
+                        "mov gs:[{off}], {}",
+                        in(reg) current,
+                        off = const TSS_RESERVED_2_OFFSET,
+                        options(nostack, preserves_flags)
+                    );
+                    ibpb();
+                }
+            }
+        }
+    }
+}
+
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack));
+    (high as u64) << 32 | low as u64
+}
+
+unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack),
+    );
+}
@@ -0,0 +1,131 @@
+//! Guard pages below kernel stacks.
+//!
+//! A kernel stack overflow faults in the guard page below the stack, and is
+//! reported as `TrapReason::KernelStackOverflow` by `TrapFrame::trap_reason()`,
+//! and to the double fault handler, since the page fault can not be pushed
+//! onto the overflowed stack either.
+//!
+//! With a guard function set by [`set_guard_fn`] before [`init()`](crate::init),
+//! the IST stacks of each CPU are allocated with a guard page below, which
+//! is unmapped by the function. The kernel can also register the guard pages
+//! of its own stacks, e.g. of each thread, by [`register`].
+//!
+//! Without feature `alloc`, the stacks are taken from a static pool sized
+//! for [`MAX_CPUS`](crate::MAX_CPUS) instead of allocated.
+
+use super::memory::{self, MemoryKind};
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc_zeroed, handle_alloc_error};
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Size of a guard page.
+pub const GUARD_SIZE: usize = 0x1000;
+
+/// Maximum number of registered guard pages.
+pub const MAX_GUARDS: usize = 1024;
+
+/// Function to unmap the guard page at `addr` of `size` bytes.
+pub type GuardFn = fn(addr: usize, size: usize);
+
+static GUARD_FN: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_GUARD: AtomicUsize = AtomicUsize::new(0);
+
+/// Start addresses of guard pages, 0 for a free slot.
+static GUARDS: [AtomicUsize; MAX_GUARDS] = [NO_GUARD; MAX_GUARDS];
+
+/// Set the function to unmap guard pages of the stacks allocated in
+/// [`init()`](crate::init) and [`init_ap()`](crate::init_ap).
+pub fn set_guard_fn(f: GuardFn) {
+    GUARD_FN.store(f as usize, Ordering::Release);
+}
+
+/// Register the guard page at `addr`, which is aligned to [`GUARD_SIZE`]
+/// and unmapped by the kernel.
+///
+/// Return false if there are already [`MAX_GUARDS`] guard pages.
+pub fn register(addr: usize) -> bool {
+    debug_assert!(addr != 0 && addr % GUARD_SIZE == 0, "invalid guard page");
+    GUARDS.iter().any(|slot| {
+        slot.compare_exchange(0, addr, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    })
+}
+
+/// Unregister the guard page at `addr`, return false if not registered.
+pub fn unregister(addr: usize) -> bool {
+    GUARDS.iter().any(|slot| {
+        slot.compare_exchange(addr, 0, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    })
+}
+
+/// Whether `addr` is in a registered guard page.
+pub fn contains(addr: usize) -> bool {
+    let page = addr & !(GUARD_SIZE - 1);
+    page != 0
+        && GUARDS
+            .iter()
+            .any(|slot| slot.load(Ordering::Acquire) == page)
+}
+
+/// Allocate a kernel stack of `size` bytes for `kind`, with a guard page
+/// below if the guard function is set. Return the top of the stack.
+pub(super) fn alloc_stack(kind: MemoryKind, size: usize) -> usize {
+    let guard_fn = match GUARD_FN.load(Ordering::Acquire) {
+        0 => None,
+        raw => Some(unsafe { core::mem::transmute::<usize, GuardFn>(raw) }),
+    };
+    let guard_size = if guard_fn.is_some() { GUARD_SIZE } else { 0 };
+    let layout = Layout::from_size_align(guard_size + size, GUARD_SIZE).unwrap();
+    let base = memory::alloc(kind, layout).unwrap_or_else(|| alloc_pages(layout));
+    if let Some(guard_fn) = guard_fn {
+        guard_fn(base, GUARD_SIZE);
+        if !register(base) {
+            log::warn!("too many guard pages, {:#x} is not registered", base);
+        }
+    }
+    base + guard_size + size
+}
+
+/// Allocate zeroed memory of `layout` from the heap.
+#[cfg(feature = "alloc")]
+fn alloc_pages(layout: Layout) -> usize {
+    let base = unsafe { alloc_zeroed(layout) };
+    if base.is_null() {
+        handle_alloc_error(layout);
+    }
+    base as usize
+}
+
+/// Size of the stack pool for each CPU: the stack of `TSS.sp0` and the IST
+/// stacks, each with a guard page.
+#[cfg(not(feature = "alloc"))]
+const POOL_SIZE_PER_CPU: usize = 0x1000 + 3 * super::gdt::IST_STACK_SIZE + 4 * GUARD_SIZE;
+
+#[cfg(not(feature = "alloc"))]
+#[repr(C, align(4096))]
+struct StackPool([u8; POOL_SIZE_PER_CPU * crate::MAX_CPUS]);
+
+#[cfg(not(feature = "alloc"))]
+static mut STACK_POOL: StackPool = StackPool([0; POOL_SIZE_PER_CPU * crate::MAX_CPUS]);
+
+/// Bytes taken from [`STACK_POOL`].
+#[cfg(not(feature = "alloc"))]
+static STACK_POOL_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Take zeroed memory of `layout`, aligned to [`GUARD_SIZE`] at most, from
+/// the static stack pool.
+#[cfg(not(feature = "alloc"))]
+fn alloc_pages(layout: Layout) -> usize {
+    let size = (layout.size() + GUARD_SIZE - 1) & !(GUARD_SIZE - 1);
+    let offset = STACK_POOL_USED.fetch_add(size, Ordering::Relaxed);
+    let pool = unsafe { &mut STACK_POOL.0 };
+    assert!(
+        offset + size <= pool.len(),
+        "static kernel stacks exhausted"
+    );
+    pool[offset..].as_mut_ptr() as usize
+}
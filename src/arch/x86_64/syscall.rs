@@ -1,26 +1,61 @@
-use super::UserContext;
-use core::arch::global_asm;
-use x86_64::registers::control::{Cr4, Cr4Flags};
-use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask};
+use super::gdt::{TSS_SP0_OFFSET, TSS_SP1_OFFSET, USER_CS, USER_CS32, USER_SS};
+use super::layout::*;
+#[cfg(feature = "syscall_filter")]
+use super::syscall_filter::SYSCALL_FILTER_LEN;
+/// Unused by `syscall.S` without feature `syscall_filter`.
+#[cfg(not(feature = "syscall_filter"))]
+const SYSCALL_FILTER_LEN: usize = 0;
+#[cfg(feature = "fpu")]
+use super::FpState;
+use super::{TrapInitError, UserContext};
+use crate::{CpuFeatures, TrapInfo};
+use core::arch::asm;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr2, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, Msr, SFMask};
 use x86_64::registers::rflags::RFlags;
 use x86_64::VirtAddr;
 
-global_asm!(include_str!("syscall.S"));
+// the pushes follow the layout of `UserContext`, checked in `layout`
+global_asm_equ!(
+    [
+        KPTI = cfg!(feature = "kpti") as usize,
+        TSS_SP0_OFFSET,
+        TSS_SP1_OFFSET,
+        FILTER_SYSCALLS = cfg!(feature = "syscall_filter") as usize,
+        SYSCALL_FILTER_LEN = SYSCALL_FILTER_LEN,
+        USER_CONTEXT_RSP_OFFSET,
+        USER_CONTEXT_RIP_OFFSET,
+        USER_CONTEXT_RFLAGS_OFFSET,
+        USER_CONTEXT_FSBASE_OFFSET,
+        USER_CONTEXT_GSBASE_OFFSET,
+        USER_CONTEXT_ERROR_CODE_OFFSET,
+        USER_CONTEXT_CS_OFFSET,
+        USER_CONTEXT_SS_OFFSET,
+    ],
+    include_str!("syscall.S")
+);
 
+/// Check that the CPU supports `syscall` and `FSGSBASE` instructions.
+pub fn check() -> Result<(), TrapInitError> {
+    let features = crate::cpu_features();
+    if !features.contains(CpuFeatures::SYSCALL) {
+        return Err(TrapInitError::UnsupportedCpu("syscall"));
+    }
+    if !features.contains(CpuFeatures::FSGSBASE) {
+        return Err(TrapInitError::UnsupportedCpu("fsgsbase"));
+    }
+    Ok(())
+}
+
+/// Enable `syscall` and `FSGSBASE` instructions, checked by [`check`].
 pub fn init() {
-    let cpuid = raw_cpuid::CpuId::new();
     unsafe {
         // enable `syscall` instruction
-        assert!(cpuid
-            .get_extended_processor_and_feature_identifiers()
-            .unwrap()
-            .has_syscall_sysret());
         Efer::update(|efer| {
             efer.insert(EferFlags::SYSTEM_CALL_EXTENSIONS);
         });
 
         // enable `FSGSBASE` instructions
-        assert!(cpuid.get_extended_feature_info().unwrap().has_fsgsbase());
         Cr4::update(|cr4| {
             cr4.insert(Cr4Flags::FSGSBASE);
         });
@@ -31,13 +66,18 @@ pub fn init() {
         const RFLAGS_MASK: u64 = 0x47700;
 
         LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+        // `sysenter` from compatibility mode, only on Intel CPUs
+        Msr::new(IA32_SYSENTER_EIP).write(sysenter_entry as usize as u64);
         SFMask::write(RFlags::from_bits(RFLAGS_MASK).unwrap());
     }
 }
 
+const IA32_SYSENTER_EIP: u32 = 0x176;
+
 extern "sysv64" {
     fn syscall_entry();
-    fn syscall_return(regs: &mut UserContext);
+    fn sysenter_entry();
+    fn syscall_return(regs: &mut UserContext, sysret: bool, user_cr3: usize);
 }
 
 impl UserContext {
@@ -45,11 +85,50 @@ impl UserContext {
     ///
     /// On return, the context will be reset to the status before the trap.
     /// Trap reason and error code will be placed at `trap_num` and `error_code`.
+    /// For page fault, the faulting address will be placed at `cr2`.
+    /// For debug exception, `DR6` will be placed at `debug.dr6`.
     ///
     /// If the trap was triggered by `syscall` instruction, the `trap_num` will be set to `0x100`.
+    /// For legacy syscalls of 32-bit programs, it is `0x80` for `int 0x80`, and `0x101`
+    /// for `sysenter`. Since `sysenter` does not save `rip` and `rsp`, they are set to 0,
+    /// and the kernel should set them as its convention before the next `run()`,
+    /// e.g. Linux takes `rsp` from `rbp` and returns to the vDSO. `TF` in `rflags`
+    /// is also lost by `sysenter`.
+    ///
+    /// If `trap_num` is `0x100`, it will go user by `sysret` (`rcx` and `r11` are dropped).
+    /// Otherwise it will also use `sysret` if `rcx` and `r11` equal to `rip` and `rflags`,
+    /// which is faster than `iret`. It falls back to `iret` if `rip` is not a canonical
+    /// user address, or `TF` or `RF` is set in `rflags`.
+    /// It always uses `iret` if `force_iret` is set, which is cleared on
+    /// return.
+    ///
+    /// It goes to user with the selectors `cs` and `ss`, which are set to
+    /// the standard 64-bit ones if 0, and always uses `iret` for others.
+    /// `ds` and `es` are loaded with `ss` by `iret` as well.
+    /// See [`set_compat_mode`](Self::set_compat_mode) to run 32-bit code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cs` or `ss` is not a selector with RPL 3.
+    /// In debug builds, also panics if the context is already running on
+    /// another CPU.
+    ///
+    /// With feature `spectre`, it issues IBPB as
+    /// [`spectre::set_ibpb_policy`](crate::spectre::set_ibpb_policy) says.
+    ///
+    /// With feature `kpti`, it switches to the page table `user_cr3` on the
+    /// way to user, and back to the current one on trap.
     ///
-    /// If `trap_num` is `0x100`, it will go user by `sysret` (`rcx` and `r11` are dropped),
-    /// otherwise it will use `iret`.
+    /// With feature `fpu`, the floating-point state in `fp` is loaded before
+    /// going to user and saved after coming back, and the state of the
+    /// kernel is saved and restored around it. With feature `lazy_fpu`,
+    /// it is loaded on the first #NM trap of the user instead, which is
+    /// handled here and not returned, unless the context was the last one
+    /// to load it on the CPU.
+    ///
+    /// With an interrupt controller set in [`crate::intc`], an external
+    /// interrupt is completed before returning, and a spurious one goes
+    /// back to user.
     ///
     /// # Example
     /// ```no_run
@@ -70,8 +149,166 @@ impl UserContext {
     /// println!("back from user: {:#x?}", context);
     /// ```
     pub fn run(&mut self) {
+        self.run_timed(false);
+    }
+
+    /// Go to user like [`run`](Self::run), and return the counter ticks
+    /// spent in user if `timed`, 0 otherwise.
+    fn run_timed(&mut self, timed: bool) -> u64 {
+        let _in_use = crate::in_use::InUseGuard::new(self);
+        debug_assert!(
+            !crate::user_access::flag(),
+            "go to user with a UserAccess guard"
+        );
+        // the kernel may use FPU and SIMD registers as well
+        #[cfg(feature = "fpu")]
+        let mut kernel_fp = FpState::default();
+        #[cfg(all(feature = "fpu", not(feature = "lazy_fpu")))]
+        {
+            kernel_fp.save();
+            self.fp.restore();
+        }
+        #[cfg(feature = "lazy_fpu")]
+        let owner = self as *const Self as usize;
+        #[cfg(feature = "lazy_fpu")]
+        let mut fp_pending = !super::lazy_fpu::begin(owner, &self.fp, &mut kernel_fp);
+        #[cfg(not(feature = "lazy_fpu"))]
+        let fp_pending = false;
+        #[cfg(feature = "kpti")]
+        let cr3 = self.user_cr3;
+        #[cfg(not(feature = "kpti"))]
+        let cr3 = 0;
+        #[cfg(feature = "spectre")]
+        super::spectre::before_run(cr3);
+        self.check_selectors();
+        let debug = self.debug.is_enabled();
+        let sysret = self.can_sysret();
+        let run = self.before_user(timed);
         unsafe {
-            syscall_return(self);
+            if debug {
+                self.debug.load();
+            }
+            self.go_to_user(sysret, cr3, fp_pending);
+        }
+        loop {
+            #[cfg(feature = "lazy_fpu")]
+            if fp_pending && self.trap_num == super::lazy_fpu::DEVICE_NOT_AVAILABLE {
+                super::lazy_fpu::load(owner, &self.fp, &mut kernel_fp);
+                fp_pending = false;
+                unsafe { self.go_to_user(self.can_sysret(), cr3, false) };
+                continue;
+            }
+            // acknowledge external interrupts, and go back to user on spurious ones
+            if !super::interrupt::is_external(self.trap_num)
+                || crate::intc::handle(self.trap_num, |_| {})
+            {
+                break;
+            }
+            unsafe { self.go_to_user(self.can_sysret(), cr3, fp_pending) };
+        }
+        self.force_iret = 0;
+        // interrupts are still disabled, so CR2 belongs to this trap
+        if self.trap_num == 14 {
+            self.cr2 = Cr2::read().as_u64() as usize;
+        }
+        if debug || self.trap_num == 1 {
+            unsafe { self.debug.save(debug) };
+        }
+        #[cfg(all(feature = "fpu", not(feature = "lazy_fpu")))]
+        {
+            self.fp.save();
+            kernel_fp.restore();
+        }
+        #[cfg(feature = "lazy_fpu")]
+        super::lazy_fpu::end(fp_pending, &mut self.fp, &kernel_fp);
+        self.after_user_trap(run)
+    }
+
+    /// Go to user by `syscall_return`, with `CR0.TS` set if `lazy_fpu`, so
+    /// that the first use of FPU by the user traps, and cleared on return.
+    unsafe fn go_to_user(&mut self, sysret: bool, cr3: usize, lazy_fpu: bool) {
+        if lazy_fpu {
+            Cr0::update(|cr0| cr0.insert(Cr0Flags::TASK_SWITCHED));
         }
+        syscall_return(self, sysret, cr3);
+        if lazy_fpu {
+            asm!("clts", options(nomem, nostack));
+        }
+    }
+
+    /// Go to user in 32-bit compatibility mode if `compat`, or in 64-bit
+    /// mode otherwise, by setting `cs` and `ss` to the standard selectors.
+    ///
+    /// It should be called after [`init`](crate::init).
+    pub fn set_compat_mode(&mut self, compat: bool) {
+        unsafe {
+            self.cs = if compat { USER_CS32 } else { USER_CS } as usize;
+            self.ss = USER_SS as usize;
+        }
+    }
+
+    /// Whether the context goes to user in 32-bit compatibility mode.
+    pub fn is_compat_mode(&self) -> bool {
+        unsafe { self.cs == USER_CS32 as usize }
+    }
+
+    /// Fill the default selectors, and check they are user selectors.
+    fn check_selectors(&mut self) {
+        unsafe {
+            if self.cs == 0 {
+                self.cs = USER_CS as usize;
+            }
+            if self.ss == 0 {
+                self.ss = USER_SS as usize;
+            }
+        }
+        let valid = |sel: usize| sel <= 0xffff && sel & 3 == 3;
+        assert!(
+            valid(self.cs) && valid(self.ss),
+            "invalid user selectors: cs = {:#x}, ss = {:#x}",
+            self.cs,
+            self.ss
+        );
+    }
+
+    /// Whether the context can go to user by `sysret`.
+    fn can_sysret(&self) -> bool {
+        const TF: usize = 1 << 8;
+        const RF: usize = 1 << 16;
+        // `sysret` loads the standard 64-bit selectors
+        let standard = unsafe { self.cs == USER_CS as usize && self.ss == USER_SS as usize };
+        let g = &self.general;
+        // `sysret` loads `rip` from `rcx` and `rflags` from `r11`
+        let regs_match = self.trap_num == 0x100 || (g.rcx == g.rip && g.r11 == g.rflags);
+        // `sysret` to a non-canonical address faults in kernel on Intel CPUs,
+        // and the user half is 56 bits wide with 5-level paging. LAM does not
+        // apply to `rip`, so metadata bits make it non-canonical too.
+        let canonical = super::is_user_addr(g.rip);
+        self.force_iret == 0 && standard && regs_match && canonical && g.rflags & (TF | RF) == 0
+    }
+
+    /// Go to user by `iret` in the next [`run`](Self::run), e.g. to return
+    /// from a syscall to a modified `rip`, `cs` or `ss`.
+    pub fn set_force_iret(&mut self) {
+        self.force_iret = 1;
+    }
+
+    /// Go to user space like [`run`](Self::run), but trap after executing
+    /// one instruction, by setting `RFLAGS.TF`.
+    ///
+    /// `trap_reason()` returns `TrapReason::SingleStep` if the instruction
+    /// completes without other traps. `TF` is restored on return.
+    pub fn run_single_step(&mut self) {
+        const TF: usize = 1 << 8;
+        let tf = self.general.rflags & TF;
+        self.general.rflags |= TF;
+        self.run();
+        self.general.rflags = (self.general.rflags & !TF) | tf;
+    }
+
+    /// Go to user space like [`run`](Self::run), and return information of the trap.
+    pub fn run_until_trap(&mut self) -> TrapInfo {
+        let cycles = self.run_timed(true);
+        TrapInfo::new(self.trap_reason(), cycles)
     }
 }
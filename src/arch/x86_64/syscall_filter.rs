@@ -0,0 +1,100 @@
+//! Fast rejection of syscalls by a global filter.
+//!
+//! With a filter set by [`set_syscall_filter`], the `syscall` entry checks
+//! the number in `rax` against it before saving the context, and returns
+//! `-ENOSYS` to user right away by `sysret` for a denied syscall, so
+//! `UserContext::run()` does not return for it. Numbers beyond
+//! [`SYSCALL_FILTER_LEN`] are always denied.
+//!
+//! The filter is shared by all CPUs. It only applies to `syscall` in
+//! 64-bit mode, not to legacy syscalls by `int 0x80` or `sysenter`, and a
+//! `syscall` at the end of the lower half still goes the slow path, as
+//! `sysret` can not return to a non-canonical address.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Number of syscalls covered by a filter.
+pub const SYSCALL_FILTER_LEN: usize = 512;
+
+const WORDS: usize = SYSCALL_FILTER_LEN / 64;
+
+/// Whether the filter is enabled, read by `syscall.S`.
+#[no_mangle]
+static SYSCALL_FILTER_ON: AtomicBool = AtomicBool::new(false);
+
+/// Bitmap of allowed syscalls, read by `syscall.S`.
+#[no_mangle]
+static SYSCALL_FILTER: [AtomicU64; WORDS] = {
+    const DENY: AtomicU64 = AtomicU64::new(0);
+    [DENY; WORDS]
+};
+
+/// Set of syscall numbers allowed by the filter.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SyscallFilter {
+    /// Bit in bits: 1 indicates allowed, 0 indicates denied.
+    bits: [u64; WORDS],
+}
+
+impl SyscallFilter {
+    /// Create a filter denying all syscalls.
+    pub const fn deny_all() -> Self {
+        SyscallFilter { bits: [0; WORDS] }
+    }
+
+    /// Create a filter allowing all syscalls below [`SYSCALL_FILTER_LEN`].
+    pub const fn allow_all() -> Self {
+        SyscallFilter { bits: [!0; WORDS] }
+    }
+
+    /// Allow syscall `num`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num` is not below [`SYSCALL_FILTER_LEN`].
+    pub fn allow(&mut self, num: usize) -> &mut Self {
+        assert!(num < SYSCALL_FILTER_LEN, "syscall {} out of filter", num);
+        self.bits[num / 64] |= 1 << (num % 64);
+        self
+    }
+
+    /// Deny syscall `num`, ignored if not below [`SYSCALL_FILTER_LEN`].
+    pub fn deny(&mut self, num: usize) -> &mut Self {
+        if num < SYSCALL_FILTER_LEN {
+            self.bits[num / 64] &= !(1 << (num % 64));
+        }
+        self
+    }
+
+    /// Whether syscall `num` is allowed.
+    pub fn is_allowed(&self, num: usize) -> bool {
+        num < SYSCALL_FILTER_LEN && self.bits[num / 64] & (1 << (num % 64)) != 0
+    }
+}
+
+/// Set the filter checked by the `syscall` entry on all CPUs, or disable
+/// it with `None`.
+///
+/// A syscall entered on another CPU while setting is checked by either the
+/// old or the new filter, or not checked.
+pub fn set_syscall_filter(filter: Option<&SyscallFilter>) {
+    SYSCALL_FILTER_ON.store(false, Ordering::SeqCst);
+    if let Some(filter) = filter {
+        for (word, &bits) in SYSCALL_FILTER.iter().zip(&filter.bits) {
+            word.store(bits, Ordering::Relaxed);
+        }
+        SYSCALL_FILTER_ON.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Get the filter checked by the `syscall` entry, `None` if disabled.
+pub fn syscall_filter() -> Option<SyscallFilter> {
+    if !SYSCALL_FILTER_ON.load(Ordering::SeqCst) {
+        return None;
+    }
+    let mut filter = SyscallFilter::deny_all();
+    for (bits, word) in filter.bits.iter_mut().zip(&SYSCALL_FILTER) {
+        *bits = word.load(Ordering::Relaxed);
+    }
+    Some(filter)
+}
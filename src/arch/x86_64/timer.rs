@@ -0,0 +1,63 @@
+//! Local APIC timer in TSC-deadline mode.
+//!
+//! The kernel configures the timer LVT with its vector in TSC-deadline mode,
+//! and tells the vector by [`set_vector`], so that `trap_reason()` reports
+//! it as `TrapReason::Timer`.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+
+/// `IA32_TSC_DEADLINE`
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+/// EOI register of x2APIC
+const IA32_X2APIC_EOI: u32 = 0x80b;
+
+/// Vector of the timer, 0 for unknown.
+static VECTOR: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the vector of the local APIC timer.
+pub fn set_vector(vector: u8) {
+    VECTOR.store(vector as usize, Ordering::Relaxed);
+}
+
+/// Whether `trap_num` is the vector of the timer.
+pub(super) fn is_vector(trap_num: usize) -> bool {
+    trap_num != 0 && trap_num == VECTOR.load(Ordering::Relaxed)
+}
+
+/// Current time in TSC ticks.
+pub fn now() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Fire the timer when TSC reaches `deadline`.
+///
+/// # Safety
+///
+/// The local APIC timer must be in TSC-deadline mode.
+pub unsafe fn set_deadline(deadline: u64) {
+    // a deadline of 0 disarms the timer
+    Msr::new(IA32_TSC_DEADLINE).write(deadline.max(1));
+}
+
+/// Disarm the timer.
+///
+/// # Safety
+///
+/// The local APIC timer must be in TSC-deadline mode.
+pub unsafe fn clear() {
+    Msr::new(IA32_TSC_DEADLINE).write(0);
+}
+
+/// Acknowledge the timer interrupt by EOI of x2APIC.
+///
+/// Not needed if an interrupt controller is set in [`crate::intc`].
+///
+/// # Safety
+///
+/// The local APIC must be in x2APIC mode. In xAPIC mode, write the EOI
+/// register at the kernel mapping of the APIC instead.
+pub unsafe fn ack() {
+    Msr::new(IA32_X2APIC_EOI).write(0);
+}
@@ -1,6 +1,42 @@
-use core::arch::global_asm;
+use super::gdt::{TSS_SP0_OFFSET, TSS_SP1_OFFSET};
+use super::layout::USER_CONTEXT_SS_OFFSET;
+use crate::{PageFaultInfo, TrapReason};
+use core::arch::{asm, global_asm};
+use x86_64::registers::control::Cr2;
 
-global_asm!(include_str!("trap.S"));
+/// Stack of `__alltraps` on entry, pushed by the CPU, the vector and
+/// `__alltraps` itself.
+#[allow(dead_code)]
+#[repr(C)]
+struct EntryFrame {
+    rax: usize,
+    trap_num: usize,
+    error_code: usize,
+    rip: usize,
+    cs: usize,
+    rflags: usize,
+    rsp: usize,
+    ss: usize,
+}
+
+// the pushes follow the layout of `UserContext`, checked in `layout`
+global_asm_equ!(
+    [
+        KPTI = cfg!(feature = "kpti") as usize,
+        USER_CONTEXT_SS_OFFSET,
+        TSS_SP0_OFFSET,
+        TSS_SP1_OFFSET,
+        ENTRY_RAX_OFFSET = offset_of!(EntryFrame, rax),
+        ENTRY_TRAP_NUM_OFFSET = offset_of!(EntryFrame, trap_num),
+        ENTRY_ERROR_CODE_OFFSET = offset_of!(EntryFrame, error_code),
+        ENTRY_RIP_OFFSET = offset_of!(EntryFrame, rip),
+        ENTRY_CS_OFFSET = offset_of!(EntryFrame, cs),
+        ENTRY_RFLAGS_OFFSET = offset_of!(EntryFrame, rflags),
+        ENTRY_RSP_OFFSET = offset_of!(EntryFrame, rsp),
+        ENTRY_SS_OFFSET = offset_of!(EntryFrame, ss),
+    ],
+    include_str!("trap.S")
+);
 global_asm!(include_str!(concat!(env!("OUT_DIR"), "/vector.S")));
 
 /// Trap frame of kernel interrupt
@@ -23,7 +59,13 @@ global_asm!(include_str!(concat!(env!("OUT_DIR"), "/vector.S")));
 ///     }
 /// }
 /// ```
-#[derive(Debug, Default, Clone, Copy)]
+///
+/// Handlers for specific vectors can also be registered by
+/// [`register_handler`](crate::interrupt::register_handler), which take
+/// precedence over `trap_handler`.
+#[derive(Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zerocopy", derive(zerocopy::FromBytes, zerocopy::AsBytes))]
 #[repr(C)]
 pub struct TrapFrame {
     // Pushed by 'trap.S'
@@ -54,3 +96,55 @@ pub struct TrapFrame {
     pub cs: usize,
     pub rflags: usize,
 }
+
+impl_bytemuck!(TrapFrame);
+
+impl TrapFrame {
+    /// Whether the trap interrupted user mode, by the privilege level of `cs`.
+    ///
+    /// Traps from user are returned from `UserContext::run()` instead, so
+    /// it is false for frames passed to the trap handler. See
+    /// [`nesting`](crate::nesting) for the depth of nested traps.
+    pub fn is_from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+
+    /// Get information of the trap if it is a page fault.
+    ///
+    /// The faulting address is read from `CR2`, so this must be called in
+    /// the trap handler before interrupts are enabled.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        if self.trap_num != 14 {
+            return None;
+        }
+        Some(PageFaultInfo::new(
+            Cr2::read().as_u64() as usize,
+            super::page_fault_flags(self.error_code),
+        ))
+    }
+
+    /// Decode the reason of the trap.
+    ///
+    /// A page fault or double fault in a guard page of
+    /// [`stack_guard`](crate::stack_guard) is `KernelStackOverflow`. Like
+    /// [`page_fault_info`](Self::page_fault_info), this must be called in
+    /// the trap handler before interrupts are enabled.
+    pub fn trap_reason(&self) -> TrapReason {
+        let cr2 = match self.trap_num {
+            8 | 14 => Cr2::read().as_u64() as usize,
+            _ => 0,
+        };
+        if matches!(self.trap_num, 8 | 14) && super::stack_guard::contains(cr2) {
+            return TrapReason::KernelStackOverflow { addr: cr2 };
+        }
+        let dr6 = match self.trap_num {
+            1 => {
+                let dr6: usize;
+                unsafe { asm!("mov {}, dr6", out(reg) dr6, options(nomem, nostack)) };
+                dr6
+            }
+            _ => 0,
+        };
+        super::reason(self.trap_num, self.error_code, cr2, dr6)
+    }
+}
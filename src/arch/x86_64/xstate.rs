@@ -0,0 +1,203 @@
+//! Extended processor state, saved by `xsave`.
+
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// State components we are willing to enable in XCR0:
+/// x87 | SSE | AVX | opmask | ZMM_Hi256 | Hi16_ZMM
+const XCR0_WANTED: u64 = 0b1110_0111;
+
+#[cfg(feature = "alloc")]
+/// XSAVE area must be 64 bytes aligned.
+const XSAVE_ALIGN: usize = 64;
+
+#[cfg(feature = "alloc")]
+/// Offset of MXCSR in the legacy region.
+const MXCSR_OFFSET: usize = 24;
+
+/// Enabled state components, 0 for not probed yet.
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+/// Size of XSAVE area for enabled state components.
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the CPU supports `xsave`.
+fn has_xsave() -> bool {
+    crate::cpu_features().contains(crate::CpuFeatures::XSAVE)
+}
+
+/// Enable `xsave` and all wanted state components supported by the CPU.
+///
+/// Do nothing if the CPU does not support `xsave`.
+#[cfg(baremetal)]
+pub(super) unsafe fn init() {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    if !has_xsave() {
+        return;
+    }
+    Cr4::update(|cr4| {
+        cr4.insert(Cr4Flags::OSXSAVE);
+    });
+    let leaf = __cpuid_count(0xd, 0);
+    let supported = (leaf.edx as u64) << 32 | leaf.eax as u64;
+    let xcr0 = supported & XCR0_WANTED;
+    asm!(
+        "xsetbv",
+        in("ecx") 0,
+        in("eax") xcr0 as u32,
+        in("edx") (xcr0 >> 32) as u32,
+        options(nostack),
+    );
+    probe();
+}
+
+/// Read XCR0 and the area size of enabled state components.
+fn probe() -> (u64, usize) {
+    let (eax, edx): (u32, u32);
+    unsafe {
+        asm!("xgetbv", in("ecx") 0, out("eax") eax, out("edx") edx, options(nomem, nostack));
+    }
+    let features = (edx as u64) << 32 | eax as u64;
+    // EBX: size required by the enabled features in XCR0
+    let size = unsafe { __cpuid_count(0xd, 0) }.ebx as usize;
+    FEATURES.store(features, Ordering::Relaxed);
+    AREA_SIZE.store(size, Ordering::Relaxed);
+    (features, size)
+}
+
+/// Get enabled state components (XCR0) and the size of XSAVE area.
+///
+/// Return `None` if `xsave` is unavailable.
+pub fn xsave_layout() -> Option<(u64, usize)> {
+    let features = FEATURES.load(Ordering::Relaxed);
+    if features != 0 {
+        return Some((features, AREA_SIZE.load(Ordering::Relaxed)));
+    }
+    if !has_xsave() {
+        return None;
+    }
+    let cpuid = raw_cpuid::CpuId::new();
+    if !cpuid.get_feature_info().map_or(false, |f| f.has_oxsave()) {
+        return None;
+    }
+    Some(probe())
+}
+
+/// Extended processor state, including x87, SSE, AVX and AVX-512 registers
+/// as far as they are enabled.
+///
+/// The area is sized and aligned according to XCR0, probed at `init()`
+/// (or at first use when running on a hosted OS).
+#[cfg(feature = "alloc")]
+pub struct ExtendedState {
+    area: *mut u8,
+    size: usize,
+    features: u64,
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl Send for ExtendedState {}
+#[cfg(feature = "alloc")]
+unsafe impl Sync for ExtendedState {}
+
+#[cfg(feature = "alloc")]
+impl ExtendedState {
+    /// Allocate an area in the initial state.
+    ///
+    /// Return `None` if `xsave` is unavailable.
+    pub fn new() -> Option<Self> {
+        let (features, size) = xsave_layout()?;
+        let layout = Layout::from_size_align(size, XSAVE_ALIGN).unwrap();
+        let area = unsafe { alloc_zeroed(layout) };
+        if area.is_null() {
+            handle_alloc_error(layout);
+        }
+        let mut state = ExtendedState {
+            area,
+            size,
+            features,
+        };
+        // All components are in initial state since XSTATE_BV is 0,
+        // except MXCSR which is always loaded from the legacy region.
+        state.as_bytes_mut()[MXCSR_OFFSET..MXCSR_OFFSET + 4]
+            .copy_from_slice(&0x1f80u32.to_le_bytes());
+        Some(state)
+    }
+
+    /// Size of the area in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// State components covered by the area (XCR0 at allocation).
+    pub fn features(&self) -> u64 {
+        self.features
+    }
+
+    /// Save the current extended state into `self`.
+    pub fn xsave(&mut self) {
+        unsafe {
+            asm!(
+                "xsave64 [{}]",
+                in(reg) self.area,
+                in("eax") self.features as u32,
+                in("edx") (self.features >> 32) as u32,
+                options(nostack),
+            );
+        }
+    }
+
+    /// Load the extended state from `self`.
+    pub fn xrstor(&self) {
+        unsafe {
+            asm!(
+                "xrstor64 [{}]",
+                in(reg) self.area,
+                in("eax") self.features as u32,
+                in("edx") (self.features >> 32) as u32,
+                options(nostack),
+            );
+        }
+    }
+
+    /// Get the raw bytes of the XSAVE area.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.area, self.size) }
+    }
+
+    /// Get the mutable raw bytes of the XSAVE area.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.area, self.size) }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Clone for ExtendedState {
+    fn clone(&self) -> Self {
+        let mut state = ExtendedState::new().unwrap();
+        assert_eq!(state.size, self.size);
+        state.as_bytes_mut().copy_from_slice(self.as_bytes());
+        state
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Drop for ExtendedState {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.size, XSAVE_ALIGN).unwrap();
+        unsafe { dealloc(self.area, layout) };
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl core::fmt::Debug for ExtendedState {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("ExtendedState")
+            .field("size", &self.size)
+            .field("features", &self.features)
+            .finish()
+    }
+}
@@ -0,0 +1,163 @@
+//! Backtrace from a trap frame by frame pointers.
+//!
+//! The kernel must be built with frame pointers, e.g. by
+//! `-C force-frame-pointers=yes`. On mipsel, where the frame layout is not
+//! fixed by the ABI, only the trapped address is reported. powerpc64 walks
+//! the back chain of the stack frames instead, which is always kept.
+
+use crate::TrapFrame;
+
+/// Maximum number of frames to walk.
+const MAX_DEPTH: usize = 64;
+
+/// Call `f` with the trapped program counter of `tf`, then the return
+/// address of each frame on the stack, until `f` returns false or the
+/// frame chain ends.
+///
+/// The walk stops at a null, misaligned or non-ascending frame pointer,
+/// or after 64 frames.
+///
+/// # Safety
+///
+/// The frame pointers reachable from `tf` must be null or point to
+/// readable memory, i.e. the trap is from kernel code with frame pointers.
+pub unsafe fn backtrace(tf: &TrapFrame, mut f: impl FnMut(usize) -> bool) {
+    let (pc, mut fp) = imp::start(tf);
+    if !f(pc) {
+        return;
+    }
+    for _ in 0..MAX_DEPTH {
+        if fp == 0 || fp % core::mem::align_of::<usize>() != 0 {
+            return;
+        }
+        let (ra, next) = match imp::next(fp) {
+            Some(frame) => frame,
+            None => return,
+        };
+        if ra == 0 || !f(ra) || next <= fp {
+            return;
+        }
+        fp = next;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use crate::TrapFrame;
+
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.rip, tf.rbp)
+    }
+
+    /// `[fp]` is the previous `rbp`, `[fp + 8]` is the return address.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let frame = fp as *const usize;
+        Some((*frame.add(1), *frame))
+    }
+}
+
+#[cfg(target_arch = "x86")]
+mod imp {
+    use crate::TrapFrame;
+
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.eip, tf.ebp)
+    }
+
+    /// `[fp]` is the previous `ebp`, `[fp + 4]` is the return address.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let frame = fp as *const usize;
+        Some((*frame.add(1), *frame))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use crate::TrapFrame;
+
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.elr, tf.general.x29)
+    }
+
+    /// `[fp]` is the previous `x29`, `[fp + 8]` is the saved `x30`.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let frame = fp as *const usize;
+        Some((*frame.add(1), *frame))
+    }
+}
+
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+mod imp {
+    use crate::TrapFrame;
+
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.sepc, tf.general.s0)
+    }
+
+    #[cfg(target_arch = "loongarch64")]
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.era, tf.general.fp)
+    }
+
+    /// `fp` is the top of the frame, with the return address at `[fp - 1]`
+    /// and the previous frame pointer at `[fp - 2]` in words.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let frame = fp as *const usize;
+        Some((*frame.sub(1), *frame.sub(2)))
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use crate::TrapFrame;
+
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.epc, 0)
+    }
+
+    pub unsafe fn next(_fp: usize) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use crate::TrapFrame;
+
+    /// Every frame starts with the back chain, so the stack pointer serves
+    /// as the frame pointer.
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.srr0, tf.general.r1)
+    }
+
+    /// `[fp]` is the previous stack pointer, and the return address is in
+    /// the LR save word of that frame, `[[fp] + 16]`.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let next = *(fp as *const usize);
+        if next == 0 {
+            return None;
+        }
+        Some((*(next as *const usize).add(2), next))
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use crate::TrapFrame;
+
+    /// `r11` is the frame pointer of ARM code, so Thumb code is not walked.
+    pub fn start(tf: &TrapFrame) -> (usize, usize) {
+        (tf.pc, tf.general.r11)
+    }
+
+    /// `[fp]` is the previous `r11`, `[fp + 4]` is the return address.
+    pub unsafe fn next(fp: usize) -> Option<(usize, usize)> {
+        let frame = fp as *const usize;
+        Some((*frame.add(1), *frame))
+    }
+}
@@ -0,0 +1,278 @@
+//! Minimal ELF core file generation.
+//!
+//! The core file contains one `NT_PRSTATUS` note for each thread and one
+//! `PT_LOAD` segment for each memory region provided by the caller, which
+//! is enough for GDB to show registers, backtraces and memory.
+
+use crate::arch::linux::UserRegs;
+use crate::arch::{ELF_FLAGS, ELF_MACHINE};
+use crate::UserContext;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+bitflags! {
+    /// Permission of a memory segment, as `p_flags` of program header.
+    pub struct SegmentFlags: u32 {
+        const EXECUTE = 1 << 0;
+        const WRITE = 1 << 1;
+        const READ = 1 << 2;
+    }
+}
+
+/// A memory segment of the user program to include in the core file.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<'a> {
+    /// Start virtual address
+    pub vaddr: usize,
+    /// Content of the segment
+    pub data: &'a [u8],
+    /// Permission of the segment
+    pub flags: SegmentFlags,
+}
+
+/// A thread of the user program to include in the core file.
+#[derive(Debug, Clone, Copy)]
+pub struct Thread<'a> {
+    /// Thread ID, reported as `pr_pid`
+    pub tid: u32,
+    /// Registers of the thread
+    pub context: &'a UserContext,
+}
+
+const WORD: usize = core::mem::size_of::<usize>();
+const EHDR_SIZE: usize = if WORD == 8 { 64 } else { 52 };
+const PHDR_SIZE: usize = if WORD == 8 { 56 } else { 32 };
+/// Offset of `pr_reg` in `elf_prstatus`.
+const PR_REG_OFFSET: usize = if WORD == 8 { 112 } else { 72 };
+/// Size of `elf_prstatus`, with `pr_fpvalid` and tail padding.
+const PRSTATUS_SIZE: usize =
+    (PR_REG_OFFSET + core::mem::size_of::<UserRegs>() + 4 + WORD - 1) / WORD * WORD;
+/// Size of a note with name "CORE" and an `elf_prstatus`.
+const NOTE_SIZE: usize = 12 + 8 + PRSTATUS_SIZE;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+/// Generate a core file of a user program killed by `signal`.
+///
+/// The first thread is the one that received the signal.
+pub fn generate(signal: u32, threads: &[Thread], segments: &[Segment]) -> Vec<u8> {
+    let phnum = 1 + segments.len();
+    let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    let notes_size = threads.len() * NOTE_SIZE;
+    let data_size: usize = segments.iter().map(|s| s.data.len()).sum();
+    let mut buf = Writer(Vec::with_capacity(notes_offset + notes_size + data_size));
+
+    // ELF header
+    buf.bytes(b"\x7fELF");
+    buf.u8(if WORD == 8 { 2 } else { 1 }); // EI_CLASS
+    buf.u8(1); // EI_DATA: little endian
+    buf.u8(1); // EI_VERSION
+    buf.bytes(&[0; 9]);
+    buf.u16(ET_CORE);
+    buf.u16(ELF_MACHINE);
+    buf.u32(1); // e_version
+    buf.word(0); // e_entry
+    buf.word(EHDR_SIZE); // e_phoff
+    buf.word(0); // e_shoff
+    buf.u32(ELF_FLAGS);
+    buf.u16(EHDR_SIZE as u16);
+    buf.u16(PHDR_SIZE as u16);
+    buf.u16(phnum as u16);
+    buf.u16(0); // e_shentsize
+    buf.u16(0); // e_shnum
+    buf.u16(0); // e_shstrndx
+
+    // program headers
+    buf.phdr(PT_NOTE, 0, notes_offset, 0, notes_size, 0, 4);
+    let mut offset = notes_offset + notes_size;
+    for segment in segments {
+        let size = segment.data.len();
+        let flags = segment.flags.bits();
+        buf.phdr(PT_LOAD, flags, offset, segment.vaddr, size, size, 1);
+        offset += size;
+    }
+
+    // notes
+    for thread in threads {
+        buf.u32(5); // n_namesz
+        buf.u32(PRSTATUS_SIZE as u32);
+        buf.u32(NT_PRSTATUS);
+        buf.bytes(b"CORE\0\0\0\0");
+        let start = buf.0.len();
+        buf.u32(signal); // si_signo
+        buf.u32(0); // si_code
+        buf.u32(0); // si_errno
+        buf.u16(signal as u16); // pr_cursig
+        buf.bytes(&[0; 2]);
+        buf.word(0); // pr_sigpend
+        buf.word(0); // pr_sighold
+        buf.u32(thread.tid); // pr_pid
+        buf.bytes(&[0; 12]); // pr_ppid, pr_pgrp, pr_sid
+        buf.bytes(&[0; 8 * WORD]); // pr_utime, pr_stime, pr_cutime, pr_cstime
+        buf.bytes(pod::Pod::as_bytes(&thread.context.get_user_regs()));
+        buf.u32(0); // pr_fpvalid
+        buf.0.resize(start + PRSTATUS_SIZE, 0);
+    }
+
+    // segments
+    for segment in segments {
+        buf.bytes(segment.data);
+    }
+    buf.0
+}
+
+/// Little-endian writer for ELF structures.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    fn word(&mut self, x: usize) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    /// Write a program header, whose field order differs on ELF32 and ELF64.
+    #[allow(clippy::too_many_arguments)]
+    fn phdr(
+        &mut self,
+        kind: u32,
+        flags: u32,
+        offset: usize,
+        vaddr: usize,
+        filesz: usize,
+        memsz: usize,
+        align: usize,
+    ) {
+        self.u32(kind);
+        if WORD == 8 {
+            self.u32(flags);
+        }
+        self.word(offset);
+        self.word(vaddr);
+        self.word(0); // p_paddr
+        self.word(filesz);
+        self.word(memsz);
+        if WORD != 8 {
+            self.u32(flags);
+        }
+        self.word(align);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn word_at(buf: &[u8], offset: usize) -> usize {
+        usize::from_le_bytes(buf[offset..offset + WORD].try_into().unwrap())
+    }
+
+    #[test]
+    fn offsets() {
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!((EHDR_SIZE, PHDR_SIZE, PR_REG_OFFSET), (64, 56, 112));
+        #[cfg(target_pointer_width = "32")]
+        assert_eq!((EHDR_SIZE, PHDR_SIZE, PR_REG_OFFSET), (52, 32, 72));
+        assert_eq!(PRSTATUS_SIZE % WORD, 0);
+        assert!(PRSTATUS_SIZE >= PR_REG_OFFSET + core::mem::size_of::<UserRegs>() + 4);
+    }
+
+    #[test]
+    fn generate_core() {
+        const SIGSEGV: u32 = 11;
+        let mut context = UserContext::default();
+        context.set_ip(0x1234);
+        context.set_sp(0x8000);
+        let threads = [
+            Thread {
+                tid: 7,
+                context: &context,
+            },
+            Thread {
+                tid: 8,
+                context: &UserContext::default(),
+            },
+        ];
+        let data = [0x5au8; 16];
+        let segments = [Segment {
+            vaddr: 0x4000,
+            data: &data,
+            flags: SegmentFlags::READ | SegmentFlags::WRITE,
+        }];
+        let file = generate(SIGSEGV, &threads, &segments);
+
+        // ELF header
+        assert_eq!(&file[..4], b"\x7fELF");
+        assert_eq!(file[4], if WORD == 8 { 2 } else { 1 });
+        assert_eq!(file[5], 1);
+        assert_eq!(u16_at(&file, 16), ET_CORE);
+        assert_eq!(u16_at(&file, 18), ELF_MACHINE);
+        let (phoff, ehsize) = if WORD == 8 { (32, 52) } else { (28, 40) };
+        assert_eq!(word_at(&file, phoff), EHDR_SIZE);
+        assert_eq!(u16_at(&file, ehsize), EHDR_SIZE as u16);
+        assert_eq!(u16_at(&file, ehsize + 2), PHDR_SIZE as u16);
+        assert_eq!(u16_at(&file, ehsize + 4), 2);
+
+        // program headers: p_offset, p_vaddr, p_paddr and p_filesz are
+        // consecutive words, after p_type and also p_flags on ELF64
+        let phdr = |i: usize| EHDR_SIZE + i * PHDR_SIZE;
+        let field = |i: usize, n: usize| match WORD {
+            8 => word_at(&file, phdr(i) + 8 + n * 8),
+            _ => word_at(&file, phdr(i) + 4 + n * 4),
+        };
+        let notes_offset = EHDR_SIZE + 2 * PHDR_SIZE;
+        assert_eq!(u32_at(&file, phdr(0)), PT_NOTE);
+        assert_eq!(field(0, 0), notes_offset);
+        assert_eq!(field(0, 3), 2 * NOTE_SIZE);
+        assert_eq!(u32_at(&file, phdr(1)), PT_LOAD);
+        let flags_offset = if WORD == 8 { 4 } else { 24 };
+        assert_eq!(u32_at(&file, phdr(1) + flags_offset), 0b110);
+        let data_offset = notes_offset + 2 * NOTE_SIZE;
+        assert_eq!(field(1, 0), data_offset);
+        assert_eq!(field(1, 1), 0x4000);
+        assert_eq!(field(1, 3), data.len());
+        assert_eq!(&file[data_offset..], &data);
+
+        // NT_PRSTATUS of each thread
+        for (i, thread) in threads.iter().enumerate() {
+            let note = notes_offset + i * NOTE_SIZE;
+            assert_eq!(u32_at(&file, note), 5);
+            assert_eq!(u32_at(&file, note + 4), PRSTATUS_SIZE as u32);
+            assert_eq!(u32_at(&file, note + 8), NT_PRSTATUS);
+            assert_eq!(&file[note + 12..note + 17], b"CORE\0");
+            let desc = note + 20;
+            assert_eq!(u32_at(&file, desc), SIGSEGV);
+            assert_eq!(u16_at(&file, desc + 12), SIGSEGV as u16);
+            assert_eq!(u32_at(&file, desc + 16 + 2 * WORD), thread.tid);
+            let regs = thread.context.get_user_regs();
+            let regs = pod::Pod::as_bytes(&regs);
+            let pr_reg = desc + PR_REG_OFFSET;
+            assert_eq!(&file[pr_reg..pr_reg + regs.len()], regs);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! CPU features used by the trap paths.
+//!
+//! The features are probed once on first use, by CPUID on x86, `misa` on
+//! riscv (the writable `FS` and `VS` fields of `sstatus` in S-mode), and the
+//! `ID_AA64*` registers on aarch64. Other architectures report none.
+//!
+//! The trap paths pick their variants by the result, e.g. `xsave` for the
+//! extended state, and kernels can reuse it by [`cpu_features()`].
+
+use bitflags::bitflags;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+bitflags! {
+    /// Features of the CPU, only the ones of the current architecture
+    /// can be set.
+    pub struct CpuFeatures: u64 {
+        /// x86: `syscall` and `sysret` instructions
+        const SYSCALL = 1 << 0;
+        /// x86: `rdfsbase` and `wrfsbase` family of instructions
+        const FSGSBASE = 1 << 1;
+        /// x86: `xsave` family of instructions
+        const XSAVE = 1 << 2;
+        /// x86: AVX state
+        const AVX = 1 << 3;
+        /// x86: AVX-512 foundation state
+        const AVX512 = 1 << 4;
+        /// x86: supervisor mode execution prevention
+        const SMEP = 1 << 5;
+        /// x86: supervisor mode access prevention
+        const SMAP = 1 << 6;
+        /// x86: shadow stacks of CET
+        const CET_SS = 1 << 7;
+        /// x86: indirect branch tracking of CET
+        const CET_IBT = 1 << 8;
+        /// x86: 5-level paging with 57-bit virtual addresses
+        const LA57 = 1 << 9;
+        /// x86: indirect branch prediction barrier
+        const IBPB = 1 << 10;
+
+        /// riscv: single-precision floating-point (F)
+        const RISCV_F = 1 << 16;
+        /// riscv: double-precision floating-point (D), only known in M-mode
+        const RISCV_D = 1 << 17;
+        /// riscv: compressed instructions (C), only known in M-mode
+        const RISCV_C = 1 << 18;
+        /// riscv: vector (V)
+        const RISCV_V = 1 << 19;
+        /// riscv: hypervisor (H), only known in M-mode
+        const RISCV_H = 1 << 20;
+
+        /// aarch64: floating-point
+        const FP = 1 << 32;
+        /// aarch64: Advanced SIMD
+        const ASIMD = 1 << 33;
+        /// aarch64: scalable vector extension
+        const SVE = 1 << 34;
+        /// aarch64: privileged access never
+        const PAN = 1 << 35;
+    }
+}
+
+/// Set in [`FEATURES`] once probed, not a feature.
+const PROBED: u64 = 1 << 63;
+
+/// Features probed, 0 for not probed yet.
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+
+/// Get the features of the current CPU, probed on first call.
+///
+/// All CPUs are assumed to have the same features.
+pub fn cpu_features() -> CpuFeatures {
+    let features = FEATURES.load(Ordering::Relaxed);
+    if features & PROBED != 0 {
+        return CpuFeatures::from_bits_truncate(features);
+    }
+    let features = imp::probe();
+    FEATURES.store(features.bits() | PROBED, Ordering::Relaxed);
+    features
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+mod imp {
+    use super::CpuFeatures;
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::{__cpuid, __cpuid_count};
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+    pub fn probe() -> CpuFeatures {
+        let mut features = CpuFeatures::empty();
+        let (max, max_ext) = unsafe { (__cpuid(0).eax, __cpuid(0x8000_0000).eax) };
+        let leaf1 = unsafe { __cpuid(1) };
+        features.set(CpuFeatures::XSAVE, leaf1.ecx & (1 << 26) != 0);
+        features.set(CpuFeatures::AVX, leaf1.ecx & (1 << 28) != 0);
+        if max >= 7 {
+            let leaf7 = unsafe { __cpuid_count(7, 0) };
+            features.set(CpuFeatures::FSGSBASE, leaf7.ebx & (1 << 0) != 0);
+            features.set(CpuFeatures::SMEP, leaf7.ebx & (1 << 7) != 0);
+            features.set(CpuFeatures::AVX512, leaf7.ebx & (1 << 16) != 0);
+            features.set(CpuFeatures::SMAP, leaf7.ebx & (1 << 20) != 0);
+            features.set(CpuFeatures::CET_SS, leaf7.ecx & (1 << 7) != 0);
+            features.set(CpuFeatures::LA57, leaf7.ecx & (1 << 16) != 0);
+            features.set(CpuFeatures::CET_IBT, leaf7.edx & (1 << 20) != 0);
+            // Intel
+            features.set(CpuFeatures::IBPB, leaf7.edx & (1 << 26) != 0);
+        }
+        if max_ext >= 0x8000_0001 {
+            let leaf = unsafe { __cpuid(0x8000_0001) };
+            features.set(CpuFeatures::SYSCALL, leaf.edx & (1 << 11) != 0);
+        }
+        // AMD
+        if max_ext >= 0x8000_0008 && unsafe { __cpuid(0x8000_0008) }.ebx & (1 << 12) != 0 {
+            features.insert(CpuFeatures::IBPB);
+        }
+        features
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use super::CpuFeatures;
+    use core::arch::asm;
+
+    #[cfg(feature = "riscv_m_mode")]
+    pub fn probe() -> CpuFeatures {
+        let misa: usize;
+        unsafe { asm!("csrr {}, misa", out(reg) misa, options(nomem, nostack)) };
+        let has = |letter: u8| misa & (1 << (letter - b'A')) != 0;
+        let mut features = CpuFeatures::empty();
+        features.set(CpuFeatures::RISCV_F, has(b'F'));
+        features.set(CpuFeatures::RISCV_D, has(b'D'));
+        features.set(CpuFeatures::RISCV_C, has(b'C'));
+        features.set(CpuFeatures::RISCV_V, has(b'V'));
+        features.set(CpuFeatures::RISCV_H, has(b'H'));
+        features
+    }
+
+    /// `misa` is not accessible in S-mode, `sstatus.FS` and `sstatus.VS` are
+    /// read-only zero without F and V.
+    #[cfg(not(feature = "riscv_m_mode"))]
+    pub fn probe() -> CpuFeatures {
+        /// `sstatus.FS` field
+        const FS: usize = 3 << 13;
+        /// `sstatus.VS` field
+        const VS: usize = 3 << 9;
+
+        let sstatus: usize;
+        unsafe {
+            asm!(
+                "csrrs {0}, sstatus, {1}",
+                "csrr {2}, sstatus",
+                "csrw sstatus, {0}",
+                out(reg) _,
+                in(reg) FS | VS,
+                out(reg) sstatus,
+                options(nomem, nostack),
+            );
+        }
+        let mut features = CpuFeatures::empty();
+        features.set(CpuFeatures::RISCV_F, sstatus & FS != 0);
+        features.set(CpuFeatures::RISCV_V, sstatus & VS != 0);
+        features
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use super::CpuFeatures;
+    use core::arch::asm;
+
+    pub fn probe() -> CpuFeatures {
+        let (pfr0, mmfr1): (usize, usize);
+        unsafe {
+            asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0, options(nomem, nostack));
+            asm!("mrs {}, id_aa64mmfr1_el1", out(reg) mmfr1, options(nomem, nostack));
+        }
+        let mut features = CpuFeatures::empty();
+        // 0xf for not implemented
+        features.set(CpuFeatures::FP, (pfr0 >> 16) & 0xf != 0xf);
+        features.set(CpuFeatures::ASIMD, (pfr0 >> 20) & 0xf != 0xf);
+        features.set(CpuFeatures::SVE, (pfr0 >> 32) & 0xf != 0);
+        features.set(CpuFeatures::PAN, (mmfr1 >> 20) & 0xf != 0);
+        features
+    }
+}
+
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "loongarch64",
+    target_arch = "powerpc64",
+    target_arch = "arm"
+))]
+mod imp {
+    use super::CpuFeatures;
+
+    pub fn probe() -> CpuFeatures {
+        CpuFeatures::empty()
+    }
+}
@@ -0,0 +1,67 @@
+//! Compare user contexts register by register.
+//!
+//! Handy to find the register an entry or exit path corrupts: save a copy
+//! of the context, run it through the path, and print the difference.
+
+use crate::UserContext;
+use core::fmt;
+
+/// Name of a register in [`UserContext`], as in its `Display` output.
+pub type RegName = &'static str;
+
+impl UserContext {
+    /// Get the registers differing from `other`, with the values in `self`
+    /// and `other`.
+    ///
+    /// Floating-point and vector state is not compared.
+    pub fn diff(&self, other: &Self) -> impl Iterator<Item = (RegName, u64, u64)> {
+        self.named_regs()
+            .zip(other.named_regs())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| (name, old as u64, new as u64))
+    }
+
+    /// Display the registers changed from `self` to `new`.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let old = context;
+    /// context.run();
+    /// print!("{}", old.display_diff(&context));
+    /// ```
+    pub fn display_diff<'a>(&'a self, new: &'a Self) -> ContextDiff<'a> {
+        ContextDiff { old: self, new }
+    }
+}
+
+/// Registers changed between two contexts, created by
+/// [`UserContext::display_diff`].
+///
+/// Displayed as a line of `registers changed:` followed by a line of
+/// `name: old -> new` for each register, or `no registers changed`.
+pub struct ContextDiff<'a> {
+    old: &'a UserContext,
+    new: &'a UserContext,
+}
+
+impl fmt::Display for ContextDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut diff = self.old.diff(self.new).peekable();
+        if diff.peek().is_none() {
+            return writeln!(f, "no registers changed");
+        }
+        writeln!(f, "registers changed:")?;
+        let value_width = 2 + 2 * core::mem::size_of::<usize>();
+        for (name, old, new) in diff {
+            writeln!(
+                f,
+                "{:>10}: {:#0w$x} -> {:#0w$x}",
+                name,
+                old,
+                new,
+                w = value_width
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Helpers to display registers in crash logs.
+
+use core::fmt;
+use core::mem::size_of;
+
+/// Registers in a row.
+const COLUMNS: usize = 4;
+
+/// Write `regs` as `name=value` in hex, a few in each line, with names
+/// aligned and values zero-padded.
+pub(crate) fn write_regs(f: &mut fmt::Formatter, regs: &[(&str, usize)]) -> fmt::Result {
+    let name_width = regs.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let value_width = 2 + 2 * size_of::<usize>();
+    for (i, (name, value)) in regs.iter().enumerate() {
+        let sep = if i % COLUMNS == COLUMNS - 1 || i == regs.len() - 1 {
+            "\n"
+        } else {
+            " "
+        };
+        write!(
+            f,
+            "{:>nw$}={:#0vw$x}{}",
+            name,
+            value,
+            sep,
+            nw = name_width,
+            vw = value_width
+        )?;
+    }
+    Ok(())
+}
+
+/// Write `value` of register `name` in hex, followed by the names of set
+/// bits in `bits`.
+pub(crate) fn write_flags(
+    f: &mut fmt::Formatter,
+    name: &str,
+    value: usize,
+    bits: &[(usize, &str)],
+) -> fmt::Result {
+    write!(f, "{}={:#x} [", name, value)?;
+    for &(bit, bit_name) in bits {
+        if value & bit != 0 {
+            write!(f, " {}", bit_name)?;
+        }
+    }
+    writeln!(f, " ]")
+}
+
+/// Write `value` by `Display` in a block named `name`, for `Debug`.
+///
+/// State other than the registers, e.g. floating-point registers, is not
+/// included.
+pub(crate) fn write_debug(
+    f: &mut fmt::Formatter,
+    name: &str,
+    value: &dyn fmt::Display,
+) -> fmt::Result {
+    writeln!(f, "{} {{", name)?;
+    write!(f, "{}", value)?;
+    write!(f, "}}")
+}
@@ -0,0 +1,13 @@
+//! Register access by DWARF register numbers.
+//!
+//! The numbers follow the DWARF mapping of the psABI of each architecture,
+//! as used in `.eh_frame` and `.debug_frame`, so unwinders and debuggers can
+//! access `UserContext` without architecture-specific code. Only registers
+//! in the context are accessible, e.g. floating-point registers are not.
+
+/// A DWARF register number of the architecture.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct RegIndex(pub u16);
+
+/// Pointer to a register of a context, or `None` if it is not in the context.
+pub(crate) type RegPtr = Option<*const usize>;
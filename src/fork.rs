@@ -0,0 +1,89 @@
+//! Duplicate a user context for `fork`, `vfork` and `clone`.
+
+use crate::UserContext;
+
+impl UserContext {
+    /// Duplicate the context for a child created by `fork` or `clone` in
+    /// the syscall of the parent, returning `ret` to the child, e.g. 0.
+    ///
+    /// Single-step flags are cleared, and so are hardware breakpoints on
+    /// x86_64, which belong to the tracer of the parent. Other registers,
+    /// including the thread-local storage, are copied. The caller sets a
+    /// new stack or TLS for `clone` after it. The child starts with no
+    /// `user_cycles`, and with feature `mte` on aarch64, with no asynchronous
+    /// tag check faults.
+    pub fn fork_from(&self, ret: usize) -> UserContext {
+        let mut child = *self;
+        child.set_syscall_ret(ret);
+        imp::clear_single_step(&mut child);
+        child.user_cycles = 0;
+        #[cfg(all(target_arch = "aarch64", feature = "mte"))]
+        {
+            child.mte.tfsre0 = 0;
+        }
+        child
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use crate::{DebugRegs, UserContext};
+
+    /// `RFLAGS.TF` and `RFLAGS.RF`
+    const TF_RF: usize = 1 << 8 | 1 << 16;
+
+    pub fn clear_single_step(cx: &mut UserContext) {
+        cx.general.rflags &= !TF_RF;
+        cx.debug = DebugRegs::default();
+    }
+}
+
+#[cfg(target_arch = "x86")]
+mod imp {
+    use crate::UserContext;
+
+    /// `EFLAGS.TF` and `EFLAGS.RF`
+    const TF_RF: usize = 1 << 8 | 1 << 16;
+
+    pub fn clear_single_step(cx: &mut UserContext) {
+        cx.eflags &= !TF_RF;
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use crate::UserContext;
+
+    /// `SPSR_EL1.SS`
+    const SS: usize = 1 << 21;
+
+    pub fn clear_single_step(cx: &mut UserContext) {
+        cx.spsr &= !SS;
+    }
+}
+
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "mips",
+    target_arch = "loongarch64",
+    target_arch = "arm"
+))]
+mod imp {
+    use crate::UserContext;
+
+    /// No single-step flag in the context.
+    pub fn clear_single_step(_cx: &mut UserContext) {}
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use crate::UserContext;
+
+    /// `MSR.SE` and `MSR.BE`
+    const SE_BE: usize = 1 << 10 | 1 << 9;
+
+    pub fn clear_single_step(cx: &mut UserContext) {
+        cx.srr1 &= !SE_BE;
+    }
+}
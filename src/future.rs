@@ -0,0 +1,50 @@
+//! Run user contexts in async kernels.
+//!
+//! Entering user is modeled as a future resolving to the [`TrapInfo`] of
+//! the trap. A timer interrupt or IPI, by which the kernel preempts the
+//! user, makes the future yield once, after waking the waker of the
+//! caller, so that the executor can schedule other tasks before the trap
+//! is returned.
+
+use crate::{TrapInfo, TrapReason, UserContext};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Future of [`UserContext::run_async`].
+#[must_use = "futures do nothing unless polled"]
+pub struct RunFuture<'a> {
+    context: &'a mut UserContext,
+    /// Trap of preemption, returned on the next poll
+    preempted: Option<TrapInfo>,
+}
+
+impl UserContext {
+    /// Go to user space when polled, like
+    /// [`run_until_trap`](Self::run_until_trap), and resolve to
+    /// information of the trap.
+    pub fn run_async(&mut self) -> RunFuture<'_> {
+        RunFuture {
+            context: self,
+            preempted: None,
+        }
+    }
+}
+
+impl Future for RunFuture<'_> {
+    type Output = TrapInfo;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TrapInfo> {
+        let this = self.get_mut();
+        if let Some(info) = this.preempted.take() {
+            return Poll::Ready(info);
+        }
+        let info = this.context.run_until_trap();
+        if matches!(info.reason, TrapReason::Timer | TrapReason::Ipi(_)) {
+            this.preempted = Some(info);
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(info)
+    }
+}
@@ -0,0 +1,58 @@
+//! Hooks run around a `UserContext` going to user.
+//!
+//! `run()` of every architecture and `run_fncall()` call
+//! [`UserContext::before_user`] before going to user and
+//! [`UserContext::after_user_trap`] once the trap is saved, so the optional
+//! features see the same traps on all of them.
+
+use crate::UserContext;
+
+/// A run of a context in user, from `before_user` to `after_user_trap`.
+pub(crate) struct UserRun {
+    /// The counter when going to user, if timed
+    start: Option<u64>,
+}
+
+impl UserContext {
+    /// Run the hooks of the enabled features before going to user.
+    ///
+    /// The counter is only read if `timed`, or with feature `accounting`.
+    #[inline]
+    pub(crate) fn before_user(&self, timed: bool) -> UserRun {
+        #[cfg(feature = "trace")]
+        crate::trace::emit(crate::trace::TrapEvent::EnterUser);
+        let timed = timed || cfg!(feature = "accounting");
+        UserRun {
+            start: timed.then(crate::arch::read_cycles),
+        }
+    }
+
+    /// Run the hooks of the enabled features on the trap just taken, and
+    /// return the counter ticks spent in user, 0 if not timed.
+    #[inline]
+    pub(crate) fn after_user_trap(&mut self, run: UserRun) -> u64 {
+        let cycles = match run.start {
+            Some(start) => elapsed(start, crate::arch::read_cycles()),
+            None => 0,
+        };
+        #[cfg(feature = "accounting")]
+        {
+            self.user_cycles += cycles;
+        }
+        #[cfg(feature = "stats")]
+        crate::stats::user_trap(self);
+        #[cfg(feature = "trace")]
+        crate::trace::leave_user(self);
+        cycles
+    }
+}
+
+/// Counter ticks from `start` to `end`, across a wrap of the counter.
+fn elapsed(start: u64, end: u64) -> u64 {
+    // CP0 `Count` is 32 bits wide
+    if cfg!(target_arch = "mips") {
+        (end as u32).wrapping_sub(start as u32) as u64
+    } else {
+        end.wrapping_sub(start)
+    }
+}
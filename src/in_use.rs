@@ -0,0 +1,72 @@
+//! Detect a context running on two CPUs or threads at the same time.
+//!
+//! It takes unsafe aliasing to run a `UserContext` concurrently, e.g. when
+//! the kernel keeps contexts behind raw pointers, and the context would be
+//! silently corrupted. In debug builds, the contexts being run are kept in
+//! a table, and running one of them again panics.
+
+use crate::UserContext;
+
+/// Maximum number of contexts checked at the same time.
+#[cfg(debug_assertions)]
+const MAX_RUNNING: usize = 256;
+
+#[cfg(debug_assertions)]
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_RUNNING: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Addresses of the contexts being run, 0 for a free slot.
+#[cfg(debug_assertions)]
+static RUNNING: [core::sync::atomic::AtomicUsize; MAX_RUNNING] = [NOT_RUNNING; MAX_RUNNING];
+
+/// A guard marking a context in use until dropped.
+pub(crate) struct InUseGuard {
+    /// Slot in the table, `None` if it was full
+    #[cfg(debug_assertions)]
+    slot: Option<usize>,
+}
+
+impl InUseGuard {
+    /// Mark `cx` in use.
+    ///
+    /// The slot is claimed before looking for `cx` in other slots, so of two
+    /// CPUs running `cx` at the same time, at least one sees the other.
+    /// If `MAX_RUNNING` contexts are in use, `cx` is not kept, and is only
+    /// checked against them.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if `cx` is already in use.
+    pub(crate) fn new(cx: &UserContext) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            use core::sync::atomic::Ordering;
+            let addr = cx as *const UserContext as usize;
+            let slot = RUNNING.iter().position(|slot| {
+                slot.compare_exchange(0, addr, Ordering::SeqCst, Ordering::Relaxed)
+                    .is_ok()
+            });
+            let guard = InUseGuard { slot };
+            let twice = RUNNING
+                .iter()
+                .enumerate()
+                .any(|(i, other)| Some(i) != slot && other.load(Ordering::SeqCst) == addr);
+            assert!(!twice, "UserContext {:#x} is run concurrently", addr);
+            guard
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            let _ = cx;
+            InUseGuard {}
+        }
+    }
+}
+
+impl Drop for InUseGuard {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if let Some(slot) = self.slot {
+            RUNNING[slot].store(0, core::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
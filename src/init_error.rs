@@ -0,0 +1,39 @@
+//! Errors of initialization.
+
+/// Error of `init()`, and of `init_ap()` on x86_64.
+///
+/// Only x86_64 checks all of them. Other architectures return
+/// [`TooManyCpus`](Self::TooManyCpus) when their per-CPU tables are full,
+/// and succeed otherwise.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TrapInitError {
+    /// `init()` has been called already.
+    AlreadyInitialized,
+    /// `init_ap()` is called before `init()` on the bootstrap processor.
+    NotInitialized,
+    /// The CPU does not support the named feature.
+    UnsupportedCpu(&'static str),
+    /// The current GDT has too many entries to extend.
+    GdtFull(usize),
+    /// All [`MAX_CPUS`](crate::MAX_CPUS) slots of the per-CPU tables are
+    /// taken.
+    TooManyCpus,
+    /// The top of a kernel stack for the TSS is not 16 bytes aligned.
+    MisalignedStack(usize),
+    /// A different memory provider has been set by an earlier call.
+    ProviderChanged,
+}
+
+impl core::fmt::Display for TrapInitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::AlreadyInitialized => write!(f, "trapframe is initialized already"),
+            Self::NotInitialized => write!(f, "trapframe is not initialized by BSP"),
+            Self::UnsupportedCpu(feature) => write!(f, "CPU does not support {}", feature),
+            Self::GdtFull(count) => write!(f, "too many entries in the current GDT: {}", count),
+            Self::TooManyCpus => write!(f, "more than MAX_CPUS ({}) CPUs", crate::MAX_CPUS),
+            Self::MisalignedStack(top) => write!(f, "misaligned kernel stack top {:#x}", top),
+            Self::ProviderChanged => write!(f, "a different memory provider is set"),
+        }
+    }
+}
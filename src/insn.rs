@@ -0,0 +1,467 @@
+//! Skipping the user instruction at the trap, after emulating it.
+//!
+//! The kernel fetches the bytes at `pc` from user memory as it does for
+//! emulation, and [`UserContext::advance_pc()`] moves `pc` past the
+//! instruction by its length:
+//!
+//! - x86 and x86_64: decoded from the prefixes, opcode, ModRM, SIB,
+//!   displacement and immediate, in the mode of the context
+//! - riscv: 2 bytes for compressed instructions, 4 otherwise
+//! - arm: 2 or 4 bytes in Thumb state by the first halfword, 4 otherwise
+//! - mips: 4 bytes, unless the instruction is in a branch delay slot
+//! - others: 4 bytes
+
+use crate::UserContext;
+
+impl UserContext {
+    /// Get the length of the user instruction at `pc` in bytes, from `code`
+    /// fetched at `pc`.
+    ///
+    /// Return `None` if `code` is too short or the instruction can not be
+    /// decoded, or if skipping it alone is wrong, e.g. in a delay slot.
+    pub fn insn_len(&self, code: &[u8]) -> Option<usize> {
+        imp::insn_len(self, code)
+    }
+
+    /// Move `pc` past the user instruction at `pc`, from `code` fetched at
+    /// `pc`, and return its length.
+    ///
+    /// `pc` is not changed if the length is unknown, see [`insn_len`].
+    ///
+    /// [`insn_len`]: Self::insn_len
+    pub fn advance_pc(&mut self, code: &[u8]) -> Option<usize> {
+        let len = self.insn_len(code)?;
+        self.set_ip(self.get_ip().wrapping_add(len));
+        Some(len)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use crate::UserContext;
+
+    #[cfg(baremetal)]
+    pub fn insn_len(cx: &UserContext, code: &[u8]) -> Option<usize> {
+        super::x86::insn_len(code, !cx.is_compat_mode())
+    }
+
+    #[cfg(not(baremetal))]
+    pub fn insn_len(_cx: &UserContext, code: &[u8]) -> Option<usize> {
+        super::x86::insn_len(code, true)
+    }
+}
+
+#[cfg(target_arch = "x86")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn insn_len(_cx: &UserContext, code: &[u8]) -> Option<usize> {
+        super::x86::insn_len(code, false)
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use crate::UserContext;
+
+    pub fn insn_len(_cx: &UserContext, code: &[u8]) -> Option<usize> {
+        // the lowest 2 bits are 0b11 for 32-bit instructions
+        let len = match code.first()? & 3 {
+            3 => 4,
+            _ => 2,
+        };
+        (code.len() >= len).then(|| len)
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use crate::UserContext;
+
+    /// `CPSR.T`
+    const CPSR_T: usize = 1 << 5;
+
+    pub fn insn_len(cx: &UserContext, code: &[u8]) -> Option<usize> {
+        let len = match cx.cpsr & CPSR_T {
+            0 => 4,
+            _ => {
+                let hw = u16::from_le_bytes([*code.first()?, *code.get(1)?]);
+                // 32-bit Thumb instructions start with 0b11101, 0b11110 or 0b11111
+                match hw >> 11 {
+                    0b11101..=0b11111 => 4,
+                    _ => 2,
+                }
+            }
+        };
+        (code.len() >= len).then(|| len)
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use crate::UserContext;
+
+    /// `Cause.BD`, set when `epc` is the branch before the trapped delay slot
+    const CAUSE_BD: usize = 1 << 31;
+
+    pub fn insn_len(cx: &UserContext, code: &[u8]) -> Option<usize> {
+        (cx.cause & CAUSE_BD == 0 && code.len() >= 4).then(|| 4)
+    }
+}
+
+#[cfg(any(
+    target_arch = "aarch64",
+    target_arch = "loongarch64",
+    target_arch = "powerpc64"
+))]
+mod imp {
+    use crate::UserContext;
+
+    pub fn insn_len(_cx: &UserContext, code: &[u8]) -> Option<usize> {
+        (code.len() >= 4).then(|| 4)
+    }
+}
+
+/// A small x86 instruction length decoder, for the general purpose, x87,
+/// SSE, VEX and EVEX encodings.
+///
+/// It also locates the opcode for the #GP decoder of
+/// [`emulate`](crate::emulate) on x86_64.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub(crate) mod x86 {
+    /// Longest instruction in bytes.
+    const MAX_LEN: usize = 15;
+
+    /// Size of an immediate operand.
+    #[derive(Clone, Copy)]
+    enum Imm {
+        None,
+        /// Fixed size in bytes
+        Bytes(usize),
+        /// 2 bytes with operand size prefix, 4 otherwise
+        Z,
+        /// 8 bytes with REX.W, otherwise as `Z`, for `mov r, imm`
+        V,
+        /// `Z` plus a 2 byte selector, for far `call` and `jmp`
+        Far,
+        /// By address size, for `mov` with `moffs`
+        Offset,
+    }
+
+    struct Cursor<'a> {
+        code: &'a [u8],
+        pos: usize,
+    }
+
+    impl Cursor<'_> {
+        fn peek(&self) -> Option<u8> {
+            self.code.get(self.pos).copied()
+        }
+
+        fn next(&mut self) -> Option<u8> {
+            let byte = self.peek()?;
+            self.pos += 1;
+            Some(byte)
+        }
+    }
+
+    /// Instruction decoded by [`decode`].
+    pub struct Insn {
+        /// Length in bytes
+        pub len: usize,
+        /// Offset of the opcode, after the prefixes and REX
+        pub opcode: usize,
+        /// With the operand size prefix `66`
+        pub opsize16: bool,
+        /// With the `rep` prefix `f2` or `f3`
+        pub rep: bool,
+    }
+
+    /// Get the length of the instruction at the start of `code`, in 64-bit
+    /// mode if `long`, or in 32-bit mode otherwise.
+    pub fn insn_len(code: &[u8], long: bool) -> Option<usize> {
+        decode(code, long).map(|insn| insn.len)
+    }
+
+    /// Decode the instruction at the start of `code`, in 64-bit mode if
+    /// `long`, or in 32-bit mode otherwise.
+    pub fn decode(code: &[u8], long: bool) -> Option<Insn> {
+        let mut c = Cursor { code, pos: 0 };
+        let (mut opsize16, mut addr_prefix, mut rex_w, mut rep) = (false, false, false, false);
+        loop {
+            match c.peek()? {
+                0x66 => opsize16 = true,
+                0x67 => addr_prefix = true,
+                0xf2 | 0xf3 => rep = true,
+                0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 | 0xf0 => {}
+                _ => break,
+            }
+            c.pos += 1;
+        }
+        if long && c.peek()? & 0xf0 == 0x40 {
+            rex_w = c.next()? & 8 != 0;
+        }
+        let opcode = c.pos;
+        let addr16 = !long && addr_prefix;
+        let offset_size = match (long, addr_prefix) {
+            (true, false) => 8,
+            (false, true) => 2,
+            _ => 4,
+        };
+
+        let op = c.next()?;
+        // VEX and EVEX are `les`, `lds` and `bound` in 32-bit mode, unless
+        // the next byte would be a register ModRM
+        let vex = matches!(op, 0xc4 | 0xc5 | 0x62) && (long || c.peek()? >= 0xc0);
+        let (has_modrm, imm) = if vex {
+            let map = match op {
+                0xc5 => {
+                    c.next()?;
+                    1
+                }
+                0xc4 => {
+                    let map = c.next()? & 0x1f;
+                    c.next()?;
+                    map
+                }
+                _ => {
+                    let map = c.next()? & 7;
+                    c.next()?;
+                    c.next()?;
+                    map
+                }
+            };
+            let op = c.next()?;
+            match (map, op) {
+                // vzeroupper and vzeroall
+                (1, 0x77) => (false, Imm::None),
+                (1, 0x70..=0x73 | 0xc2 | 0xc4..=0xc6) | (3, _) => (true, Imm::Bytes(1)),
+                (1..=3, _) => (true, Imm::None),
+                _ => return None,
+            }
+        } else if op == 0x0f {
+            match c.next()? {
+                0x38 => {
+                    c.next()?;
+                    (true, Imm::None)
+                }
+                0x3a => {
+                    c.next()?;
+                    (true, Imm::Bytes(1))
+                }
+                op => two_byte(op),
+            }
+        } else {
+            match one_byte(op, long)? {
+                (modrm, Imm::V) if rex_w => (modrm, Imm::Bytes(8)),
+                other => other,
+            }
+        };
+
+        let mut modrm = None;
+        if has_modrm {
+            let byte = c.next()?;
+            modrm = Some(byte);
+            let (mode, rm) = (byte >> 6, byte & 7);
+            let disp = if addr16 {
+                match (mode, rm) {
+                    (0, 6) | (2, _) => 2,
+                    (1, _) => 1,
+                    _ => 0,
+                }
+            } else {
+                let base = match (mode, rm) {
+                    (3, _) => rm,
+                    (_, 4) => c.next()? & 7,
+                    _ => rm,
+                };
+                match (mode, rm, base) {
+                    (0, 5, _) | (0, 4, 5) => 4,
+                    (1, ..) => 1,
+                    (2, ..) => 4,
+                    _ => 0,
+                }
+            };
+            c.pos += disp;
+        }
+
+        let z = if opsize16 { 2 } else { 4 };
+        let imm_size = match imm {
+            Imm::None => 0,
+            Imm::Bytes(n) => n,
+            Imm::Z | Imm::V => z,
+            Imm::Far => z + 2,
+            Imm::Offset => offset_size,
+        };
+        // `test` in group 3 has an immediate
+        let test = matches!(modrm, Some(m) if m & 0x30 == 0);
+        let imm_size = match op {
+            0xf6 if !vex && test => 1,
+            0xf7 if !vex && test => z,
+            _ => imm_size,
+        };
+        c.pos += imm_size;
+        (c.pos <= code.len() && c.pos <= MAX_LEN).then(|| Insn {
+            len: c.pos,
+            opcode,
+            opsize16,
+            rep,
+        })
+    }
+
+    /// Whether the one byte opcode `op` has ModRM, and its immediate.
+    ///
+    /// Return `None` for far pointer operands, which are invalid in 64-bit
+    /// mode.
+    fn one_byte(op: u8, long: bool) -> Option<(bool, Imm)> {
+        Some(match op {
+            // arithmetic
+            0x00..=0x3f => match op & 7 {
+                0..=3 => (true, Imm::None),
+                4 => (false, Imm::Bytes(1)),
+                5 => (false, Imm::Z),
+                _ => (false, Imm::None),
+            },
+            0x62 | 0x63 => (true, Imm::None),
+            0x68 => (false, Imm::Z),
+            0x69 => (true, Imm::Z),
+            0x6a => (false, Imm::Bytes(1)),
+            0x6b => (true, Imm::Bytes(1)),
+            0x70..=0x7f => (false, Imm::Bytes(1)),
+            0x80 | 0x82 | 0x83 => (true, Imm::Bytes(1)),
+            0x81 => (true, Imm::Z),
+            0x84..=0x8f => (true, Imm::None),
+            0x9a | 0xea if long => return None,
+            0x9a | 0xea => (false, Imm::Far),
+            0xa0..=0xa3 => (false, Imm::Offset),
+            0xa8 => (false, Imm::Bytes(1)),
+            0xa9 => (false, Imm::Z),
+            0xb0..=0xb7 => (false, Imm::Bytes(1)),
+            0xb8..=0xbf => (false, Imm::V),
+            0xc0 | 0xc1 | 0xc6 => (true, Imm::Bytes(1)),
+            0xc2 | 0xca => (false, Imm::Bytes(2)),
+            0xc4 | 0xc5 => (true, Imm::None),
+            0xc7 => (true, Imm::Z),
+            // enter
+            0xc8 => (false, Imm::Bytes(3)),
+            0xcd | 0xd4 | 0xd5 => (false, Imm::Bytes(1)),
+            0xd0..=0xd3 | 0xd8..=0xdf => (true, Imm::None),
+            0xe0..=0xe7 | 0xeb => (false, Imm::Bytes(1)),
+            0xe8 | 0xe9 => (false, Imm::Z),
+            0xf6 | 0xf7 | 0xfe | 0xff => (true, Imm::None),
+            _ => (false, Imm::None),
+        })
+    }
+
+    /// Whether the two byte opcode `0f op` has ModRM, and its immediate.
+    fn two_byte(op: u8) -> (bool, Imm) {
+        match op {
+            0x05..=0x09 | 0x0b | 0x0e | 0x30..=0x37 | 0x77 => (false, Imm::None),
+            0xa0..=0xa2 | 0xa8..=0xaa | 0xc8..=0xcf => (false, Imm::None),
+            0x80..=0x8f => (false, Imm::Z),
+            // 3DNow! has its opcode as the immediate
+            0x0f | 0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => (true, Imm::Bytes(1)),
+            _ => (true, Imm::None),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::insn_len;
+
+        /// Check the length of `code` decoded in 64-bit mode if `long`, and
+        /// that every shorter prefix of it is truncated.
+        fn check(code: &[u8], long: bool) {
+            assert_eq!(insn_len(code, long), Some(code.len()), "{:02x?}", code);
+            for len in 0..code.len() {
+                assert_eq!(insn_len(&code[..len], long), None, "{:02x?}", &code[..len]);
+            }
+        }
+
+        #[test]
+        fn prefixes() {
+            // nop, lock add [rax], eax, rep movsb, fs mov eax, [rax]
+            check(&[0x90], true);
+            check(&[0xf0, 0x01, 0x00], true);
+            check(&[0xf3, 0xa4], true);
+            check(&[0x64, 0x8b, 0x00], true);
+            // operand size prefix shortens the immediate
+            check(&[0x66, 0xb8, 0x34, 0x12], true);
+            check(&[0x66, 0x05, 0x34, 0x12], false);
+        }
+
+        #[test]
+        fn rex() {
+            // mov rax, imm64 and mov eax, imm32
+            check(&[0x48, 0xb8, 1, 2, 3, 4, 5, 6, 7, 8], true);
+            check(&[0x40, 0xb8, 1, 2, 3, 4], true);
+            // mov rax, imm32 sign extended
+            check(&[0x48, 0xc7, 0xc0, 1, 2, 3, 4], true);
+            // inc eax and dec eax in 32-bit mode
+            check(&[0x40], false);
+            check(&[0x48], false);
+        }
+
+        #[test]
+        fn vex_evex() {
+            // vzeroupper, vmovdqa xmm0, xmm1, vpshufd ymm0, ymm1, 0
+            check(&[0xc5, 0xf8, 0x77], true);
+            check(&[0xc5, 0xf9, 0x6f, 0xc1], true);
+            check(&[0xc5, 0xfd, 0x70, 0xc1, 0x00], true);
+            // vpermq ymm0, ymm1, 0 in map 0f3a
+            check(&[0xc4, 0xe3, 0xfd, 0x00, 0xc1, 0x00], true);
+            // vmovups zmm0, [rcx + 0x40]
+            check(&[0x62, 0xf1, 0x7c, 0x48, 0x10, 0x41, 0x01], true);
+            // les eax, [eax] in 32-bit mode
+            check(&[0xc4, 0x00], false);
+        }
+
+        #[test]
+        fn modrm_sib_disp() {
+            // mov eax, [rsp], [rsp + 8], [rsp + 0x100]
+            check(&[0x8b, 0x04, 0x24], true);
+            check(&[0x8b, 0x44, 0x24, 0x08], true);
+            check(&[0x8b, 0x84, 0x24, 0, 1, 0, 0], true);
+            // mov eax, [disp32] by SIB without base, and [rip + disp32]
+            check(&[0x8b, 0x04, 0x25, 1, 2, 3, 4], true);
+            check(&[0x8b, 0x05, 1, 2, 3, 4], true);
+            // mov eax, [bp + 0x10] and [0x1234] with 16-bit addressing
+            check(&[0x67, 0x8b, 0x46, 0x10], false);
+            check(&[0x67, 0x8b, 0x06, 0x34, 0x12], false);
+            // two and three byte opcodes
+            check(&[0x0f, 0x01, 0xf9], true);
+            check(&[0x66, 0x0f, 0x38, 0x00, 0xc1], true);
+            check(&[0x66, 0x0f, 0x3a, 0x0f, 0xc1, 0x08], true);
+        }
+
+        #[test]
+        fn immediates() {
+            // test al, 1 and test eax, imm32 in group 3, but not neg eax
+            check(&[0xf6, 0xc0, 0x01], true);
+            check(&[0xf7, 0xc0, 1, 2, 3, 4], true);
+            check(&[0xf7, 0xd8], true);
+            // enter, ret imm16, jmp rel32, jne rel32
+            check(&[0xc8, 0x10, 0x00, 0x00], true);
+            check(&[0xc2, 0x08, 0x00], true);
+            check(&[0xe9, 1, 2, 3, 4], true);
+            check(&[0x0f, 0x85, 1, 2, 3, 4], true);
+            // mov eax, moffs by address size
+            check(&[0xa1, 1, 2, 3, 4, 5, 6, 7, 8], true);
+            check(&[0xa1, 1, 2, 3, 4], false);
+            // far jmp is invalid in 64-bit mode
+            check(&[0xea, 1, 2, 3, 4, 5, 6], false);
+            assert_eq!(insn_len(&[0xea, 1, 2, 3, 4, 5, 6], true), None);
+        }
+
+        #[test]
+        fn too_long() {
+            // nop with 14 and 15 operand size prefixes
+            let mut code = [0x66; 16];
+            code[14] = 0x90;
+            check(&code[..15], true);
+            code[14] = 0x66;
+            code[15] = 0x90;
+            assert_eq!(insn_len(&code, true), None);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Hooks of the interrupt controller.
+//!
+//! With a controller set by [`set_controller`], the crate acknowledges
+//! external interrupts itself: it claims the interrupt on entry, and
+//! signals the end of interrupt before returning. Spurious interrupts are
+//! dropped, neither dispatched in kernel nor returned from
+//! `UserContext::run()`.
+//!
+//! Interrupts from kernel are completed after the handler returns.
+//! Interrupts from user are completed before `UserContext::run()` returns,
+//! so the kernel should mask a level-triggered source until it is served.
+
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// An interrupt controller, e.g. the local APIC.
+pub trait InterruptController: Sync {
+    /// Acknowledge the interrupt of `vector`, the architecture-specific
+    /// number of the trap.
+    ///
+    /// Return the IRQ number to complete, or `None` if it is spurious.
+    fn claim(&self, vector: usize) -> Option<usize>;
+
+    /// Signal the end of interrupt `irq`, returned by [`claim`](Self::claim).
+    fn complete(&self, irq: usize);
+}
+
+/// Maximum number of times to set the controller.
+const MAX_CONTROLLERS: usize = 8;
+
+/// Controllers ever set, never reused since a trap may be using the old one.
+static mut CONTROLLERS: [Option<&'static dyn InterruptController>; MAX_CONTROLLERS] =
+    [None; MAX_CONTROLLERS];
+/// Number of slots taken in [`CONTROLLERS`].
+static CONTROLLER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static CONTROLLER: AtomicPtr<Option<&'static dyn InterruptController>> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the interrupt controller of all CPUs.
+///
+/// It should be set once, before interrupts are enabled.
+///
+/// # Panics
+///
+/// Panics if it has been set 8 times.
+pub fn set_controller(controller: &'static dyn InterruptController) {
+    let slot = CONTROLLER_COUNT.fetch_add(1, Ordering::Relaxed);
+    assert!(
+        slot < MAX_CONTROLLERS,
+        "interrupt controller is set too many times"
+    );
+    let ptr = unsafe {
+        CONTROLLERS[slot] = Some(controller);
+        &mut CONTROLLERS[slot] as *mut _
+    };
+    let old = CONTROLLER.swap(ptr, Ordering::AcqRel);
+    if !old.is_null() {
+        log::warn!("interrupt controller is replaced");
+    }
+}
+
+/// Get the interrupt controller if set.
+pub fn controller() -> Option<&'static dyn InterruptController> {
+    let ptr = CONTROLLER.load(Ordering::Acquire);
+    unsafe { ptr.as_ref().copied().flatten() }
+}
+
+/// Claim the interrupt of `vector`, run `f` with the IRQ number, and
+/// complete it. Without a controller, `f` runs with `vector`.
+///
+/// Return false if the interrupt is spurious and `f` is not run.
+pub(crate) fn handle(vector: usize, f: impl FnOnce(usize)) -> bool {
+    let controller = match controller() {
+        Some(controller) => controller,
+        None => {
+            #[cfg(feature = "stats")]
+            crate::stats::irq(vector);
+            f(vector);
+            return true;
+        }
+    };
+    match controller.claim(vector) {
+        Some(irq) => {
+            #[cfg(feature = "stats")]
+            crate::stats::irq(irq);
+            f(irq);
+            controller.complete(irq);
+            true
+        }
+        None => {
+            #[cfg(feature = "stats")]
+            crate::stats::spurious();
+            false
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Enable and disable interrupts of the current CPU.
+//!
+//! The flag is `RFLAGS.IF` on x86, `sstatus.SIE` on riscv (`mstatus.MIE`
+//! with feature `riscv_m_mode`), `PSTATE.I` on
+//! aarch64, CP0 `Status.IE` on mipsel, `CRMD.IE` on loongarch64,
+//! `MSR.EE` on powerpc64 and `CPSR.I` on arm.
+//!
+//! Interrupts in user mode are controlled by the context instead, see
+//! [`UserContext::set_user_irq_enabled`]. They are disabled on the way to
+//! user and by the trap entries, so `UserContext::run()` returns with
+//! interrupts disabled, whatever the state was before.
+
+use crate::UserContext;
+use core::marker::PhantomData;
+
+/// A guard disabling interrupts until dropped.
+///
+/// It must be dropped on the same CPU.
+#[must_use = "interrupts are restored when the guard is dropped"]
+pub struct IrqGuard {
+    enabled_before: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        if self.enabled_before {
+            enable();
+        }
+    }
+}
+
+/// Disable interrupts, and restore them when the returned guard is dropped.
+///
+/// Guards can be nested, the outermost one enables interrupts on drop if
+/// they were enabled.
+pub fn disable() -> IrqGuard {
+    let enabled_before = is_enabled();
+    unsafe { imp::disable() };
+    IrqGuard {
+        enabled_before,
+        _not_send: PhantomData,
+    }
+}
+
+/// Enable interrupts.
+pub fn enable() {
+    unsafe { imp::enable() };
+}
+
+/// Whether interrupts are enabled.
+pub fn is_enabled() -> bool {
+    imp::is_enabled()
+}
+
+impl UserContext {
+    /// Set whether interrupts are enabled in user mode with the context.
+    ///
+    /// Interrupts disabled in user only take effect if the user can not
+    /// change the flag, e.g. `IOPL` is 0 on x86.
+    pub fn set_user_irq_enabled(&mut self, enabled: bool) {
+        imp::set_user_enabled(self, enabled);
+    }
+
+    /// Whether interrupts are enabled in user mode with the context.
+    pub fn is_user_irq_enabled(&self) -> bool {
+        imp::is_user_enabled(self)
+    }
+}
+
+/// Set or clear `bit` in `flags`.
+fn set_bit(flags: &mut usize, bit: usize, set: bool) {
+    if set {
+        *flags |= bit;
+    } else {
+        *flags &= !bit;
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `EFLAGS.IF`
+    const IF: usize = 1 << 9;
+
+    pub unsafe fn disable() {
+        asm!("cli", options(nostack));
+    }
+
+    pub unsafe fn enable() {
+        asm!("sti", options(nostack));
+    }
+
+    pub fn is_enabled() -> bool {
+        let flags: usize;
+        unsafe { asm!("pushf", "pop {}", out(reg) flags, options(nomem, preserves_flags)) };
+        flags & IF != 0
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.general.rflags, IF, enabled);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.general.rflags & IF != 0
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.eflags, IF, enabled);
+    }
+
+    #[cfg(target_arch = "x86")]
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.eflags & IF != 0
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `sstatus.SIE`
+    #[cfg(not(feature = "riscv_m_mode"))]
+    const SIE: usize = 1 << 1;
+    /// `sstatus.SPIE`, copied to `SIE` by `sret`
+    #[cfg(not(feature = "riscv_m_mode"))]
+    const SPIE: usize = 1 << 5;
+    /// `mstatus.MIE`
+    #[cfg(feature = "riscv_m_mode")]
+    const SIE: usize = 1 << 3;
+    /// `mstatus.MPIE`, copied to `MIE` by `mret`
+    #[cfg(feature = "riscv_m_mode")]
+    const SPIE: usize = 1 << 7;
+
+    pub unsafe fn disable() {
+        asm!(concat!("csrc ", xcsr!("status"), ", {}"), in(reg) SIE, options(nostack));
+    }
+
+    pub unsafe fn enable() {
+        asm!(concat!("csrs ", xcsr!("status"), ", {}"), in(reg) SIE, options(nostack));
+    }
+
+    pub fn is_enabled() -> bool {
+        let sstatus: usize;
+        unsafe {
+            asm!(concat!("csrr {}, ", xcsr!("status")), out(reg) sstatus, options(nomem, nostack))
+        };
+        sstatus & SIE != 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.sstatus, SPIE, enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.sstatus & SPIE != 0
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `PSTATE.I`, masking IRQ if set
+    const I: usize = 1 << 7;
+
+    pub unsafe fn disable() {
+        asm!("msr daifset, #2", options(nostack));
+    }
+
+    pub unsafe fn enable() {
+        asm!("msr daifclr, #2", options(nostack));
+    }
+
+    pub fn is_enabled() -> bool {
+        let daif: usize;
+        unsafe { asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack)) };
+        daif & I == 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.spsr, I, !enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.spsr & I == 0
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// CP0 `Status.IE`
+    const IE: usize = 1 << 0;
+
+    pub unsafe fn disable() {
+        let status: usize;
+        asm!("mfc0 {}, $12", out(reg) status);
+        asm!("mtc0 {}, $12", "ehb", in(reg) status & !IE);
+    }
+
+    pub unsafe fn enable() {
+        let status: usize;
+        asm!("mfc0 {}, $12", out(reg) status);
+        asm!("mtc0 {}, $12", "ehb", in(reg) status | IE);
+    }
+
+    pub fn is_enabled() -> bool {
+        let status: usize;
+        unsafe { asm!("mfc0 {}, $12", out(reg) status) };
+        status & IE != 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.status, IE, enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.status & IE != 0
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `CRMD.IE`
+    const IE: usize = 1 << 2;
+    /// `PRMD.PIE`, copied to `CRMD.IE` by `ertn`
+    const PIE: usize = 1 << 2;
+
+    pub unsafe fn disable() {
+        asm!("csrxchg {}, {}, 0x0", inout(reg) 0usize => _, in(reg) IE, options(nostack));
+    }
+
+    pub unsafe fn enable() {
+        asm!("csrxchg {}, {}, 0x0", inout(reg) IE => _, in(reg) IE, options(nostack));
+    }
+
+    pub fn is_enabled() -> bool {
+        let crmd: usize;
+        unsafe { asm!("csrrd {}, 0x0", out(reg) crmd, options(nomem, nostack)) };
+        crmd & IE != 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.prmd, PIE, enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.prmd & PIE != 0
+    }
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `MSR.EE`, also in `SRR1` to return with
+    const EE: usize = 1 << 15;
+
+    /// Write `MSR.EE` and `MSR.RI` only.
+    unsafe fn write_ee(enabled: bool) {
+        let mut msr: usize;
+        asm!("mfmsr {}", out(reg) msr, options(nomem, nostack));
+        super::set_bit(&mut msr, EE, enabled);
+        asm!("mtmsrd {}, 1", in(reg) msr, options(nostack));
+    }
+
+    pub unsafe fn disable() {
+        write_ee(false);
+    }
+
+    pub unsafe fn enable() {
+        write_ee(true);
+    }
+
+    pub fn is_enabled() -> bool {
+        let msr: usize;
+        unsafe { asm!("mfmsr {}", out(reg) msr, options(nomem, nostack)) };
+        msr & EE != 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.srr1, EE, enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.srr1 & EE != 0
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    /// `CPSR.I`, set to mask IRQ
+    const I: usize = 1 << 7;
+
+    pub unsafe fn disable() {
+        asm!("cpsid i", options(nomem, nostack));
+    }
+
+    pub unsafe fn enable() {
+        asm!("cpsie i", options(nomem, nostack));
+    }
+
+    pub fn is_enabled() -> bool {
+        let cpsr: usize;
+        unsafe { asm!("mrs {}, cpsr", out(reg) cpsr, options(nomem, nostack)) };
+        cpsr & I == 0
+    }
+
+    pub fn set_user_enabled(cx: &mut UserContext, enabled: bool) {
+        super::set_bit(&mut cx.cpsr, I, !enabled);
+    }
+
+    pub fn is_user_enabled(cx: &UserContext) -> bool {
+        cx.cpsr & I == 0
+    }
+}
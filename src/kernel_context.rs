@@ -0,0 +1,554 @@
+//! Switch between kernel threads.
+
+/// Context of a kernel thread switched out by [`KernelContext::switch`].
+///
+/// Only the registers preserved across calls are kept: the stack pointer,
+/// the address to resume at, and the callee-saved general registers in
+/// `regs`, which are
+///
+/// - `rbx`, `rbp`, `r12`-`r15` on x86_64
+/// - `ebx`, `esi`, `edi`, `ebp` on x86
+/// - `s0`-`s11` on riscv
+/// - `x19`-`x29` on aarch64
+/// - `s0`-`s7`, `fp` on mipsel
+/// - `s0`-`s8`, `fp` on loongarch64
+/// - `r14`-`r31`, `cr`, `r2` on powerpc64
+/// - `r4`-`r11` on arm
+///
+/// Floating-point registers are not kept, kernels switching them must do it
+/// themselves. Neither are the shadow stacks of CET on x86_64.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct KernelContext {
+    /// Stack pointer
+    pub sp: usize,
+    /// Address to resume at
+    pub ip: usize,
+    /// Callee-saved general registers
+    pub regs: [usize; imp::CALLEE_SAVED],
+}
+
+impl KernelContext {
+    /// Create a context to run `entry(arg)` on the stack below `stack_top`,
+    /// when it is switched to for the first time.
+    ///
+    /// The stack top is aligned down to 16 bytes, and the frame pointer is
+    /// cleared to end backtraces. `entry` must not return, which panics.
+    pub fn new(entry: fn(usize), arg: usize, stack_top: usize) -> Self {
+        let mut cx = KernelContext {
+            sp: stack_top & !0xf,
+            ip: imp::thread_entry as usize,
+            ..Default::default()
+        };
+        imp::init(&mut cx, entry as usize, arg);
+        cx
+    }
+
+    /// Save the current thread into `self`, and switch to the thread of `to`.
+    ///
+    /// It returns when another thread switches back to `self`.
+    ///
+    /// # Safety
+    ///
+    /// `to` must be saved by a previous `switch`, or set up to run a kernel
+    /// thread on a valid stack.
+    pub unsafe fn switch(&mut self, to: &mut KernelContext) {
+        imp::switch(self, to)
+    }
+}
+
+/// Start a kernel thread created by [`KernelContext::new`].
+extern "C" fn thread_start(entry: usize, arg: usize) -> ! {
+    let entry: fn(usize) = unsafe { core::mem::transmute(entry) };
+    entry(arg);
+    panic!("kernel thread returned");
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 6;
+
+    /// `rbx` = entry, `rbp` = arg
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "mov rdi, rbx",
+            "mov rsi, rbp",
+            "xor ebp, ebp",
+            "call {start}",
+            "ud2",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "mov [rdi + 16], rbx",
+            "mov [rdi + 24], rbp",
+            "mov [rdi + 32], r12",
+            "mov [rdi + 40], r13",
+            "mov [rdi + 48], r14",
+            "mov [rdi + 56], r15",
+            "pop rax",
+            "mov [rdi + 8], rax",
+            "mov [rdi], rsp",
+            "mov rsp, [rsi]",
+            "mov rbx, [rsi + 16]",
+            "mov rbp, [rsi + 24]",
+            "mov r12, [rsi + 32]",
+            "mov r13, [rsi + 40]",
+            "mov r14, [rsi + 48]",
+            "mov r15, [rsi + 56]",
+            // not an indirect jump, which must land on `endbr64` with CET IBT
+            "push qword ptr [rsi + 8]",
+            "ret",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "x86")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 4;
+
+    /// `ebx` = entry, `esi` = arg
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            // keep the stack 16 bytes aligned at the call
+            "sub esp, 8",
+            "push esi",
+            "push ebx",
+            "xor ebp, ebp",
+            "call {start}",
+            "ud2",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "mov eax, [esp + 4]",
+            "mov ecx, [esp + 8]",
+            "mov [eax + 8], ebx",
+            "mov [eax + 12], esi",
+            "mov [eax + 16], edi",
+            "mov [eax + 20], ebp",
+            "pop edx",
+            "mov [eax + 4], edx",
+            "mov [eax], esp",
+            "mov esp, [ecx]",
+            "mov ebx, [ecx + 8]",
+            "mov esi, [ecx + 12]",
+            "mov edi, [ecx + 16]",
+            "mov ebp, [ecx + 20]",
+            "jmp dword ptr [ecx + 4]",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 12;
+
+    /// `s1` = entry, `s2` = arg, with the frame pointer `s0` cleared
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[1] = entry;
+        cx.regs[2] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "mv a0, s1",
+            "mv a1, s2",
+            "call {start}",
+            "unimp",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[cfg(target_arch = "riscv32")]
+    macro_rules! save {
+        ($reg:literal, $n:literal, $base:literal) => {
+            concat!("sw ", $reg, ", ", $n, "*4(", $base, ")")
+        };
+    }
+    #[cfg(target_arch = "riscv32")]
+    macro_rules! restore {
+        ($reg:literal, $n:literal, $base:literal) => {
+            concat!("lw ", $reg, ", ", $n, "*4(", $base, ")")
+        };
+    }
+    #[cfg(target_arch = "riscv64")]
+    macro_rules! save {
+        ($reg:literal, $n:literal, $base:literal) => {
+            concat!("sd ", $reg, ", ", $n, "*8(", $base, ")")
+        };
+    }
+    #[cfg(target_arch = "riscv64")]
+    macro_rules! restore {
+        ($reg:literal, $n:literal, $base:literal) => {
+            concat!("ld ", $reg, ", ", $n, "*8(", $base, ")")
+        };
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            save!("sp", 0, "a0"),
+            save!("ra", 1, "a0"),
+            save!("s0", 2, "a0"),
+            save!("s1", 3, "a0"),
+            save!("s2", 4, "a0"),
+            save!("s3", 5, "a0"),
+            save!("s4", 6, "a0"),
+            save!("s5", 7, "a0"),
+            save!("s6", 8, "a0"),
+            save!("s7", 9, "a0"),
+            save!("s8", 10, "a0"),
+            save!("s9", 11, "a0"),
+            save!("s10", 12, "a0"),
+            save!("s11", 13, "a0"),
+            restore!("sp", 0, "a1"),
+            restore!("ra", 1, "a1"),
+            restore!("s0", 2, "a1"),
+            restore!("s1", 3, "a1"),
+            restore!("s2", 4, "a1"),
+            restore!("s3", 5, "a1"),
+            restore!("s4", 6, "a1"),
+            restore!("s5", 7, "a1"),
+            restore!("s6", 8, "a1"),
+            restore!("s7", 9, "a1"),
+            restore!("s8", 10, "a1"),
+            restore!("s9", 11, "a1"),
+            restore!("s10", 12, "a1"),
+            restore!("s11", 13, "a1"),
+            "ret",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 11;
+
+    /// `x19` = entry, `x20` = arg, with the frame pointer `x29` cleared
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "mov x0, x19",
+            "mov x1, x20",
+            "bl {start}",
+            "brk #0",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "mov x2, sp",
+            "stp x2, x30, [x0]",
+            "stp x19, x20, [x0, 16]",
+            "stp x21, x22, [x0, 32]",
+            "stp x23, x24, [x0, 48]",
+            "stp x25, x26, [x0, 64]",
+            "stp x27, x28, [x0, 80]",
+            "str x29, [x0, 96]",
+            "ldp x2, x30, [x1]",
+            "mov sp, x2",
+            "ldp x19, x20, [x1, 16]",
+            "ldp x21, x22, [x1, 32]",
+            "ldp x23, x24, [x1, 48]",
+            "ldp x25, x26, [x1, 64]",
+            "ldp x27, x28, [x1, 80]",
+            "ldr x29, [x1, 96]",
+            "ret",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 9;
+
+    /// `s0` = entry, `s1` = arg, with the frame pointer `fp` cleared
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "move $a0, $s0",
+            "move $a1, $s1",
+            // home space of the arguments, reserved by the caller in o32
+            "addiu $sp, $sp, -16",
+            "la $t9, {start}",
+            "jalr $t9",
+            "nop",
+            "break",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "sw $sp, 0($a0)",
+            "sw $ra, 4($a0)",
+            "sw $s0, 8($a0)",
+            "sw $s1, 12($a0)",
+            "sw $s2, 16($a0)",
+            "sw $s3, 20($a0)",
+            "sw $s4, 24($a0)",
+            "sw $s5, 28($a0)",
+            "sw $s6, 32($a0)",
+            "sw $s7, 36($a0)",
+            "sw $fp, 40($a0)",
+            "lw $sp, 0($a1)",
+            "lw $ra, 4($a1)",
+            "lw $s0, 8($a1)",
+            "lw $s1, 12($a1)",
+            "lw $s2, 16($a1)",
+            "lw $s3, 20($a1)",
+            "lw $s4, 24($a1)",
+            "lw $s5, 28($a1)",
+            "lw $s6, 32($a1)",
+            "lw $s7, 36($a1)",
+            "lw $fp, 40($a1)",
+            "jr $ra",
+            "nop",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 10;
+
+    /// `s0` = entry, `s1` = arg, with the frame pointer `fp` cleared
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "move $a0, $s0",
+            "move $a1, $s1",
+            "bl {start}",
+            "break 0",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "st.d $sp, $a0, 0",
+            "st.d $ra, $a0, 8",
+            "st.d $s0, $a0, 16",
+            "st.d $s1, $a0, 24",
+            "st.d $s2, $a0, 32",
+            "st.d $s3, $a0, 40",
+            "st.d $s4, $a0, 48",
+            "st.d $s5, $a0, 56",
+            "st.d $s6, $a0, 64",
+            "st.d $s7, $a0, 72",
+            "st.d $s8, $a0, 80",
+            "st.d $fp, $a0, 88",
+            "ld.d $sp, $a1, 0",
+            "ld.d $ra, $a1, 8",
+            "ld.d $s0, $a1, 16",
+            "ld.d $s1, $a1, 24",
+            "ld.d $s2, $a1, 32",
+            "ld.d $s3, $a1, 40",
+            "ld.d $s4, $a1, 48",
+            "ld.d $s5, $a1, 56",
+            "ld.d $s6, $a1, 64",
+            "ld.d $s7, $a1, 72",
+            "ld.d $s8, $a1, 80",
+            "ld.d $fp, $a1, 88",
+            "jr $ra",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 20;
+
+    /// `r14` = entry, `r15` = arg, with the TOC pointer `r2` of the kernel
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+        unsafe { asm!("mr {}, 2", out(reg) cx.regs[19], options(nomem, nostack)) };
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            // the minimum frame, with a null back chain to end backtraces
+            "li 0, 0",
+            "stdu 0, -32(1)",
+            "mr 3, 14",
+            "mr 4, 15",
+            "bl {start}",
+            "nop",
+            "trap",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "std 1, 0(3)",
+            "mflr 0",
+            "std 0, 8(3)",
+            "std 14, 16(3)",
+            "std 15, 24(3)",
+            "std 16, 32(3)",
+            "std 17, 40(3)",
+            "std 18, 48(3)",
+            "std 19, 56(3)",
+            "std 20, 64(3)",
+            "std 21, 72(3)",
+            "std 22, 80(3)",
+            "std 23, 88(3)",
+            "std 24, 96(3)",
+            "std 25, 104(3)",
+            "std 26, 112(3)",
+            "std 27, 120(3)",
+            "std 28, 128(3)",
+            "std 29, 136(3)",
+            "std 30, 144(3)",
+            "std 31, 152(3)",
+            "mfcr 0",
+            "std 0, 160(3)",
+            "std 2, 168(3)",
+            "ld 1, 0(4)",
+            "ld 0, 8(4)",
+            "mtlr 0",
+            "ld 14, 16(4)",
+            "ld 15, 24(4)",
+            "ld 16, 32(4)",
+            "ld 17, 40(4)",
+            "ld 18, 48(4)",
+            "ld 19, 56(4)",
+            "ld 20, 64(4)",
+            "ld 21, 72(4)",
+            "ld 22, 80(4)",
+            "ld 23, 88(4)",
+            "ld 24, 96(4)",
+            "ld 25, 104(4)",
+            "ld 26, 112(4)",
+            "ld 27, 120(4)",
+            "ld 28, 128(4)",
+            "ld 29, 136(4)",
+            "ld 30, 144(4)",
+            "ld 31, 152(4)",
+            "ld 0, 160(4)",
+            "mtcr 0",
+            "ld 2, 168(4)",
+            "blr",
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use super::KernelContext;
+    use core::arch::asm;
+
+    pub const CALLEE_SAVED: usize = 8;
+
+    /// `r4` = entry, `r5` = arg, with the frame pointer `r11` cleared
+    pub fn init(cx: &mut KernelContext, entry: usize, arg: usize) {
+        cx.regs[0] = entry;
+        cx.regs[1] = arg;
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn thread_entry() -> ! {
+        asm!(
+            "mov r0, r4",
+            "mov r1, r5",
+            "bl {start}",
+            "udf #0",
+            start = sym super::thread_start,
+            options(noreturn),
+        )
+    }
+
+    #[naked]
+    pub unsafe extern "C" fn switch(_from: &mut KernelContext, _to: &mut KernelContext) {
+        asm!(
+            "str sp, [r0]",
+            "str lr, [r0, #4]",
+            "add r2, r0, #8",
+            "stmia r2, {{r4-r11}}",
+            "ldr sp, [r1]",
+            "ldr lr, [r1, #4]",
+            "add r2, r1, #8",
+            "ldmia r2, {{r4-r11}}",
+            "bx lr",
+            options(noreturn),
+        )
+    }
+}
@@ -1,14 +1,156 @@
 #![no_std]
 #![feature(linkage)]
+#![feature(asm_const)]
+#![feature(const_ptr_offset_from)]
+#![feature(naked_functions)]
+#![feature(asm_sym)]
+#![feature(fn_align)]
+#![cfg_attr(fncall, feature(thread_local))]
 #![deny(warnings)]
-#![cfg_attr(target_arch = "mips", feature(asm_experimental_arch))]
+#![cfg_attr(
+    any(
+        target_arch = "mips",
+        target_arch = "loongarch64",
+        target_arch = "powerpc64"
+    ),
+    feature(asm_experimental_arch)
+)]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(not(feature = "alloc"))]
+#[cfg(not(baremetal))]
+compile_error!("feature `alloc` is required except on bare metal, with feature `baremetal`");
+
+#[cfg(all(not(feature = "alloc"), feature = "serde"))]
+compile_error!("feature `serde` requires feature `alloc`");
+
+#[cfg(target_arch = "mips64")]
+compile_error!("the MIPS backend supports the 32-bit o32 ABI only, not MIPS64 or MIPS64r6");
+
+/// Maximum number of CPUs, for the per-CPU tables kept in static arrays.
+///
+/// Set by the environment variable `TRAPFRAME_MAX_CPUS` at compile time,
+/// 64 by default.
+pub const MAX_CPUS: usize = parse_max_cpus(option_env!("TRAPFRAME_MAX_CPUS"));
+
+const fn parse_max_cpus(value: Option<&str>) -> usize {
+    let bytes = match value {
+        Some(value) => value.as_bytes(),
+        None => return 64,
+    };
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "invalid TRAPFRAME_MAX_CPUS");
+        n = n * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    assert!(n > 0, "invalid TRAPFRAME_MAX_CPUS");
+    n
+}
+
+/// Implement `bytemuck::Pod` for plain register structures.
+macro_rules! impl_bytemuck {
+    ($($t:ty),*) => {$(
+        #[cfg(feature = "bytemuck")]
+        unsafe impl bytemuck::Zeroable for $t {}
+        #[cfg(feature = "bytemuck")]
+        unsafe impl bytemuck::Pod for $t {}
+    )*};
+}
+
+/// Implement `Debug` of register structures by their `Display`, with
+/// aligned hex registers and decoded flags.
+macro_rules! impl_debug_by_display {
+    ($($t:ident),*) => {$(
+        impl core::fmt::Debug for $t {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                crate::display::write_debug(f, stringify!($t), self)
+            }
+        }
+    )*};
+}
+
+/// Offset in bytes of `$field` in `$ty`, evaluated at compile time.
+macro_rules! offset_of {
+    ($ty:ty, $($field:ident).+) => {{
+        let base = core::mem::MaybeUninit::<$ty>::uninit();
+        let ptr = base.as_ptr();
+        #[allow(unused_unsafe)]
+        unsafe {
+            core::ptr::addr_of!((*ptr).$($field).+)
+                .cast::<u8>()
+                .offset_from(ptr.cast::<u8>()) as usize
+        }
+    }};
+}
+
+/// Define the offset constants of fields of `$ty` by their stable index in
+/// words, checked against the structure at compile time.
+macro_rules! layout {
+    ($ty:ty { $($name:ident = $index:literal => $first:ident $(. $rest:ident)?,)* }) => {$(
+        #[doc = concat!(
+            "Offset of `", stringify!($ty), ".", stringify!($first),
+            $(".", stringify!($rest),)? "` in bytes"
+        )]
+        pub const $name: usize = $index * core::mem::size_of::<usize>();
+        const _: () = assert!(
+            offset_of!($ty, $first $(. $rest)?) == $name,
+            concat!("layout of `", stringify!($ty), "` changed: ", stringify!($name))
+        );
+    )*};
+}
+
+/// `global_asm!` with the assembler symbols `$name` defined before the
+/// assembly, as the constants `$value` or the constants of the same name,
+/// so that offsets in it follow the Rust structures instead of hard-coded
+/// numbers.
+macro_rules! global_asm_equ {
+    ([$($name:ident $(= $value:expr)?),* $(,)?], $($asm:expr),+ $(,)?) => {
+        core::arch::global_asm!(
+            $(concat!(".equ ", stringify!($name), ", {", stringify!($name), "}"),)*
+            $($asm,)+
+            $($name = const global_asm_equ!(@value $name $(, $value)?),)*
+        );
+    };
+    (@value $name:ident) => {
+        $name
+    };
+    (@value $name:ident, $value:expr) => {
+        $value
+    };
+}
+
+/// Name of the trap CSR `$csr` on riscv, e.g. `sstatus` for `"status"`, or
+/// `mstatus` with feature `riscv_m_mode`.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[cfg(not(feature = "riscv_m_mode"))]
+macro_rules! xcsr {
+    ($csr:literal) => {
+        concat!("s", $csr)
+    };
+}
+
+/// Name of the trap CSR `$csr` on riscv, e.g. `sstatus` for `"status"`, or
+/// `mstatus` with feature `riscv_m_mode`.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[cfg(feature = "riscv_m_mode")]
+macro_rules! xcsr {
+    ($csr:literal) => {
+        concat!("m", $csr)
+    };
+}
+
 #[cfg(target_arch = "x86_64")]
 #[path = "arch/x86_64/mod.rs"]
 mod arch;
 
+#[cfg(target_arch = "x86")]
+#[path = "arch/x86/mod.rs"]
+mod arch;
+
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 #[path = "arch/riscv/mod.rs"]
 mod arch;
@@ -21,4 +163,74 @@ pub mod arch;
 #[path = "arch/aarch64/mod.rs"]
 pub mod arch;
 
+#[cfg(target_arch = "loongarch64")]
+#[path = "arch/loongarch64/mod.rs"]
+mod arch;
+
+#[cfg(target_arch = "powerpc64")]
+#[path = "arch/powerpc64/mod.rs"]
+mod arch;
+
+#[cfg(target_arch = "arm")]
+#[path = "arch/arm/mod.rs"]
+mod arch;
+
+#[cfg(baremetal)]
+mod backtrace;
+#[cfg(feature = "alloc")]
+pub mod coredump;
+#[cfg(any(baremetal, target_arch = "x86_64", target_arch = "x86"))]
+mod cpu_features;
+mod diff;
+mod display;
+mod dwarf;
+mod fork;
+#[cfg(feature = "async")]
+#[cfg(baremetal)]
+mod future;
+mod hooks;
+mod in_use;
+#[cfg(baremetal)]
+mod init_error;
+mod insn;
+#[cfg(baremetal)]
+pub mod intc;
+#[cfg(baremetal)]
+pub mod irq;
+mod kernel_context;
+mod ptrace;
+mod reason;
+mod signal;
+mod spawn;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "syscall_args")]
+pub mod syscall_args;
+mod tag;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(baremetal)]
+pub mod user_access;
+
+#[cfg(baremetal)]
+pub use backtrace::backtrace;
+#[cfg(any(baremetal, target_arch = "x86_64", target_arch = "x86"))]
+pub use cpu_features::{cpu_features, CpuFeatures};
+#[cfg(feature = "async")]
+#[cfg(baremetal)]
+pub use future::RunFuture;
+#[cfg(feature = "gdbstub")]
+pub use gdbstub_arch;
+#[cfg(baremetal)]
+pub use init_error::TrapInitError;
+
 pub use arch::*;
+pub use diff::{ContextDiff, RegName};
+pub use dwarf::RegIndex;
+pub use kernel_context::KernelContext;
+pub use ptrace::Regset;
+pub use reason::{
+    EmulatableKind, PageFaultFlags, PageFaultInfo, PrivilegedKind, TrapInfo, TrapReason,
+};
+pub use signal::SigInfo;
+pub use tag::{addr_tag, untag_addr};
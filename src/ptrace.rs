@@ -0,0 +1,215 @@
+//! Register sets of `PTRACE_GETREGSET` and `PTRACE_SETREGSET`.
+//!
+//! A [`Regset`] is read and written as bytes in the layout of Linux, at any
+//! offset and length, so partial accesses by debuggers work as on Linux:
+//! - `NT_PRSTATUS`: `elf_gregset_t` of [`get_user_regs()`], on all
+//!   architectures
+//! - `NT_PRFPREG`: the FXSAVE area on x86_64 with feature `fpu`
+//!
+//! Writes go through [`set_user_regs()`], which keeps the registers user can
+//! not change.
+//!
+//! The register sets of state kept outside the context are read and written
+//! the same way by its own type:
+//! - `NT_X86_XSTATE`: the XSAVE area of an `ExtendedState` on x86_64, with
+//!   all the enabled components
+//! - `NT_ARM_SVE`: an `SveState` on aarch64 with feature `sve`, once the
+//!   context has used SVE
+//!
+//! [`get_user_regs()`]: UserContext::get_user_regs
+//! [`set_user_regs()`]: UserContext::set_user_regs
+
+use crate::UserContext;
+use pod::Pod;
+
+/// A register set, by its ELF note type.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Regset {
+    /// `NT_PRSTATUS`, general registers
+    PrStatus,
+    /// `NT_PRFPREG`, floating-point registers
+    FpRegs,
+    /// `NT_X86_XSTATE`, XSAVE area, of an `ExtendedState`
+    X86Xstate,
+    /// `NT_ARM_SVE`, scalable vector registers, of an `SveState`
+    ArmSve,
+}
+
+impl Regset {
+    /// Get the note type, the `addr` argument of `PTRACE_GETREGSET`.
+    pub const fn note_type(self) -> u32 {
+        match self {
+            Regset::PrStatus => 1,
+            Regset::FpRegs => 2,
+            Regset::X86Xstate => 0x202,
+            Regset::ArmSve => 0x405,
+        }
+    }
+
+    /// Get the register set of note type `ty`.
+    pub fn from_note_type(ty: u32) -> Option<Self> {
+        [
+            Regset::PrStatus,
+            Regset::FpRegs,
+            Regset::X86Xstate,
+            Regset::ArmSve,
+        ]
+        .into_iter()
+        .find(|set| set.note_type() == ty)
+    }
+}
+
+impl UserContext {
+    /// Get the size of `set` in bytes, or `None` if not supported.
+    ///
+    /// `NT_X86_XSTATE` and `NT_ARM_SVE` are not supported here, but by
+    /// `ExtendedState` and `SveState`, which keep that state.
+    pub fn regset_size(&self, set: Regset) -> Option<usize> {
+        let mut size = 0;
+        self.with_regset(set, |bytes| size = bytes.len())?;
+        Some(size)
+    }
+
+    /// Read `set` from byte `offset` into `buf`.
+    ///
+    /// Return the number of bytes read, less than `buf.len()` at the end of
+    /// the set, or `None` if not supported.
+    pub fn read_regset(&self, set: Regset, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        self.with_regset(set, |bytes| len = read_bytes(bytes, offset, buf))?;
+        Some(len)
+    }
+
+    /// Write `data` into `set` from byte `offset`, leaving the other bytes.
+    ///
+    /// Return the number of bytes written, less than `data.len()` at the end
+    /// of the set, or `None` if not supported.
+    pub fn write_regset(&mut self, set: Regset, offset: usize, data: &[u8]) -> Option<usize> {
+        match set {
+            Regset::PrStatus => {
+                let mut regs = self.get_user_regs();
+                let len = write_bytes(regs.as_bytes_mut(), offset, data);
+                self.set_user_regs(&regs);
+                Some(len)
+            }
+            #[cfg(all(target_arch = "x86_64", feature = "fpu"))]
+            Regset::FpRegs => {
+                let mut fp = self.fp;
+                let len = write_bytes(fp.as_bytes_mut(), offset, data);
+                self.fp = x86_64::sanitize(fp);
+                Some(len)
+            }
+            _ => None,
+        }
+    }
+
+    /// Read a word at byte `offset` of `struct user`, as `PTRACE_PEEKUSER`.
+    ///
+    /// Only the general registers at the start of `struct user` are supported.
+    /// Return `None` if `offset` is not aligned to a word or out of them.
+    pub fn peek_user(&self, offset: usize) -> Option<usize> {
+        let mut word = [0; WORD];
+        if offset % WORD != 0 || self.read_regset(Regset::PrStatus, offset, &mut word)? != WORD {
+            return None;
+        }
+        Some(usize::from_ne_bytes(word))
+    }
+
+    /// Write a word at byte `offset` of `struct user`, as `PTRACE_POKEUSER`.
+    ///
+    /// Return `None` under the same conditions as [`peek_user()`](Self::peek_user).
+    pub fn poke_user(&mut self, offset: usize, value: usize) -> Option<()> {
+        if offset % WORD != 0 || offset.checked_add(WORD)? > self.regset_size(Regset::PrStatus)? {
+            return None;
+        }
+        self.write_regset(Regset::PrStatus, offset, &value.to_ne_bytes())?;
+        Some(())
+    }
+
+    /// Call `f` with the bytes of `set`, or return `None` if not supported.
+    fn with_regset(&self, set: Regset, f: impl FnOnce(&[u8])) -> Option<()> {
+        match set {
+            Regset::PrStatus => f(self.get_user_regs().as_bytes()),
+            #[cfg(all(target_arch = "x86_64", feature = "fpu"))]
+            Regset::FpRegs => f(self.fp.as_bytes()),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Copy `src` from `offset` into `buf`, and return the number of bytes copied.
+pub(crate) fn read_bytes(src: &[u8], offset: usize, buf: &mut [u8]) -> usize {
+    let src = src.get(offset..).unwrap_or(&[]);
+    let len = src.len().min(buf.len());
+    buf[..len].copy_from_slice(&src[..len]);
+    len
+}
+
+/// Copy `data` into `dst` from `offset`, and return the number of bytes copied.
+pub(crate) fn write_bytes(dst: &mut [u8], offset: usize, data: &[u8]) -> usize {
+    let dst = dst.get_mut(offset..).unwrap_or(&mut []);
+    let len = dst.len().min(data.len());
+    dst[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "fpu"))]
+mod x86_64 {
+    use crate::FpState;
+
+    /// Clear the reserved bits of MXCSR, which fault in `fxrstor`.
+    pub fn sanitize(mut fp: FpState) -> FpState {
+        fp.as_bytes_mut()[26..28].fill(0);
+        fp
+    }
+}
+
+/// Offset of `sw_reserved` in the legacy region, where Linux reports XCR0
+/// to debuggers.
+#[cfg(all(target_arch = "x86_64", feature = "alloc"))]
+const SW_RESERVED_OFFSET: usize = 464;
+
+#[cfg(all(target_arch = "x86_64", feature = "alloc"))]
+impl crate::ExtendedState {
+    /// Read `NT_X86_XSTATE` from byte `offset` into `buf`, and return the
+    /// number of bytes read, less than `buf.len()` at the end of the area.
+    ///
+    /// The area is in the standard format of `xsave`, with XCR0 at the
+    /// allocation in `sw_reserved` as on Linux.
+    pub fn read_regset(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = read_bytes(self.as_bytes(), offset, buf);
+        let xcr0 = self.features().to_le_bytes();
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            let pos = (offset + i).wrapping_sub(SW_RESERVED_OFFSET);
+            if let Some(&b) = xcr0.get(pos) {
+                *byte = b;
+            }
+        }
+        len
+    }
+
+    /// Write `data` into `NT_X86_XSTATE` from byte `offset`, leaving the
+    /// other bytes, and return the number of bytes written.
+    ///
+    /// Writes to `sw_reserved` are ignored, and the reserved bits of MXCSR
+    /// are cleared. Return `None` and leave the state if the XSAVE header
+    /// would fault in `xrstor`: with components not in
+    /// [`features()`](Self::features), or nonzero `XCOMP_BV` or reserved
+    /// bytes.
+    pub fn write_regset(&mut self, offset: usize, data: &[u8]) -> Option<usize> {
+        let mut area = self.clone();
+        let len = write_bytes(area.as_bytes_mut(), offset, data);
+        let bytes = area.as_bytes_mut();
+        bytes[SW_RESERVED_OFFSET..512].copy_from_slice(&self.as_bytes()[SW_RESERVED_OFFSET..512]);
+        let xstate_bv = u64::from_le_bytes(bytes[512..520].try_into().unwrap());
+        if xstate_bv & !self.features() != 0 || bytes[520..576].iter().any(|&b| b != 0) {
+            return None;
+        }
+        bytes[26..28].fill(0);
+        self.as_bytes_mut().copy_from_slice(area.as_bytes());
+        Some(len)
+    }
+}
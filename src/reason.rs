@@ -0,0 +1,265 @@
+//! Architecture-independent trap reasons.
+
+use crate::{untag_addr, UserContext};
+use bitflags::bitflags;
+
+/// Reason of a trap, decoded from the architecture-specific registers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrapReason {
+    /// System call instruction
+    Syscall,
+    /// Legacy 32-bit system call on x86_64, by `int 0x80` or `sysenter`
+    LegacySyscall {
+        /// Entered by `sysenter`, which does not save `rip` and `rsp`
+        sysenter: bool,
+    },
+    /// Page fault or access fault
+    PageFault {
+        /// Faulting virtual address as reported, with the tag if any
+        addr: usize,
+        /// Access that caused the fault
+        flags: PageFaultFlags,
+    },
+    /// Page fault in a guard page below a kernel stack
+    KernelStackOverflow {
+        /// Faulting virtual address
+        addr: usize,
+    },
+    /// Breakpoint instruction or hardware breakpoint
+    Breakpoint,
+    /// Trap after executing one instruction in single-step mode
+    SingleStep,
+    /// Undefined or illegal instruction
+    IllegalInstruction,
+    /// Misaligned memory access or instruction address
+    Misaligned,
+    /// Hardware interrupt, with the architecture-specific number
+    Interrupt(usize),
+    /// External interrupt, with the IRQ number claimed from the interrupt
+    /// controller
+    External(usize),
+    /// Timer interrupt, see the `timer` module of the architecture
+    Timer,
+    /// Inter-processor interrupt with a payload, see the `ipi` module of the
+    /// architecture
+    Ipi(usize),
+    /// Non-maskable interrupt
+    Nmi,
+    /// Instruction of user trapped for the kernel to emulate
+    EmulatableInstruction {
+        /// The instruction
+        kind: EmulatableKind,
+    },
+    /// Privileged instruction of user that raised a general protection
+    /// fault, on x86_64. Linux sends `SIGSEGV` with `SI_KERNEL` for it, unless
+    /// the kernel emulates it.
+    PrivilegedInstruction {
+        /// The instruction
+        kind: PrivilegedKind,
+        /// Length in bytes of the instruction with its prefixes, to skip it
+        /// after emulation
+        len: usize,
+    },
+    /// Other exceptions, with the architecture-specific number
+    Unknown(usize),
+}
+
+/// Instruction of user trapped for emulation, on x86_64.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EmulatableKind {
+    /// `rdtsc`, trapped by `CR4.TSD`
+    Rdtsc,
+    /// `rdtscp`, trapped by `CR4.TSD`
+    Rdtscp,
+    /// `rdpmc`, trapped without `CR4.PCE`
+    Rdpmc,
+    /// `cpuid`, trapped by CPUID faulting
+    Cpuid {
+        /// Leaf in `eax`
+        leaf: u32,
+        /// Subleaf in `ecx`
+        subleaf: u32,
+    },
+}
+
+impl EmulatableKind {
+    /// Length in bytes of the instruction, to skip it after emulation.
+    pub fn insn_len(self) -> usize {
+        match self {
+            EmulatableKind::Rdtsc | EmulatableKind::Rdpmc | EmulatableKind::Cpuid { .. } => 2,
+            EmulatableKind::Rdtscp => 3,
+        }
+    }
+}
+
+/// Privileged instruction of user that raised a general protection fault,
+/// on x86_64, with the operands read from the registers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PrivilegedKind {
+    /// `rdmsr`
+    Rdmsr {
+        /// MSR in `ecx`
+        msr: u32,
+    },
+    /// `wrmsr`
+    Wrmsr {
+        /// MSR in `ecx`
+        msr: u32,
+        /// Value in `edx:eax`
+        value: u64,
+    },
+    /// `hlt`
+    Hlt,
+    /// `cli`, with `IOPL` below 3
+    Cli,
+    /// `sti`, with `IOPL` below 3
+    Sti,
+    /// `in` to `al`, `ax` or `eax`, without permission to the port
+    In {
+        /// Port in the immediate or `dx`
+        port: u16,
+        /// Size of the access in bytes
+        size: u8,
+    },
+    /// `out` from `al`, `ax` or `eax`, without permission to the port
+    Out {
+        /// Port in the immediate or `dx`
+        port: u16,
+        /// Size of the access in bytes
+        size: u8,
+    },
+    /// `ins` to `[rdi]`, without permission to the port
+    Ins {
+        /// Port in `dx`
+        port: u16,
+        /// Size of each access in bytes
+        size: u8,
+        /// With a `rep` prefix, for `rcx` times
+        rep: bool,
+    },
+    /// `outs` from `[rsi]`, without permission to the port
+    Outs {
+        /// Port in `dx`
+        port: u16,
+        /// Size of each access in bytes
+        size: u8,
+        /// With a `rep` prefix, for `rcx` times
+        rep: bool,
+    },
+}
+
+bitflags! {
+    /// Access that caused a page fault.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct PageFaultFlags: u32 {
+        /// Caused by a write, otherwise by a read.
+        const WRITE = 1 << 0;
+        /// Caused by an instruction fetch.
+        const EXECUTE = 1 << 1;
+        /// Caused in user mode.
+        const USER = 1 << 2;
+        /// The page is present, i.e. the access violates its permission.
+        const PRESENT = 1 << 3;
+    }
+}
+
+/// Information of a page fault.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageFaultInfo {
+    /// Faulting virtual address, without the tag, see [`untag_addr()`]
+    pub addr: usize,
+    /// Faulting virtual address as reported by the CPU, with the tag if any
+    pub tagged_addr: usize,
+    /// Access that caused the fault
+    pub flags: PageFaultFlags,
+}
+
+impl PageFaultInfo {
+    /// Create the information of a fault at `tagged_addr` as reported.
+    pub fn new(tagged_addr: usize, flags: PageFaultFlags) -> Self {
+        PageFaultInfo {
+            addr: untag_addr(tagged_addr),
+            tagged_addr,
+            flags,
+        }
+    }
+
+    /// Get the tag of the faulting address, in place.
+    pub fn tag(&self) -> usize {
+        self.tagged_addr ^ self.addr
+    }
+
+    /// Whether the fault was caused by a write.
+    pub fn is_write(&self) -> bool {
+        self.flags.contains(PageFaultFlags::WRITE)
+    }
+
+    /// Whether the fault was caused in user mode.
+    pub fn is_user(&self) -> bool {
+        self.flags.contains(PageFaultFlags::USER)
+    }
+
+    /// Whether the fault was caused by an instruction fetch.
+    pub fn is_instruction_fetch(&self) -> bool {
+        self.flags.contains(PageFaultFlags::EXECUTE)
+    }
+
+    /// Whether the page is present, i.e. the fault is a protection violation.
+    pub fn is_present(&self) -> bool {
+        self.flags.contains(PageFaultFlags::PRESENT)
+    }
+}
+
+impl UserContext {
+    /// Get information of the last trap if it is a page fault.
+    pub fn page_fault_info(&self) -> Option<PageFaultInfo> {
+        match self.trap_reason() {
+            TrapReason::PageFault { addr, flags } => Some(PageFaultInfo::new(addr, flags)),
+            _ => None,
+        }
+    }
+}
+
+/// Information of a trap, returned by `UserContext::run_until_trap()`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrapInfo {
+    /// Reason of the trap
+    pub reason: TrapReason,
+    /// Faulting address if the trap is a page fault
+    pub fault_addr: Option<usize>,
+    /// Counter ticks from entering user to coming back, including the switching.
+    ///
+    /// The counter is `TSC` on x86_64, `cycle` on riscv, `CNTVCT_EL0` on aarch64,
+    /// CP0 `Count` on mipsel, the time base on powerpc64 and `CNTVCT` on arm.
+    pub user_cycles: u64,
+    /// Whether the trap is an interrupt, otherwise an exception or syscall
+    pub is_interrupt: bool,
+}
+
+impl TrapInfo {
+    pub(crate) fn new(reason: TrapReason, user_cycles: u64) -> Self {
+        let fault_addr = match reason {
+            TrapReason::PageFault { addr, .. } => Some(addr),
+            _ => None,
+        };
+        let is_interrupt = matches!(
+            reason,
+            TrapReason::Interrupt(_)
+                | TrapReason::External(_)
+                | TrapReason::Timer
+                | TrapReason::Ipi(_)
+                | TrapReason::Nmi
+        );
+        TrapInfo {
+            reason,
+            fault_addr,
+            user_cycles,
+            is_interrupt,
+        }
+    }
+}
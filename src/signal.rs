@@ -0,0 +1,67 @@
+//! Linux signal information and helpers to access signal frames on user stack.
+
+use pod::Pod;
+
+/// `siginfo_t`
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigInfo {
+    pub signo: i32,
+    #[cfg(not(target_arch = "mips"))]
+    pub errno: i32,
+    pub code: i32,
+    #[cfg(target_arch = "mips")]
+    pub errno: i32,
+    /// Signal-specific fields, including the padding before them on 64-bit
+    pub fields: [i32; 29],
+}
+
+unsafe impl Pod for SigInfo {}
+
+impl SigInfo {
+    /// Create a signal information with `signo` and `code`.
+    pub fn new(signo: i32, code: i32) -> Self {
+        SigInfo {
+            signo,
+            code,
+            ..Default::default()
+        }
+    }
+}
+
+/// Write `value` at user address `addr`, where `stack` is mapped at `base`.
+///
+/// Return `None` if out of range.
+pub(crate) fn write_at<T: Pod>(
+    stack: &mut [u8],
+    base: usize,
+    addr: usize,
+    value: &T,
+) -> Option<()> {
+    let offset = addr.checked_sub(base)?;
+    let bytes = value.as_bytes();
+    stack
+        .get_mut(offset..offset.checked_add(bytes.len())?)?
+        .copy_from_slice(bytes);
+    Some(())
+}
+
+/// Read a value at user address `addr`, where `stack` is mapped at `base`.
+///
+/// Return `None` if out of range.
+pub(crate) fn read_at<T: Pod + Default>(stack: &[u8], base: usize, addr: usize) -> Option<T> {
+    let offset = addr.checked_sub(base)?;
+    let mut value = T::default();
+    let bytes = value.as_bytes_mut();
+    bytes.copy_from_slice(stack.get(offset..offset.checked_add(bytes.len())?)?);
+    Some(value)
+}
+
+/// Fill `len` bytes at user address `addr` with 0, where `stack` is mapped at `base`.
+///
+/// Return `None` if out of range.
+pub(crate) fn zero_at(stack: &mut [u8], base: usize, addr: usize, len: usize) -> Option<()> {
+    let offset = addr.checked_sub(base)?;
+    stack.get_mut(offset..offset.checked_add(len)?)?.fill(0);
+    Some(())
+}
@@ -0,0 +1,180 @@
+//! Set up user contexts for new threads.
+
+use crate::UserContext;
+
+impl UserContext {
+    /// Create a context to run the user function at `entry` on the stack
+    /// at `stack`, with `args` in argument registers of the calling
+    /// convention.
+    ///
+    /// Flags are set for user mode with interrupts enabled, e.g. `IF` and
+    /// the reserved bit 1 in `RFLAGS` on x86_64, or `SPIE` in `sstatus` on
+    /// riscv. The stack pointer is taken as is, align it as the entry
+    /// expects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more `args` than argument registers: 6 on
+    /// x86_64, 3 on x86 by `regparm(3)`, 4 on mipsel and arm, and 8 on
+    /// others.
+    pub fn new_fn(entry: usize, stack: usize, args: &[usize]) -> Self {
+        let mut cx = UserContext::default();
+        cx.set_ip(entry);
+        cx.set_sp(stack);
+        imp::init_flags(&mut cx);
+        let regs = imp::arg_regs(&mut cx);
+        assert!(args.len() <= regs.len(), "too many arguments");
+        for (reg, &arg) in regs.into_iter().zip(args) {
+            *reg = arg;
+        }
+        cx
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // IF and the reserved bit 1
+        cx.general.rflags = 0x202;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 6] {
+        let g = &mut cx.general;
+        [
+            &mut g.rdi, &mut g.rsi, &mut g.rdx, &mut g.rcx, &mut g.r8, &mut g.r9,
+        ]
+    }
+}
+
+#[cfg(target_arch = "x86")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // IF and the reserved bit 1
+        cx.eflags = 0x202;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 3] {
+        let g = &mut cx.general;
+        [&mut g.eax, &mut g.edx, &mut g.ecx]
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use crate::UserContext;
+
+    #[cfg(not(feature = "riscv_m_mode"))]
+    pub fn init_flags(cx: &mut UserContext) {
+        // SPIE, and FS = Initial so that the user can use the FPU
+        cx.sstatus = 1 << 5 | 1 << 13;
+    }
+
+    #[cfg(feature = "riscv_m_mode")]
+    pub fn init_flags(cx: &mut UserContext) {
+        // MPIE, MPP = user, and FS = Initial
+        cx.sstatus = 1 << 7 | 1 << 13;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 8] {
+        let g = &mut cx.general;
+        [
+            &mut g.a0, &mut g.a1, &mut g.a2, &mut g.a3, &mut g.a4, &mut g.a5, &mut g.a6, &mut g.a7,
+        ]
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // EL0t with DAIF clear
+        cx.spsr = 0;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 8] {
+        let g = &mut cx.general;
+        [
+            &mut g.x0, &mut g.x1, &mut g.x2, &mut g.x3, &mut g.x4, &mut g.x5, &mut g.x6, &mut g.x7,
+        ]
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use crate::UserContext;
+    use core::arch::asm;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // the interrupt mask of the kernel, with KSU = user and IE
+        const KSU: usize = 0b11 << 3;
+        const EXL_ERL: usize = 0b11 << 1;
+        let status: usize;
+        unsafe { asm!("mfc0 {}, $12", out(reg) status) };
+        cx.status = (status & !(KSU | EXL_ERL)) | 1 << 4 | 1;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 4] {
+        let g = &mut cx.general;
+        [&mut g.a0, &mut g.a1, &mut g.a2, &mut g.a3]
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // PPLV = 3 and PIE
+        cx.prmd = 0b111;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 8] {
+        let g = &mut cx.general;
+        [
+            &mut g.a0, &mut g.a1, &mut g.a2, &mut g.a3, &mut g.a4, &mut g.a5, &mut g.a6, &mut g.a7,
+        ]
+    }
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // SF, EE, PR, FP, ME, IR, DR, RI, LE
+        cx.srr1 = 1 << 63 | 0xf033;
+        // the global entry point of ELFv2 computes the TOC from r12
+        cx.general.r12 = cx.srr0;
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 8] {
+        let g = &mut cx.general;
+        [
+            &mut g.r3, &mut g.r4, &mut g.r5, &mut g.r6, &mut g.r7, &mut g.r8, &mut g.r9, &mut g.r10,
+        ]
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn init_flags(cx: &mut UserContext) {
+        // User mode, with Thumb state for an odd entry address
+        cx.cpsr = 0x10;
+        if cx.pc & 1 != 0 {
+            cx.cpsr |= 1 << 5;
+            cx.pc &= !1;
+        }
+    }
+
+    pub fn arg_regs(cx: &mut UserContext) -> [&mut usize; 4] {
+        let g = &mut cx.general;
+        [&mut g.r0, &mut g.r1, &mut g.r2, &mut g.r3]
+    }
+}
@@ -0,0 +1,150 @@
+//! Per-CPU trap counters, for `/proc/interrupts`-style reporting.
+//!
+//! Traps from user are counted when `UserContext::run()` or `run_fncall()`
+//! returns. IRQs are counted where the crate dispatches them by
+//! [`intc`](crate::intc): the external interrupts on x86_64, and the ones
+//! claimed from the controller on other architectures. Local interrupts not going through the
+//! controller, e.g. the timer on riscv, are left to the kernel.
+//!
+//! The counters of a CPU are only written on that CPU, by relaxed atomic
+//! increments on its own cache lines.
+
+use crate::{TrapReason, UserContext, MAX_CPUS};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Number of IRQ counters, IRQ numbers from `MAX_IRQS - 1` up share the
+/// last one.
+pub const MAX_IRQS: usize = 256;
+
+/// Counters of a CPU, read by [`get`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TrapStats {
+    /// System calls from user
+    pub syscalls: usize,
+    /// Page faults from user
+    pub page_faults: usize,
+    /// Interrupts by IRQ number, or by vector without a controller
+    pub irqs: [usize; MAX_IRQS],
+    /// Spurious interrupts, dropped by the controller
+    pub spurious: usize,
+}
+
+impl TrapStats {
+    /// Total number of interrupts, without the spurious ones.
+    pub fn total_irqs(&self) -> usize {
+        self.irqs.iter().sum()
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(align(64))]
+struct CpuStats {
+    syscalls: AtomicUsize,
+    page_faults: AtomicUsize,
+    irqs: [AtomicUsize; MAX_IRQS],
+    spurious: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_STATS: CpuStats = CpuStats {
+    syscalls: ZERO,
+    page_faults: ZERO,
+    irqs: [ZERO; MAX_IRQS],
+    spurious: ZERO,
+};
+
+static STATS: [CpuStats; MAX_CPUS] = [EMPTY_STATS; MAX_CPUS];
+
+/// Function to get the current CPU ID, null for not set.
+static CPU_ID: AtomicPtr<fn() -> usize> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the function to get the ID of the current CPU, from 0 to
+/// [`MAX_CPUS`] - 1.
+///
+/// Until it is set, all traps are counted on CPU 0.
+///
+/// ```ignore
+/// static CPU_ID: fn() -> usize = my_cpu_id;
+/// trapframe::stats::set_cpu_id(&CPU_ID);
+/// ```
+pub fn set_cpu_id(cpu_id: &'static fn() -> usize) {
+    CPU_ID.store(cpu_id as *const _ as *mut _, Ordering::Release);
+}
+
+/// Get the counters of the current CPU, `None` if the ID is out of range.
+fn current() -> Option<&'static CpuStats> {
+    let id = match unsafe { CPU_ID.load(Ordering::Acquire).as_ref() } {
+        Some(cpu_id) => cpu_id(),
+        None => 0,
+    };
+    STATS.get(id)
+}
+
+fn inc(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count the trap `cx` came back from user with.
+pub(crate) fn user_trap(cx: &UserContext) {
+    if let Some(stats) = current() {
+        match cx.trap_reason() {
+            TrapReason::Syscall | TrapReason::LegacySyscall { .. } => inc(&stats.syscalls),
+            TrapReason::PageFault { .. } => inc(&stats.page_faults),
+            _ => {}
+        }
+    }
+}
+
+/// Count interrupt `irq`.
+#[cfg(baremetal)]
+pub(crate) fn irq(irq: usize) {
+    if let Some(stats) = current() {
+        inc(&stats.irqs[irq.min(MAX_IRQS - 1)]);
+    }
+}
+
+/// Count a spurious interrupt.
+#[cfg(baremetal)]
+pub(crate) fn spurious() {
+    if let Some(stats) = current() {
+        inc(&stats.spurious);
+    }
+}
+
+/// Get the counters of `cpu`.
+///
+/// # Panics
+///
+/// Panics if `cpu` is not less than [`MAX_CPUS`].
+pub fn get(cpu: usize) -> TrapStats {
+    let stats = &STATS[cpu];
+    let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+    let mut irqs = [0; MAX_IRQS];
+    for (irq, counter) in irqs.iter_mut().zip(&stats.irqs) {
+        *irq = load(counter);
+    }
+    TrapStats {
+        syscalls: load(&stats.syscalls),
+        page_faults: load(&stats.page_faults),
+        irqs,
+        spurious: load(&stats.spurious),
+    }
+}
+
+/// Reset the counters of `cpu` to 0.
+///
+/// Traps counted meanwhile on that CPU may be lost.
+///
+/// # Panics
+///
+/// Panics if `cpu` is not less than [`MAX_CPUS`].
+pub fn reset(cpu: usize) {
+    let stats = &STATS[cpu];
+    let clear = |counter: &AtomicUsize| counter.store(0, Ordering::Relaxed);
+    clear(&stats.syscalls);
+    clear(&stats.page_faults);
+    stats.irqs.iter().for_each(clear);
+    clear(&stats.spurious);
+}
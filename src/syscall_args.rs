@@ -0,0 +1,273 @@
+//! Validation of syscall arguments, before the kernel uses them.
+//!
+//! [`UserContext::check_syscall_args()`] checks the arguments of the last
+//! syscall by the kinds the kernel expects: pointers must be in the user
+//! half of the address space without tags, see
+//! [`untag_addr()`](crate::untag_addr), and 32-bit integers must be properly
+//! sign or zero extended to the register width. A failed check is reported as a
+//! [`SyscallArgError`], which kernels usually turn into `EFAULT` or `EINVAL`.
+//!
+//! The user half is:
+//! - x86_64: the lower canonical half by [`va_bits()`](crate::va_bits)
+//!   and [`LamMode`](crate::LamMode), or the low 4GB for legacy syscalls of
+//!   32-bit programs
+//! - riscv64: the lower half of `Sv39`, `Sv48` or `Sv57` by `satp.MODE`
+//! - aarch64: the range of `TTBR0_EL1` by `TCR_EL1.T0SZ`
+//! - arm: the range of `TTBR0` by `TTBCR.N`
+//! - mipsel: `kuseg` below `0x8000_0000`
+//! - loongarch64: the lower half of the `VALEN` bits in `CPUCFG1`
+//! - powerpc64: quadrant 0 of the radix MMU, with bits 63:62 clear
+//!
+//! All addresses are valid on x86 and riscv32, and without paging on riscv64
+//! or arm.
+
+use crate::UserContext;
+use core::fmt;
+
+/// What a syscall argument is expected to be.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ArgKind {
+    /// Anything, not checked
+    Any,
+    /// Pointer to user memory
+    UserPtr,
+    /// Signed 32-bit integer, sign extended
+    I32,
+    /// Unsigned 32-bit integer, zero extended
+    U32,
+}
+
+/// An invalid syscall argument.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SyscallArgError {
+    /// Pointer `value` of argument `index` is not a user address.
+    BadAddress { index: usize, value: usize },
+    /// 32-bit integer `value` of argument `index` is not extended to the
+    /// register width.
+    NotExtended { index: usize, value: usize },
+}
+
+impl SyscallArgError {
+    /// Index of the invalid argument
+    pub fn index(&self) -> usize {
+        match *self {
+            SyscallArgError::BadAddress { index, .. } => index,
+            SyscallArgError::NotExtended { index, .. } => index,
+        }
+    }
+
+    /// Whether it is a bad address, to be reported as `EFAULT`.
+    pub fn is_fault(&self) -> bool {
+        matches!(self, SyscallArgError::BadAddress { .. })
+    }
+}
+
+impl fmt::Display for SyscallArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyscallArgError::BadAddress { index, value } => {
+                write!(f, "argument {} is not a user address: {:#x}", index, value)
+            }
+            SyscallArgError::NotExtended { index, value } => {
+                write!(
+                    f,
+                    "argument {} is not a 32-bit integer: {:#x}",
+                    index, value
+                )
+            }
+        }
+    }
+}
+
+impl UserContext {
+    /// Get the syscall args like `get_syscall_args()`, after checking the
+    /// first `kinds.len()` of them by `kinds`.
+    ///
+    /// The first invalid one is returned as the error. 32-bit integers of
+    /// legacy syscalls on x86_64 are not checked, as the upper halves of
+    /// the registers are not defined.
+    pub fn check_syscall_args(&self, kinds: &[ArgKind]) -> Result<[usize; 6], SyscallArgError> {
+        let args = self.get_syscall_args();
+        let narrow = imp::narrow(self);
+        for (index, (&kind, &value)) in kinds.iter().zip(args.iter()).enumerate() {
+            match kind {
+                ArgKind::Any => {}
+                // the pointer itself must be below the end, even for an empty buffer
+                ArgKind::UserPtr if !self.is_user_range(crate::untag_addr(value), 1) => {
+                    return Err(SyscallArgError::BadAddress { index, value });
+                }
+                ArgKind::I32 if !narrow && value as i32 as isize as usize != value => {
+                    return Err(SyscallArgError::NotExtended { index, value });
+                }
+                ArgKind::U32 if !narrow && value as u32 as usize != value => {
+                    return Err(SyscallArgError::NotExtended { index, value });
+                }
+                _ => {}
+            }
+        }
+        Ok(args)
+    }
+
+    /// Whether `[addr, addr + len)` is in the user half of the address space
+    /// for the last syscall.
+    pub fn is_user_range(&self, addr: usize, len: usize) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        match imp::user_end(self) {
+            Some(user_end) => end <= user_end,
+            None => true,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use crate::{LamMode, UserContext};
+
+    pub fn user_end(cx: &UserContext) -> Option<usize> {
+        if cx.is_legacy_syscall() {
+            return Some(1 << 32);
+        }
+        // the half is 47 bits wide with `LAM_U48` even under 5-level paging
+        match LamMode::current() {
+            LamMode::U48 => Some(1 << 47),
+            _ => Some(1 << (crate::va_bits() - 1)),
+        }
+    }
+
+    pub fn narrow(cx: &UserContext) -> bool {
+        cx.is_legacy_syscall()
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+mod imp {
+    use crate::UserContext;
+
+    #[cfg(all(baremetal, not(feature = "riscv_m_mode")))]
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        let satp: usize;
+        unsafe { core::arch::asm!("csrr {}, satp", out(reg) satp, options(nomem, nostack)) };
+        // the lower half of Sv39, Sv48 and Sv57
+        match satp >> 60 {
+            8 => Some(1 << 38),
+            9 => Some(1 << 47),
+            10 => Some(1 << 56),
+            _ => None,
+        }
+    }
+
+    /// M-mode kernels run user without paging.
+    #[cfg(not(all(baremetal, not(feature = "riscv_m_mode"))))]
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        None
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use crate::UserContext;
+
+    #[cfg(baremetal)]
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        let tcr: usize;
+        unsafe { core::arch::asm!("mrs {}, tcr_el1", out(reg) tcr, options(nomem, nostack)) };
+        // TTBR0 covers all with T0SZ = 0
+        1usize.checked_shl(64 - (tcr & 0x3f) as u32)
+    }
+
+    /// Linux and macOS leave 48 bits to user.
+    #[cfg(not(baremetal))]
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        Some(1 << 48)
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(target_arch = "arm")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        let ttbcr: usize;
+        unsafe {
+            core::arch::asm!("mrc p15, 0, {}, c2, c0, 2", out(reg) ttbcr, options(nomem, nostack));
+        }
+        // TTBR0 covers all with N = 0
+        match ttbcr & 0x7 {
+            0 => None,
+            n => Some(1 << (32 - n)),
+        }
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(target_arch = "mips")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        Some(0x8000_0000)
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(target_arch = "loongarch64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        let cfg1: usize;
+        unsafe {
+            core::arch::asm!("cpucfg {}, {}", out(reg) cfg1, in(reg) 1, options(nomem, nostack));
+        }
+        // VALEN - 1 in bits 19:12
+        let valen = ((cfg1 >> 12) & 0xff) + 1;
+        Some(1 << (valen - 1))
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(target_arch = "powerpc64")]
+mod imp {
+    use crate::UserContext;
+
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        Some(1 << 62)
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "riscv32"))]
+mod imp {
+    use crate::UserContext;
+
+    pub fn user_end(_cx: &UserContext) -> Option<usize> {
+        None
+    }
+
+    pub fn narrow(_cx: &UserContext) -> bool {
+        false
+    }
+}
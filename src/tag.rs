@@ -0,0 +1,75 @@
+//! Tags in the high bits of user addresses, ignored by the MMU.
+//!
+//! User programs may keep tags in pointers by top byte ignore (TBI) on
+//! aarch64, or linear address masking (LAM) on x86_64. Such an address is
+//! seen as is in fault addresses and syscall arguments, and has to be
+//! untagged by [`untag_addr()`] before looking it up in page tables.
+//!
+//! The tagging mode is read from the current CPU: `TCR_EL1.TBI0` on aarch64
+//! and `CR3.LAM_U48` or `CR3.LAM_U57` on x86_64, so it must be called in
+//! the address space of the user. In user space, TBI is assumed on aarch64
+//! as on Linux, and LAM is assumed off on x86_64. Other architectures have
+//! no tags.
+
+/// Strip the tag of user address `addr`, leaving the address the MMU
+/// translates.
+///
+/// Addresses in the upper half get the tag bits set instead, which leaves
+/// canonical kernel addresses as is. The half is told by bit 55 on aarch64
+/// as the MMU does with TBI, and by bit 63 otherwise.
+pub fn untag_addr(addr: usize) -> usize {
+    let mask = imp::tag_mask();
+    let upper = addr & (1 << imp::HALF_BIT) != 0;
+    match upper {
+        false => addr & !mask,
+        true => addr | mask,
+    }
+}
+
+/// Get the tag bits of user address `addr`, in place.
+pub fn addr_tag(addr: usize) -> usize {
+    addr ^ untag_addr(addr)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    pub const HALF_BIT: u32 = 63;
+
+    pub fn tag_mask() -> usize {
+        crate::LamMode::current().mask()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    pub const HALF_BIT: u32 = 55;
+
+    /// The top byte
+    const TOP_BYTE: usize = 0xff << 56;
+
+    #[cfg(baremetal)]
+    pub fn tag_mask() -> usize {
+        /// `TCR_EL1.TBI0`
+        const TBI0: usize = 1 << 37;
+        let tcr: usize;
+        unsafe { core::arch::asm!("mrs {}, tcr_el1", out(reg) tcr, options(nomem, nostack)) };
+        match tcr & TBI0 {
+            0 => 0,
+            _ => TOP_BYTE,
+        }
+    }
+
+    #[cfg(not(baremetal))]
+    pub fn tag_mask() -> usize {
+        TOP_BYTE
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    pub const HALF_BIT: u32 = usize::BITS - 1;
+
+    pub fn tag_mask() -> usize {
+        0
+    }
+}
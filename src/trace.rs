@@ -0,0 +1,67 @@
+//! Hooks for tracing traps, e.g. by a `perf`-like sampler or for time
+//! accounting.
+//!
+//! The tracer set by [`set_trap_tracer`] is called on the events in
+//! [`TrapEvent`], on the CPU of the trap and with interrupts disabled,
+//! except for entering user. It should be short, and must not go to user.
+//!
+//! Kernel IRQs are traced where the crate dispatches them by
+//! [`intc`](crate::intc), the same as the ones counted by `stats`.
+
+use crate::{TrapReason, UserContext};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// An event of trap, passed to the tracer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TrapEvent {
+    /// Going to user by `UserContext::run()` or `run_fncall()`
+    EnterUser,
+    /// Back from user, with the reason of the trap
+    LeaveUser(TrapReason),
+    /// Begin of the kernel handler of an IRQ, with the IRQ number
+    KernelIrqBegin(usize),
+    /// End of the kernel handler of an IRQ, with the IRQ number
+    KernelIrqEnd(usize),
+}
+
+/// The tracer, null for not set.
+static TRACER: AtomicPtr<fn(&TrapEvent)> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set the tracer of all CPUs, replacing the old one.
+///
+/// ```ignore
+/// static TRACER: fn(&TrapEvent) = my_tracer;
+/// trapframe::trace::set_trap_tracer(&TRACER);
+/// ```
+pub fn set_trap_tracer(tracer: &'static fn(&TrapEvent)) {
+    TRACER.store(tracer as *const _ as *mut _, Ordering::Release);
+}
+
+/// Remove the tracer.
+pub fn clear_trap_tracer() {
+    TRACER.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+/// Get the tracer if set.
+#[inline]
+fn tracer() -> Option<&'static fn(&TrapEvent)> {
+    unsafe { TRACER.load(Ordering::Acquire).as_ref() }
+}
+
+/// Call the tracer with `event` if set.
+#[inline]
+pub(crate) fn emit(event: TrapEvent) {
+    if let Some(tracer) = tracer() {
+        tracer(&event);
+    }
+}
+
+/// Call the tracer with the trap `cx` came back from user with, if set.
+///
+/// The reason of the trap is only decoded when there is a tracer.
+#[inline]
+pub(crate) fn leave_user(cx: &UserContext) {
+    if let Some(tracer) = tracer() {
+        tracer(&TrapEvent::LeaveUser(cx.trap_reason()));
+    }
+}
@@ -0,0 +1,166 @@
+//! Scoped access to user memory from the kernel.
+//!
+//! With SMAP on x86, PAN on aarch64, or `sstatus.SUM` cleared on riscv, the
+//! kernel faults when it touches user memory, unless the access is allowed
+//! explicitly. A [`UserAccess`] guard allows it until dropped.
+//!
+//! The trap entries disallow it again for the kernel, so a user can not
+//! leak the permission into a trap handler: `AC` is cleared on x86 and
+//! `sstatus.SUM` on riscv, while aarch64 relies on `SCTLR_EL1.SPAN` being 0
+//! to set PAN on exceptions. mipsel, loongarch64, powerpc64 and arm have no
+//! such protection.
+
+use core::marker::PhantomData;
+
+/// A guard allowing the kernel to access user memory until dropped.
+///
+/// It must be dropped on the same CPU, and must not be alive when going to
+/// user by `UserContext::run()`.
+pub struct UserAccess {
+    allowed_before: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl UserAccess {
+    /// Allow the kernel to access user memory, by `stac` on x86, setting
+    /// `sstatus.SUM` on riscv, or clearing `PSTATE.PAN` on aarch64.
+    ///
+    /// Guards can be nested, the outermost one disallows it on drop.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let allowed_before = is_allowed();
+        if !allowed_before {
+            unsafe { imp::allow() };
+        }
+        UserAccess {
+            allowed_before,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for UserAccess {
+    fn drop(&mut self) {
+        if !self.allowed_before {
+            unsafe { imp::deny() };
+        }
+    }
+}
+
+/// Whether the kernel can access user memory now.
+///
+/// Always `true` if the protection is not enabled or not supported.
+pub fn is_allowed() -> bool {
+    !imp::enforced() || imp::flag()
+}
+
+/// Whether the flag allowing user access is set, regardless of whether
+/// the protection is enabled.
+#[allow(dead_code)]
+pub(crate) fn flag() -> bool {
+    imp::flag()
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+mod imp {
+    use core::arch::asm;
+
+    /// `EFLAGS.AC`
+    const AC: usize = 1 << 18;
+    /// `CR4.SMAP`
+    const CR4_SMAP: usize = 1 << 21;
+
+    /// Whether SMAP is enabled. `stac` and `clac` are only valid then.
+    pub fn enforced() -> bool {
+        let cr4: usize;
+        unsafe { asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags)) };
+        cr4 & CR4_SMAP != 0
+    }
+
+    pub fn flag() -> bool {
+        let flags: usize;
+        unsafe { asm!("pushf", "pop {}", out(reg) flags, options(nomem, preserves_flags)) };
+        flags & AC != 0
+    }
+
+    pub unsafe fn allow() {
+        asm!("stac", options(nomem, nostack));
+    }
+
+    pub unsafe fn deny() {
+        asm!("clac", options(nomem, nostack));
+    }
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod imp {
+    use core::arch::asm;
+
+    /// `sstatus.SUM`
+    const SUM: usize = 1 << 18;
+
+    pub fn enforced() -> bool {
+        true
+    }
+
+    pub fn flag() -> bool {
+        let sstatus: usize;
+        unsafe { asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack)) };
+        sstatus & SUM != 0
+    }
+
+    pub unsafe fn allow() {
+        asm!("csrs sstatus, {}", in(reg) SUM, options(nomem, nostack));
+    }
+
+    pub unsafe fn deny() {
+        asm!("csrc sstatus, {}", in(reg) SUM, options(nomem, nostack));
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use core::arch::asm;
+
+    /// `PSTATE.PAN` in the `PAN` register, as `s3_0_c4_c2_3`
+    const PAN: usize = 1 << 22;
+
+    /// Whether the CPU supports PAN.
+    pub fn enforced() -> bool {
+        crate::cpu_features().contains(crate::CpuFeatures::PAN)
+    }
+
+    pub fn flag() -> bool {
+        let pan: usize;
+        unsafe { asm!("mrs {}, s3_0_c4_c2_3", out(reg) pan, options(nomem, nostack)) };
+        pan & PAN == 0
+    }
+
+    pub unsafe fn allow() {
+        asm!("msr s3_0_c4_c2_3, xzr", options(nomem, nostack));
+    }
+
+    pub unsafe fn deny() {
+        asm!("msr s3_0_c4_c2_3, {}", in(reg) PAN, options(nomem, nostack));
+    }
+}
+
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "loongarch64",
+    target_arch = "powerpc64",
+    target_arch = "arm"
+))]
+mod imp {
+    pub fn enforced() -> bool {
+        false
+    }
+
+    pub fn flag() -> bool {
+        true
+    }
+
+    pub unsafe fn allow() {}
+
+    pub unsafe fn deny() {}
+}
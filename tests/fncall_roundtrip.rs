@@ -0,0 +1,99 @@
+//! Round-trip arbitrary register values through `UserContext::run_fncall()`.
+//!
+//! The user program returns to the kernel at once, so the context saved on
+//! return must be bit-exact with the one restored, except the registers
+//! defined to change by `syscall_fn_entry`, and fsbase and gsbase which are
+//! left to the other tests.
+
+#![cfg(all(
+    feature = "fncall",
+    target_arch = "x86_64",
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use core::arch::global_asm;
+use proptest::prelude::*;
+use trapframe::{GeneralRegs, UserContext};
+
+#[cfg(target_os = "macos")]
+global_asm!(".set _return_at_once, return_at_once");
+#[cfg(target_os = "macos")]
+global_asm!(".set _return_at_once_end, return_at_once_end");
+
+// Mock user program returning to the kernel at once.
+global_asm!(
+    r#"
+return_at_once:
+    call syscall_fn_entry
+return_at_once_end:
+"#
+);
+
+extern "sysv64" {
+    fn return_at_once();
+    fn return_at_once_end();
+}
+
+/// Flags of `rflags` user can set freely: CF, PF, AF, ZF, SF, DF, OF and AC.
+///
+/// The others are reserved, privileged, or trap like TF.
+const USER_FLAGS: usize = 1 << 0 | 1 << 2 | 1 << 4 | 1 << 6 | 1 << 7 | 1 << 10 | 1 << 11 | 1 << 18;
+
+fn general_regs() -> impl Strategy<Value = GeneralRegs> {
+    (any::<[usize; 8]>(), any::<[usize; 7]>(), any::<usize>()).prop_map(|(a, b, rflags)| {
+        GeneralRegs {
+            rax: a[0],
+            rbx: a[1],
+            rcx: a[2],
+            rdx: a[3],
+            rsi: a[4],
+            rdi: a[5],
+            rbp: a[6],
+            r8: a[7],
+            r9: b[0],
+            r10: b[1],
+            r11: b[2],
+            r12: b[3],
+            r13: b[4],
+            r14: b[5],
+            r15: b[6],
+            rflags: rflags & USER_FLAGS,
+            // set below, and don't set fsbase and gsbase to garbage values
+            ..Default::default()
+        }
+    })
+}
+
+proptest! {
+    #[test]
+    fn run_fncall_roundtrip(regs in general_regs()) {
+        let mut stack = vec![0u8; 0x1000];
+        let stack_top = stack.as_mut_ptr() as usize + stack.len();
+        let regs = GeneralRegs {
+            rsp: stack_top,
+            rip: return_at_once as usize,
+            ..regs
+        };
+        let mut cx = UserContext {
+            general: regs,
+            ..Default::default()
+        };
+        cx.run_fncall();
+        let saved = cx.general;
+        prop_assert_eq!(saved.rflags & USER_FLAGS, regs.rflags);
+        prop_assert_eq!(
+            saved,
+            GeneralRegs {
+                // rsp is saved to r11 by `syscall_fn_entry`, like `syscall`
+                r11: stack_top,
+                // after the `call`
+                rip: return_at_once_end as usize,
+                rflags: saved.rflags,
+                // set to the initial TLS of user for 0
+                fsbase: saved.fsbase,
+                gsbase: saved.gsbase,
+                ..regs
+            }
+        );
+    }
+}
@@ -0,0 +1,8 @@
+fn main() {
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    if arch == "riscv64" || arch == "aarch64" {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-arg=-T{}/linker-{}.ld", dir, arch);
+        println!("cargo:rerun-if-changed=linker-{}.ld", arch);
+    }
+}
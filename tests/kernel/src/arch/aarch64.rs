@@ -0,0 +1,212 @@
+//! aarch64 on `qemu-system-aarch64 -machine virt`, entered at EL1.
+//!
+//! The RAM gigabyte is mapped at its physical address for the kernel, and
+//! aliased at [`USER_BASE`] for user. The timer interrupt comes through the
+//! GICv2.
+
+use core::arch::{asm, global_asm};
+use trapframe::gic::GicV2;
+use trapframe::UserContext;
+
+/// Physical address of the RAM gigabyte.
+const KERNEL_BASE: usize = 0x4000_0000;
+/// Virtual address of the user alias of the RAM gigabyte.
+const USER_BASE: usize = 0x8000_0000;
+/// An address not mapped.
+pub const UNMAPPED: usize = 0xc000_0000;
+
+/// PL011 UART
+const UART: usize = 0x0900_0000;
+/// GICv2 distributor
+const GICD: usize = 0x0800_0000;
+/// GICv2 CPU interface
+const GICC: usize = 0x0801_0000;
+/// INTID of the EL1 physical timer
+const TIMER_IRQ: usize = 30;
+
+static GIC: GicV2 = unsafe { GicV2::new(GICC) };
+
+global_asm!(
+    r#"
+    .section .text.entry
+    .global _start
+_start:
+    ldr x0, =boot_stack_top
+    mov sp, x0
+    // CPACR_EL1.FPEN, not to trap floating-point instructions
+    mov x0, #(3 << 20)
+    msr cpacr_el1, x0
+    isb
+    bl rust_main
+    b .
+
+    .section .bss.stack
+    .balign 16
+    .space 0x10000
+boot_stack_top:
+"#
+);
+
+/// Level 1 translation table of 4K granule.
+#[repr(align(4096))]
+struct PageTable([usize; 512]);
+
+static mut TABLE: PageTable = PageTable([0; 512]);
+
+#[no_mangle]
+extern "C" fn rust_main() -> ! {
+    unsafe {
+        init_mmu();
+        init_gic();
+        trapframe::init().unwrap();
+    }
+    trapframe::intc::set_controller(&GIC);
+    crate::run_tests()
+}
+
+unsafe fn init_mmu() {
+    // block, AF
+    const BLOCK: usize = 1 | 1 << 10;
+    // AttrIndx 1 of normal memory, inner shareable
+    const NORMAL: usize = 1 << 2 | 3 << 8;
+    // AP = EL0 and EL1 read-write
+    const USER: usize = 1 << 6;
+    const PXN: usize = 1 << 53;
+    const UXN: usize = 1 << 54;
+    TABLE.0[0] = BLOCK | PXN | UXN;
+    TABLE.0[KERNEL_BASE >> 30] = KERNEL_BASE | BLOCK | NORMAL | UXN;
+    TABLE.0[USER_BASE >> 30] = KERNEL_BASE | BLOCK | NORMAL | USER | PXN;
+
+    // attribute 0: device nGnRnE, 1: normal write-back
+    let mair: usize = 0xff << 8;
+    // T0SZ = 25, IRGN0 = ORGN0 = write-back, SH0 = inner, EPD1
+    let tcr: usize = 25 | 1 << 8 | 1 << 10 | 3 << 12 | 1 << 23;
+    asm!(
+        "msr mair_el1, {mair}",
+        "msr tcr_el1, {tcr}",
+        "msr ttbr0_el1, {ttbr0}",
+        "tlbi vmalle1",
+        "dsb ish",
+        "isb",
+        "mrs {tmp}, sctlr_el1",
+        // M, C, I
+        "orr {tmp}, {tmp}, #(1 << 0 | 1 << 2 | 1 << 12)",
+        "msr sctlr_el1, {tmp}",
+        "isb",
+        mair = in(reg) mair,
+        tcr = in(reg) tcr,
+        ttbr0 = in(reg) TABLE.0.as_ptr(),
+        tmp = out(reg) _,
+    );
+}
+
+unsafe fn init_gic() {
+    let write = |addr: usize, value: u32| (addr as *mut u32).write_volatile(value);
+    // GICD_CTLR, GICD_ISENABLER0
+    write(GICD, 1);
+    write(GICD + 0x100, 1 << TIMER_IRQ);
+    // GICC_PMR, GICC_CTLR
+    write(GICC + 0x4, 0xff);
+    write(GICC, 1);
+}
+
+/// Create a user context at EL0 with interrupts on.
+pub fn user_context(ip: usize, sp: usize, arg: usize) -> UserContext {
+    let mut cx = UserContext::default();
+    cx.set_ip(ip);
+    cx.set_sp(sp);
+    cx.general.x0 = arg;
+    cx
+}
+
+/// Get the user alias of the kernel address `addr`.
+pub fn user_addr(addr: usize) -> usize {
+    addr - KERNEL_BASE + USER_BASE
+}
+
+pub fn putchar(c: u8) {
+    unsafe {
+        // wait while UARTFR.TXFF
+        while ((UART + 0x18) as *const u32).read_volatile() & (1 << 5) != 0 {}
+        (UART as *mut u32).write_volatile(c as u32);
+    }
+}
+
+/// Power off by PSCI `SYSTEM_OFF`, the runner checks the output instead of
+/// `code`.
+pub fn exit(_code: i32) -> ! {
+    unsafe { asm!("hvc #0", in("x0") 0x8400_0008usize, options(noreturn)) }
+}
+
+pub fn arm_timer() {
+    trapframe::timer::set_deadline(trapframe::timer::now() + 100_000);
+}
+
+pub fn ack_timer() {
+    trapframe::timer::clear();
+}
+
+/// `elr` is after `svc` already.
+pub fn skip_syscall(_cx: &mut UserContext) {}
+
+/// The context does not carry the floating-point registers, so save `d0`
+/// as a kernel does, clobber it and restore it.
+pub fn clobber_fp(_cx: &UserContext) {
+    unsafe {
+        let saved: usize;
+        // fmov x9, d0; fmov d0, xzr
+        asm!(".word 0x9e660009", ".word 0x9e6703e0", out("x9") saved);
+        // fmov d0, x9
+        asm!(".word 0x9e670120", in("x9") saved);
+    }
+}
+
+// User stubs, with the floating-point instructions as raw words since the
+// target is soft-float:
+//   fmov d0, x0: 0x9e670000
+//   fmov x0, d0: 0x9e660000
+global_asm!(
+    r#"
+    .section .text
+    .balign 4
+    .global syscall_stub
+syscall_stub:
+    mov x8, #42
+    svc #0
+
+    .balign 4
+    .global fault_stub
+fault_stub:
+    ldr x1, [x0]
+    svc #0
+
+    .balign 4
+    .global loop_stub
+loop_stub:
+    b loop_stub
+
+    .balign 4
+    .global fp_stub
+fp_stub:
+    .inst 0x9e670000
+    mov x0, #0
+    mov x8, #42
+    svc #0
+    .inst 0x9e660000
+    mov x8, #42
+    svc #0
+"#
+);
+
+extern "C" {
+    pub fn syscall_stub();
+    pub fn fault_stub();
+    pub fn loop_stub();
+    pub fn fp_stub();
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("{}", info);
+    exit(1)
+}
@@ -0,0 +1,151 @@
+//! riscv64 on `qemu-system-riscv64 -machine virt`, booted by OpenSBI.
+//!
+//! The kernel is mapped by a gigapage at its physical address, and aliased
+//! at [`USER_BASE`] for user.
+
+use core::arch::{asm, global_asm};
+use trapframe::UserContext;
+
+extern crate opensbi_rt;
+
+/// Physical address of the kernel gigapage.
+const KERNEL_BASE: usize = 0x8000_0000;
+/// Virtual address of the user alias of the kernel gigapage.
+const USER_BASE: usize = 0x1_0000_0000;
+/// An address not mapped.
+pub const UNMAPPED: usize = 0x2_0000_0000;
+
+/// Sv39 root page table.
+#[repr(align(4096))]
+struct PageTable([usize; 512]);
+
+static mut ROOT: PageTable = PageTable([0; 512]);
+
+#[no_mangle]
+extern "C" fn main() {
+    unsafe {
+        trapframe::init().unwrap();
+        init_paging();
+        // sie.STIE
+        asm!("csrs sie, {}", in(reg) 1 << 5);
+    }
+    crate::run_tests()
+}
+
+unsafe fn init_paging() {
+    // V, R, W, X, A, D
+    const KERNEL: usize = 0b1100_1111;
+    // and U
+    const USER: usize = KERNEL | 1 << 4;
+    let ppn = KERNEL_BASE >> 12;
+    ROOT.0[KERNEL_BASE >> 30] = ppn << 10 | KERNEL;
+    ROOT.0[USER_BASE >> 30] = ppn << 10 | USER;
+    let satp = 8 << 60 | ROOT.0.as_ptr() as usize >> 12;
+    asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+}
+
+/// Create a user context with interrupts and the floating-point unit on.
+pub fn user_context(ip: usize, sp: usize, arg: usize) -> UserContext {
+    // SPIE, FS = Initial
+    const SSTATUS: usize = 1 << 5 | 1 << 13;
+    let mut cx = UserContext {
+        sstatus: SSTATUS,
+        ..Default::default()
+    };
+    cx.set_ip(ip);
+    cx.set_sp(sp);
+    cx.general.a0 = arg;
+    cx
+}
+
+/// Get the user alias of the kernel address `addr`.
+pub fn user_addr(addr: usize) -> usize {
+    addr - KERNEL_BASE + USER_BASE
+}
+
+pub fn putchar(c: u8) {
+    unsafe { asm!("ecall", inlateout("a0") c as usize => _, in("a7") 1) };
+}
+
+/// Shut down by SBI, the runner checks the output instead of `code`.
+pub fn exit(_code: i32) -> ! {
+    unsafe { asm!("ecall", in("a7") 8, options(noreturn)) }
+}
+
+pub fn arm_timer() {
+    trapframe::timer::set_deadline(trapframe::timer::now() + 100_000);
+}
+
+pub fn ack_timer() {
+    trapframe::timer::clear();
+}
+
+/// Step over `ecall`.
+pub fn skip_syscall(cx: &mut UserContext) {
+    cx.sepc += 4;
+}
+
+/// The context does not carry the floating-point registers, so save them
+/// as a kernel does when `sstatus.FS` of the user is Dirty, clobber `f0`,
+/// and restore them.
+pub fn clobber_fp(cx: &UserContext) {
+    const FS: usize = 3 << 13;
+    assert_eq!(cx.sstatus & FS, FS, "user FS is not Dirty");
+    unsafe {
+        let saved: usize;
+        // FS = Initial for the kernel, fmv.x.d t0, f0; fmv.d.x f0, zero
+        asm!(
+            "csrs sstatus, {fs}",
+            ".word 0xe20002d3",
+            ".word 0xf2000053",
+            fs = in(reg) 1 << 13,
+            out("t0") saved,
+        );
+        // fmv.d.x f0, t0, then FS = Off
+        asm!(".word 0xf2028053", "csrc sstatus, {fs}", fs = in(reg) FS, in("t0") saved);
+    }
+}
+
+// User stubs, with the floating-point instructions as raw words since the
+// target has no D:
+//   fmv.d.x f0, a0: 0xf2050053
+//   fmv.x.d a0, f0: 0xe2000553
+global_asm!(
+    r#"
+    .section .text
+    .balign 4
+    .global syscall_stub
+syscall_stub:
+    li a7, 42
+    ecall
+
+    .balign 4
+    .global fault_stub
+fault_stub:
+    ld a1, 0(a0)
+    ecall
+
+    .balign 4
+    .global loop_stub
+loop_stub:
+    j loop_stub
+
+    .balign 4
+    .global fp_stub
+fp_stub:
+    .word 0xf2050053
+    li a0, 0
+    li a7, 42
+    ecall
+    .word 0xe2000553
+    li a7, 42
+    ecall
+"#
+);
+
+extern "C" {
+    pub fn syscall_stub();
+    pub fn fault_stub();
+    pub fn loop_stub();
+    pub fn fp_stub();
+}
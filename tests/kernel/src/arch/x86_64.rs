@@ -0,0 +1,136 @@
+//! x86_64 on `qemu-system-x86_64` with OVMF, booted as a UEFI application.
+//!
+//! The firmware identity-maps the memory, which is made accessible to user
+//! as well. The timer interrupt is the one of the firmware.
+
+use core::arch::{asm, global_asm};
+use trapframe::UserContext;
+use uefi::prelude::*;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+
+/// An address not mapped.
+pub const UNMAPPED: usize = 0x7000_0000_0000;
+
+/// Vector of the timer interrupt of OVMF.
+const TIMER_VECTOR: u8 = 0x68;
+/// COM1
+const SERIAL: u16 = 0x3f8;
+/// I/O port of `isa-debug-exit`.
+const DEBUG_EXIT: u16 = 0xf4;
+/// EOI register of xAPIC, identity-mapped.
+const XAPIC_EOI: usize = 0xfee0_00b0;
+
+#[entry]
+fn efi_main(_image: Handle, mut st: SystemTable<Boot>) -> Status {
+    uefi_services::init(&mut st).expect_success("failed to initialize utilities");
+    unsafe {
+        // NX, and allow writing the page table
+        Efer::update(|f| f.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|f| f.remove(Cr0Flags::WRITE_PROTECT));
+        trapframe::init().unwrap();
+    }
+    allow_user_access(syscall_stub as usize);
+    let stack = unsafe { crate::USER_STACK.0.as_ptr() } as usize;
+    for page in (stack..stack + crate::USER_STACK_SIZE).step_by(0x1000) {
+        allow_user_access(page);
+    }
+    trapframe::timer::set_vector(TIMER_VECTOR);
+    crate::run_tests()
+}
+
+/// Set the user bit along the page table walk of `vaddr`.
+fn allow_user_access(vaddr: usize) {
+    let mut page_table = Cr3::read().0.start_address().as_u64() as *mut PageTable;
+    for level in 0..4 {
+        let index = (vaddr >> (12 + (3 - level) * 9)) & 0o777;
+        let entry = unsafe { &mut (&mut *page_table)[index] };
+        let flags = entry.flags();
+        entry.set_flags(flags | PageTableFlags::USER_ACCESSIBLE);
+        if level == 3 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return;
+        }
+        page_table = entry.frame().unwrap().start_address().as_u64() as *mut PageTable;
+    }
+}
+
+/// Create a user context with interrupts on.
+pub fn user_context(ip: usize, sp: usize, arg: usize) -> UserContext {
+    let mut cx = UserContext::default();
+    // IF
+    cx.general.rflags = 0x202;
+    cx.set_ip(ip);
+    cx.set_sp(sp);
+    cx.general.rdi = arg;
+    cx
+}
+
+/// The memory is identity-mapped.
+pub fn user_addr(addr: usize) -> usize {
+    addr
+}
+
+pub fn putchar(c: u8) {
+    unsafe { Port::new(SERIAL).write(c) };
+}
+
+/// Exit by `isa-debug-exit`, with status `code << 1 | 1` of QEMU.
+pub fn exit(code: i32) -> ! {
+    unsafe { Port::new(DEBUG_EXIT).write(code as u32) };
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// The timer of the firmware is periodic.
+pub fn arm_timer() {}
+
+pub fn ack_timer() {
+    unsafe { (XAPIC_EOI as *mut u32).write_volatile(0) };
+}
+
+/// `rip` is after `syscall` already.
+pub fn skip_syscall(_cx: &mut UserContext) {}
+
+/// The context carries the floating-point registers with feature `fpu`.
+pub fn clobber_fp(_cx: &UserContext) {
+    unsafe { asm!("xorps xmm0, xmm0") };
+}
+
+global_asm!(
+    r#"
+    .section .text
+    .global syscall_stub
+syscall_stub:
+    mov eax, 42
+    syscall
+
+    .global fault_stub
+fault_stub:
+    mov rsi, [rdi]
+    syscall
+
+    .global loop_stub
+loop_stub:
+    jmp loop_stub
+
+    .global fp_stub
+fp_stub:
+    movq xmm0, rdi
+    xor edi, edi
+    mov eax, 42
+    syscall
+    movq rdi, xmm0
+    mov eax, 42
+    syscall
+"#
+);
+
+extern "sysv64" {
+    pub fn syscall_stub();
+    pub fn fault_stub();
+    pub fn loop_stub();
+    pub fn fp_stub();
+}
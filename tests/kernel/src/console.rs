@@ -0,0 +1,30 @@
+//! Print to the serial port.
+
+use core::fmt::{self, Write};
+
+struct Serial;
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            crate::arch::putchar(b);
+        }
+        Ok(())
+    }
+}
+
+pub fn print(args: fmt::Arguments) {
+    Serial.write_fmt(args).unwrap();
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::print(format_args!($($arg)*))
+    };
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {
+        $crate::console::print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
@@ -0,0 +1,97 @@
+//! A minimal kernel booted under QEMU by `tests/qemu.rs`, running the tests
+//! of a bare-metal backend and reporting them over the serial port.
+//!
+//! Each test goes to user with a stub in `arch`, and checks the trap it
+//! comes back with. The results are lines of `test <name> ... ok`, ended
+//! by `all tests passed`, or a panic message.
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+mod console;
+
+#[cfg_attr(target_arch = "x86_64", path = "arch/x86_64.rs")]
+#[cfg_attr(target_arch = "riscv64", path = "arch/riscv64.rs")]
+#[cfg_attr(target_arch = "aarch64", path = "arch/aarch64.rs")]
+mod arch;
+
+use trapframe::{PageFaultFlags, TrapReason, UserContext};
+
+/// Size of the user stack.
+const USER_STACK_SIZE: usize = 0x4000;
+
+/// Stack shared by the user stubs.
+#[repr(align(4096))]
+struct UserStack([u8; USER_STACK_SIZE]);
+
+static mut USER_STACK: UserStack = UserStack([0; USER_STACK_SIZE]);
+
+/// Syscall number made by the stubs.
+const SYSCALL_NUM: usize = 42;
+
+/// Run all tests, called by `arch` after the initialization.
+fn run_tests() -> ! {
+    test("syscall", syscall);
+    test("page_fault", page_fault);
+    test("timer", timer);
+    test("fp_state", fp_state);
+    println!("all tests passed");
+    arch::exit(0)
+}
+
+fn test(name: &str, f: fn()) {
+    print!("test {} ... ", name);
+    f();
+    println!("ok");
+}
+
+/// Create a context to run `stub` in user with the first argument `arg`.
+fn user_context(stub: usize, arg: usize) -> UserContext {
+    let stack = unsafe { USER_STACK.0.as_ptr() as usize + USER_STACK_SIZE };
+    arch::user_context(arch::user_addr(stub), arch::user_addr(stack), arg)
+}
+
+fn syscall() {
+    let mut cx = user_context(arch::syscall_stub as usize, 0);
+    cx.run();
+    assert_eq!(cx.trap_reason(), TrapReason::Syscall);
+    assert_eq!(cx.get_syscall_num(), SYSCALL_NUM);
+}
+
+fn page_fault() {
+    let mut cx = user_context(arch::fault_stub as usize, arch::UNMAPPED);
+    cx.run();
+    match cx.trap_reason() {
+        TrapReason::PageFault { addr, flags } => {
+            assert_eq!(addr, arch::UNMAPPED);
+            assert!(!flags.contains(PageFaultFlags::WRITE));
+        }
+        reason => panic!("expect a page fault, got {:?}", reason),
+    }
+}
+
+fn timer() {
+    let mut cx = user_context(arch::loop_stub as usize, 0);
+    arch::arm_timer();
+    cx.run();
+    let reason = cx.trap_reason();
+    arch::ack_timer();
+    assert_eq!(reason, TrapReason::Timer);
+}
+
+fn fp_state() {
+    const PATTERN: usize = 0x0123_4567_89ab_cdef;
+    let mut cx = user_context(arch::fp_stub as usize, PATTERN);
+    // the stub loads the pattern to a floating-point register
+    cx.run();
+    assert_eq!(cx.trap_reason(), TrapReason::Syscall);
+    arch::skip_syscall(&mut cx);
+    // the kernel uses it meanwhile, saving it first if the context does not
+    // carry it
+    arch::clobber_fp(&cx);
+    // and reads it back as the first argument
+    cx.run();
+    assert_eq!(cx.trap_reason(), TrapReason::Syscall);
+    assert_eq!(cx.get_syscall_args()[0], PATTERN);
+}
@@ -0,0 +1,78 @@
+//! Preempt a spinning user program of `UserContext::run_fncall()` by the
+//! host timer signal of `preempt`.
+//!
+//! The timer signal is delivered to any thread not blocking it, and only
+//! the thread calling `preempt::enable()` blocks it, so this runs without
+//! the test harness, on the main thread alone.
+
+#[cfg(all(feature = "fncall", target_arch = "x86_64", target_os = "linux"))]
+mod preempt {
+    use core::arch::global_asm;
+    use trapframe::{preempt, GeneralRegs, UserContext};
+
+    const SIGVTALRM: i32 = 26;
+
+    // Mock user program spinning until preempted.
+    global_asm!(
+        r#"
+spin:
+    jmp spin
+"#
+    );
+
+    extern "sysv64" {
+        fn spin();
+    }
+
+    pub fn spin_preempted() {
+        unsafe { preempt::enable(SIGVTALRM, 10_000) }.unwrap();
+        let mut stack = vec![0u8; 0x1000];
+        let regs = GeneralRegs {
+            rax: 0,
+            rbx: 1,
+            rcx: 2,
+            rdx: 3,
+            rsi: 4,
+            rdi: 5,
+            rbp: 6,
+            rsp: stack.as_mut_ptr() as usize + stack.len(),
+            r8: 8,
+            r9: 9,
+            r10: 10,
+            r11: 11,
+            r12: 12,
+            r13: 13,
+            r14: 14,
+            r15: 15,
+            rip: spin as usize,
+            ..Default::default()
+        };
+        let mut cx = UserContext {
+            general: regs,
+            ..Default::default()
+        };
+        // preempted again after going back to the spinning program
+        for _ in 0..3 {
+            cx.run_fncall();
+            assert_eq!(cx.trap_num, preempt::TRAP_NUM);
+            assert_eq!(cx.error_code, 0);
+            // all registers are kept, fsbase is set to the initial TLS block
+            assert_eq!(
+                cx.general,
+                GeneralRegs {
+                    rflags: cx.general.rflags,
+                    fsbase: cx.general.fsbase,
+                    ..regs
+                }
+            );
+        }
+    }
+}
+
+fn main() {
+    #[cfg(all(feature = "fncall", target_arch = "x86_64", target_os = "linux"))]
+    {
+        preempt::spin_preempted();
+        println!("test preempt::spin_preempted ... ok");
+    }
+}
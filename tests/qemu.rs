@@ -0,0 +1,160 @@
+//! Boot the test kernel in `tests/kernel` under QEMU for each bare-metal
+//! backend, and check the results it reports over the serial port.
+//!
+//! Run by `cargo test --features qemu-tests`. A test fails if its
+//! `qemu-system-*` is not installed, run the others by name to skip it, e.g.
+//! `cargo test --features qemu-tests --test qemu riscv64`.
+
+#![cfg(feature = "qemu-tests")]
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Line printed by the test kernel when all tests pass.
+const PASSED: &str = "all tests passed";
+
+/// Time for a kernel to boot and finish the tests.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn target_dir() -> PathBuf {
+    root().join("target").join("qemu-tests")
+}
+
+/// Panic if `program` can not be run.
+fn require_program(program: &str) {
+    let found = Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    assert!(found, "{} not found", program);
+}
+
+/// Build the test kernel for `target`, and return the path of the image.
+fn build_kernel(target: &str, extra_args: &[&str]) -> PathBuf {
+    let kernel_dir = root().join("tests").join("kernel");
+    let status = Command::new(env!("CARGO"))
+        .current_dir(&kernel_dir)
+        .args(&["build", "--release", "--target", target])
+        .args(extra_args)
+        .arg("--target-dir")
+        .arg(target_dir())
+        .status()
+        .expect("failed to run cargo");
+    assert!(
+        status.success(),
+        "failed to build the test kernel for {}",
+        target
+    );
+    let image = target_dir().join(target).join("release");
+    match target.ends_with("-uefi") {
+        true => image.join("trapframe-test-kernel.efi"),
+        false => image.join("trapframe-test-kernel"),
+    }
+}
+
+/// Run `qemu` with `args` until it exits or times out, and check the output.
+fn run_qemu(qemu: &str, args: &[&str], kernel: &Path) {
+    let mut child = Command::new(qemu)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .expect("failed to run QEMU");
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).into_owned()
+    });
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let output = reader.join().unwrap();
+    print!("{}", output);
+    assert!(
+        output.contains(PASSED),
+        "tests failed on {} with {}",
+        qemu,
+        kernel.display()
+    );
+}
+
+#[test]
+fn riscv64() {
+    const QEMU: &str = "qemu-system-riscv64";
+    require_program(QEMU);
+    let kernel = build_kernel("riscv64imac-unknown-none-elf", &[]);
+    let kernel_arg = kernel.to_str().unwrap();
+    #[rustfmt::skip]
+    let args = [
+        "-machine", "virt",
+        "-nographic",
+        "-bios", "default",
+        "-kernel", kernel_arg,
+    ];
+    run_qemu(QEMU, &args, &kernel);
+}
+
+#[test]
+fn aarch64() {
+    const QEMU: &str = "qemu-system-aarch64";
+    require_program(QEMU);
+    let kernel = build_kernel("aarch64-unknown-none-softfloat", &[]);
+    let kernel_arg = kernel.to_str().unwrap();
+    #[rustfmt::skip]
+    let args = [
+        "-machine", "virt",
+        "-cpu", "cortex-a72",
+        "-nographic",
+        "-kernel", kernel_arg,
+    ];
+    run_qemu(QEMU, &args, &kernel);
+}
+
+#[test]
+fn x86_64() {
+    const QEMU: &str = "qemu-system-x86_64";
+    require_program(QEMU);
+    let kernel = build_kernel(
+        "x86_64-unknown-uefi",
+        &[
+            "-Z",
+            "build-std=core,alloc",
+            "-Z",
+            "build-std-features=compiler-builtins-mem",
+        ],
+    );
+    // boot from a FAT directory as the EFI system partition
+    let esp = target_dir().join("esp");
+    let boot = esp.join("EFI").join("Boot");
+    std::fs::create_dir_all(&boot).unwrap();
+    std::fs::copy(&kernel, boot.join("BootX64.efi")).unwrap();
+    let ovmf = root().join("examples").join("uefi").join("OVMF.fd");
+    let ovmf_arg = ovmf.to_str().unwrap();
+    let drive = format!("format=raw,file=fat:rw:{}", esp.display());
+    #[rustfmt::skip]
+    let args = [
+        "-bios", ovmf_arg,
+        "-drive", &drive,
+        "-net", "none",
+        "-nographic",
+        "-device", "isa-debug-exit,iobase=0xf4,iosize=0x04",
+    ];
+    run_qemu(QEMU, &args, &kernel);
+}